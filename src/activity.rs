@@ -7,10 +7,12 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+use crate::dist::rust::{block_moments, BlockMoments};
 use crate::frame::*;
 use crate::rdo::DistortionScale;
+use crate::superres::resampled_plane;
 use crate::tiling::*;
-use crate::util::math::Fixed;
+use crate::util::math::{CastFromPrimitive, Fixed};
 use crate::util::*;
 use itertools::izip;
 
@@ -66,6 +68,70 @@ impl ActivityMask {
   }
 }
 
+/// Estimates the SSIM of a reconstructed luma plane against its source, as
+/// the mean SSIM of non-overlapping 8×8 blocks. A source of a different size
+/// than the reconstruction, such as the full-size source of a frame coded at
+/// a reduced size, is resampled to the size of the reconstruction first.
+#[profiling::function]
+pub fn estimate_ssim<T: Pixel>(
+  src: &Plane<T>, rec: &Plane<T>, bit_depth: usize,
+) -> f64
+where
+  u32: CastFromPrimitive<T>,
+{
+  let PlaneConfig { width, height, .. } = PlaneConfig::new(&rec.geometry());
+  let src_cfg = PlaneConfig::new(&src.geometry());
+  let resampled;
+  let src = if (src_cfg.width, src_cfg.height) != (width, height) {
+    resampled =
+      resampled_plane(src, src_cfg.width, src_cfg.height, width, height);
+    &resampled
+  } else {
+    src
+  };
+  let rect = Rect { x: 0, y: 0, width, height };
+  let src = PlaneRegion::new(src, rect);
+  let rec = PlaneRegion::new(rec, rect);
+
+  // The usual constants for stabilizing the division of weak denominators.
+  let max = ((1 << bit_depth) - 1) as f64;
+  let c1 = (0.01 * max) * (0.01 * max);
+  let c2 = (0.03 * max) * (0.03 * max);
+
+  let mut ssim_sum = 0.;
+  let mut nblocks = 0;
+  for y in (0..height).step_by(8) {
+    for x in (0..width).step_by(8) {
+      let w = (width - x).min(8);
+      let h = (height - y).min(8);
+      let block_rect =
+        Area::Rect { x: x as isize, y: y as isize, width: w, height: h };
+      let BlockMoments { sum_s, sum_d, sum_s2, sum_d2, sum_sd } =
+        block_moments(
+          &src.subregion(block_rect),
+          &rec.subregion(block_rect),
+          w,
+          h,
+        );
+
+      let n = (w * h) as f64;
+      let mean_s = sum_s as f64 / n;
+      let mean_d = sum_d as f64 / n;
+      let var_s = sum_s2 as f64 / n - mean_s * mean_s;
+      let var_d = sum_d2 as f64 / n - mean_d * mean_d;
+      let cov = sum_sd as f64 / n - mean_s * mean_d;
+      ssim_sum += ((2. * mean_s * mean_d + c1) * (2. * cov + c2))
+        / ((mean_s * mean_s + mean_d * mean_d + c1) * (var_s + var_d + c2));
+      nblocks += 1;
+    }
+  }
+  if nblocks == 0 {
+    1.
+  } else {
+    ssim_sum / nblocks as f64
+  }
+}
+
 // Adapted from the source variance calculation in `cdef_dist_wxh_8x8`.
 #[inline(never)]
 fn variance_8x8<T: Pixel>(src: &PlaneRegion<'_, T>) -> u32 {
@@ -275,3 +341,24 @@ mod ssim_boost_tests {
     );
   }
 }
+
+#[cfg(test)]
+mod estimate_ssim_tests {
+  use super::*;
+  use v_frame::chroma::ChromaSubsampling;
+
+  /// Test that a reconstruction at a reduced size is compared against the
+  /// source resampled to its size rather than against its top-left corner.
+  #[test]
+  fn reduced_size_rec() {
+    let mut src = Frame::<u8>::new(64, 64, ChromaSubsampling::Monochrome);
+    for (y, row) in src.y_plane.rows_mut().enumerate() {
+      for (x, pixel) in row.iter_mut().enumerate() {
+        *pixel = if (x / 16 + y / 16) % 2 == 0 { 16 } else { 235 };
+      }
+    }
+    let rec = resampled_plane(&src.y_plane, 64, 64, 32, 32);
+    assert!((estimate_ssim(&src.y_plane, &src.y_plane, 8) - 1.).abs() < 1e-9);
+    assert!((estimate_ssim(&src.y_plane, &rec, 8) - 1.).abs() < 1e-9);
+  }
+}
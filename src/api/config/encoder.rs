@@ -12,7 +12,7 @@ use itertools::*;
 use crate::api::color::*;
 use crate::api::config::GrainTableSegment;
 use crate::api::{Rational, SpeedSettings};
-use crate::encoder::Tune;
+use crate::encoder::{ScreenContentMode, SuperblockSize, SuperresMode, Tune};
 use crate::serialize::{Deserialize, Serialize};
 
 use std::fmt;
@@ -81,14 +81,124 @@ pub struct EncoderConfig {
   ///
   /// In this mode the frame reordering is disabled.
   pub low_latency: bool,
+  /// Depth of the pyramid of frames coded out of order when
+  /// [`low_latency`] is disabled, from 1 to 4. Each group of reordered
+  /// frames is `1 << pyramid_depth` frames long.
+  ///
+  /// [`low_latency`]: #structfield.low_latency
+  pub pyramid_depth: u64,
   /// The base quantizer to use.
   pub quantizer: usize,
+  /// Flag to enable lossless mode.
+  ///
+  /// In this mode every frame is coded with a quantizer index of 0 and the
+  /// Walsh-Hadamard transform, and all in-loop filters are disabled, so the
+  /// decoded frames match the input exactly. [`quantizer`] is ignored.
+  ///
+  /// [`quantizer`]: #structfield.quantizer
+  pub lossless: bool,
   /// The minimum allowed base quantizer to use in bitrate mode.
   pub min_quantizer: u8,
   /// The target bitrate for the bitrate mode.
   pub bitrate: i32,
+  /// The maximum bitrate for the capped quality mode, or 0 to disable it.
+  ///
+  /// In this mode [`quantizer`] is kept unless the rate control predicts
+  /// that coding the frame with it would exceed this maximum, in which case
+  /// the quantizer is raised just enough to stay under it. The cap is
+  /// enforced by the same leaky bucket as the bitrate mode rather than by a
+  /// sliding window: a reservoir of [`reservoir_frame_delay`] temporal units
+  /// at this bitrate starts full, is refilled at this bitrate and is drained
+  /// by the coded frames. Short bursts may exceed the maximum as long as the
+  /// reservoir has room for them, and since the frame sizes are predicted,
+  /// the maximum is not a hard bound on any window of the stream.
+  /// It must not be negative and cannot be combined with [`bitrate`].
+  ///
+  /// [`quantizer`]: #structfield.quantizer
+  /// [`reservoir_frame_delay`]: #structfield.reservoir_frame_delay
+  /// [`bitrate`]: #structfield.bitrate
+  pub max_bitrate: i32,
+  /// The SSIM of the luma plane to reach in the target quality mode, between
+  /// 0 and 1, or `None` to disable it.
+  ///
+  /// In this mode the base quantizer index of every frame is searched with
+  /// a few trial encodes, starting from [`quantizer`] and then from the one
+  /// selected for the previous frame of the same type, and the coarsest one
+  /// that reaches the target is kept. The estimated SSIM of each shown frame
+  /// is reported in [`Packet::quality`]. It cannot be combined with
+  /// [`bitrate`] or [`max_bitrate`].
+  ///
+  /// [`quantizer`]: #structfield.quantizer
+  /// [`bitrate`]: #structfield.bitrate
+  /// [`max_bitrate`]: #structfield.max_bitrate
+  /// [`Packet::quality`]: crate::api::Packet::quality
+  pub target_quality: Option<f64>,
+  /// The offset of the luma DC quantizer index from the one selected for
+  /// each frame, from -63 to 63.
+  ///
+  /// There is no luma AC offset: the luma AC quantizer index is the base
+  /// quantizer index of the frame, set by [`quantizer`] or by the rate
+  /// control.
+  ///
+  /// [`quantizer`]: #structfield.quantizer
+  pub luma_dc_delta_q: i8,
+  /// The offsets of the Cb and Cr DC quantizer indices from the ones
+  /// selected for each frame, from -63 to 63.
+  ///
+  /// The Cb and Cr quantizers are already offset from the luma one
+  /// depending on the chroma subsampling and on the matrix coefficients of
+  /// [`color_description`].
+  ///
+  /// [`color_description`]: #structfield.color_description
+  pub chroma_dc_delta_q: [i8; 2],
+  /// The offsets of the Cb and Cr AC quantizer indices from the ones
+  /// selected for each frame, from -63 to 63.
+  ///
+  /// The Cb and Cr quantizers are already offset from the luma one
+  /// depending on the chroma subsampling and on the matrix coefficients of
+  /// [`color_description`].
+  ///
+  /// [`color_description`]: #structfield.color_description
+  pub chroma_ac_delta_q: [i8; 2],
+  /// The size in bits of the decoder buffer for the constant bitrate mode,
+  /// or 0 to disable it.
+  ///
+  /// In this mode every frame fits in the buffer when it is decoded, frames
+  /// are coded again with a coarser quantizer if needed, and the bits that
+  /// would overflow the buffer are spent on padding OBUs, so the stream
+  /// never underflows or overflows a decoder buffer filled at [`bitrate`].
+  /// A frame that does not fit even with the coarsest quantizer makes
+  /// [`Context::receive_packet()`] return [`EncoderStatus::Failure`].
+  /// [`quantizer`] is ignored.
+  ///
+  /// [`Context::receive_packet()`]: crate::api::Context::receive_packet
+  /// [`EncoderStatus::Failure`]: crate::api::EncoderStatus::Failure
+  ///
+  /// [`bitrate`]: #structfield.bitrate
+  /// [`quantizer`]: #structfield.quantizer
+  pub buffer_size: u64,
+  /// The fullness in bits of the decoder buffer when decoding starts in the
+  /// constant bitrate mode, 90% of [`buffer_size`] by default.
+  ///
+  /// [`buffer_size`]: #structfield.buffer_size
+  pub buffer_initial_fullness: Option<u64>,
   /// Metric to tune the quality for.
   pub tune: Tune,
+  /// Whether to use the coding tools for screen content, such as palette
+  /// prediction and intra block copy.
+  pub screen_content: ScreenContentMode,
+  /// Which frames are coded at a reduced width and upscaled to the full
+  /// width by the decoder (superres).
+  pub superres_mode: SuperresMode,
+  /// Denominator of the width of the frames coded in
+  /// [`SuperresMode::Fixed`] mode, from 9 (8/9 of the full width) to 16
+  /// (half of the full width).
+  pub superres_denom: u8,
+  /// Base quantizer index above which frames are coded at a reduced width
+  /// in [`SuperresMode::Dynamic`] mode.
+  pub superres_qthresh: u8,
+  /// Size of the superblocks the frames are partitioned into.
+  pub superblock_size: SuperblockSize,
   /// Parameters for grain synthesis.
   pub film_grain_params: Option<Vec<GrainTableSegment>>,
   /// Number of tiles horizontally. Must be a power of two.
@@ -163,9 +273,23 @@ impl EncoderConfig {
       min_quantizer: 0,
       reservoir_frame_delay: None,
       low_latency: false,
+      pyramid_depth: 2,
       quantizer: 100,
+      lossless: false,
       bitrate: 0,
+      max_bitrate: 0,
+      target_quality: None,
+      luma_dc_delta_q: 0,
+      chroma_dc_delta_q: [0; 2],
+      chroma_ac_delta_q: [0; 2],
+      buffer_size: 0,
+      buffer_initial_fullness: None,
       tune: Tune::default(),
+      screen_content: ScreenContentMode::default(),
+      superres_mode: SuperresMode::default(),
+      superres_denom: 12,
+      superres_qthresh: 160,
+      superblock_size: SuperblockSize::default(),
       film_grain_params: None,
       tile_cols: 0,
       tile_rows: 0,
@@ -238,6 +362,13 @@ impl EncoderConfig {
       .unwrap_or(false)
   }
 
+  /// The matrix coefficients of the output, if known.
+  pub(crate) fn matrix_coefficients(&self) -> MatrixCoefficients {
+    self.color_description.map_or(MatrixCoefficients::Unspecified, |colors| {
+      colors.matrix_coefficients
+    })
+  }
+
   pub(crate) fn get_film_grain_at(
     &self, timestamp: u64,
   ) -> Option<&GrainTableSegment> {
@@ -265,10 +396,34 @@ impl fmt::Display for EncoderConfig {
       ("keyint_min", self.min_key_frame_interval.to_string()),
       ("keyint_max", self.max_key_frame_interval.to_string()),
       ("quantizer", self.quantizer.to_string()),
+      ("lossless", self.lossless.to_string()),
       ("bitrate", self.bitrate.to_string()),
+      ("max_bitrate", self.max_bitrate.to_string()),
+      (
+        "target_quality",
+        self
+          .target_quality
+          .map_or_else(|| "none".to_string(), |q| q.to_string()),
+      ),
       ("min_quantizer", self.min_quantizer.to_string()),
+      ("luma_dc_delta_q", self.luma_dc_delta_q.to_string()),
+      ("chroma_dc_delta_q", self.chroma_dc_delta_q.iter().join(",")),
+      ("chroma_ac_delta_q", self.chroma_ac_delta_q.iter().join(",")),
+      ("buffer_size", self.buffer_size.to_string()),
+      (
+        "buffer_initial_fullness",
+        self
+          .buffer_initial_fullness
+          .map_or_else(|| "default".to_string(), |f| f.to_string()),
+      ),
       ("low_latency", self.low_latency.to_string()),
+      ("pyramid_depth", self.pyramid_depth.to_string()),
       ("tune", self.tune.to_string()),
+      ("screen_content", self.screen_content.to_string()),
+      ("superres_mode", self.superres_mode.to_string()),
+      ("superres_denom", self.superres_denom.to_string()),
+      ("superres_qthresh", self.superres_qthresh.to_string()),
+      ("superblock_size", self.superblock_size.to_string()),
       (
         "rdo_lookahead_frames",
         self.speed_settings.rdo_lookahead_frames.to_string(),
@@ -283,7 +438,12 @@ impl fmt::Display for EncoderConfig {
         self.speed_settings.scene_detection_mode.to_string(),
       ),
       ("cdef", self.speed_settings.cdef.to_string()),
+      (
+        "cdef_search_method",
+        self.speed_settings.cdef_search_method.to_string(),
+      ),
       ("lrf", self.speed_settings.lrf.to_string()),
+      ("lrf_wiener", self.speed_settings.lrf_wiener.to_string()),
       ("enable_timing_info", self.enable_timing_info.to_string()),
       (
         "min_block_size",
@@ -329,6 +489,23 @@ impl fmt::Display for EncoderConfig {
         "fine_directional_intra",
         self.speed_settings.prediction.fine_directional_intra.to_string(),
       ),
+      (
+        "filter_intra",
+        self.speed_settings.prediction.filter_intra.to_string(),
+      ),
+      (
+        "masked_compound",
+        self.speed_settings.prediction.masked_compound.to_string(),
+      ),
+      (
+        "distance_compound",
+        self.speed_settings.prediction.distance_compound.to_string(),
+      ),
+      (
+        "switchable_filter",
+        self.speed_settings.prediction.switchable_filter.to_string(),
+      ),
+      ("dual_filter", self.speed_settings.prediction.dual_filter.to_string()),
       (
         "include_near_mvs",
         self.speed_settings.motion.include_near_mvs.to_string(),
@@ -13,6 +13,8 @@ use rayon::{ThreadPool, ThreadPoolBuilder};
 use std::sync::Arc;
 
 use crate::api::{ChromaSubsampling, Context, ContextInner, PixelRange};
+use crate::encoder::SuperresMode;
+use crate::superres::{SUPERRES_DENOM_MAX, SUPERRES_DENOM_MIN};
 use crate::util::{self, Pixel};
 
 mod encoder;
@@ -103,11 +105,57 @@ pub enum InvalidConfig {
     "invalid switch frame interval {0} (must only be used with low latency mode)"
   )]
   InvalidSwitchFrameInterval(u64),
+  /// The depth of the reordering pyramid is invalid.
+  #[error("invalid pyramid depth {0} (expected >= 1, <= 4)")]
+  InvalidPyramidDepth(u64),
+  /// The size of the decoder buffer is invalid.
+  #[error("invalid buffer size {actual} (expected <= {max})")]
+  InvalidBufferSize {
+    /// The actual value.
+    actual: u64,
+    /// The maximal supported value.
+    max: u64,
+  },
+  /// The initial fullness of the decoder buffer is invalid.
+  #[error("invalid buffer initial fullness {actual} (expected <= {max})")]
+  InvalidBufferInitialFullness {
+    /// The actual value.
+    actual: u64,
+    /// The maximal supported value.
+    max: u64,
+  },
+  /// A quantizer index offset is invalid.
+  #[error("invalid delta q offset {0} (expected >= -63, <= 63)")]
+  InvalidDeltaQ(i8),
 
   /// An option unsupported in still picture mode was enabled along with it.
   #[error("invalid option {0} specified with still picture mode")]
   InvalidOptionWithStillPicture(&'static str),
 
+  /// The superres denominator is invalid.
+  #[error("invalid superres denominator {0} (expected >= 9, <= 16)")]
+  InvalidSuperresDenom(u8),
+
+  /// An option unsupported with a target bitrate was enabled along with it.
+  #[error("invalid option {0} specified with a target bitrate")]
+  InvalidOptionWithBitrate(&'static str),
+
+  /// The maximum bitrate is invalid.
+  #[error("invalid maximum bitrate {0} (expected >= 0)")]
+  InvalidMaxBitrate(i32),
+
+  /// The target quality is invalid.
+  #[error("invalid target quality (expected > 0, < 1)")]
+  InvalidTargetQuality,
+
+  /// An option unsupported in target quality mode was enabled along with it.
+  #[error("invalid option {0} specified with a target quality")]
+  InvalidOptionWithTargetQuality(&'static str),
+
+  /// An option unsupported in lossless mode was enabled along with it.
+  #[error("invalid option {0} specified with lossless mode")]
+  InvalidOptionWithLossless(&'static str),
+
   /// The rate control needs a target bitrate in order to produce results
   #[error("The rate control requires a target bitrate")]
   TargetBitrateNeeded,
@@ -403,10 +451,85 @@ impl Config {
       return Err(InvalidSwitchFrameInterval(config.switch_frame_interval));
     }
 
+    if !(1..=4).contains(&config.pyramid_depth) {
+      return Err(InvalidPyramidDepth(config.pyramid_depth));
+    }
+
+    if config.max_bitrate < 0 {
+      return Err(InvalidMaxBitrate(config.max_bitrate));
+    }
+    if config.max_bitrate != 0 && config.bitrate != 0 {
+      return Err(InvalidOptionWithBitrate("max_bitrate"));
+    }
+
+    if let Some(quality) = config.target_quality {
+      if quality.is_nan() || quality <= 0. || quality >= 1. {
+        return Err(InvalidTargetQuality);
+      }
+      if config.bitrate != 0 {
+        return Err(InvalidOptionWithBitrate("target_quality"));
+      }
+      if config.max_bitrate != 0 {
+        return Err(InvalidOptionWithTargetQuality("max_bitrate"));
+      }
+    }
+
+    if config.buffer_size > 0 && config.bitrate <= 0 {
+      return Err(TargetBitrateNeeded);
+    }
+    if config.buffer_size > 1 << 40 {
+      return Err(InvalidBufferSize {
+        actual: config.buffer_size,
+        max: 1 << 40,
+      });
+    }
+    if let Some(fullness) = config.buffer_initial_fullness {
+      if fullness > config.buffer_size {
+        return Err(InvalidBufferInitialFullness {
+          actual: fullness,
+          max: config.buffer_size,
+        });
+      }
+    }
+
+    let [dc_u, dc_v] = config.chroma_dc_delta_q;
+    let [ac_u, ac_v] = config.chroma_ac_delta_q;
+    let delta_qs = [config.luma_dc_delta_q, dc_u, dc_v, ac_u, ac_v];
+    for delta_q in delta_qs {
+      if !(-63..=63).contains(&delta_q) {
+        return Err(InvalidDeltaQ(delta_q));
+      }
+    }
+
     if config.enable_timing_info && config.still_picture {
       return Err(InvalidOptionWithStillPicture("enable_timing_info"));
     }
 
+    if config.lossless && config.bitrate != 0 {
+      return Err(InvalidOptionWithLossless("bitrate"));
+    }
+    if config.lossless && config.max_bitrate != 0 {
+      return Err(InvalidOptionWithLossless("max_bitrate"));
+    }
+    if config.lossless && config.target_quality.is_some() {
+      return Err(InvalidOptionWithLossless("target_quality"));
+    }
+    if config.lossless && delta_qs.iter().any(|&delta_q| delta_q != 0) {
+      return Err(InvalidOptionWithLossless("delta_q"));
+    }
+    if config.lossless && config.film_grain_params.is_some() {
+      return Err(InvalidOptionWithLossless("film_grain_params"));
+    }
+    if config.lossless && config.superres_mode != SuperresMode::Off {
+      return Err(InvalidOptionWithLossless("superres_mode"));
+    }
+
+    if !(SUPERRES_DENOM_MIN..=SUPERRES_DENOM_MAX)
+      .contains(&config.superres_denom)
+    {
+      return Err(InvalidSuperresDenom(config.superres_denom));
+    }
+
     // <https://aomediacodec.github.io/av1-spec/#color-config-syntax>
     if let Some(color_description) = config.color_description {
       if config.chroma_sampling != ChromaSubsampling::Monochrome
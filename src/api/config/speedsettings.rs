@@ -38,6 +38,11 @@ pub struct SpeedSettings {
   /// Enables CDEF.
   pub cdef: bool,
 
+  /// How the CDEF strengths of each frame are chosen.
+  ///
+  /// Searching is slower.
+  pub cdef_search_method: CDEFSearchMethod,
+
   /// Enables LRF.
   pub lrf: bool,
 
@@ -48,6 +53,12 @@ pub struct SpeedSettings {
   /// The amount of search done for self guided restoration.
   pub sgr_complexity: SGRComplexityLevel,
 
+  /// Enables searching Wiener restoration filters in addition to self
+  /// guided ones.
+  ///
+  /// Enabled is slower.
+  pub lrf_wiener: bool,
+
   /// Search level for segmentation.
   ///
   /// Full search is at least twice as slow.
@@ -76,16 +87,18 @@ impl Default for SpeedSettings {
       rdo_lookahead_frames: 40,
       scene_detection_mode: SceneDetectionSpeed::Standard,
       cdef: true,
+      cdef_search_method: CDEFSearchMethod::FullSearch,
       lrf: true,
       lru_on_skip: true,
       sgr_complexity: SGRComplexityLevel::Full,
+      lrf_wiener: true,
       segmentation: SegmentationLevel::Complex,
       partition: PartitionSpeedSettings {
         encode_bottomup: true,
         non_square_partition_max_threshold: BlockSize::BLOCK_64X64,
         partition_range: PartitionRange::new(
           BlockSize::BLOCK_4X4,
-          BlockSize::BLOCK_64X64,
+          BlockSize::BLOCK_128X128,
         ),
       },
       transform: TransformSpeedSettings {
@@ -100,11 +113,19 @@ impl Default for SpeedSettings {
       prediction: PredictionSpeedSettings {
         prediction_modes: PredictionModesSetting::ComplexAll,
         fine_directional_intra: true,
+        filter_intra: true,
+        masked_compound: true,
+        distance_compound: true,
+        switchable_filter: true,
+        dual_filter: true,
       },
       motion: MotionSpeedSettings {
         include_near_mvs: true,
         use_satd_subpel: true,
         me_allow_full_search: true,
+        global_motion: true,
+        warped_motion: true,
+        obmc: true,
       },
     }
   }
@@ -122,6 +143,10 @@ impl SpeedSettings {
     }
 
     if speed >= 2 {
+      settings.cdef_search_method = CDEFSearchMethod::FastSearch;
+
+      settings.prediction.dual_filter = false;
+
       settings.partition.non_square_partition_max_threshold =
         BlockSize::BLOCK_8X8;
 
@@ -133,7 +158,7 @@ impl SpeedSettings {
       settings.rdo_lookahead_frames = 30;
 
       settings.partition.partition_range =
-        PartitionRange::new(BlockSize::BLOCK_8X8, BlockSize::BLOCK_64X64);
+        PartitionRange::new(BlockSize::BLOCK_8X8, BlockSize::BLOCK_128X128);
     }
 
     if speed >= 4 {
@@ -141,8 +166,11 @@ impl SpeedSettings {
     }
 
     if speed >= 5 {
+      settings.cdef_search_method = CDEFSearchMethod::PickFromQ;
       settings.sgr_complexity = SGRComplexityLevel::Reduced;
       settings.motion.include_near_mvs = false;
+
+      settings.prediction.switchable_filter = false;
     }
 
     if speed >= 6 {
@@ -156,6 +184,12 @@ impl SpeedSettings {
 
     if speed >= 7 {
       settings.prediction.prediction_modes = PredictionModesSetting::Simple;
+      settings.prediction.filter_intra = false;
+      settings.prediction.masked_compound = false;
+      settings.prediction.distance_compound = false;
+      settings.motion.global_motion = false;
+      settings.motion.warped_motion = false;
+      settings.motion.obmc = false;
       // Multiref is enabled automatically if low_latency is false.
       //
       // If low_latency is true, enabling multiref allows using multiple
@@ -163,6 +197,7 @@ impl SpeedSettings {
       // backwards references.
       settings.multiref = false;
       settings.fast_deblock = true;
+      settings.lrf_wiener = false;
     }
 
     if speed >= 8 {
@@ -238,6 +273,7 @@ pub struct PartitionSpeedSettings {
   /// Range of partition sizes that can be used. Larger ranges are slower.
   ///
   /// Must be based on square block sizes, so e.g. 8×4 isn't allowed here.
+  /// The maximum is clamped to the superblock size.
   pub partition_range: PartitionRange,
 }
 
@@ -258,6 +294,25 @@ pub struct MotionSpeedSettings {
   /// Enable full search in some parts of motion estimation. Allowing full
   /// search is slower.
   pub me_allow_full_search: bool,
+
+  /// Estimate the global motion of each frame, from camera pans and zooms,
+  /// and predict blocks with it.
+  ///
+  /// Enabled is slower.
+  pub global_motion: bool,
+
+  /// Allow blocks to be predicted with a local warp, fitted to the motion
+  /// of their neighbors.
+  ///
+  /// Enabled is slower.
+  pub warped_motion: bool,
+
+  /// Allow blocks to blend the motion of their above and left neighbors
+  /// into their prediction near the edges, with overlapped block motion
+  /// compensation.
+  ///
+  /// Enabled is slower.
+  pub obmc: bool,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -271,6 +326,36 @@ pub struct PredictionSpeedSettings {
 
   /// Use fine directional intra prediction
   pub fine_directional_intra: bool,
+
+  /// Enables the recursive filter intra prediction modes, searched for
+  /// luma blocks up to 32x32.
+  ///
+  /// Enabled is slower.
+  pub filter_intra: bool,
+
+  /// Enables blending inter predictions with intra ones, and the two
+  /// references of compound blocks along a wedge or by the difference
+  /// between their predictions.
+  ///
+  /// Enabled is slower.
+  pub masked_compound: bool,
+
+  /// Enables weighting the two references of compound blocks by their
+  /// distance to the frame.
+  ///
+  /// Enabled is slower.
+  pub distance_compound: bool,
+
+  /// Enables choosing the interpolation filter of each inter block.
+  ///
+  /// Enabled is slower.
+  pub switchable_filter: bool,
+
+  /// Enables choosing distinct horizontal and vertical interpolation
+  /// filters, when `switchable_filter` is enabled.
+  ///
+  /// Enabled is slower.
+  pub dual_filter: bool,
 }
 
 /// Range of block sizes to use.
@@ -380,6 +465,41 @@ impl fmt::Display for PredictionModesSetting {
   }
 }
 
+/// Method used to choose the CDEF strengths of a frame
+#[derive(
+  Clone,
+  Copy,
+  Debug,
+  PartialOrd,
+  PartialEq,
+  Eq,
+  FromPrimitive,
+  Serialize,
+  Deserialize,
+)]
+pub enum CDEFSearchMethod {
+  /// Derive a single strength from the quantizer
+  PickFromQ,
+  /// Search a reduced set of strengths on the reconstructed frame
+  FastSearch,
+  /// Search all strengths on the reconstructed frame
+  FullSearch,
+}
+
+impl fmt::Display for CDEFSearchMethod {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    write!(
+      f,
+      "{}",
+      match self {
+        CDEFSearchMethod::PickFromQ => "Pick-From-Q",
+        CDEFSearchMethod::FastSearch => "Fast-Search",
+        CDEFSearchMethod::FullSearch => "Full-Search",
+      }
+    )
+  }
+}
+
 /// Search level for self guided restoration
 #[derive(
   Clone,
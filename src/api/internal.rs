@@ -8,10 +8,11 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 #![deny(missing_docs)]
 
-use crate::activity::ActivityMask;
+use crate::activity::{estimate_ssim, ActivityMask};
 use crate::api::lookahead::{
-  compute_motion_vectors, estimate_intra_costs, IMP_BLOCK_AREA_IN_MV_UNITS,
-  IMP_BLOCK_MV_UNITS_PER_PIXEL, IMP_BLOCK_SIZE_IN_MV_UNITS,
+  compute_motion_vectors, detect_screen_content, estimate_intra_costs,
+  IMP_BLOCK_AREA_IN_MV_UNITS, IMP_BLOCK_MV_UNITS_PER_PIXEL,
+  IMP_BLOCK_SIZE_IN_MV_UNITS,
 };
 use crate::api::{
   EncoderConfig, EncoderStatus, FrameType, Opaque, Packet, T35,
@@ -20,12 +21,14 @@ use crate::dist::get_satd;
 use crate::encoder::*;
 use crate::frame::PlanePad;
 use crate::frame::*;
+use crate::global_motion::estimate_global_motion;
 use crate::partition::*;
 use crate::rate::{
-  RCState, FRAME_NSUBTYPES, FRAME_SUBTYPE_I, FRAME_SUBTYPE_P,
+  inter_frame_subtype, RCState, FRAME_NSUBTYPES, FRAME_SUBTYPE_I,
   FRAME_SUBTYPE_SEF,
 };
 use crate::stats::EncoderStats;
+use crate::superres::resample_frame;
 use crate::tiling::Area;
 use crate::util::{math, pixel, Pixel};
 use arrayvec::ArrayVec;
@@ -35,7 +38,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use v_frame::chroma::ChromaSubsampling;
 
 /// The set of options that controls frame re-ordering and reference picture
@@ -48,7 +51,8 @@ pub struct InterConfig {
   /// Whether P-frames can use multiple references.
   pub(crate) multiref: bool,
   /// The depth of the re-ordering pyramid.
-  /// The current code cannot support values larger than 2.
+  /// Each level of the pyramid is stored in its own reference slot, so the
+  /// current code cannot support values larger than 4.
   pub(crate) pyramid_depth: u64,
   /// Number of input frames in group.
   pub(crate) group_input_len: u64,
@@ -64,11 +68,11 @@ pub struct InterConfig {
 impl InterConfig {
   pub(crate) fn new(enc_config: &EncoderConfig) -> InterConfig {
     let reorder = !enc_config.low_latency;
-    // A group always starts with (group_output_len - group_input_len) hidden
-    //  frames, followed by group_input_len shown frames.
-    // The shown frames iterate over the input frames in order, with frames
-    //  already encoded as hidden frames now displayed with Show Existing
-    //  Frame.
+    // A group codes the frames of the pyramid depth-first.
+    // Each input frame at the bottom of the pyramid is preceded by the
+    //  hidden frames it references that are not coded yet, and followed by
+    //  the next input frame, already encoded as a hidden frame and now
+    //  displayed with Show Existing Frame.
     // For example, for a pyramid depth of 2, the group is as follows:
     //                      |TU         |TU |TU |TU
     // idx_in_group_output:   0   1   2   3   4   5
@@ -77,11 +81,15 @@ impl InterConfig {
     // level:                 0   1   2   1   2   0
     //                        ^^^^^   ^^^^^^^^^^^^^
     //                        hidden      shown
-    // TODO: This only works for pyramid_depth <= 2 --- after that we need
-    //  more hidden frames in the middle of the group.
-    let pyramid_depth = if reorder { 2 } else { 0 };
+    // With a depth of 3, hidden frames also appear in the middle of the
+    //  group:
+    //                      |TU             |TU |TU |TU     |TU |TU |TU
+    // idx_in_group_output:   0   1   2   3   4   5   6   7   8   9  10  11
+    // input_frameno:         8   4   2   1  SEF  3  SEF  6   5  SEF  7  SEF
+    // level:                 0   1   2   3   2   3   1   2   3   2   3   0
+    let pyramid_depth = if reorder { enc_config.pyramid_depth } else { 0 };
     let group_input_len = 1 << pyramid_depth;
-    let group_output_len = group_input_len + pyramid_depth;
+    let group_output_len = group_input_len + (group_input_len >> 1);
     let switch_frame_interval = enc_config.switch_frame_interval;
     assert!(switch_frame_interval % group_input_len == 0);
     InterConfig {
@@ -106,6 +114,41 @@ impl InterConfig {
     (output_frameno_in_gop - 1) % self.group_output_len
   }
 
+  /// Get the offset of the input frame of an output frame from the start of
+  ///  its re-ordering group, and whether the output frame is shown, given
+  ///  the index of that output frame in its group.
+  const fn get_offset_in_group(
+    &self, idx_in_group_output: u64,
+  ) -> (u64, bool) {
+    if !self.reorder {
+      return (1, true);
+    }
+    let mut idx = idx_in_group_output;
+    // Walk the input frames at the bottom of the pyramid, which sit at odd
+    //  offsets.
+    let mut pos = 1;
+    loop {
+      // The hidden frame at a distance of (1 << k) after the previous input
+      //  frame is coded before it if that frame starts a span of (2 << k).
+      let mut k = self.pyramid_depth;
+      while k > 0 {
+        if (pos - 1) % (2 << k) == 0 {
+          if idx == 0 {
+            return (pos - 1 + (1 << k), false);
+          }
+          idx -= 1;
+        }
+        k -= 1;
+      }
+      // The frame itself, then the Show Existing Frame of the next one.
+      if idx < 2 {
+        return (pos + idx, true);
+      }
+      idx -= 2;
+      pos += 2;
+    }
+  }
+
   /// Get the order-hint of an output frame given the output frame number of the
   ///  frame in the current keyframe gop and the index of that output frame
   ///  in its re-ordering gorup.
@@ -120,12 +163,7 @@ impl InterConfig {
     // Subtract 1 because the first frame in the gop is always a keyframe.
     let group_idx = (output_frameno_in_gop - 1) / self.group_output_len;
     // Get the offset to the corresponding input frame.
-    // TODO: This only works with pyramid_depth <= 2.
-    let offset = if idx_in_group_output < self.pyramid_depth {
-      self.group_input_len >> idx_in_group_output
-    } else {
-      idx_in_group_output - self.pyramid_depth + 1
-    };
+    let (offset, _) = self.get_offset_in_group(idx_in_group_output);
     // Construct the final order hint relative to the start of the group.
     (self.group_input_len * group_idx + offset) as u32
   }
@@ -134,16 +172,9 @@ impl InterConfig {
   pub(crate) const fn get_level(&self, idx_in_group_output: u64) -> u64 {
     if !self.reorder {
       0
-    } else if idx_in_group_output < self.pyramid_depth {
-      // Hidden frames are output first (to be shown in the future).
-      idx_in_group_output
     } else {
-      // Shown frames
-      // TODO: This only works with pyramid_depth <= 2.
-      pos_to_lvl(
-        idx_in_group_output - self.pyramid_depth + 1,
-        self.pyramid_depth,
-      )
+      let (offset, _) = self.get_offset_in_group(idx_in_group_output);
+      pos_to_lvl(offset, self.pyramid_depth)
     }
   }
 
@@ -153,13 +184,13 @@ impl InterConfig {
     if level == 0 {
       (order_hint >> self.pyramid_depth) & 3
     } else {
-      // This only works with pyramid_depth <= 4.
+      // There are only enough slots for pyramid_depth <= 4.
       3 + level as u32
     }
   }
 
   pub(crate) const fn get_show_frame(&self, idx_in_group_output: u64) -> bool {
-    idx_in_group_output >= self.pyramid_depth
+    self.get_offset_in_group(idx_in_group_output).1
   }
 
   pub(crate) const fn get_show_existing_frame(
@@ -169,8 +200,7 @@ impl InterConfig {
     //  avoiding a bunch of work when it's false.
     self.reorder
       && self.get_show_frame(idx_in_group_output)
-      && (idx_in_group_output - self.pyramid_depth + 1).count_ones() == 1
-      && idx_in_group_output != self.pyramid_depth
+      && self.get_level(idx_in_group_output) < self.pyramid_depth
   }
 
   pub(crate) fn get_input_frameno(
@@ -220,6 +250,35 @@ impl<T: Pixel> FrameData<T> {
     let fs = FrameState::new_with_frame(&fi, frame);
     FrameData { fi, fs }
   }
+
+  /// Codes the frame at `width` by `height`, or the closest size its
+  /// references allow, resampling its input and lookahead data.
+  fn resize(&mut self, (width, height): (usize, usize)) {
+    if self.fi.sequence.reduced_still_picture_hdr {
+      return;
+    }
+    let (width, height) = self.fi.clamp_frame_size(width, height);
+    if (width, height) == (self.fi.width, self.fi.height) {
+      return;
+    }
+    let fi = self.fi.resized(width, height);
+    let input = resample_frame(
+      &self.fs.input,
+      self.fi.width,
+      self.fi.height,
+      width,
+      height,
+    );
+    let mut fs = FrameState::new_with_frame(&fi, Arc::new(input));
+    fs.frame_me_stats = {
+      let me_stats = self.fs.frame_me_stats.read().expect("poisoned lock");
+      Arc::new(RwLock::new(std::array::from_fn(|i| {
+        me_stats[i].resampled(fi.w_in_b, fi.h_in_b)
+      })))
+    };
+    self.fi = fi;
+    self.fs = fs;
+  }
 }
 
 type FrameQueue<T> = BTreeMap<u64, Option<Arc<Frame<T>>>>;
@@ -262,6 +321,12 @@ pub(crate) struct ContextInner<T: Pixel> {
   opaque_q: BTreeMap<u64, Opaque>,
   /// Optional T35 metadata per frame
   t35_q: BTreeMap<u64, Box<[T35]>>,
+  /// Maps *`input_frameno`* to the presentation timestamp and, once known,
+  /// the duration of the frame
+  timestamp_q: BTreeMap<u64, (u64, Option<u64>)>,
+  /// Maps *`input_frameno`* to the size the frame was requested to be
+  /// coded at
+  frame_size_q: BTreeMap<u64, (usize, usize)>,
 }
 
 impl<T: Pixel> ContextInner<T>
@@ -279,8 +344,21 @@ where
     let mut keyframes = BTreeSet::new();
     keyframes.insert(0);
 
-    let maybe_ac_qi_max =
-      if enc.quantizer < 255 { Some(enc.quantizer as u8) } else { None };
+    // The buffer constraints of the constant bitrate mode take precedence
+    //  over the maximum quantizer, and in capped quality mode the quantizer
+    //  is the one to keep rather than a maximum.
+    let maybe_capped_qi =
+      (enc.max_bitrate > 0).then_some(enc.quantizer.min(255) as u8);
+    let maybe_ac_qi_max = if enc.quantizer < 255
+      && enc.buffer_size == 0
+      && maybe_capped_qi.is_none()
+    {
+      Some(enc.quantizer as u8)
+    } else {
+      None
+    };
+    let maybe_buffer_size =
+      (enc.buffer_size > 0).then_some(enc.buffer_size as i64);
 
     let seq = Arc::new(Sequence::new(enc));
     let inter_cfg = InterConfig::new(enc);
@@ -331,17 +409,23 @@ where
         enc.height as i32,
         enc.time_base.den as i64,
         enc.time_base.num as i64,
-        enc.bitrate,
+        if maybe_capped_qi.is_some() { enc.max_bitrate } else { enc.bitrate },
         maybe_ac_qi_max,
         enc.min_quantizer,
         enc.max_key_frame_interval as i32,
         enc.reservoir_frame_delay,
+        maybe_buffer_size,
+        enc.buffer_initial_fullness.map(|fullness| fullness as i64),
+        maybe_capped_qi,
+        enc.target_quality,
       ),
       maybe_prev_log_base_q: None,
       next_lookahead_frame: 1,
       next_lookahead_output_frameno: 0,
       opaque_q: BTreeMap::new(),
       t35_q: BTreeMap::new(),
+      timestamp_q: BTreeMap::new(),
+      frame_size_q: BTreeMap::new(),
     }
   }
 
@@ -380,6 +464,12 @@ where
     }
     self.frame_q.insert(input_frameno, frame);
 
+    if !is_flushing {
+      let (pts, duration) =
+        params.as_ref().map_or((None, None), |p| (p.pts, p.duration));
+      self.add_timestamp(input_frameno, pts, duration);
+    }
+
     if let Some(params) = params {
       if params.frame_type_override == FrameTypeOverride::Key {
         self.keyframes_forced.insert(input_frameno);
@@ -388,6 +478,9 @@ where
         self.opaque_q.insert(input_frameno, op);
       }
       self.t35_q.insert(input_frameno, params.t35_metadata);
+      if let Some(size) = params.frame_size {
+        self.frame_size_q.insert(input_frameno, size);
+      }
     }
 
     if !self.needs_more_frame_q_lookahead(self.next_lookahead_frame) {
@@ -493,7 +586,7 @@ where
 
     self.frame_data.insert(
       output_frameno,
-      fi.map(|fi| {
+      fi.map(|mut fi| {
         let frame = self
           .frame_q
           .get(&fi.input_frameno)
@@ -501,6 +594,12 @@ where
           .unwrap()
           .as_ref()
           .unwrap();
+        if self.config.screen_content == ScreenContentMode::Auto {
+          fi.set_screen_content(detect_screen_content(
+            frame,
+            self.config.bit_depth,
+          ));
+        }
         FrameData::new(fi, frame.clone())
       }),
     );
@@ -756,10 +855,12 @@ where
         frame: fs.input.clone(),
         input_hres: fs.input_hres.clone(),
         input_qres: fs.input_qres.clone(),
+        scaled_y_plane: None,
         cdfs: fs.cdfs,
         frame_me_stats: fs.frame_me_stats.clone(),
         output_frameno,
         segmentation: fs.segmentation,
+        globalmv_params: fi.globalmv_params,
       });
       for i in 0..REF_FRAMES {
         if (fi.refresh_frame_flags & (1 << i)) != 0 {
@@ -786,6 +887,7 @@ where
     //
     // Compute the motion vectors.
     compute_motion_vectors(fi, fs, &self.inter_cfg);
+    estimate_global_motion(fi, fs, &self.inter_cfg);
 
     let coded_data = fi.coded_frame_data.as_mut().unwrap();
 
@@ -838,10 +940,12 @@ where
       frame: fs.input.clone(),
       input_hres: fs.input_hres.clone(),
       input_qres: fs.input_qres.clone(),
+      scaled_y_plane: None,
       cdfs: fs.cdfs,
       frame_me_stats: fs.frame_me_stats.clone(),
       output_frameno,
       segmentation: fs.segmentation,
+      globalmv_params: fi.globalmv_params,
     });
     for i in 0..REF_FRAMES {
       if (fi.refresh_frame_flags & (1 << i)) != 0 {
@@ -1334,6 +1438,9 @@ where
   pub fn encode_show_existing_packet(
     &mut self, cur_output_frameno: u64,
   ) -> Result<Packet<T>, EncoderStatus> {
+    let duration = self.frame_duration(
+      self.frame_data[&cur_output_frameno].as_ref().unwrap().fi.input_frameno,
+    );
     let frame_data =
       self.frame_data.get_mut(&cur_output_frameno).unwrap().as_mut().unwrap();
     let sef_data = encode_show_existing_frame(
@@ -1341,16 +1448,30 @@ where
       &mut frame_data.fs,
       &self.inter_cfg,
     );
+    let maybe_quality = self.config.target_quality.map(|_| {
+      estimate_ssim(
+        &frame_data.fs.input.y_plane,
+        &frame_data.fs.rec.y_plane,
+        self.config.bit_depth,
+      )
+    });
     let bits = (sef_data.len() * 8) as i64;
     self.packet_data.extend(sef_data);
     self.rc_state.update_state(
       bits,
       FRAME_SUBTYPE_SEF,
       frame_data.fi.show_frame,
+      duration,
       0,
       false,
       false,
     );
+    let padding_bytes = self.rc_state.take_padding_bytes();
+    if padding_bytes > 0
+      && write_padding_obu(&mut self.packet_data, padding_bytes).is_err()
+    {
+      return Err(EncoderStatus::Failure);
+    }
     let (rec, source) = if frame_data.fi.show_frame {
       (Some(frame_data.fs.rec.clone()), Some(frame_data.fs.input.clone()))
     } else {
@@ -1362,8 +1483,25 @@ where
     let input_frameno = frame_data.fi.input_frameno;
     let frame_type = frame_data.fi.frame_type;
     let qp = frame_data.fi.base_q_idx;
+    let screen_content = frame_data.fi.allow_screen_content_tools != 0;
     let enc_stats = frame_data.fs.enc_stats.clone();
-    self.finalize_packet(rec, source, input_frameno, frame_type, qp, enc_stats)
+    let frame_size = frame_data.fi.rec_buffer.frames
+      [frame_data.fi.frame_to_show_map_idx as usize]
+      .as_ref()
+      .map_or((frame_data.fi.width, frame_data.fi.height), |rec| {
+        (rec.width as usize, rec.height as usize)
+      });
+    self.finalize_packet(
+      rec,
+      source,
+      input_frameno,
+      frame_type,
+      qp,
+      maybe_quality,
+      screen_content,
+      enc_stats,
+      frame_size,
+    )
   }
 
   #[profiling::function]
@@ -1418,8 +1556,14 @@ where
       }
     }
 
+    if let Some(size) = self.frame_size_q.remove(&frame_data.fi.input_frameno)
+    {
+      frame_data.resize(size);
+    }
+
     let fti = frame_data.fi.get_frame_subtype();
-    let qps = self.rc_state.select_qi(
+    let duration = self.frame_duration(frame_data.fi.input_frameno);
+    let mut qps = self.rc_state.select_qi(
       self,
       cur_output_frameno,
       fti,
@@ -1427,19 +1571,22 @@ where
       log_isqrt_mean_scale,
     );
     frame_data.fi.set_quantizers(&qps);
+    frame_data.fi.set_superres_denom();
 
     if self.rc_state.needs_trial_encode(fti) {
       let mut trial_fs = frame_data.fs.clone();
-      let data = encode_frame(&frame_data.fi, &mut trial_fs, &self.inter_cfg);
+      let data =
+        encode_frame(&mut frame_data.fi, &mut trial_fs, &self.inter_cfg);
       self.rc_state.update_state(
         (data.len() * 8) as i64,
         fti,
         frame_data.fi.show_frame,
+        duration,
         qps.log_target_q,
         true,
         false,
       );
-      let qps = self.rc_state.select_qi(
+      qps = self.rc_state.select_qi(
         self,
         cur_output_frameno,
         fti,
@@ -1447,10 +1594,110 @@ where
         log_isqrt_mean_scale,
       );
       frame_data.fi.set_quantizers(&qps);
+      frame_data.fi.set_superres_denom();
     }
 
-    let data =
-      encode_frame(&frame_data.fi, &mut frame_data.fs, &self.inter_cfg);
+    // Keep the state from before encoding in case the frame does not fit in
+    //  the buffer of the constant bitrate mode and has to be coded again.
+    let maybe_snapshot = self
+      .rc_state
+      .has_strict_buffer()
+      .then(|| (frame_data.fi.clone(), frame_data.fs.deep_clone()));
+    let mut maybe_quality = None;
+    let mut data = if let Some(mut search) = self
+      .rc_state
+      .start_quality_search(fti, self.config.quantizer.min(255) as u8)
+    {
+      // Code the frame with every base quantizer index the search tries and
+      //  keep the coarsest one that reaches the target quality, each from the
+      //  state from before encoding.
+      let fi = frame_data.fi.clone();
+      let fs = frame_data.fs.deep_clone();
+      let mut data = Vec::new();
+      let mut last_qi = None;
+      while let Some(qi) = search.next_qi() {
+        qps = RCState::select_flat_qi(
+          qi,
+          self.config.bit_depth,
+          fti,
+          self.config.chroma_sampling,
+          self.config.matrix_coefficients(),
+          log_isqrt_mean_scale,
+        );
+        frame_data.fi = fi.clone();
+        frame_data.fi.set_quantizers(&qps);
+        frame_data.fi.set_superres_denom();
+        frame_data.fs = fs.deep_clone();
+        data = encode_frame(
+          &mut frame_data.fi,
+          &mut frame_data.fs,
+          &self.inter_cfg,
+        );
+        search.update(estimate_ssim(
+          &frame_data.fs.input.y_plane,
+          &frame_data.fs.rec.y_plane,
+          self.config.bit_depth,
+        ));
+        last_qi = Some(qi);
+      }
+      let (qi, quality) = search.best().unwrap();
+      if last_qi != Some(qi) {
+        qps = RCState::select_flat_qi(
+          qi,
+          self.config.bit_depth,
+          fti,
+          self.config.chroma_sampling,
+          self.config.matrix_coefficients(),
+          log_isqrt_mean_scale,
+        );
+        frame_data.fi = fi;
+        frame_data.fi.set_quantizers(&qps);
+        frame_data.fi.set_superres_denom();
+        frame_data.fs = fs;
+        data = encode_frame(
+          &mut frame_data.fi,
+          &mut frame_data.fs,
+          &self.inter_cfg,
+        );
+      }
+      self.rc_state.update_quality_qi(fti, qi);
+      maybe_quality = Some(quality);
+      data
+    } else {
+      encode_frame(&mut frame_data.fi, &mut frame_data.fs, &self.inter_cfg)
+    };
+    if let Some((fi, fs)) = maybe_snapshot {
+      while let Some(reencode_qps) = self.rc_state.select_reencode_qi(
+        (data.len() * 8) as i64,
+        fti,
+        &qps,
+        self.config.bit_depth,
+        self.config.chroma_sampling,
+        self.config.matrix_coefficients(),
+        log_isqrt_mean_scale,
+      ) {
+        qps = reencode_qps;
+        frame_data.fi = fi.clone();
+        frame_data.fi.set_quantizers(&qps);
+        frame_data.fi.set_superres_denom();
+        frame_data.fs = fs.deep_clone();
+        data = encode_frame(
+          &mut frame_data.fi,
+          &mut frame_data.fs,
+          &self.inter_cfg,
+        );
+      }
+      // The frame is already coded with the coarsest quantizer, so emitting
+      //  it would underflow the decoder buffer.
+      if !self.rc_state.fits_buffer((data.len() * 8) as i64) {
+        log::error!(
+          "Frame {} does not fit in the decoder buffer even at the maximum \
+           quantizer; increase the buffer size or the bitrate",
+          frame_data.fi.input_frameno
+        );
+        return Err(EncoderStatus::Failure);
+      }
+    }
     #[cfg(feature = "dump_lookahead_data")]
     {
       let input_frameno = frame_data.fi.input_frameno;
@@ -1459,16 +1706,22 @@ where
     }
     let enc_stats = frame_data.fs.enc_stats.clone();
     self.maybe_prev_log_base_q = Some(qps.log_base_q);
-    // TODO: Add support for dropping frames.
     self.rc_state.update_state(
       (data.len() * 8) as i64,
       fti,
       frame_data.fi.show_frame,
+      duration,
       qps.log_target_q,
       false,
       false,
     );
     self.packet_data.extend(data);
+    let padding_bytes = self.rc_state.take_padding_bytes();
+    if padding_bytes > 0
+      && write_padding_obu(&mut self.packet_data, padding_bytes).is_err()
+    {
+      return Err(EncoderStatus::Failure);
+    }
 
     let planes = if frame_data.fi.sequence.chroma_sampling
       == ChromaSubsampling::Monochrome
@@ -1526,13 +1779,18 @@ where
       let input_frameno = fi.input_frameno;
       let frame_type = fi.frame_type;
       let qp = fi.base_q_idx;
+      let screen_content = fi.allow_screen_content_tools != 0;
+      let frame_size = (fi.upscaled_width, fi.height);
       self.finalize_packet(
         rec,
         source,
         input_frameno,
         frame_type,
         qp,
+        maybe_quality,
+        screen_content,
         enc_stats,
+        frame_size,
       )
     } else {
       Err(EncoderStatus::Encoded)
@@ -1592,8 +1850,8 @@ where
 
   fn finalize_packet(
     &mut self, rec: Option<Arc<Frame<T>>>, source: Option<Arc<Frame<T>>>,
-    input_frameno: u64, frame_type: FrameType, qp: u8,
-    enc_stats: EncoderStats,
+    input_frameno: u64, frame_type: FrameType, qp: u8, quality: Option<f64>,
+    screen_content: bool, enc_stats: EncoderStats, frame_size: (usize, usize),
   ) -> Result<Packet<T>, EncoderStatus> {
     let data = self.packet_data.clone();
     self.packet_data.clear();
@@ -1601,19 +1859,75 @@ where
       return Err(EncoderStatus::Failure);
     }
 
+    // Frames are always timestamped when received, but fall back to the
+    //  timestamps of a constant frame rate rather than failing if not.
+    let (pts, duration) = self
+      .timestamp_q
+      .get(&input_frameno)
+      .copied()
+      .unwrap_or((input_frameno, None));
+    let duration =
+      duration.unwrap_or_else(|| self.rc_state.estimated_tu_duration());
+
     self.frames_processed += 1;
     Ok(Packet {
       data,
       rec,
       source,
       input_frameno,
+      pts,
+      duration,
       frame_type,
       qp,
+      quality,
+      screen_content,
       enc_stats,
+      frame_size,
       opaque: None,
     })
   }
 
+  /// Records the timestamp of a newly received frame.
+  ///
+  /// Missing timestamps follow on from the previous frame, and the duration
+  /// of the previous frame is derived from this timestamp if it was not set.
+  fn add_timestamp(
+    &mut self, input_frameno: u64, pts: Option<u64>, duration: Option<u64>,
+  ) {
+    let duration = duration.filter(|&d| d > 0);
+    let prev = input_frameno
+      .checked_sub(1)
+      .and_then(|prev_frameno| self.timestamp_q.get_mut(&prev_frameno));
+    let pts = match prev {
+      Some((prev_pts, prev_duration)) => {
+        let pts =
+          pts.unwrap_or_else(|| *prev_pts + prev_duration.unwrap_or(1));
+        if prev_duration.is_none() {
+          let d = pts.saturating_sub(*prev_pts).max(1);
+          *prev_duration = Some(d);
+          self.rc_state.update_vfr_scale(d);
+        }
+        pts
+      }
+      None => pts.unwrap_or(input_frameno),
+    };
+    if let Some(d) = duration {
+      self.rc_state.update_vfr_scale(d);
+    }
+    self.timestamp_q.insert(input_frameno, (pts, duration));
+  }
+
+  /// The duration of an input frame, if already known.
+  fn frame_duration(&self, input_frameno: u64) -> Option<u64> {
+    self.timestamp_q.get(&input_frameno).and_then(|&(_, duration)| duration)
+  }
+
+  /// The known durations of the upcoming temporal units, starting with the
+  /// one containing the next shown frame.
+  pub(crate) fn tu_durations(&self) -> impl Iterator<Item = u64> + '_ {
+    (self.frames_processed..).map_while(|n| self.frame_duration(n))
+  }
+
   #[profiling::function]
   fn garbage_collect(&mut self, cur_input_frameno: u64) {
     if cur_input_frameno == 0 {
@@ -1622,6 +1936,8 @@ where
     let frame_q_start = self.frame_q.keys().next().cloned().unwrap_or(0);
     for i in frame_q_start..cur_input_frameno {
       self.frame_q.remove(&i);
+      self.timestamp_q.remove(&i);
+      self.frame_size_q.remove(&i);
     }
 
     if self.output_frameno < 2 {
@@ -1763,8 +2079,8 @@ where
         acc[FRAME_SUBTYPE_SEF] += 1;
       } else {
         // TODO: Implement golden P-frames.
-        let fti = FRAME_SUBTYPE_P
-          + (self.inter_cfg.get_level(idx_in_group_output) as usize);
+        let fti =
+          inter_frame_subtype(self.inter_cfg.get_level(idx_in_group_output));
         acc[fti] += 1;
         nframes_total += 1;
       }
@@ -14,6 +14,7 @@ use crate::tiling::{Area, PlaneRegion, TileRect};
 use crate::transform::TxSize;
 use crate::util::{self, Aligned};
 use crate::Pixel;
+use arrayvec::ArrayVec;
 use rayon::iter::*;
 use std::num::{NonZeroU8, NonZeroUsize};
 use std::sync::Arc;
@@ -66,6 +67,7 @@ where
         0,
         0,
         bsize,
+        BlockSize::BLOCK_64X64.width_log2(),
         PlaneOffset {
           x: (x * IMPORTANCE_BLOCK_SIZE) as isize,
           y: (y * IMPORTANCE_BLOCK_SIZE) as isize,
@@ -294,3 +296,91 @@ pub(crate) fn compute_motion_vectors<T: Pixel>(
       estimate_tile_motion(fi, ts, inter_cfg);
     });
 }
+
+/// Size of the blocks in which the screen content detector counts colors.
+const SCREEN_CONTENT_BLOCK_SIZE: usize = 16;
+
+/// Largest difference between neighboring luma values, in 8-bit units, that
+/// is still considered a soft gradient rather than a sharp edge.
+const SCREEN_CONTENT_SOFT_GRADIENT: u16 = 8;
+
+/// Outcome of classifying a frame as natural video or screen content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ScreenContentDecision {
+  /// Whether the frame should be coded with the screen content tools.
+  pub screen_content: bool,
+  /// Whether motion vectors should be restricted to whole pixels.
+  pub integer_mv: bool,
+}
+
+/// Classifies a frame as natural video or screen content, from the number
+/// of colors in its blocks and the distribution of its luma gradients.
+///
+/// Synthetic content has many blocks with only a handful of colors, and
+/// neighboring pixels tend to be either identical or separated by a sharp
+/// edge. Camera footage is noisy, so small gradients dominate instead.
+#[profiling::function]
+pub(crate) fn detect_screen_content<T: Pixel>(
+  frame: &Frame<T>, bit_depth: usize,
+) -> ScreenContentDecision {
+  let plane = &frame.y_plane;
+  let (width, height) = (plane.width().get(), plane.height().get());
+  let region = plane.as_region();
+
+  // Blocks with two to four colors, typical of text and user interfaces.
+  // Flat blocks are left out since natural video has plenty of them too.
+  let mut blocks = 0;
+  let mut few_color_blocks = 0;
+  for y in (0..height / SCREEN_CONTENT_BLOCK_SIZE)
+    .map(|by| by * SCREEN_CONTENT_BLOCK_SIZE)
+  {
+    let rows: ArrayVec<_, SCREEN_CONTENT_BLOCK_SIZE> =
+      (y..y + SCREEN_CONTENT_BLOCK_SIZE).map(|y| &region[y]).collect();
+    for x in (0..width / SCREEN_CONTENT_BLOCK_SIZE)
+      .map(|bx| bx * SCREEN_CONTENT_BLOCK_SIZE)
+    {
+      let mut colors = ArrayVec::<T, 5>::new();
+      'block: for row in &rows {
+        for &p in &row[x..x + SCREEN_CONTENT_BLOCK_SIZE] {
+          if !colors.contains(&p) {
+            if colors.is_full() {
+              break 'block;
+            }
+            colors.push(p);
+          }
+        }
+      }
+      blocks += 1;
+      if (2..=4).contains(&colors.len()) {
+        few_color_blocks += 1;
+      }
+    }
+  }
+
+  // Horizontal and vertical gradients, split into flat, soft and sharp.
+  let shift = bit_depth - 8;
+  let (mut flat, mut soft, mut sharp) = (0usize, 0usize, 0usize);
+  let mut count = |a: T, b: T| {
+    let d = a.to_u16().abs_diff(b.to_u16()) >> shift;
+    if d == 0 {
+      flat += 1;
+    } else if d <= SCREEN_CONTENT_SOFT_GRADIENT {
+      soft += 1;
+    } else {
+      sharp += 1;
+    }
+  };
+  for (row, next) in region.rows_iter().zip(region.rows_iter().skip(1)) {
+    for x in 0..width - 1 {
+      count(row[x], row[x + 1]);
+      count(row[x], next[x]);
+    }
+  }
+
+  let screen_content = few_color_blocks * 10 > blocks && soft < flat;
+  ScreenContentDecision {
+    screen_content,
+    // Subpixel motion only pays off when there are soft edges to track.
+    integer_mv: screen_content && soft * 4 < sharp,
+  }
+}
@@ -69,6 +69,40 @@ fn setup_encoder<T: Pixel>(
   cfg.new_context().unwrap()
 }
 
+/// Returns the configuration of a fast low-latency encoder of 64×80 frames,
+/// for the tests to adjust before calling [`new_test_context`].
+fn test_encoder_config() -> EncoderConfig {
+  let mut enc = EncoderConfig::with_speed_preset(10);
+  enc.width = 64;
+  enc.height = 80;
+  enc.low_latency = true;
+  enc
+}
+
+fn new_test_context<T: Pixel>(enc: EncoderConfig) -> Context<T> {
+  Config::new().with_encoder_config(enc).with_threads(1).new_context().unwrap()
+}
+
+/// Receives the packets of a flushed encoder until the limit is reached,
+/// passing each of them to `f`, and returns how many there were or the
+/// first unexpected status.
+fn receive_packets<T: Pixel>(
+  ctx: &mut Context<T>, mut f: impl FnMut(Packet<T>),
+) -> Result<usize, EncoderStatus> {
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(packet) => {
+        f(packet);
+        count += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => return Ok(count),
+      Err(e) => return Err(e),
+    }
+  }
+}
+
 /*
 fn fill_frame<T: Pixel>(ra: &mut ChaChaRng, frame: &mut Frame<T>) {
   for plane in frame.planes.iter_mut() {
@@ -714,6 +748,113 @@ fn pyramid_level_reorder_minus(missing: u64) {
   );
 }
 
+#[test]
+fn pyramid_depth_3_reorder() {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    9,
+    9,
+    0,
+    false,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.pyramid_depth = 3;
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+
+  assert_eq!(ctx.inner.inter_cfg.group_input_len, 8);
+
+  send_frames(&mut ctx, 9, 0);
+  ctx.flush();
+
+  // data[output_frameno] = (input_frameno, pyramid_level, show_existing)
+  let data = get_frame_invariants(ctx)
+    .map(|fi| fi.unwrap())
+    .map(|fi| {
+      (fi.input_frameno, fi.pyramid_level, fi.is_show_existing_frame())
+    })
+    .collect::<Vec<_>>();
+
+  assert_eq!(
+    &data[..],
+    &[
+      (0, 0, false), // I-frame
+      (8, 0, false),
+      (4, 1, false),
+      (2, 2, false),
+      (1, 3, false),
+      (2, 2, true),
+      (3, 3, false),
+      (4, 1, true),
+      (6, 2, false),
+      (5, 3, false),
+      (6, 2, true),
+      (7, 3, false),
+      (8, 0, true),
+    ][..]
+  );
+}
+
+#[test]
+fn pyramid_depth_4_quantizers() {
+  let mut enc = test_encoder_config();
+  enc.low_latency = false;
+  enc.pyramid_depth = 4;
+  enc.quantizer = 100;
+  enc.min_key_frame_interval = 17;
+  enc.max_key_frame_interval = 17;
+  enc.speed_settings.scene_detection_mode = SceneDetectionSpeed::None;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  send_frames(&mut ctx, 17, 0);
+  ctx.flush();
+
+  // The lowest and highest quantizers of the inter frames at each level.
+  let mut qi = [(u8::MAX, u8::MIN); 5];
+  let mut key_qi = None;
+  let outputs: Vec<_> = ctx.inner.frame_data.keys().copied().collect();
+  for i in outputs {
+    let fi = &ctx.inner.frame_data[&i].as_ref().unwrap().fi;
+    if fi.is_show_existing_frame() {
+      continue;
+    }
+    ctx.inner.encode_packet(i).unwrap();
+    let fi = &ctx.inner.frame_data[&i].as_ref().unwrap().fi;
+    if fi.frame_type == FrameType::KEY {
+      key_qi = Some(fi.base_q_idx);
+    } else {
+      let (min, max) = &mut qi[fi.pyramid_level as usize];
+      *min = (*min).min(fi.base_q_idx);
+      *max = (*max).max(fi.base_q_idx);
+    }
+  }
+
+  // Every level is coded, and deeper levels are never finer than the
+  // levels they are predicted from.
+  assert!(qi.iter().all(|&(min, max)| min <= max), "{qi:?}");
+  assert!(key_qi.unwrap() <= qi[0].0);
+  for (level, pair) in qi.windows(2).enumerate() {
+    assert!(pair[0].1 <= pair[1].0, "level {}: {qi:?}", level + 1);
+  }
+}
+
+#[test]
+fn pyramid_depth_out_of_range() {
+  let enc = EncoderConfig { pyramid_depth: 5, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(
+    config.validate(),
+    Err(InvalidConfig::InvalidPyramidDepth(5))
+  ));
+}
+
 #[interpolate_test(0, 0)]
 #[interpolate_test(1, 1)]
 #[interpolate_test(2, 2)]
@@ -1316,6 +1457,7 @@ fn send_frame_kf<T: Pixel>(ctx: &mut Context<T>, keyframe: bool) {
     frame_type_override,
     opaque,
     t35_metadata: Box::new([]),
+    ..Default::default()
   };
 
   let _ = ctx.send_frame((input, fp));
@@ -1370,7 +1512,12 @@ fn send_frame_t35<T: Pixel>(ctx: &mut Context<T>) {
     data: Box::new(*b"AYAYA"),
   }]);
 
-  let fp = FrameParameters { frame_type_override, opaque, t35_metadata };
+  let fp = FrameParameters {
+    frame_type_override,
+    opaque,
+    t35_metadata,
+    ..Default::default()
+  };
 
   let _ = ctx.send_frame((input, fp));
 }
@@ -1403,6 +1550,463 @@ fn test_t35_parameter() {
   while ctx.receive_packet().is_ok() {}
 }
 
+fn send_frame_timestamp<T: Pixel>(
+  ctx: &mut Context<T>, pts: Option<u64>, duration: Option<u64>,
+) {
+  let input = ctx.new_frame();
+
+  let fp = FrameParameters { pts, duration, ..Default::default() };
+
+  let _ = ctx.send_frame((input, fp));
+}
+
+#[test]
+fn test_timestamp_delivery() {
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    5,
+    0,
+    false,
+    0,
+    false,
+    10,
+    None,
+  );
+
+  // Missing durations are taken from the next timestamp, and missing
+  // timestamps follow on from the previous frame.
+  let timestamps = [
+    (Some(0), None),
+    (Some(3), None),
+    (Some(4), Some(5)),
+    (None, None),
+    (Some(20), Some(2)),
+  ];
+  let expected = [(0, 3), (3, 1), (4, 5), (9, 11), (20, 2)];
+
+  for &(pts, duration) in timestamps.iter() {
+    send_frame_timestamp(&mut ctx, pts, duration);
+  }
+  ctx.flush();
+
+  let count = receive_packets(&mut ctx, |pkt| {
+    let Packet { input_frameno, pts, duration, .. } = pkt;
+    assert_eq!((pts, duration), expected[input_frameno as usize]);
+  });
+  assert_eq!(count, Ok(expected.len()));
+}
+
+#[test]
+fn vfr_scale_tracks_frame_durations() {
+  let mut enc = test_encoder_config();
+  enc.bitrate = 1000;
+  enc.time_base = Rational::new(1, 1000);
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for i in 0..30 {
+    send_frame_timestamp(&mut ctx, Some(i * 40), None);
+  }
+
+  assert_eq!(ctx.inner.rc_state.estimated_tu_duration(), 40);
+}
+
+#[test]
+fn buffer_never_underflows_or_overflows() {
+  let mut enc = test_encoder_config();
+  enc.low_latency = false;
+  enc.time_base = Rational::new(1, 30);
+  enc.bitrate = 100_000;
+  enc.buffer_size = 20_000;
+  enc.buffer_initial_fullness = Some(15_000);
+  let mut ctx = new_test_context::<u8>(enc);
+
+  let limit = 20;
+  for _ in 0..limit {
+    send_frame_pattern(&mut ctx, false);
+  }
+  ctx.flush();
+
+  // Simulate the decoder buffer, filled at the bitrate and drained by a
+  //  whole packet at a time.
+  let tu_bits = 100_000 / 30;
+  let mut fullness = 15_000;
+  let count = receive_packets(&mut ctx, |packet| {
+    let bits = (packet.data.len() * 8) as i64;
+    assert!(bits <= fullness, "underflow at frame {}", packet.input_frameno);
+    fullness += tu_bits - bits;
+    assert!(fullness <= 20_000, "overflow at frame {}", packet.input_frameno);
+  });
+  assert_eq!(count, Ok(limit));
+}
+
+#[test]
+fn tiny_buffer_never_underflows() {
+  let mut enc = test_encoder_config();
+  enc.time_base = Rational::new(1, 30);
+  enc.bitrate = 30_000;
+  enc.buffer_size = 4_000;
+  enc.buffer_initial_fullness = Some(3_000);
+  let mut ctx = new_test_context::<u8>(enc);
+
+  // Noise is far too complex to be coded at the target rate, so every frame
+  //  is coded again with coarser quantizers.
+  let mut state = 0x1234_5678u32;
+  let limit = 10;
+  for _ in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      for pixel in plane.data.iter_mut() {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        *pixel = (state >> 24) as u8;
+      }
+    }
+    let _ = ctx.send_frame(Arc::new(input));
+  }
+  ctx.flush();
+
+  let tu_bits = 30_000 / 30;
+  let mut fullness = 3_000;
+  let mut count = 0;
+  let status = receive_packets(&mut ctx, |packet| {
+    fullness -= (packet.data.len() * 8) as i64;
+    assert!(fullness >= 0, "underflow at frame {}", packet.input_frameno);
+    fullness = (fullness + tu_bits).min(4_000);
+    count += 1;
+  });
+  // A frame that does not fit even at the maximum quantizer is reported
+  //  instead of being emitted.
+  assert!(matches!(status, Ok(_) | Err(EncoderStatus::Failure)));
+  assert!(count > 0);
+}
+
+#[test]
+fn buffer_initial_fullness_out_of_range() {
+  let enc = EncoderConfig {
+    bitrate: 100_000,
+    buffer_size: 20_000,
+    buffer_initial_fullness: Some(30_000),
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(
+    config.validate(),
+    Err(InvalidConfig::InvalidBufferInitialFullness {
+      actual: 30_000,
+      max: 20_000
+    })
+  ));
+}
+
+fn send_frame_pattern<T: Pixel>(ctx: &mut Context<T>, screen_content: bool) {
+  let mut input = ctx.new_frame();
+  let plane = &mut input.planes[0];
+  let stride = plane.cfg.stride;
+  for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+    for (x, pixel) in row.iter_mut().enumerate() {
+      let v = if screen_content {
+        // Two-colored text-like pattern.
+        if (x / 3 + y / 5) % 2 == 0 {
+          16
+        } else {
+          235
+        }
+      } else {
+        // Noisy ramp, as in camera footage.
+        64 + (x + y) / 4 + (x * x * 31 + y * 17) % 5
+      };
+      *pixel = T::cast_from(v as u8);
+    }
+  }
+  let _ = ctx.send_frame(Arc::new(input));
+}
+
+#[test]
+fn screen_content_auto_detection() {
+  let mut enc = test_encoder_config();
+  enc.screen_content = ScreenContentMode::Auto;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for i in 0..4 {
+    send_frame_pattern(&mut ctx, i < 2);
+  }
+  ctx.flush();
+
+  let count = receive_packets(&mut ctx, |pkt| {
+    assert_eq!(pkt.screen_content, pkt.input_frameno < 2);
+  });
+  assert_eq!(count, Ok(4));
+}
+
+#[test]
+fn screen_content_integer_mvs() {
+  let mut enc = test_encoder_config();
+  enc.screen_content = ScreenContentMode::Auto;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  // Text scrolling by a few pixels per frame.
+  let limit = 6;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    let plane = &mut input.planes[0];
+    let stride = plane.cfg.stride;
+    for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+      for (x, pixel) in row.iter_mut().enumerate() {
+        *pixel = if ((x + i * 2) / 3 + y / 5) % 2 == 0 { 16 } else { 235 };
+      }
+    }
+    let _ = ctx.send_frame(Arc::new(input));
+  }
+  ctx.flush();
+
+  // Encoding asserts that every MV of these frames is whole pixels,
+  // including those taken from the candidate list.
+  let integer_mv_frames = (0..limit as u64)
+    .filter(|&i| {
+      ctx.inner.encode_packet(i).unwrap();
+      let fi = &ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap().fi;
+      fi.force_integer_mv != 0
+    })
+    .count();
+  assert!(integer_mv_frames > 0);
+}
+
+#[interpolate_test(fixed, SuperresMode::Fixed)]
+#[interpolate_test(random, SuperresMode::Random)]
+fn superres_reconstructs_full_width(mode: SuperresMode) {
+  let mut enc = test_encoder_config();
+  enc.width = 96;
+  enc.height = 64;
+  enc.superres_mode = mode;
+  enc.superres_denom = 16;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for _ in 0..4 {
+    send_frame_pattern(&mut ctx, false);
+  }
+  ctx.flush();
+
+  let count = receive_packets(&mut ctx, |pkt| {
+    if let Some(rec) = pkt.rec {
+      assert_eq!(rec.planes[0].cfg.width, 96);
+      assert_eq!(rec.planes[1].cfg.width, 48);
+    }
+  });
+  assert_eq!(count, Ok(4));
+}
+
+#[interpolate_test(sb64_bottomup, SuperblockSize::Sb64, 2)]
+#[interpolate_test(sb64_topdown, SuperblockSize::Sb64, 6)]
+#[interpolate_test(sb128_bottomup, SuperblockSize::Sb128, 2)]
+#[interpolate_test(sb128_topdown, SuperblockSize::Sb128, 6)]
+fn superblock_size_encode(superblock_size: SuperblockSize, speed: u8) {
+  // Neither dimension is a multiple of the superblock size.
+  let mut enc = test_encoder_config();
+  enc.speed_settings = SpeedSettings::from_preset(speed);
+  enc.width = 160;
+  enc.height = 72;
+  enc.superblock_size = superblock_size;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for _ in 0..3 {
+    send_frame_pattern(&mut ctx, false);
+  }
+  ctx.flush();
+
+  let count = receive_packets(&mut ctx, |pkt| {
+    assert!(!pkt.data.is_empty());
+  });
+  assert_eq!(count, Ok(3));
+}
+
+/// Encodes three frames searching the CDEF strengths with `method`, and
+/// returns the `cdef_bits` and the luma and chroma strengths of each frame.
+fn encode_cdef_strengths(
+  method: CDEFSearchMethod, noisy: bool,
+) -> Vec<(u8, [u8; 8], [u8; 8])> {
+  let mut enc = test_encoder_config();
+  enc.quantizer = 200;
+  enc.screen_content = ScreenContentMode::Off;
+  enc.speed_settings.cdef_search_method = method;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  let limit = 3;
+  let mut state = 0x1234_5678u32;
+  for _ in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          // Sharp edges with noise on top, which ring once quantized.
+          state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+          let noise = (state >> 28) as u8;
+          *pixel = if !noisy {
+            128
+          } else if (x / 3 + y / 5) % 2 == 0 {
+            16 + noise
+          } else {
+            220 + noise
+          };
+        }
+      }
+    }
+    let _ = ctx.send_frame(Arc::new(input));
+  }
+  ctx.flush();
+
+  (0..limit)
+    .map(|i| {
+      ctx.inner.encode_packet(i).unwrap();
+      let fi = &ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap().fi;
+      (fi.cdef_bits, fi.cdef_y_strengths, fi.cdef_uv_strengths)
+    })
+    .collect()
+}
+
+#[interpolate_test(fast, CDEFSearchMethod::FastSearch)]
+#[interpolate_test(full, CDEFSearchMethod::FullSearch)]
+fn cdef_search_strengths_in_range(method: CDEFSearchMethod) {
+  for noisy in [false, true] {
+    for (cdef_bits, y_strengths, uv_strengths) in
+      encode_cdef_strengths(method, noisy)
+    {
+      assert!(cdef_bits <= 3);
+      let n = 1 << cdef_bits;
+      for strengths in [y_strengths, uv_strengths] {
+        assert!(strengths.iter().all(|&s| s < 64));
+        assert!(strengths[n..].iter().all(|&s| s == 0));
+      }
+    }
+  }
+}
+
+#[interpolate_test(fast, CDEFSearchMethod::FastSearch)]
+#[interpolate_test(full, CDEFSearchMethod::FullSearch)]
+fn cdef_search_filters_noisy_frames(method: CDEFSearchMethod) {
+  let frames = encode_cdef_strengths(method, true);
+  assert!(frames.iter().any(|(_, y_strengths, uv_strengths)| {
+    y_strengths.iter().chain(uv_strengths).any(|&s| s != 0)
+  }));
+}
+
+#[interpolate_test(fast, CDEFSearchMethod::FastSearch)]
+#[interpolate_test(full, CDEFSearchMethod::FullSearch)]
+fn cdef_search_skips_flat_frames(method: CDEFSearchMethod) {
+  for (cdef_bits, y_strengths, uv_strengths) in
+    encode_cdef_strengths(method, false)
+  {
+    assert_eq!(cdef_bits, 0);
+    assert_eq!((y_strengths, uv_strengths), ([0; 8], [0; 8]));
+  }
+}
+
+#[test]
+fn cdef_full_search_encode_at_speed_0() {
+  let mut enc = test_encoder_config();
+  enc.speed_settings = SpeedSettings::from_preset(0);
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for _ in 0..3 {
+    send_frame_pattern(&mut ctx, false);
+  }
+  ctx.flush();
+
+  assert_eq!(receive_packets(&mut ctx, |_| {}), Ok(3));
+}
+
+#[test]
+fn encode_switchable_dual_filters() {
+  let mut enc = test_encoder_config();
+  enc.low_latency = false;
+  enc.speed_settings.prediction.switchable_filter = true;
+  enc.speed_settings.prediction.dual_filter = true;
+  enc.speed_settings.prediction.distance_compound = true;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  // A ramp panning by a few pixels per frame, so that inter blocks are
+  // chosen and interpolated.
+  let limit = 8;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    let plane = &mut input.planes[0];
+    let stride = plane.cfg.stride;
+    for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+      for (x, pixel) in row.iter_mut().enumerate() {
+        *pixel = ((x * 3 + i * 5) % 200 + y / 2) as u8;
+      }
+    }
+    let _ = ctx.send_frame(Arc::new(input));
+  }
+  ctx.flush();
+
+  let mut frame_types = Vec::new();
+  let count = receive_packets(&mut ctx, |packet| {
+    frame_types.push(packet.frame_type);
+  });
+  assert_eq!(count, Ok(limit));
+  assert!(frame_types.contains(&FrameType::INTER));
+}
+
+#[test]
+fn encode_filter_intra() {
+  let mut enc = test_encoder_config();
+  enc.speed_settings.prediction.filter_intra = true;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for _ in 0..3 {
+    send_frame_pattern(&mut ctx, false);
+  }
+  ctx.flush();
+
+  assert_eq!(receive_packets(&mut ctx, |_| {}), Ok(3));
+}
+
+#[test]
+fn frame_size_per_frame() {
+  let mut enc = test_encoder_config();
+  enc.width = 96;
+  enc.height = 64;
+  let mut ctx = new_test_context::<u8>(enc);
+
+  // The last request is clamped to half the size of its references.
+  let sizes = [(96, 64), (48, 32), (60, 40), (16, 16)];
+  for &size in sizes.iter() {
+    let mut input = ctx.new_frame();
+    fill_frame_const(&mut input, 128);
+    let fp = FrameParameters { frame_size: Some(size), ..Default::default() };
+    let _ = ctx.send_frame((Arc::new(input), fp));
+  }
+  ctx.flush();
+
+  let mut frame_sizes = Vec::new();
+  receive_packets(&mut ctx, |pkt| {
+    assert_eq!(pkt.frame_type == FrameType::KEY, pkt.input_frameno == 0);
+    frame_sizes.push(pkt.frame_size);
+  })
+  .unwrap();
+  assert_eq!(frame_sizes, [(96, 64), (48, 32), (60, 40), (30, 20)]);
+}
+
+#[test]
+fn superres_denom_out_of_range() {
+  let enc = EncoderConfig {
+    superres_mode: SuperresMode::Fixed,
+    superres_denom: 17,
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(
+    config.validate(),
+    Err(InvalidConfig::InvalidSuperresDenom(17))
+  ));
+}
+
 #[interpolate_test(0, 0)]
 #[interpolate_test(1, 1)]
 #[interpolate_test(2, 2)]
@@ -2122,10 +2726,24 @@ fn log_q_exp_overflow() {
     max_key_frame_interval: 240,
     reservoir_frame_delay: None,
     low_latency: false,
+    pyramid_depth: 2,
     quantizer: 100,
+    lossless: false,
     min_quantizer: 64,
     bitrate: 1,
+    max_bitrate: 0,
+    target_quality: None,
+    luma_dc_delta_q: 0,
+    chroma_dc_delta_q: [0; 2],
+    chroma_ac_delta_q: [0; 2],
+    buffer_size: 0,
+    buffer_initial_fullness: None,
     tune: Tune::Psychovisual,
+    screen_content: ScreenContentMode::Off,
+    superres_mode: SuperresMode::Off,
+    superres_denom: 12,
+    superres_qthresh: 160,
+    superblock_size: SuperblockSize::Auto,
     film_grain_params: None,
     tile_cols: 0,
     tile_rows: 0,
@@ -2199,10 +2817,24 @@ fn guess_frame_subtypes_assert() {
     max_key_frame_interval: 1,
     reservoir_frame_delay: None,
     low_latency: false,
+    pyramid_depth: 2,
     quantizer: 100,
+    lossless: false,
     min_quantizer: 0,
     bitrate: 16384,
+    max_bitrate: 0,
+    target_quality: None,
+    luma_dc_delta_q: 0,
+    chroma_dc_delta_q: [0; 2],
+    chroma_ac_delta_q: [0; 2],
+    buffer_size: 0,
+    buffer_initial_fullness: None,
     tune: Tune::Psychovisual,
+    screen_content: ScreenContentMode::Off,
+    superres_mode: SuperresMode::Off,
+    superres_denom: 12,
+    superres_qthresh: 160,
+    superblock_size: SuperblockSize::Auto,
     film_grain_params: None,
     tile_cols: 0,
     tile_rows: 0,
@@ -2379,3 +3011,237 @@ fn max_quantizer_bounds_correctly() {
     }
   }
 }
+
+#[interpolate_test(under_max_bitrate, 10_000_000, false)]
+#[interpolate_test(over_max_bitrate, 2000, true)]
+fn capped_quality_bounds_correctly(max_bitrate: i32, raised: bool) {
+  let new_context = |max_bitrate| {
+    let mut cfg = setup_config(
+      64,
+      80,
+      10,
+      100,
+      8,
+      ChromaSampling::Cs420,
+      25,
+      25,
+      0,
+      true,
+      0,
+      true,
+      1,
+      None,
+    );
+    cfg.enc.max_bitrate = max_bitrate;
+    cfg.new_context::<u8>().unwrap()
+  };
+  let mut cq_ctx = new_context(0);
+  let mut ctx = new_context(max_bitrate);
+
+  let limit = 25;
+  send_frames(&mut cq_ctx, limit, 0);
+  cq_ctx.flush();
+  send_frames(&mut ctx, limit, 0);
+  ctx.flush();
+
+  let mut nraised = 0;
+  for i in 0..limit {
+    cq_ctx.inner.encode_packet(i).unwrap();
+    ctx.inner.encode_packet(i).unwrap();
+    let cq_frame_data =
+      cq_ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap();
+    let frame_data = ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap();
+    assert!(frame_data.fi.base_q_idx >= cq_frame_data.fi.base_q_idx);
+    if frame_data.fi.base_q_idx > cq_frame_data.fi.base_q_idx {
+      nraised += 1;
+    }
+  }
+  assert_eq!(nraised > 0, raised);
+}
+
+#[test]
+fn negative_max_bitrate() {
+  let enc = EncoderConfig { max_bitrate: -1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(
+    config.validate(),
+    Err(InvalidConfig::InvalidMaxBitrate(-1000))
+  ));
+}
+
+#[test]
+fn max_bitrate_with_bitrate() {
+  let enc =
+    EncoderConfig { bitrate: 1000, max_bitrate: 2000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(
+    config.validate(),
+    Err(InvalidConfig::InvalidOptionWithBitrate("max_bitrate"))
+  ));
+}
+
+fn encode_target_quality(target_quality: f64) -> Vec<(u8, Option<f64>)> {
+  let mut enc = test_encoder_config();
+  enc.target_quality = Some(target_quality);
+  let mut ctx = new_test_context::<u8>(enc);
+
+  for _ in 0..5 {
+    send_frame_pattern(&mut ctx, false);
+  }
+  ctx.flush();
+
+  let mut packets = Vec::new();
+  receive_packets(&mut ctx, |packet| {
+    packets.push((packet.qp, packet.quality));
+  })
+  .unwrap();
+  packets
+}
+
+#[test]
+fn target_quality_selects_quantizer() {
+  let high = encode_target_quality(0.9999);
+  let low = encode_target_quality(0.5);
+  assert_eq!(high.len(), 5);
+  assert_eq!(low.len(), 5);
+  for &(_, quality) in low.iter() {
+    assert!(quality.unwrap() >= 0.5);
+  }
+  let qp_sum = |packets: &[(u8, Option<f64>)]| {
+    packets.iter().map(|&(qp, _)| qp as u32).sum::<u32>()
+  };
+  assert!(qp_sum(&low) > qp_sum(&high));
+}
+
+#[test]
+fn target_quality_out_of_range() {
+  let enc = EncoderConfig { target_quality: Some(1.5), ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(
+    config.validate(),
+    Err(InvalidConfig::InvalidTargetQuality)
+  ));
+}
+
+#[test]
+fn delta_q_offsets_frame_quantizers() {
+  let new_context = |luma_dc, chroma_dc, chroma_ac| {
+    let mut cfg = setup_config(
+      64,
+      80,
+      10,
+      100,
+      8,
+      ChromaSampling::Cs420,
+      10,
+      10,
+      0,
+      true,
+      0,
+      true,
+      1,
+      None,
+    );
+    cfg.enc.luma_dc_delta_q = luma_dc;
+    cfg.enc.chroma_dc_delta_q = chroma_dc;
+    cfg.enc.chroma_ac_delta_q = chroma_ac;
+    cfg.new_context::<u8>().unwrap()
+  };
+  let mut ref_ctx = new_context(0, [0, 0], [0, 0]);
+  let mut ctx = new_context(-8, [4, -4], [6, 2]);
+
+  let limit = 5;
+  send_frames(&mut ref_ctx, limit, 0);
+  ref_ctx.flush();
+  send_frames(&mut ctx, limit, 0);
+  ctx.flush();
+
+  for i in 0..limit {
+    ref_ctx.inner.encode_packet(i).unwrap();
+    ctx.inner.encode_packet(i).unwrap();
+    let ref_fi =
+      &ref_ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap().fi;
+    let fi = &ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap().fi;
+    assert_eq!(fi.base_q_idx, ref_fi.base_q_idx);
+    assert_eq!(fi.dc_delta_q[0], ref_fi.dc_delta_q[0] - 8);
+    assert_eq!(fi.dc_delta_q[1], ref_fi.dc_delta_q[1] + 4);
+    assert_eq!(fi.dc_delta_q[2], ref_fi.dc_delta_q[2] - 4);
+    assert_eq!(fi.ac_delta_q[0], 0);
+    assert_eq!(fi.ac_delta_q[1], ref_fi.ac_delta_q[1] + 6);
+    assert_eq!(fi.ac_delta_q[2], ref_fi.ac_delta_q[2] + 2);
+  }
+}
+
+#[test]
+fn delta_q_offsets_frame_header() {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    10,
+    10,
+    0,
+    true,
+    0,
+    true,
+    1,
+    None,
+  );
+  cfg.enc.luma_dc_delta_q = -8;
+  cfg.enc.chroma_dc_delta_q = [4, -4];
+  cfg.enc.chroma_ac_delta_q = [6, 2];
+  let mut ctx = cfg.new_context::<u8>().unwrap();
+
+  let limit = 5;
+  send_frames(&mut ctx, limit, 0);
+  ctx.flush();
+
+  // The quantization parameters of the frame header, as a sequence of bits:
+  //  base_q_idx, then each delta_q as a flag and a 7-bit signed value.
+  let push_bits = |bits: &mut Vec<bool>, value: u8, n: usize| {
+    bits.extend((0..n).rev().map(|i| (value >> i) & 1 != 0));
+  };
+  let push_delta_q = |bits: &mut Vec<bool>, delta_q: i8| {
+    bits.push(delta_q != 0);
+    if delta_q != 0 {
+      push_bits(bits, delta_q as u8 & 0x7f, 7);
+    }
+  };
+
+  for i in 0..limit {
+    let packet = ctx.inner.encode_packet(i).unwrap();
+    let fi = &ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap().fi;
+    if fi.is_show_existing_frame() {
+      continue;
+    }
+    let mut expected = Vec::new();
+    push_bits(&mut expected, fi.base_q_idx, 8);
+    push_delta_q(&mut expected, fi.dc_delta_q[0]);
+    expected.push(true); // diff_uv_delta
+    for (&dc, &ac) in fi.dc_delta_q[1..].iter().zip(&fi.ac_delta_q[1..]) {
+      push_delta_q(&mut expected, dc);
+      push_delta_q(&mut expected, ac);
+    }
+    assert!(fi.dc_delta_q[0] < 0 && fi.dc_delta_q[1] != fi.dc_delta_q[2]);
+
+    let mut bits = Vec::new();
+    for &byte in packet.data.iter() {
+      push_bits(&mut bits, byte, 8);
+    }
+    assert!(
+      bits.windows(expected.len()).any(|w| w == expected.as_slice()),
+      "delta_q values of frame {} not found in its header",
+      i
+    );
+  }
+}
+
+#[test]
+fn delta_q_out_of_range() {
+  let enc = EncoderConfig { chroma_ac_delta_q: [0, 64], ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(matches!(config.validate(), Err(InvalidConfig::InvalidDeltaQ(64))));
+}
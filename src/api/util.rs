@@ -197,7 +197,10 @@ pub enum EncoderStatus {
 ///
 /// A packet contains one shown frame together with zero or more additional
 /// frames.
+///
+/// Fields may be added in future releases.
 #[derive(Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct Packet<T: Pixel> {
   /// The packet data.
   pub data: Vec<u8>,
@@ -210,15 +213,32 @@ pub struct Packet<T: Pixel> {
   /// The number of the input frame corresponding to the one shown frame in the
   /// TU stored in this packet. Since AV1 does not explicitly reorder frames,
   /// these will increase sequentially.
-  // TODO: When we want to add VFR support, we will need a more explicit time
-  // stamp here.
   pub input_frameno: u64,
+  /// Presentation timestamp of the shown frame, in units of the `time_base`.
+  pub pts: u64,
+  /// Display duration of the shown frame, in units of the `time_base`.
+  pub duration: u64,
   /// Type of the shown frame.
   pub frame_type: FrameType,
   /// QP selected for the frame.
   pub qp: u8,
+  /// Estimated SSIM of the luma plane of the shown frame, reported in the
+  /// target quality mode.
+  ///
+  /// See [`EncoderConfig::target_quality`].
+  ///
+  /// [`EncoderConfig::target_quality`]: crate::api::EncoderConfig::target_quality
+  pub quality: Option<f64>,
+  /// Whether the frame was coded with the screen content tools.
+  pub screen_content: bool,
   /// Block-level encoding stats for the frame
   pub enc_stats: EncoderStats,
+  /// Width and height the shown frame was coded at, which `rec` holds
+  /// and decoders output it at.
+  ///
+  /// This is the configured size unless another was requested through
+  /// [`FrameParameters::frame_size`].
+  pub frame_size: (usize, usize),
   /// Optional user-provided opaque data
   #[cfg_attr(feature = "serialize", serde(skip))]
   pub opaque: Option<Opaque>,
@@ -77,6 +77,64 @@ type AvgHBDFn = unsafe extern fn(
   bitdepth_max: i32,
 );
 
+type WarpFn = unsafe extern fn(
+  dst: *mut u8,
+  dst_stride: isize,
+  src: *const u8,
+  src_stride: isize,
+  abcd: *const i16,
+  mx: i32,
+  my: i32,
+);
+
+type WarpHBDFn = unsafe extern fn(
+  dst: *mut u16,
+  dst_stride: isize,
+  src: *const u16,
+  src_stride: isize,
+  abcd: *const i16,
+  mx: i32,
+  my: i32,
+  bitdepth_max: i32,
+);
+
+type WarpTmpFn = unsafe extern fn(
+  tmp: *mut i16,
+  tmp_stride: isize,
+  src: *const u8,
+  src_stride: isize,
+  abcd: *const i16,
+  mx: i32,
+  my: i32,
+);
+
+type WarpTmpHBDFn = unsafe extern fn(
+  tmp: *mut i16,
+  tmp_stride: isize,
+  src: *const u16,
+  src_stride: isize,
+  abcd: *const i16,
+  mx: i32,
+  my: i32,
+  bitdepth_max: i32,
+);
+
+type BlendFn = unsafe extern fn(
+  dst: *mut u8,
+  dst_stride: isize,
+  tmp: *const u8,
+  w: i32,
+  h: i32,
+);
+
+type BlendHBDFn = unsafe extern fn(
+  dst: *mut u16,
+  dst_stride: isize,
+  tmp: *const u16,
+  w: i32,
+  h: i32,
+);
+
 // gets an index that can be mapped to a function for a pair of filter modes
 #[inline]
 const fn get_2d_mode_idx(mode_x: FilterMode, mode_y: FilterMode) -> usize {
@@ -303,6 +361,249 @@ pub fn mc_avg<T: Pixel>(
   }
 }
 
+/// # Panics
+///
+/// - If `src` does not hold the 15x15 samples around the 8x8 unit
+/// - If `dst` is smaller than 8x8
+pub fn put_warp_8x8<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, src: &[T], src_stride: usize,
+  abcd: &[i16; 4], mx: i32, my: i32, bit_depth: usize, cpu: CpuFeatureLevel,
+) {
+  let call_rust = |dst: &mut PlaneRegionMut<'_, T>| {
+    rust::put_warp_8x8(dst, src, src_stride, abcd, mx, my, bit_depth, cpu);
+  };
+  #[cfg(feature = "check_asm")]
+  let ref_dst = {
+    let mut copy = dst.scratch_copy();
+    call_rust(&mut copy.as_region_mut());
+    copy
+  };
+
+  // SAFETY: The assembly reads the 15x15 samples starting at `src`, with
+  //         loads that may extend up to one sample past the last column
+  unsafe {
+    // SAFETY: Check bounds of dst
+    assert!(dst.rect().width >= 8 && dst.rect().height >= 8);
+
+    // SAFETY: Check bounds of src
+    assert!(src.len() >= 14 * src_stride + 16);
+
+    // The assembly expects src to point at the integer position of the
+    // top-left sample, and steps back to the first filter tap itself.
+    let src_ptr = src.as_ptr().add(3 * src_stride + 3);
+    match T::type_enum() {
+      PixelType::U8 => match WARP_FNS[cpu.as_index()] {
+        Some(func) => (func)(
+          dst.data_ptr_mut() as *mut _,
+          T::to_asm_stride(dst.plane_cfg.stride),
+          src_ptr as *const _,
+          T::to_asm_stride(src_stride),
+          abcd.as_ptr(),
+          mx,
+          my,
+        ),
+        None => call_rust(dst),
+      },
+      PixelType::U16 => match WARP_HBD_FNS[cpu.as_index()] {
+        Some(func) => (func)(
+          dst.data_ptr_mut() as *mut _,
+          T::to_asm_stride(dst.plane_cfg.stride),
+          src_ptr as *const _,
+          T::to_asm_stride(src_stride),
+          abcd.as_ptr(),
+          mx,
+          my,
+          (1 << bit_depth) - 1,
+        ),
+        None => call_rust(dst),
+      },
+    }
+  }
+  #[cfg(feature = "check_asm")]
+  {
+    for (dst_row, ref_row) in
+      dst.rows_iter().zip(ref_dst.as_region().rows_iter())
+    {
+      for (dst, reference) in dst_row.iter().zip(ref_row) {
+        assert_eq!(*dst, *reference);
+      }
+    }
+  }
+}
+
+/// # Panics
+///
+/// - If `src` does not hold the 15x15 samples around the 8x8 unit
+/// - If `tmp` does not hold 8 rows of `tmp_stride` values
+pub fn prep_warp_8x8<T: Pixel>(
+  tmp: &mut [i16], tmp_stride: usize, src: &[T], src_stride: usize,
+  abcd: &[i16; 4], mx: i32, my: i32, bit_depth: usize, cpu: CpuFeatureLevel,
+) {
+  let call_rust = |tmp: &mut [i16]| {
+    rust::prep_warp_8x8(
+      tmp, tmp_stride, src, src_stride, abcd, mx, my, bit_depth, cpu,
+    );
+  };
+  #[cfg(feature = "check_asm")]
+  let ref_tmp = {
+    let mut copy = tmp[..7 * tmp_stride + 8].to_vec();
+    call_rust(&mut copy);
+    copy
+  };
+
+  // SAFETY: The assembly reads the 15x15 samples starting at `src`, with
+  //         loads that may extend up to one sample past the last column
+  unsafe {
+    // SAFETY: Check length of tmp
+    assert!(tmp_stride >= 8 && tmp.len() >= 7 * tmp_stride + 8);
+
+    // SAFETY: Check bounds of src
+    assert!(src.len() >= 14 * src_stride + 16);
+
+    let src_ptr = src.as_ptr().add(3 * src_stride + 3);
+    match T::type_enum() {
+      PixelType::U8 => match WARP_TMP_FNS[cpu.as_index()] {
+        Some(func) => (func)(
+          tmp.as_mut_ptr(),
+          tmp_stride as isize,
+          src_ptr as *const _,
+          T::to_asm_stride(src_stride),
+          abcd.as_ptr(),
+          mx,
+          my,
+        ),
+        None => call_rust(tmp),
+      },
+      PixelType::U16 if bit_depth > 8 => {
+        match WARP_TMP_HBD_FNS[cpu.as_index()] {
+          Some(func) => (func)(
+            tmp.as_mut_ptr(),
+            tmp_stride as isize,
+            src_ptr as *const _,
+            T::to_asm_stride(src_stride),
+            abcd.as_ptr(),
+            mx,
+            my,
+            (1 << bit_depth) - 1,
+          ),
+          None => call_rust(tmp),
+        }
+      }
+      _ => call_rust(tmp),
+    }
+  }
+  #[cfg(feature = "check_asm")]
+  {
+    for (tmp_row, ref_row) in
+      tmp.chunks(tmp_stride).zip(ref_tmp.chunks(tmp_stride))
+    {
+      assert_eq!(&tmp_row[..8], &ref_row[..8]);
+    }
+  }
+}
+
+/// # Panics
+///
+/// - If the overlap is not a power of two from 2 to 32 samples
+/// - If `dst` or `tmp` is smaller than `width` by `height`
+pub fn blend_obmc_above<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp: &[T], width: usize, height: usize,
+  cpu: CpuFeatureLevel,
+) {
+  assert!(height.is_power_of_two() && (2..=32).contains(&height));
+  assert!(width.is_power_of_two() && (2..=128).contains(&width));
+  blend_obmc_with(
+    dst,
+    tmp,
+    width,
+    height,
+    cpu,
+    rust::blend_obmc_above,
+    &BLEND_H_FNS,
+    &BLEND_H_HBD_FNS,
+  );
+}
+
+/// # Panics
+///
+/// - If the overlap is not a power of two from 2 to 32 samples
+/// - If `dst` or `tmp` is smaller than `width` by `height`
+pub fn blend_obmc_left<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp: &[T], width: usize, height: usize,
+  cpu: CpuFeatureLevel,
+) {
+  assert!(width.is_power_of_two() && (2..=32).contains(&width));
+  blend_obmc_with(
+    dst,
+    tmp,
+    width,
+    height,
+    cpu,
+    rust::blend_obmc_left,
+    &BLEND_V_FNS,
+    &BLEND_V_HBD_FNS,
+  );
+}
+
+fn blend_obmc_with<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp: &[T], width: usize, height: usize,
+  cpu: CpuFeatureLevel,
+  rust_fn: fn(&mut PlaneRegionMut<'_, T>, &[T], usize, usize, CpuFeatureLevel),
+  fns: &[Option<BlendFn>], hbd_fns: &[Option<BlendHBDFn>],
+) {
+  let call_rust = |dst: &mut PlaneRegionMut<'_, T>| {
+    rust_fn(dst, tmp, width, height, cpu);
+  };
+  #[cfg(feature = "check_asm")]
+  let ref_dst = {
+    let mut copy = dst.scratch_copy();
+    call_rust(&mut copy.as_region_mut());
+    copy
+  };
+
+  // SAFETY: The callers check that the overlap is supported by the assembly
+  unsafe {
+    // SAFETY: Check bounds of dst
+    assert!(dst.rect().width >= width && dst.rect().height >= height);
+
+    // SAFETY: Check length of tmp
+    assert!(tmp.len() >= width * height);
+
+    match T::type_enum() {
+      PixelType::U8 => match fns[cpu.as_index()] {
+        Some(func) => (func)(
+          dst.data_ptr_mut() as *mut _,
+          T::to_asm_stride(dst.plane_cfg.stride),
+          tmp.as_ptr() as *const _,
+          width as i32,
+          height as i32,
+        ),
+        None => call_rust(dst),
+      },
+      PixelType::U16 => match hbd_fns[cpu.as_index()] {
+        Some(func) => (func)(
+          dst.data_ptr_mut() as *mut _,
+          T::to_asm_stride(dst.plane_cfg.stride),
+          tmp.as_ptr() as *const _,
+          width as i32,
+          height as i32,
+        ),
+        None => call_rust(dst),
+      },
+    }
+  }
+  #[cfg(feature = "check_asm")]
+  {
+    for (dst_row, ref_row) in
+      dst.rows_iter().zip(ref_dst.as_region().rows_iter())
+    {
+      for (dst, reference) in dst_row.iter().zip(ref_row) {
+        assert_eq!(*dst, *reference);
+      }
+    }
+  }
+}
+
 macro_rules! decl_mc_fns {
   ($(($mode_x:expr, $mode_y:expr, $func_name:ident)),+) => {
     extern {
@@ -488,3 +789,91 @@ cpu_function_lookup_table!(
   default: None,
   [(NEON, Some(rav1e_avg_16bpc_neon))]
 );
+
+extern {
+  fn rav1e_warp_affine_8x8_8bpc_neon(
+    dst: *mut u8, dst_stride: isize, src: *const u8, src_stride: isize,
+    abcd: *const i16, mx: i32, my: i32,
+  );
+
+  fn rav1e_warp_affine_8x8t_8bpc_neon(
+    tmp: *mut i16, tmp_stride: isize, src: *const u8, src_stride: isize,
+    abcd: *const i16, mx: i32, my: i32,
+  );
+
+  fn rav1e_warp_affine_8x8_16bpc_neon(
+    dst: *mut u16, dst_stride: isize, src: *const u16, src_stride: isize,
+    abcd: *const i16, mx: i32, my: i32, bitdepth_max: i32,
+  );
+
+  fn rav1e_warp_affine_8x8t_16bpc_neon(
+    tmp: *mut i16, tmp_stride: isize, src: *const u16, src_stride: isize,
+    abcd: *const i16, mx: i32, my: i32, bitdepth_max: i32,
+  );
+}
+
+cpu_function_lookup_table!(
+  WARP_FNS: [Option<WarpFn>],
+  default: None,
+  [(NEON, Some(rav1e_warp_affine_8x8_8bpc_neon))]
+);
+
+cpu_function_lookup_table!(
+  WARP_TMP_FNS: [Option<WarpTmpFn>],
+  default: None,
+  [(NEON, Some(rav1e_warp_affine_8x8t_8bpc_neon))]
+);
+
+cpu_function_lookup_table!(
+  WARP_HBD_FNS: [Option<WarpHBDFn>],
+  default: None,
+  [(NEON, Some(rav1e_warp_affine_8x8_16bpc_neon))]
+);
+
+cpu_function_lookup_table!(
+  WARP_TMP_HBD_FNS: [Option<WarpTmpHBDFn>],
+  default: None,
+  [(NEON, Some(rav1e_warp_affine_8x8t_16bpc_neon))]
+);
+
+extern {
+  fn rav1e_blend_h_8bpc_neon(
+    dst: *mut u8, dst_stride: isize, tmp: *const u8, w: i32, h: i32,
+  );
+
+  fn rav1e_blend_v_8bpc_neon(
+    dst: *mut u8, dst_stride: isize, tmp: *const u8, w: i32, h: i32,
+  );
+
+  fn rav1e_blend_h_16bpc_neon(
+    dst: *mut u16, dst_stride: isize, tmp: *const u16, w: i32, h: i32,
+  );
+
+  fn rav1e_blend_v_16bpc_neon(
+    dst: *mut u16, dst_stride: isize, tmp: *const u16, w: i32, h: i32,
+  );
+}
+
+cpu_function_lookup_table!(
+  BLEND_H_FNS: [Option<BlendFn>],
+  default: None,
+  [(NEON, Some(rav1e_blend_h_8bpc_neon))]
+);
+
+cpu_function_lookup_table!(
+  BLEND_V_FNS: [Option<BlendFn>],
+  default: None,
+  [(NEON, Some(rav1e_blend_v_8bpc_neon))]
+);
+
+cpu_function_lookup_table!(
+  BLEND_H_HBD_FNS: [Option<BlendHBDFn>],
+  default: None,
+  [(NEON, Some(rav1e_blend_h_16bpc_neon))]
+);
+
+cpu_function_lookup_table!(
+  BLEND_V_HBD_FNS: [Option<BlendHBDFn>],
+  default: None,
+  [(NEON, Some(rav1e_blend_v_16bpc_neon))]
+);
@@ -8,6 +8,10 @@ use std::mem::transmute;
 
 use crate::mc::rust;
 
+pub use crate::mc::rust::{
+  blend_obmc_above, blend_obmc_left, prep_warp_8x8, put_warp_8x8,
+};
+
 #[inline(always)]
 fn get_filter(
   mode: FilterMode, frac: i32, length: usize,
@@ -8,7 +8,7 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use crate::error::*;
-use crate::muxer::{create_muxer, Muxer};
+use crate::muxer::{create_muxer, Container, Muxer};
 use crate::stats::MetricsEnabled;
 use clap::builder::styling::AnsiColor;
 use clap::builder::Styles;
@@ -42,12 +42,30 @@ pub struct CliOptions {
   /// Uncompressed YUV4MPEG2 video input
   #[clap(value_parser, help_heading = "INPUT/OUTPUT")]
   pub input: PathBuf,
-  /// Compressed AV1 in IVF video output
+  /// Compressed AV1 video output, in IVF, Matroska, WebM, MP4, AVIF or a raw
+  /// OBU stream depending on the extension. AVIF requires still picture mode
   #[clap(long, short, value_parser, help_heading = "INPUT/OUTPUT")]
   pub output: PathBuf,
   /// Overwrite output file.
   #[clap(short = 'y', help_heading = "INPUT/OUTPUT")]
   pub overwrite: bool,
+  /// Output format, overriding the one chosen by the output extension
+  #[clap(long, value_enum, help_heading = "INPUT/OUTPUT")]
+  pub container: Option<Container>,
+  /// Write MP4 output as a series of fragments, which can be streamed while
+  /// the encode is running.
+  #[clap(long, help_heading = "INPUT/OUTPUT")]
+  pub fragmented_mp4: bool,
+  /// Uncompressed YUV4MPEG2 input whose luma plane is the alpha channel of
+  /// the picture, stored as an auxiliary image in AVIF output
+  #[clap(
+    long,
+    value_parser,
+    value_name = "ALPHA_Y4M",
+    requires = "still_picture",
+    help_heading = "INPUT/OUTPUT"
+  )]
+  pub alpha: Option<PathBuf>,
 
   /// Set the threadpool size. If 0, will use the number of logical CPUs.
   /// rav1e will use up to this many threads. Additional tiles may be needed to
@@ -113,12 +131,97 @@ pub struct CliOptions {
   /// Quantizer (0-255), smaller values are higher quality [default: 100]
   #[clap(long, value_parser, help_heading = "ENCODE SETTINGS")]
   pub quantizer: Option<u8>,
+  /// Lossless mode; the decoded output matches the input exactly.
+  /// Overrides the quantizer and disables all in-loop filters.
+  #[clap(
+    long,
+    conflicts_with = "bitrate",
+    conflicts_with = "photon_noise",
+    conflicts_with = "film_grain_table",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub lossless: bool,
   /// Minimum quantizer (0-255) to use in bitrate mode [default: 0]
   #[clap(long, value_parser, help_heading = "ENCODE SETTINGS")]
   pub min_quantizer: Option<u8>,
   /// Bitrate (kbps)
   #[clap(long, short, value_parser, help_heading = "ENCODE SETTINGS")]
   pub bitrate: Option<i32>,
+  /// Maximum bitrate (kbps) for the capped quality mode, enforced by a
+  /// reservoir of the reservoir frame delay rather than a sliding window;
+  /// the quantizer is raised only to stay under it
+  #[clap(
+    long,
+    value_parser,
+    conflicts_with = "bitrate",
+    conflicts_with = "lossless",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub max_bitrate: Option<i32>,
+  /// Target SSIM (0-1) of every frame; the quantizer of each frame is
+  /// searched with trial encodes, starting from --quantizer
+  #[clap(
+    long,
+    value_parser,
+    conflicts_with = "bitrate",
+    conflicts_with = "max_bitrate",
+    conflicts_with = "lossless",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub target_quality: Option<f64>,
+  /// Size of the decoder buffer (kbits) for the constant bitrate mode.
+  /// Requires a bitrate. Frames always fit in the buffer and padding fills
+  /// it up when it would overflow
+  #[clap(
+    long,
+    value_parser,
+    requires = "bitrate",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub buffer_size: Option<u64>,
+  /// Initial fullness of the decoder buffer (kbits) [default: 90% of the
+  /// buffer size]
+  #[clap(
+    long,
+    value_parser,
+    requires = "buffer_size",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub buffer_initial: Option<u64>,
+  /// Offset (-63 to 63) of the luma DC quantizer index; the luma AC one is
+  /// the base quantizer index and has no offset [default: 0]
+  #[clap(
+    long,
+    value_parser = clap::value_parser!(i8).range(-63..=63),
+    allow_negative_numbers = true,
+    conflicts_with = "lossless",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub luma_dc_delta_q: Option<i8>,
+  /// Offsets (-63 to 63) of the Cb and Cr DC quantizer indices, as Cb,Cr
+  /// [default: 0,0]
+  #[clap(
+    long,
+    value_parser = clap::value_parser!(i8).range(-63..=63),
+    allow_negative_numbers = true,
+    value_delimiter = ',',
+    num_args = 2,
+    conflicts_with = "lossless",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub chroma_dc_delta_q: Option<Vec<i8>>,
+  /// Offsets (-63 to 63) of the Cb and Cr AC quantizer indices, as Cb,Cr
+  /// [default: 0,0]
+  #[clap(
+    long,
+    value_parser = clap::value_parser!(i8).range(-63..=63),
+    allow_negative_numbers = true,
+    value_delimiter = ',',
+    num_args = 2,
+    conflicts_with = "lossless",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub chroma_ac_delta_q: Option<Vec<i8>>,
   /// Speed level (0 is best quality, 10 is fastest).
   /// Speeds 10 and 0 are extremes and are generally not recommended.
   #[clap(long, short, value_parser = clap::value_parser!(u8).range(0..=10), default_value_t = 6, help_heading = "ENCODE SETTINGS", long_help = build_speed_long_help())]
@@ -163,6 +266,10 @@ pub struct CliOptions {
   /// Has a significant speed-to-quality trade-off
   #[clap(long, help_heading = "ENCODE SETTINGS")]
   pub low_latency: bool,
+  /// Depth of the pyramid of reordered frames; groups of 2^depth frames
+  /// are coded out of order. Ignored in low latency mode
+  #[clap(long, value_parser = clap::value_parser!(u64).range(1..=4), default_value_t = 2, help_heading = "ENCODE SETTINGS")]
+  pub pyramid_depth: u64,
   /// Disables scene detection entirely.
   /// Has a significant speed-to-quality trade-off in full encodes.
   /// Useful for chunked encoding.
@@ -175,6 +282,31 @@ pub struct CliOptions {
   /// Quality tuning
   #[clap(long, value_parser, default_value_t = Tune::Psychovisual, help_heading = "ENCODE SETTINGS")]
   pub tune: Tune,
+  /// Use the coding tools for screen content, such as palette prediction and
+  /// intra block copy. "auto" detects screen content on each frame
+  #[clap(long, value_parser, default_value_t = ScreenContentMode::Off, help_heading = "ENCODE SETTINGS")]
+  pub screen_content: ScreenContentMode,
+  /// Code frames at a reduced width and upscale them in the decoder
+  /// (superres). "fixed" uses --superres-denom for every frame, "dynamic"
+  /// reduces the width of frames whose quantizer exceeds --superres-qthresh
+  #[clap(long, value_parser, default_value_t = SuperresMode::Off, help_heading = "ENCODE SETTINGS")]
+  pub superres: SuperresMode,
+  /// Denominator of the coded width in fixed superres mode, from 9 (8/9 of
+  /// the width) to 16 (half of the width)
+  #[clap(long, value_parser = clap::value_parser!(u8).range(9..=16), default_value_t = 12, help_heading = "ENCODE SETTINGS")]
+  pub superres_denom: u8,
+  /// Quantizer (0-255) above which dynamic superres mode reduces the width
+  #[clap(
+    long,
+    value_parser,
+    default_value_t = 160,
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub superres_qthresh: u8,
+  /// Size of the superblocks, "64" or "128". "auto" uses 128x128
+  /// superblocks above 1080p when the speed allows 128x128 blocks
+  #[clap(long, value_parser, default_value_t = SuperblockSize::Auto, help_heading = "ENCODE SETTINGS")]
+  pub superblock_size: SuperblockSize,
   /// Still picture mode
   #[clap(long, help_heading = "ENCODE SETTINGS")]
   pub still_picture: bool,
@@ -360,6 +492,7 @@ pub struct ParsedCliOptions {
   #[allow(unused)]
   pub slots: usize,
   pub force_highbitdepth: bool,
+  pub alpha: Option<PathBuf>,
 }
 
 #[cfg(feature = "serialize")]
@@ -451,6 +584,10 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
     None => None,
   };
 
+  let container = match matches.container {
+    Some(container) => container,
+    None => Container::from_path(&matches.output)?,
+  };
   let os_input = &matches.input;
   let io = EncoderIO {
     input: match os_input.to_str() {
@@ -460,7 +597,12 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
           .map_err(|e| e.context("Cannot open input file"))?,
       ) as Box<dyn Read + Send>,
     },
-    output: create_muxer(&matches.output, matches.overwrite)?,
+    output: create_muxer(
+      &matches.output,
+      matches.overwrite,
+      container,
+      matches.fragmented_mp4,
+    )?,
     rec,
   };
 
@@ -487,6 +629,14 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
     panic!("A limit cannot be set above 1 in still picture mode");
   }
 
+  let avif = container == Container::Avif;
+  if avif && !enc.still_picture {
+    return Err(CliError::new("AVIF output requires still picture mode"));
+  }
+  if matches.alpha.is_some() && !avif {
+    return Err(CliError::new("An alpha channel requires AVIF output"));
+  }
+
   #[cfg(feature = "unstable")]
   let slots = matches.slots;
 
@@ -507,6 +657,7 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
     save_config: save_config_path,
     photon_noise: matches.photon_noise,
     force_highbitdepth: matches.high_bitdepth,
+    alpha: matches.alpha.clone(),
     #[cfg(feature = "unstable")]
     slots,
   })
@@ -674,15 +825,38 @@ fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
   cfg.still_picture = matches.still_picture;
 
   cfg.quantizer = quantizer;
+  cfg.lossless = matches.lossless;
   cfg.min_quantizer = matches.min_quantizer.unwrap_or(0);
   cfg.bitrate = bitrate.checked_mul(1000).expect("Bitrate too high");
+  cfg.max_bitrate = matches.max_bitrate.map_or(0, |max_bitrate| {
+    max_bitrate.checked_mul(1000).expect("Maximum bitrate too high")
+  });
   cfg.reservoir_frame_delay = matches.reservoir_frame_delay;
+  cfg.target_quality = matches.target_quality;
+  cfg.buffer_size = matches
+    .buffer_size
+    .map_or(0, |size| size.checked_mul(1000).expect("Buffer size too high"));
+  cfg.buffer_initial_fullness = matches.buffer_initial.map(|fullness| {
+    fullness.checked_mul(1000).expect("Buffer initial fullness too high")
+  });
+  cfg.luma_dc_delta_q = matches.luma_dc_delta_q.unwrap_or(0);
+  if let Some(delta_q) = &matches.chroma_dc_delta_q {
+    cfg.chroma_dc_delta_q = [delta_q[0], delta_q[1]];
+  }
+  if let Some(delta_q) = &matches.chroma_ac_delta_q {
+    cfg.chroma_ac_delta_q = [delta_q[0], delta_q[1]];
+  }
 
   if let Some(rdo_frames) = matches.rdo_lookahead_frames {
     cfg.speed_settings.rdo_lookahead_frames = rdo_frames;
   }
 
   cfg.tune = matches.tune;
+  cfg.screen_content = matches.screen_content;
+  cfg.superres_mode = matches.superres;
+  cfg.superres_denom = matches.superres_denom;
+  cfg.superres_qthresh = matches.superres_qthresh;
+  cfg.superblock_size = matches.superblock_size;
 
   if cfg.tune == Tune::Psychovisual {
     cfg.speed_settings.transform.tx_domain_distortion = false;
@@ -712,6 +886,7 @@ fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
   }
 
   cfg.low_latency = matches.low_latency;
+  cfg.pyramid_depth = matches.pyramid_depth;
   // Disables scene_detection
   if matches.no_scene_detection {
     cfg.speed_settings.scene_detection_mode = SceneDetectionSpeed::None;
@@ -90,17 +90,17 @@ impl Decoder for y4m::Decoder<Box<dyn Read + Send>> {
       .map(|frame| {
         let mut f = ctx.new_frame();
 
-        let (chroma_width, _) = cfg
-          .chroma_sampling
-          .chroma_dimensions(cfg.width, cfg.height)
-          .unwrap();
-
         f.y_plane.copy_from_raw_u8(
           frame.get_y_plane(),
           cfg.width * bytes,
           bytes,
         );
         if cfg.chroma_sampling != ChromaSubsampling::Monochrome {
+          let (chroma_width, _) = cfg
+            .chroma_sampling
+            .chroma_dimensions(cfg.width, cfg.height)
+            .unwrap();
+
           f.u_plane.copy_from_raw_u8(
             frame.get_u_plane(),
             chroma_width * bytes,
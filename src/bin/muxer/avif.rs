@@ -0,0 +1,350 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::mp4::{put_box, put_full_box, put_u16, put_u32};
+use super::{strip_temporal_delimiter, Muxer};
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const ALPHA_URN: &[u8] = b"urn:mpeg:mpegB:cicp:systems:auxiliary:alpha\0";
+
+const COLOR_ITEM: u16 = 1;
+const ALPHA_ITEM: u16 = 2;
+
+/// Property association flag: the property must be understood to decode
+/// the item.
+const ESSENTIAL: u8 = 0x80;
+
+struct Image {
+  sequence_header: Vec<u8>,
+  data: Vec<u8>,
+}
+
+pub struct AvifMuxer {
+  output: BufWriter<File>,
+  width: u32,
+  height: u32,
+  bit_depth: u8,
+  channels: u8,
+  color_description: Option<ColorDescription>,
+  pixel_range: PixelRange,
+  sequence_header: Vec<u8>,
+  /// Data of the first frame, the only one stored.
+  data: Option<Vec<u8>>,
+  alpha: Option<Image>,
+}
+
+impl Muxer for AvifMuxer {
+  fn write_header(
+    &mut self, width: usize, height: usize, _framerate_num: usize,
+    _framerate_den: usize, sequence_header: &[u8],
+  ) {
+    self.width = width as u32;
+    self.height = height as u32;
+    self.sequence_header = sequence_header.to_vec();
+  }
+
+  fn set_color_config(&mut self, config: &EncoderConfig) {
+    self.bit_depth = config.bit_depth as u8;
+    self.channels =
+      if config.chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+    self.color_description = config.color_description;
+    self.pixel_range = config.pixel_range;
+  }
+
+  fn write_alpha(
+    &mut self, sequence_header: &[u8], data: &[u8],
+  ) -> Result<(), CliError> {
+    self.alpha = Some(Image {
+      sequence_header: sequence_header.to_vec(),
+      data: strip_temporal_delimiter(data).to_vec(),
+    });
+    Ok(())
+  }
+
+  fn write_frame(
+    &mut self, _pts: u64, _duration: u64, data: &[u8], _frame_type: FrameType,
+  ) -> io::Result<()> {
+    self.data.get_or_insert_with(|| strip_temporal_delimiter(data).to_vec());
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    let data = self.data.take().unwrap_or_default();
+
+    let mut ftyp = Vec::new();
+    ftyp.extend_from_slice(b"avif");
+    put_u32(&mut ftyp, 0);
+    for brand in [b"avif", b"mif1", b"miaf"] {
+      ftyp.extend_from_slice(brand);
+    }
+    let mut head = Vec::new();
+    put_box(&mut head, b"ftyp", &ftyp);
+
+    // The item locations do not change the size of the meta box, so it can
+    // be built once to find where the media data starts.
+    let meta_size = self.meta(&data, 0).len() as u32;
+    let data_offset = head.len() as u32 + meta_size + 8;
+    head.extend_from_slice(&self.meta(&data, data_offset));
+
+    let alpha = self.alpha.as_ref().map_or(&[][..], |a| &a.data);
+    put_u32(&mut head, (data.len() + alpha.len()) as u32 + 8);
+    head.extend_from_slice(b"mdat");
+    self.output.write_all(&head)?;
+    self.output.write_all(&data)?;
+    self.output.write_all(alpha)?;
+    self.output.flush()
+  }
+}
+
+impl AvifMuxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let avif = AvifMuxer {
+      output: BufWriter::new(
+        File::create(path)
+          .map_err(|e| e.context("Cannot open output file"))?,
+      ),
+      width: 0,
+      height: 0,
+      bit_depth: 8,
+      channels: 3,
+      color_description: None,
+      pixel_range: PixelRange::Limited,
+      sequence_header: Vec::new(),
+      data: None,
+      alpha: None,
+    };
+    Ok(Box::new(avif))
+  }
+
+  /// Builds the meta box, describing the color image and its alpha channel
+  /// stored from `data_offset`.
+  fn meta(&self, data: &[u8], data_offset: u32) -> Vec<u8> {
+    let mut meta = Vec::new();
+    let mut body = Vec::new();
+    put_u32(&mut body, 0);
+    body.extend_from_slice(b"pict");
+    body.resize(body.len() + 12, 0);
+    body.extend_from_slice(b"rav1e\0");
+    put_full_box(&mut meta, b"hdlr", 0, 0, &body);
+
+    put_full_box(&mut meta, b"pitm", 0, 0, &COLOR_ITEM.to_be_bytes());
+
+    let mut items = vec![(COLOR_ITEM, data.len() as u32, &b"Color\0"[..])];
+    if let Some(alpha) = &self.alpha {
+      items.push((ALPHA_ITEM, alpha.data.len() as u32, &b"Alpha\0"[..]));
+    }
+
+    body.clear();
+    body.push(0x44); // 32-bit offsets and lengths
+    body.push(0); // no base offset
+    put_u16(&mut body, items.len() as u16);
+    let mut offset = data_offset;
+    for &(id, len, _) in &items {
+      put_u16(&mut body, id);
+      put_u16(&mut body, 0); // data reference index
+      put_u16(&mut body, 1); // extent count
+      put_u32(&mut body, offset);
+      put_u32(&mut body, len);
+      offset += len;
+    }
+    put_full_box(&mut meta, b"iloc", 0, 0, &body);
+
+    body.clear();
+    put_u16(&mut body, items.len() as u16);
+    for &(id, _, name) in &items {
+      let mut infe = Vec::new();
+      put_u16(&mut infe, id);
+      put_u16(&mut infe, 0); // protection index
+      infe.extend_from_slice(b"av01");
+      infe.extend_from_slice(name);
+      put_full_box(&mut body, b"infe", 2, 0, &infe);
+    }
+    put_full_box(&mut meta, b"iinf", 0, 0, &body);
+
+    if self.alpha.is_some() {
+      let mut auxl = Vec::new();
+      put_u16(&mut auxl, ALPHA_ITEM);
+      put_u16(&mut auxl, 1);
+      put_u16(&mut auxl, COLOR_ITEM);
+      body.clear();
+      put_box(&mut body, b"auxl", &auxl);
+      put_full_box(&mut meta, b"iref", 0, 0, &body);
+    }
+
+    // Properties are referenced by their 1-based index in the container.
+    let mut ipco = Vec::new();
+    body.clear();
+    put_u32(&mut body, self.width);
+    put_u32(&mut body, self.height);
+    put_full_box(&mut ipco, b"ispe", 0, 0, &body);
+    put_full_box(
+      &mut ipco,
+      b"pixi",
+      0,
+      0,
+      &[&[self.channels][..], &vec![self.bit_depth; self.channels as usize]]
+        .concat(),
+    );
+    put_box(&mut ipco, b"av1C", &self.sequence_header);
+    let mut color_properties = vec![1, 2, ESSENTIAL | 3];
+    // Without a color description, decoders rely on the sequence header.
+    if let Some(cd) = self.color_description {
+      body.clear();
+      body.extend_from_slice(b"nclx");
+      put_u16(&mut body, cd.color_primaries as u16);
+      put_u16(&mut body, cd.transfer_characteristics as u16);
+      put_u16(&mut body, cd.matrix_coefficients as u16);
+      body.push(if self.pixel_range == PixelRange::Full { 0x80 } else { 0 });
+      put_box(&mut ipco, b"colr", &body);
+      color_properties.push(4);
+    }
+    // The properties of the alpha channel follow those of the color image.
+    let first = color_properties.len() as u8 + 1;
+    let mut associations = vec![(COLOR_ITEM, color_properties)];
+
+    if let Some(alpha) = &self.alpha {
+      put_full_box(&mut ipco, b"pixi", 0, 0, &[1, self.bit_depth]);
+      put_box(&mut ipco, b"av1C", &alpha.sequence_header);
+      put_full_box(&mut ipco, b"auxC", 0, 0, ALPHA_URN);
+      associations.push((
+        ALPHA_ITEM,
+        vec![1, first, ESSENTIAL | (first + 1), ESSENTIAL | (first + 2)],
+      ));
+    }
+
+    body.clear();
+    put_u32(&mut body, associations.len() as u32);
+    for (id, properties) in associations {
+      put_u16(&mut body, id);
+      body.push(properties.len() as u8);
+      body.extend_from_slice(&properties);
+    }
+    let mut iprp = Vec::new();
+    put_box(&mut iprp, b"ipco", &ipco);
+    put_full_box(&mut iprp, b"ipma", 0, 0, &body);
+    put_box(&mut meta, b"iprp", &iprp);
+
+    let mut buf = Vec::new();
+    put_full_box(&mut buf, b"meta", 0, 0, &meta);
+    buf
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::muxer::mp4::test::{boxes, child, u32_at};
+  use std::env::temp_dir;
+
+  #[test]
+  fn alpha_round_trip() {
+    let path =
+      temp_dir().join(format!("rav1e-avif-{}.avif", std::process::id()));
+    let mut muxer = AvifMuxer::open(&path).unwrap();
+    muxer.set_color_config(&EncoderConfig {
+      bit_depth: 10,
+      color_description: Some(ColorDescription {
+        color_primaries: ColorPrimaries::BT709,
+        transfer_characteristics: TransferCharacteristics::SRGB,
+        matrix_coefficients: MatrixCoefficients::BT601,
+      }),
+      pixel_range: PixelRange::Full,
+      ..Default::default()
+    });
+    muxer
+      .write_alpha(&[0x81, 0x00, 0x1C, 0x00], &[0x12, 0x00, 7, 8, 9])
+      .unwrap();
+    muxer.write_header(64, 48, 1, 1, &[0x81, 0x00, 0x0C, 0x00]);
+    muxer
+      .write_frame(0, 1, &[0x12, 0x00, 1, 2, 3, 4], FrameType::KEY)
+      .unwrap();
+    muxer.finish().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let top = boxes(&data);
+    let kinds: Vec<_> = top.iter().map(|(k, _)| k).collect();
+    assert_eq!(kinds, [b"ftyp", b"meta", b"mdat"]);
+    assert_eq!(&top[0].1[..4], b"avif");
+    let meta = &top[1].1[4..];
+    assert_eq!(child(meta, &[b"pitm"])[4..], COLOR_ITEM.to_be_bytes());
+
+    let iloc = child(meta, &[b"iloc"]);
+    assert_eq!(iloc[7], 2);
+    let extent = |i: usize| {
+      let offset = u32_at(iloc, 14 + i * 14) as usize;
+      let len = u32_at(iloc, 18 + i * 14) as usize;
+      &data[offset..offset + len]
+    };
+    assert_eq!(extent(0), [1, 2, 3, 4]);
+    assert_eq!(extent(1), [7, 8, 9]);
+
+    let auxl = child(&child(meta, &[b"iref"])[4..], &[b"auxl"]);
+    assert_eq!(auxl, [0, 2, 0, 1, 0, 1]);
+
+    let ipco = boxes(child(meta, &[b"iprp", b"ipco"]));
+    let kinds: Vec<_> = ipco.iter().map(|(k, _)| k).collect();
+    assert_eq!(
+      kinds,
+      [b"ispe", b"pixi", b"av1C", b"colr", b"pixi", b"av1C", b"auxC"]
+    );
+    assert_eq!(ipco[0].1[4..], [0, 0, 0, 64, 0, 0, 0, 48]);
+    assert_eq!(ipco[1].1[4..], [3, 10, 10, 10]);
+    assert_eq!(ipco[2].1, [0x81, 0x00, 0x0C, 0x00]);
+    assert_eq!(ipco[3].1, *b"nclx\0\x01\0\x0d\0\x06\x80");
+    assert_eq!(ipco[4].1[4..], [1, 10]);
+    assert_eq!(ipco[5].1, [0x81, 0x00, 0x1C, 0x00]);
+    assert_eq!(&ipco[6].1[4..], ALPHA_URN);
+
+    let ipma = child(meta, &[b"iprp", b"ipma"]);
+    assert_eq!(
+      ipma[4..],
+      [0, 0, 0, 2, 0, 1, 4, 1, 2, 0x83, 4, 0, 2, 4, 1, 5, 0x86, 0x87]
+    );
+  }
+
+  #[test]
+  fn no_colr_without_color_description() {
+    let path = temp_dir()
+      .join(format!("rav1e-avif-nocolr-{}.avif", std::process::id()));
+    let mut muxer = AvifMuxer::open(&path).unwrap();
+    muxer.set_color_config(&EncoderConfig::default());
+    muxer
+      .write_alpha(&[0x81, 0x00, 0x1C, 0x00], &[0x12, 0x00, 7, 8, 9])
+      .unwrap();
+    muxer.write_header(64, 48, 1, 1, &[0x81, 0x00, 0x0C, 0x00]);
+    muxer
+      .write_frame(0, 1, &[0x12, 0x00, 1, 2, 3, 4], FrameType::KEY)
+      .unwrap();
+    muxer.finish().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let meta = &boxes(&data)[1].1[4..];
+    let ipco = boxes(child(meta, &[b"iprp", b"ipco"]));
+    let kinds: Vec<_> = ipco.iter().map(|(k, _)| k).collect();
+    assert_eq!(kinds, [b"ispe", b"pixi", b"av1C", b"pixi", b"av1C", b"auxC"]);
+    let ipma = child(meta, &[b"iprp", b"ipma"]);
+    assert_eq!(
+      ipma[4..],
+      [0, 0, 0, 2, 0, 1, 3, 1, 2, 0x83, 0, 2, 4, 1, 4, 0x85, 0x86]
+    );
+  }
+}
@@ -25,7 +25,7 @@ pub struct IvfMuxer {
 impl Muxer for IvfMuxer {
   fn write_header(
     &mut self, width: usize, height: usize, framerate_num: usize,
-    framerate_den: usize,
+    framerate_den: usize, _sequence_header: &[u8],
   ) {
     write_ivf_header(
       &mut self.output,
@@ -37,8 +37,11 @@ impl Muxer for IvfMuxer {
   }
 
   #[profiling::function]
-  fn write_frame(&mut self, pts: u64, data: &[u8], _frame_type: FrameType) {
+  fn write_frame(
+    &mut self, pts: u64, _duration: u64, data: &[u8], _frame_type: FrameType,
+  ) -> io::Result<()> {
     write_ivf_frame(&mut self.output, pts, data);
+    Ok(())
   }
 
   fn flush(&mut self) -> io::Result<()> {
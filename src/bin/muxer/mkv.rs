@@ -0,0 +1,401 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::{strip_temporal_delimiter, Muxer};
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const VOID: u32 = 0xEC;
+
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const DEFAULT_DURATION: u32 = 0x23_E383;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const REFERENCE_BLOCK: u32 = 0xFB;
+
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+/// Timestamps are stored in milliseconds.
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+
+/// Space kept at the start of the segment for the seek head, which can only
+/// be written once the position of the cues is known.
+const SEEK_HEAD_RESERVED: usize = 96;
+
+/// A size of unknown length, patched once the segment is complete.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+fn put_id(buf: &mut Vec<u8>, id: u32) {
+  let len = 4 - id.leading_zeros() as usize / 8;
+  buf.extend_from_slice(&id.to_be_bytes()[4 - len..]);
+}
+
+fn put_size(buf: &mut Vec<u8>, size: u64) {
+  let mut len = 1;
+  while len < 8 && size >= (1 << (7 * len)) - 1 {
+    len += 1;
+  }
+  let marked = size | (1 << (7 * len));
+  buf.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn put_master(buf: &mut Vec<u8>, id: u32, body: &[u8]) {
+  put_id(buf, id);
+  put_size(buf, body.len() as u64);
+  buf.extend_from_slice(body);
+}
+
+fn put_uint(buf: &mut Vec<u8>, id: u32, value: u64) {
+  let len = (8 - value.leading_zeros() as usize / 8).max(1);
+  put_master(buf, id, &value.to_be_bytes()[8 - len..]);
+}
+
+fn put_int(buf: &mut Vec<u8>, id: u32, value: i64) {
+  // Keep the sign bit in the most significant byte written.
+  let bits = 64 - (value ^ (value >> 63)).leading_zeros() as usize;
+  let len = (bits / 8 + 1).min(8);
+  put_master(buf, id, &value.to_be_bytes()[8 - len..]);
+}
+
+fn put_float(buf: &mut Vec<u8>, id: u32, value: f64) {
+  put_master(buf, id, &value.to_be_bytes());
+}
+
+fn put_void(buf: &mut Vec<u8>, len: usize) {
+  // One byte for the ID and one for the size.
+  assert!((2..2 + 127).contains(&len));
+  put_id(buf, VOID);
+  put_size(buf, len as u64 - 2);
+  buf.resize(buf.len() + len - 2, 0);
+}
+
+struct Cluster {
+  timestamp: u64,
+  data: Vec<u8>,
+}
+
+pub struct MatroskaMuxer {
+  output: BufWriter<File>,
+  doc_type: &'static str,
+  /// Number of bytes written to `output`.
+  offset: u64,
+  /// Time base of the packet timestamps.
+  time_base: Rational,
+  segment_start: u64,
+  info_start: u64,
+  tracks_start: u64,
+  duration_pos: u64,
+  cluster: Option<Cluster>,
+  /// Timestamp and cluster position of each key frame.
+  cues: Vec<(u64, u64)>,
+  /// Timestamp of the last block written.
+  last_timestamp: u64,
+  /// Timestamp at which the last block shown ends.
+  end_timestamp: u64,
+}
+
+impl Muxer for MatroskaMuxer {
+  fn write_header(
+    &mut self, width: usize, height: usize, framerate_num: usize,
+    framerate_den: usize, sequence_header: &[u8],
+  ) {
+    self.time_base = Rational::new(framerate_den as u64, framerate_num as u64);
+
+    let mut head = Vec::new();
+    let mut body = Vec::new();
+    put_uint(&mut body, EBML_VERSION, 1);
+    put_uint(&mut body, EBML_READ_VERSION, 1);
+    put_uint(&mut body, EBML_MAX_ID_LENGTH, 4);
+    put_uint(&mut body, EBML_MAX_SIZE_LENGTH, 8);
+    put_master(&mut body, DOC_TYPE, self.doc_type.as_bytes());
+    put_uint(&mut body, DOC_TYPE_VERSION, 4);
+    put_uint(&mut body, DOC_TYPE_READ_VERSION, 2);
+    put_master(&mut head, EBML, &body);
+
+    put_id(&mut head, SEGMENT);
+    head.extend_from_slice(&UNKNOWN_SIZE);
+    self.segment_start = head.len() as u64;
+    put_void(&mut head, SEEK_HEAD_RESERVED);
+
+    body.clear();
+    put_uint(&mut body, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+    put_master(&mut body, MUXING_APP, b"rav1e");
+    put_master(
+      &mut body,
+      WRITING_APP,
+      concat!("rav1e ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    // Patched with the actual duration at the end.
+    put_float(&mut body, DURATION, 0.0);
+    self.info_start = head.len() as u64 - self.segment_start;
+    put_master(&mut head, INFO, &body);
+    self.duration_pos = head.len() as u64 - 8;
+
+    let mut video = Vec::new();
+    put_uint(&mut video, PIXEL_WIDTH, width as u64);
+    put_uint(&mut video, PIXEL_HEIGHT, height as u64);
+    body.clear();
+    put_uint(&mut body, TRACK_NUMBER, 1);
+    put_uint(&mut body, TRACK_UID, 1);
+    put_uint(&mut body, TRACK_TYPE, 1); // video
+    put_uint(&mut body, FLAG_LACING, 0);
+    // Blocks lasting another number of ticks signal their own duration.
+    put_uint(&mut body, DEFAULT_DURATION, self.to_nanoseconds(1));
+    put_master(&mut body, CODEC_ID, b"V_AV1");
+    put_master(&mut body, CODEC_PRIVATE, sequence_header);
+    put_master(&mut body, VIDEO, &video);
+    let mut track = Vec::new();
+    put_master(&mut track, TRACK_ENTRY, &body);
+    self.tracks_start = head.len() as u64 - self.segment_start;
+    put_master(&mut head, TRACKS, &track);
+
+    self.write_all(&head);
+  }
+
+  #[profiling::function]
+  fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], frame_type: FrameType,
+  ) -> io::Result<()> {
+    let timestamp = self.to_nanoseconds(pts) / TIMESTAMP_SCALE_NS;
+    let keyframe = frame_type == FrameType::KEY;
+    let end_timestamp =
+      self.to_nanoseconds(pts + duration) / TIMESTAMP_SCALE_NS;
+    self.end_timestamp = self.end_timestamp.max(end_timestamp);
+
+    let new_cluster = match &self.cluster {
+      Some(cluster) => {
+        keyframe || timestamp - cluster.timestamp > i16::MAX as u64
+      }
+      None => true,
+    };
+    if new_cluster {
+      self.write_cluster();
+      if keyframe {
+        self.cues.push((timestamp, self.offset - self.segment_start));
+      }
+      self.cluster = Some(Cluster { timestamp, data: Vec::new() });
+    }
+
+    let cluster = self.cluster.as_mut().unwrap();
+    let data = strip_temporal_delimiter(data);
+    let relative = (timestamp - cluster.timestamp) as i16;
+    if duration == 1 {
+      put_id(&mut cluster.data, SIMPLE_BLOCK);
+      put_size(&mut cluster.data, data.len() as u64 + 4);
+      cluster.data.push(0x81); // track number
+      cluster.data.extend_from_slice(&relative.to_be_bytes());
+      cluster.data.push(if keyframe { 0x80 } else { 0 });
+      cluster.data.extend_from_slice(data);
+    } else {
+      // Only block groups can override the default duration. Their blocks
+      // have no key frame flag, other frames reference the previous block
+      // instead.
+      let mut block = Vec::with_capacity(data.len() + 4);
+      block.push(0x81); // track number
+      block.extend_from_slice(&relative.to_be_bytes());
+      block.push(0);
+      block.extend_from_slice(data);
+      let mut group = Vec::new();
+      put_master(&mut group, BLOCK, &block);
+      put_uint(&mut group, BLOCK_DURATION, end_timestamp - timestamp);
+      if !keyframe {
+        put_int(
+          &mut group,
+          REFERENCE_BLOCK,
+          self.last_timestamp as i64 - timestamp as i64,
+        );
+      }
+      put_master(&mut cluster.data, BLOCK_GROUP, &group);
+    }
+    self.last_timestamp = timestamp;
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.write_cluster();
+
+    let cues_start = self.offset - self.segment_start;
+    let mut cues = Vec::new();
+    for &(timestamp, position) in &self.cues {
+      let mut positions = Vec::new();
+      put_uint(&mut positions, CUE_TRACK, 1);
+      put_uint(&mut positions, CUE_CLUSTER_POSITION, position);
+      let mut point = Vec::new();
+      put_uint(&mut point, CUE_TIME, timestamp);
+      put_master(&mut point, CUE_TRACK_POSITIONS, &positions);
+      put_master(&mut cues, CUE_POINT, &point);
+    }
+    let mut buf = Vec::new();
+    put_master(&mut buf, CUES, &cues);
+    self.write_all(&buf);
+
+    let mut seek_head = Vec::new();
+    for (id, position) in [
+      (INFO, self.info_start),
+      (TRACKS, self.tracks_start),
+      (CUES, cues_start),
+    ] {
+      let mut seek_id = Vec::new();
+      put_id(&mut seek_id, id);
+      let mut seek = Vec::new();
+      put_master(&mut seek, SEEK_ID, &seek_id);
+      put_uint(&mut seek, SEEK_POSITION, position);
+      put_master(&mut seek_head, SEEK, &seek);
+    }
+    let mut buf = Vec::new();
+    put_master(&mut buf, SEEK_HEAD, &seek_head);
+    let padding = SEEK_HEAD_RESERVED - buf.len();
+    put_void(&mut buf, padding);
+
+    let segment_size = self.offset - self.segment_start;
+    self.output.seek(SeekFrom::Start(self.segment_start - 7))?;
+    self.output.write_all(&segment_size.to_be_bytes()[1..])?;
+    self.output.seek(SeekFrom::Start(self.segment_start))?;
+    self.output.write_all(&buf)?;
+    self.output.seek(SeekFrom::Start(self.duration_pos))?;
+    self.output.write_all(&(self.end_timestamp as f64).to_be_bytes())?;
+    self.output.seek(SeekFrom::End(0))?;
+    self.output.flush()
+  }
+}
+
+impl MatroskaMuxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P, webm: bool,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let mkv = MatroskaMuxer {
+      output: BufWriter::new(
+        File::create(path)
+          .map_err(|e| e.context("Cannot open output file"))?,
+      ),
+      doc_type: if webm { "webm" } else { "matroska" },
+      offset: 0,
+      time_base: Rational::new(1, 1),
+      segment_start: 0,
+      info_start: 0,
+      tracks_start: 0,
+      duration_pos: 0,
+      cluster: None,
+      cues: Vec::new(),
+      last_timestamp: 0,
+      end_timestamp: 0,
+    };
+    Ok(Box::new(mkv))
+  }
+
+  fn to_nanoseconds(&self, pts: u64) -> u64 {
+    (pts as u128 * self.time_base.num as u128 * 1_000_000_000
+      / self.time_base.den as u128) as u64
+  }
+
+  fn write_cluster(&mut self) {
+    if let Some(cluster) = self.cluster.take() {
+      let mut body = Vec::new();
+      put_uint(&mut body, TIMESTAMP, cluster.timestamp);
+      body.extend_from_slice(&cluster.data);
+      let mut buf = Vec::new();
+      put_master(&mut buf, CLUSTER, &body);
+      self.write_all(&buf);
+    }
+  }
+
+  fn write_all(&mut self, buf: &[u8]) {
+    self.output.write_all(buf).unwrap();
+    self.offset += buf.len() as u64;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::env::temp_dir;
+
+  const SEQUENCE_HEADER: [u8; 4] = [0x81, 0x1F, 0x0C, 0x00];
+
+  #[test]
+  fn block_durations() {
+    let path =
+      temp_dir().join(format!("rav1e-mkv-{}.mkv", std::process::id()));
+    let mut muxer = MatroskaMuxer::open(&path, false).unwrap();
+    // 25 fps, so that each tick lasts 40 ms.
+    muxer.write_header(64, 48, 25, 1, &SEQUENCE_HEADER);
+    muxer.write_frame(0, 1, &[0x12, 0x00, 1], FrameType::KEY).unwrap();
+    muxer.write_frame(1, 2, &[0x12, 0x00, 2], FrameType::INTER).unwrap();
+    muxer.write_frame(3, 1, &[0x12, 0x00, 3], FrameType::INTER).unwrap();
+    muxer.finish().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let find = |pattern: &[u8]| {
+      data.windows(pattern.len()).any(|window| window == pattern)
+    };
+    // The segment lasts until the end of the last frame.
+    let mut duration = vec![0x44, 0x89, 0x88];
+    duration.extend_from_slice(&160f64.to_be_bytes());
+    assert!(find(&duration));
+    // The second frame is in a block group lasting 80 ms and referencing
+    // the first frame, 40 ms earlier.
+    let block = [0xA1, 0x85, 0x81, 0x00, 0x28, 0x00, 2];
+    let mut group = vec![0xA0, 0x8D];
+    group.extend_from_slice(&block);
+    group.extend_from_slice(&[0x9B, 0x81, 80, 0xFB, 0x81, 0xD8]);
+    assert!(find(&group));
+    // The others are simple blocks lasting the default duration.
+    assert!(find(&[0xA3, 0x85, 0x81, 0x00, 0x00, 0x80, 1]));
+    assert!(find(&[0xA3, 0x85, 0x81, 0x00, 0x78, 0x00, 3]));
+  }
+}
@@ -7,9 +7,21 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+mod avif;
+use self::avif::AvifMuxer;
+
 mod ivf;
 use self::ivf::IvfMuxer;
 
+mod mkv;
+use self::mkv::MatroskaMuxer;
+
+mod mp4;
+use self::mp4::Mp4Muxer;
+
+mod obu;
+use self::obu::ObuMuxer;
+
 mod y4m;
 pub use self::y4m::write_y4m_frame;
 
@@ -24,38 +36,111 @@ use crate::error::*;
 pub trait Muxer: Send {
   fn write_header(
     &mut self, width: usize, height: usize, framerate_num: usize,
-    framerate_den: usize,
+    framerate_den: usize, sequence_header: &[u8],
   );
 
-  fn write_frame(&mut self, pts: u64, data: &[u8], frame_type: FrameType);
+  /// Describes the color of the frames to containers which signal it
+  /// outside of the bitstream.
+  fn set_color_config(&mut self, _config: &EncoderConfig) {}
+
+  /// Adds an alpha channel, encoded as a separate monochrome stream, to a
+  /// still picture.
+  fn write_alpha(
+    &mut self, _sequence_header: &[u8], _data: &[u8],
+  ) -> Result<(), CliError> {
+    Err(CliError::new("An alpha channel requires AVIF output"))
+  }
+
+  /// Writes the packet of a temporal unit shown at `pts` for `duration`,
+  /// both in units of the time base.
+  fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], frame_type: FrameType,
+  ) -> io::Result<()>;
 
   fn flush(&mut self) -> io::Result<()>;
+
+  /// Completes the output after the last frame, e.g. with indexes which
+  /// can only be written once every frame is known.
+  fn finish(&mut self) -> io::Result<()> {
+    self.flush()
+  }
+}
+
+/// Removes the leading temporal delimiter from a packet, which containers
+/// signal by other means.
+fn strip_temporal_delimiter(data: &[u8]) -> &[u8] {
+  data.strip_prefix(&[0x12, 0x00]).unwrap_or(data)
+}
+
+/// Output format of the encoded stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Container {
+  Ivf,
+  Mkv,
+  Webm,
+  Mp4,
+  Avif,
+  /// Low-overhead OBU stream, as in Section 5 of the AV1 specification
+  Obu,
+  /// Length-delimited OBU stream, as in Annex B of the AV1 specification
+  #[value(name = "annexb")]
+  AnnexB,
+}
+
+impl Container {
+  /// Guesses the container from the extension of the output path.
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Container, CliError> {
+    if path.as_ref().to_str() == Some("-") {
+      return Ok(Container::Ivf);
+    }
+
+    let ext = path
+      .as_ref()
+      .extension()
+      .and_then(OsStr::to_str)
+      .map(str::to_lowercase)
+      .unwrap_or_else(|| "ivf".into());
+
+    match &ext[..] {
+      "ivf" => Ok(Container::Ivf),
+      "mkv" => Ok(Container::Mkv),
+      "webm" => Ok(Container::Webm),
+      "mp4" => Ok(Container::Mp4),
+      "avif" => Ok(Container::Avif),
+      "obu" => Ok(Container::Obu),
+      _e => Err(CliError::new(&format!(
+        "{ext} is not a supported extension, please change to .ivf, .mkv, \
+         .webm, .mp4, .avif or .obu, or set --container"
+      ))),
+    }
+  }
 }
 
 pub fn create_muxer<P: AsRef<Path>>(
-  path: P, overwrite: bool,
+  path: P, overwrite: bool, container: Container, fragmented: bool,
 ) -> Result<Box<dyn Muxer + Send>, CliError> {
   if !overwrite {
     IvfMuxer::check_file(path.as_ref())?;
   }
 
-  if let Some(path) = path.as_ref().to_str() {
-    if path == "-" {
-      return IvfMuxer::open(path);
-    }
+  if path.as_ref().to_str() == Some("-")
+    && !matches!(
+      container,
+      Container::Ivf | Container::Obu | Container::AnnexB
+    )
+  {
+    return Err(CliError::new(
+      "Only IVF and OBU streams can be written to standard output",
+    ));
   }
 
-  let ext = path
-    .as_ref()
-    .extension()
-    .and_then(OsStr::to_str)
-    .map(str::to_lowercase)
-    .unwrap_or_else(|| "ivf".into());
-
-  match &ext[..] {
-    "ivf" => IvfMuxer::open(path),
-    _e => {
-      panic!("{ext} is not a supported extension, please change to .ivf");
-    }
+  match container {
+    Container::Ivf => IvfMuxer::open(path),
+    Container::Mkv => MatroskaMuxer::open(path, false),
+    Container::Webm => MatroskaMuxer::open(path, true),
+    Container::Mp4 => Mp4Muxer::open(path, fragmented),
+    Container::Avif => AvifMuxer::open(path),
+    Container::Obu => ObuMuxer::open(path, false),
+    Container::AnnexB => ObuMuxer::open(path, true),
   }
 }
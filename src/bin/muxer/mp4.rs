@@ -0,0 +1,681 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::{strip_temporal_delimiter, Muxer};
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Identity transformation of the movie and track headers.
+const MATRIX: [u32; 9] =
+  [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Sample flags of a key frame, which depends on no other sample.
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+/// Sample flags of a frame predicted from others.
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// `tfhd` flag: data offsets are relative to the start of the `moof` box.
+const DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+/// `trun` flags: data offset, then sample durations, sizes and flags.
+const TRUN_FLAGS: u32 = 0x0001 | 0x0100 | 0x0200 | 0x0400;
+
+/// Size of the media data box header, which always uses a 64-bit size.
+const MDAT_HEADER_SIZE: u64 = 16;
+
+/// Amount of media data moved at once when placing the movie box in front.
+const MOVE_CHUNK_SIZE: usize = 1 << 20;
+
+pub(super) fn put_u16(buf: &mut Vec<u8>, value: u16) {
+  buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(super) fn put_u32(buf: &mut Vec<u8>, value: u32) {
+  buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(super) fn put_u64(buf: &mut Vec<u8>, value: u64) {
+  buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(super) fn put_box(buf: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+  put_u32(buf, body.len() as u32 + 8);
+  buf.extend_from_slice(kind);
+  buf.extend_from_slice(body);
+}
+
+pub(super) fn put_full_box(
+  buf: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, body: &[u8],
+) {
+  let mut full = Vec::with_capacity(body.len() + 4);
+  put_u32(&mut full, (version as u32) << 24 | flags);
+  full.extend_from_slice(body);
+  put_box(buf, kind, &full);
+}
+
+struct Sample {
+  pts: u64,
+  /// Duration in units of the time base, or 0 if unknown.
+  duration: u64,
+  size: u32,
+  sync: bool,
+}
+
+pub struct Mp4Muxer {
+  output: BufWriter<File>,
+  /// Write a movie fragment for every key frame instead of a single index.
+  fragmented: bool,
+  /// Number of bytes written to `output`.
+  offset: u64,
+  width: u32,
+  height: u32,
+  /// Number of media time units per second.
+  timescale: u32,
+  /// Duration of one timestamp tick, in media time units.
+  tick: u32,
+  sequence_header: Vec<u8>,
+  /// Position of the media data box holding every sample when not
+  /// fragmented.
+  mdat_start: u64,
+  /// Samples not yet described by a `moov` or `moof` box.
+  samples: Vec<Sample>,
+  /// Data of the samples in the current fragment.
+  fragment: Vec<u8>,
+  sequence_number: u32,
+  first_pts: Option<u64>,
+  /// Duration of the last sample with a known duration or successor,
+  /// reused for the final sample.
+  last_duration: u32,
+}
+
+impl Muxer for Mp4Muxer {
+  fn write_header(
+    &mut self, width: usize, height: usize, framerate_num: usize,
+    framerate_den: usize, sequence_header: &[u8],
+  ) {
+    self.width = width as u32;
+    self.height = height as u32;
+    self.timescale = framerate_num as u32;
+    self.tick = framerate_den as u32;
+    self.last_duration = self.tick;
+    self.sequence_header = sequence_header.to_vec();
+
+    let mut head = Vec::new();
+    let mut ftyp = Vec::new();
+    ftyp.extend_from_slice(b"isom");
+    put_u32(&mut ftyp, 0x200);
+    for brand in [b"isom", b"iso6", b"av01", b"mp41"] {
+      ftyp.extend_from_slice(brand);
+    }
+    put_box(&mut head, b"ftyp", &ftyp);
+
+    if self.fragmented {
+      let moov = self.moov(&[], 0);
+      head.extend_from_slice(&moov);
+    } else {
+      self.mdat_start = head.len() as u64;
+      // The 64-bit size is patched once every sample is written.
+      put_u32(&mut head, 1);
+      head.extend_from_slice(b"mdat");
+      put_u64(&mut head, 0);
+    }
+
+    self.write_all(&head);
+  }
+
+  #[profiling::function]
+  fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], frame_type: FrameType,
+  ) -> io::Result<()> {
+    let sync = frame_type == FrameType::KEY;
+    let data = strip_temporal_delimiter(data);
+    self.first_pts.get_or_insert(pts);
+
+    if self.fragmented {
+      if sync && !self.samples.is_empty() {
+        self.write_fragment(Some(pts));
+      }
+      self.fragment.extend_from_slice(data);
+    } else {
+      self.write_all(data);
+    }
+    self.samples.push(Sample { pts, duration, size: data.len() as u32, sync });
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    if self.fragmented {
+      if !self.samples.is_empty() {
+        self.write_fragment(None);
+      }
+      return self.output.flush();
+    }
+
+    let mdat_size = self.offset - self.mdat_start;
+    self.output.seek(SeekFrom::Start(self.mdat_start + 8))?;
+    self.output.write_all(&mdat_size.to_be_bytes())?;
+    self.output.flush()?;
+
+    // The movie box goes in front of the media data so playback can start
+    // before the whole file is downloaded.
+    let durations = self.durations(None);
+    let moov_size = self.moov(&durations, 0).len() as u64;
+    let moov =
+      self.moov(&durations, self.mdat_start + moov_size + MDAT_HEADER_SIZE);
+
+    let file = self.output.get_mut();
+    let mut chunk = vec![0; MOVE_CHUNK_SIZE];
+    let mut end = self.offset;
+    while end > self.mdat_start {
+      let start =
+        end.saturating_sub(MOVE_CHUNK_SIZE as u64).max(self.mdat_start);
+      let chunk = &mut chunk[..(end - start) as usize];
+      file.seek(SeekFrom::Start(start))?;
+      file.read_exact(chunk)?;
+      file.seek(SeekFrom::Start(start + moov_size))?;
+      file.write_all(chunk)?;
+      end = start;
+    }
+    file.seek(SeekFrom::Start(self.mdat_start))?;
+    file.write_all(&moov)?;
+    self.offset += moov_size;
+    file.seek(SeekFrom::End(0))?;
+    file.flush()
+  }
+}
+
+impl Mp4Muxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P, fragmented: bool,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .open(path)
+      .map_err(|e| e.context("Cannot open output file"))?;
+    Ok(Box::new(Mp4Muxer::new(file, fragmented)))
+  }
+
+  fn new(file: File, fragmented: bool) -> Self {
+    Mp4Muxer {
+      output: BufWriter::new(file),
+      fragmented,
+      offset: 0,
+      width: 0,
+      height: 0,
+      timescale: 1,
+      tick: 1,
+      sequence_header: Vec::new(),
+      mdat_start: 0,
+      samples: Vec::new(),
+      fragment: Vec::new(),
+      sequence_number: 0,
+      first_pts: None,
+      last_duration: 1,
+    }
+  }
+
+  /// Durations of the pending samples, in media time units. Samples without
+  /// a duration last until the next one, which for the last sample starts
+  /// at `next_pts` if known.
+  fn durations(&mut self, next_pts: Option<u64>) -> Vec<u32> {
+    let mut durations = Vec::with_capacity(self.samples.len());
+    for (i, sample) in self.samples.iter().enumerate() {
+      let next = self.samples.get(i + 1).map(|s| s.pts).or(next_pts);
+      let duration = match (sample.duration, next) {
+        (0, Some(next)) => Some(next - sample.pts),
+        (0, None) => None,
+        (duration, _) => Some(duration),
+      };
+      if let Some(duration) = duration {
+        self.last_duration = (duration * self.tick as u64) as u32;
+      }
+      durations.push(self.last_duration);
+    }
+    durations
+  }
+
+  /// Builds the movie box. Without fragments it indexes the pending
+  /// samples, stored contiguously from `data_offset`.
+  fn moov(&self, durations: &[u32], data_offset: u64) -> Vec<u8> {
+    let duration = durations.iter().map(|&d| d as u64).sum::<u64>();
+
+    let mut body = Vec::new();
+    put_u64(&mut body, 0); // creation time
+    put_u64(&mut body, 0); // modification time
+    put_u32(&mut body, self.timescale);
+    put_u64(&mut body, duration);
+    put_u32(&mut body, 0x0001_0000); // rate
+    put_u16(&mut body, 0x0100); // volume
+    body.resize(body.len() + 10, 0);
+    MATRIX.iter().for_each(|&m| put_u32(&mut body, m));
+    body.resize(body.len() + 24, 0);
+    put_u32(&mut body, 2); // next track ID
+    let mut moov = Vec::new();
+    put_full_box(&mut moov, b"mvhd", 1, 0, &body);
+
+    body.clear();
+    put_u64(&mut body, 0); // creation time
+    put_u64(&mut body, 0); // modification time
+    put_u32(&mut body, 1); // track ID
+    put_u32(&mut body, 0);
+    put_u64(&mut body, duration);
+    body.resize(body.len() + 16, 0);
+    MATRIX.iter().for_each(|&m| put_u32(&mut body, m));
+    put_u32(&mut body, self.width << 16);
+    put_u32(&mut body, self.height << 16);
+    let mut trak = Vec::new();
+    // Enabled and used in the presentation.
+    put_full_box(&mut trak, b"tkhd", 1, 3, &body);
+
+    body.clear();
+    put_u64(&mut body, 0); // creation time
+    put_u64(&mut body, 0); // modification time
+    put_u32(&mut body, self.timescale);
+    put_u64(&mut body, duration);
+    put_u16(&mut body, 0x55C4); // "und"
+    put_u16(&mut body, 0);
+    let mut mdia = Vec::new();
+    put_full_box(&mut mdia, b"mdhd", 1, 0, &body);
+
+    body.clear();
+    put_u32(&mut body, 0);
+    body.extend_from_slice(b"vide");
+    body.resize(body.len() + 12, 0);
+    body.extend_from_slice(b"VideoHandler\0");
+    put_full_box(&mut mdia, b"hdlr", 0, 0, &body);
+
+    let mut minf = Vec::new();
+    put_full_box(&mut minf, b"vmhd", 0, 1, &[0; 8]);
+    body.clear();
+    put_u32(&mut body, 1);
+    // The samples are in this file.
+    put_full_box(&mut body, b"url ", 0, 1, &[]);
+    let mut dinf = Vec::new();
+    put_full_box(&mut dinf, b"dref", 0, 0, &body);
+    put_box(&mut minf, b"dinf", &dinf);
+    put_box(&mut minf, b"stbl", &self.stbl(durations, data_offset));
+    put_box(&mut mdia, b"minf", &minf);
+    put_box(&mut trak, b"mdia", &mdia);
+    put_box(&mut moov, b"trak", &trak);
+
+    if self.fragmented {
+      body.clear();
+      put_u32(&mut body, 1); // track ID
+      put_u32(&mut body, 1); // sample description index
+      put_u32(&mut body, 0); // sample duration
+      put_u32(&mut body, 0); // sample size
+      put_u32(&mut body, 0); // sample flags
+      let mut mvex = Vec::new();
+      put_full_box(&mut mvex, b"trex", 0, 0, &body);
+      put_box(&mut moov, b"mvex", &mvex);
+    }
+
+    let mut buf = Vec::new();
+    put_box(&mut buf, b"moov", &moov);
+    buf
+  }
+
+  fn stbl(&self, durations: &[u32], data_offset: u64) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.resize(6, 0);
+    put_u16(&mut entry, 1); // data reference index
+    entry.resize(entry.len() + 16, 0);
+    put_u16(&mut entry, self.width as u16);
+    put_u16(&mut entry, self.height as u16);
+    put_u32(&mut entry, 0x0048_0000); // 72 dpi
+    put_u32(&mut entry, 0x0048_0000);
+    put_u32(&mut entry, 0);
+    put_u16(&mut entry, 1); // frame count
+    let mut compressor = [0; 32];
+    compressor[0] = 5;
+    compressor[1..6].copy_from_slice(b"rav1e");
+    entry.extend_from_slice(&compressor);
+    put_u16(&mut entry, 0x0018); // depth
+    put_u16(&mut entry, 0xFFFF);
+    put_box(&mut entry, b"av1C", &self.sequence_header);
+    let mut body = Vec::new();
+    put_u32(&mut body, 1);
+    put_box(&mut body, b"av01", &entry);
+    let mut stbl = Vec::new();
+    put_full_box(&mut stbl, b"stsd", 0, 0, &body);
+
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &duration in durations {
+      match runs.last_mut() {
+        Some((count, delta)) if *delta == duration => *count += 1,
+        _ => runs.push((1, duration)),
+      }
+    }
+    body.clear();
+    put_u32(&mut body, runs.len() as u32);
+    for (count, delta) in runs {
+      put_u32(&mut body, count);
+      put_u32(&mut body, delta);
+    }
+    put_full_box(&mut stbl, b"stts", 0, 0, &body);
+
+    // Fragments flag their sync samples themselves.
+    if !self.fragmented {
+      body.clear();
+      let sync = self.samples.iter().enumerate().filter(|(_, s)| s.sync);
+      put_u32(&mut body, sync.clone().count() as u32);
+      for (i, _) in sync {
+        put_u32(&mut body, i as u32 + 1);
+      }
+      put_full_box(&mut stbl, b"stss", 0, 0, &body);
+    }
+
+    // Every sample is in a single chunk.
+    let chunks = !self.samples.is_empty() as u32;
+    body.clear();
+    put_u32(&mut body, chunks);
+    if chunks > 0 {
+      put_u32(&mut body, 1); // first chunk
+      put_u32(&mut body, self.samples.len() as u32);
+      put_u32(&mut body, 1); // sample description index
+    }
+    put_full_box(&mut stbl, b"stsc", 0, 0, &body);
+
+    body.clear();
+    put_u32(&mut body, 0); // sizes differ
+    put_u32(&mut body, self.samples.len() as u32);
+    self.samples.iter().for_each(|s| put_u32(&mut body, s.size));
+    put_full_box(&mut stbl, b"stsz", 0, 0, &body);
+
+    body.clear();
+    put_u32(&mut body, chunks);
+    if let Ok(offset) = u32::try_from(data_offset) {
+      if chunks > 0 {
+        put_u32(&mut body, offset);
+      }
+      put_full_box(&mut stbl, b"stco", 0, 0, &body);
+    } else {
+      put_u64(&mut body, data_offset);
+      put_full_box(&mut stbl, b"co64", 0, 0, &body);
+    }
+
+    stbl
+  }
+
+  fn moof(&self, durations: &[u32], data_offset: u32) -> Vec<u8> {
+    let mut traf = Vec::new();
+    put_full_box(
+      &mut traf,
+      b"tfhd",
+      0,
+      DEFAULT_BASE_IS_MOOF,
+      &1u32.to_be_bytes(),
+    );
+    let decode_time =
+      (self.samples[0].pts - self.first_pts.unwrap_or(0)) * self.tick as u64;
+    put_full_box(&mut traf, b"tfdt", 1, 0, &decode_time.to_be_bytes());
+
+    let mut body = Vec::new();
+    put_u32(&mut body, self.samples.len() as u32);
+    put_u32(&mut body, data_offset);
+    for (sample, &duration) in self.samples.iter().zip(durations) {
+      put_u32(&mut body, duration);
+      put_u32(&mut body, sample.size);
+      put_u32(
+        &mut body,
+        if sample.sync { SYNC_SAMPLE_FLAGS } else { NON_SYNC_SAMPLE_FLAGS },
+      );
+    }
+    put_full_box(&mut traf, b"trun", 0, TRUN_FLAGS, &body);
+
+    let mut moof = Vec::new();
+    put_full_box(
+      &mut moof,
+      b"mfhd",
+      0,
+      0,
+      &self.sequence_number.to_be_bytes(),
+    );
+    put_box(&mut moof, b"traf", &traf);
+    let mut buf = Vec::new();
+    put_box(&mut buf, b"moof", &moof);
+    buf
+  }
+
+  /// Writes the pending samples as a movie fragment. The fragment following
+  /// them, if any, starts at `next_pts`.
+  fn write_fragment(&mut self, next_pts: Option<u64>) {
+    let durations = self.durations(next_pts);
+    self.sequence_number += 1;
+    let moof_size = self.moof(&durations, 0).len() as u32;
+    let mut buf = self.moof(&durations, moof_size + 8);
+    put_box(&mut buf, b"mdat", &self.fragment);
+    self.write_all(&buf);
+    self.samples.clear();
+    self.fragment.clear();
+  }
+
+  fn write_all(&mut self, buf: &[u8]) {
+    self.output.write_all(buf).unwrap();
+    self.offset += buf.len() as u64;
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use super::*;
+  use std::env::temp_dir;
+
+  const SEQUENCE_HEADER: [u8; 4] = [0x81, 0x1F, 0x0C, 0x00];
+
+  pub(crate) fn boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+      let mut size =
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+      let kind = data[pos + 4..pos + 8].try_into().unwrap();
+      let mut header = 8;
+      if size == 1 {
+        size = u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap())
+          as usize;
+        header = 16;
+      }
+      boxes.push((kind, &data[pos + header..pos + size]));
+      pos += size;
+    }
+    assert_eq!(pos, data.len());
+    boxes
+  }
+
+  pub(crate) fn child<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+    path.iter().fold(data, |data, kind| {
+      boxes(data).into_iter().find(|(k, _)| k == *kind).unwrap().1
+    })
+  }
+
+  pub(crate) fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+  }
+
+  /// Frames 0 to 9 with key frames at 0 and 6, and a gap after frame 8,
+  /// whose duration is unknown. The last frame lasts two ticks.
+  fn frames() -> Vec<(u64, Vec<u8>, bool, u64)> {
+    (0..10u8)
+      .map(|i| {
+        let pts = if i < 9 { i as u64 } else { 11 };
+        let duration = match i {
+          8 => 0,
+          9 => 2,
+          _ => 1,
+        };
+        let mut data = vec![0x12, 0x00];
+        data.extend(std::iter::repeat(i).take(i as usize * 7 + 1));
+        (pts, data, i % 6 == 0, duration)
+      })
+      .collect()
+  }
+
+  fn mux(fragmented: bool) -> Vec<u8> {
+    let name = format!(
+      "rav1e-mp4-{}-{}.mp4",
+      std::process::id(),
+      if fragmented { "fragmented" } else { "progressive" }
+    );
+    let path = temp_dir().join(name);
+    let mut muxer = Mp4Muxer::open(&path, fragmented).unwrap();
+    muxer.write_header(64, 48, 30000, 1001, &SEQUENCE_HEADER);
+    for (pts, data, sync, duration) in frames() {
+      let frame_type = if sync { FrameType::KEY } else { FrameType::INTER };
+      muxer.write_frame(pts, duration, &data, frame_type).unwrap();
+    }
+    muxer.finish().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    data
+  }
+
+  fn check_sample_entry(moov: &[u8]) {
+    let stsd = child(moov, &[b"trak", b"mdia", b"minf", b"stbl", b"stsd"]);
+    assert_eq!(u32_at(stsd, 4), 1);
+    let entries = boxes(&stsd[8..]);
+    assert_eq!(&entries[0].0, b"av01");
+    let entry = entries[0].1;
+    assert_eq!(u32_at(entry, 24), 64 << 16 | 48);
+    assert_eq!(child(&entry[78..], &[b"av1C"]), SEQUENCE_HEADER);
+    let mdhd = child(moov, &[b"trak", b"mdia", b"mdhd"]);
+    assert_eq!(u32_at(mdhd, 20), 30000);
+  }
+
+  fn expected_durations() -> Vec<u32> {
+    vec![1001, 1001, 1001, 1001, 1001, 1001, 1001, 1001, 3003, 2002]
+  }
+
+  #[test]
+  fn progressive_round_trip() {
+    let data = mux(false);
+    let top = boxes(&data);
+    let kinds: Vec<_> = top.iter().map(|(k, _)| k).collect();
+    assert_eq!(kinds, [b"ftyp", b"moov", b"mdat"]);
+    let moov = top[1].1;
+    check_sample_entry(moov);
+
+    let stbl = child(moov, &[b"trak", b"mdia", b"minf", b"stbl"]);
+    let stsz = child(stbl, &[b"stsz"]);
+    let stco = child(stbl, &[b"stco"]);
+    let stss = child(stbl, &[b"stss"]);
+    let stts = child(stbl, &[b"stts"]);
+
+    let mut durations = Vec::new();
+    for run in 0..u32_at(stts, 4) as usize {
+      let count = u32_at(stts, 8 + run * 8);
+      let delta = u32_at(stts, 12 + run * 8);
+      durations.extend(std::iter::repeat(delta).take(count as usize));
+    }
+    assert_eq!(durations, expected_durations());
+
+    let sync: Vec<_> =
+      (0..u32_at(stss, 4) as usize).map(|i| u32_at(stss, 8 + i * 4)).collect();
+    assert_eq!(sync, [1, 7]);
+
+    assert_eq!(u32_at(stco, 4), 1);
+    let mut offset = u32_at(stco, 8) as usize;
+    assert_eq!(u32_at(stsz, 8), 10);
+    for (i, (_, frame, _, _)) in frames().iter().enumerate() {
+      let size = u32_at(stsz, 12 + i * 4) as usize;
+      assert_eq!(data[offset..offset + size], frame[2..]);
+      offset += size;
+    }
+    assert_eq!(offset, data.len());
+  }
+
+  #[test]
+  fn fragmented_round_trip() {
+    let data = mux(true);
+    let top = boxes(&data);
+    let kinds: Vec<_> = top.iter().map(|(k, _)| k).collect();
+    assert_eq!(kinds, [b"ftyp", b"moov", b"moof", b"mdat", b"moof", b"mdat"]);
+    check_sample_entry(top[1].1);
+    child(top[1].1, &[b"mvex", b"trex"]);
+
+    let mut samples = Vec::new();
+    let mut moof_start = top[0].1.len() + top[1].1.len() + 16;
+    for (i, pair) in top[2..].chunks(2).enumerate() {
+      let moof = pair[0].1;
+      assert_eq!(u32_at(child(moof, &[b"mfhd"]), 4), i as u32 + 1);
+      let traf = child(moof, &[b"traf"]);
+      let tfdt = child(traf, &[b"tfdt"]);
+      let decode_time =
+        u64::from_be_bytes(tfdt[4..12].try_into().unwrap()) as u32;
+      let trun = child(traf, &[b"trun"]);
+      let mut offset = moof_start + u32_at(trun, 8) as usize;
+      let mut time = decode_time;
+      for s in 0..u32_at(trun, 4) as usize {
+        let duration = u32_at(trun, 12 + s * 12);
+        let size = u32_at(trun, 16 + s * 12) as usize;
+        let flags = u32_at(trun, 20 + s * 12);
+        samples.push((
+          time,
+          duration,
+          data[offset..offset + size].to_vec(),
+          flags == SYNC_SAMPLE_FLAGS,
+        ));
+        offset += size;
+        time += duration;
+      }
+      moof_start += pair[0].1.len() + pair[1].1.len() + 16;
+    }
+
+    let mut time = 0;
+    for ((frame, duration), sample) in
+      frames().iter().zip(expected_durations()).zip(samples)
+    {
+      assert_eq!(sample, (time, duration, frame.1[2..].to_vec(), frame.2));
+      time += duration;
+    }
+  }
+
+  #[test]
+  fn large_data_offset() {
+    let path =
+      temp_dir().join(format!("rav1e-mp4-{}-co64.mp4", std::process::id()));
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .open(&path)
+      .unwrap();
+    let mut muxer = Mp4Muxer::new(file, false);
+    muxer.write_header(64, 48, 30000, 1001, &SEQUENCE_HEADER);
+    muxer.samples.push(Sample { pts: 0, duration: 1, size: 8, sync: true });
+
+    // Media data past 4 GiB can only be addressed by 64-bit chunk offsets.
+    let data_offset = (1 << 32) + 0x30;
+    let moov = muxer.moov(&[1001], data_offset);
+    let top = boxes(&moov);
+    let stbl = child(top[0].1, &[b"trak", b"mdia", b"minf", b"stbl"]);
+    let kinds: Vec<_> = boxes(stbl).into_iter().map(|(k, _)| k).collect();
+    assert!(!kinds.contains(b"stco"));
+    let co64 = child(stbl, &[b"co64"]);
+    assert_eq!(u32_at(co64, 4), 1);
+    assert_eq!(
+      u64::from_be_bytes(co64[8..16].try_into().unwrap()),
+      data_offset
+    );
+
+    drop(muxer);
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,190 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::Muxer;
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+const OBU_FRAME_HEADER: u8 = 3;
+const OBU_FRAME: u8 = 6;
+
+const OBU_EXTENSION_FLAG: u8 = 0x04;
+const OBU_HAS_SIZE_FIELD: u8 = 0x02;
+
+/// Writes the packets as a bare OBU stream, either in the low-overhead
+/// format of Section 5 or length-delimited as in Annex B.
+pub struct ObuMuxer {
+  output: Box<dyn Write + Send>,
+  annex_b: bool,
+}
+
+impl Muxer for ObuMuxer {
+  fn write_header(
+    &mut self, _width: usize, _height: usize, _framerate_num: usize,
+    _framerate_den: usize, _sequence_header: &[u8],
+  ) {
+  }
+
+  #[profiling::function]
+  fn write_frame(
+    &mut self, _pts: u64, _duration: u64, data: &[u8], _frame_type: FrameType,
+  ) -> io::Result<()> {
+    if self.annex_b {
+      self.output.write_all(&temporal_unit(data)?)
+    } else {
+      self.output.write_all(data)
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+}
+
+impl ObuMuxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P, annex_b: bool,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let obu = ObuMuxer {
+      output: match path.as_ref().to_str() {
+        Some("-") => Box::new(std::io::stdout()),
+        _ => Box::new(
+          File::create(path)
+            .map_err(|e| e.context("Cannot open output file"))?,
+        ),
+      },
+      annex_b,
+    };
+    Ok(Box::new(obu))
+  }
+}
+
+fn put_leb128(buf: &mut Vec<u8>, mut value: usize) {
+  while value >= 0x80 {
+    buf.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  buf.push(value as u8);
+}
+
+fn invalid_packet(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the value and the number of bytes read.
+fn read_leb128(data: &[u8]) -> io::Result<(usize, usize)> {
+  let mut value = 0;
+  for (i, &byte) in data.iter().enumerate().take(8) {
+    value |= ((byte & 0x7F) as usize) << (i * 7);
+    if byte & 0x80 == 0 {
+      return Ok((value, i + 1));
+    }
+  }
+  Err(invalid_packet("Invalid leb128 value in packet"))
+}
+
+/// Converts a packet of low-overhead OBUs into an Annex B temporal unit.
+/// Each frame, with the OBUs preceding it, becomes a frame unit, and every
+/// OBU loses its size field in favor of an `obu_length` prefix.
+fn temporal_unit(data: &[u8]) -> io::Result<Vec<u8>> {
+  let mut frame_units: Vec<Vec<u8>> = Vec::new();
+  let mut has_frame = false;
+  let mut pos = 0;
+  while pos < data.len() {
+    let header = data[pos];
+    let obu_type = (header >> 3) & 0xF;
+    let header_len = if header & OBU_EXTENSION_FLAG != 0 { 2 } else { 1 };
+    if header & OBU_HAS_SIZE_FIELD == 0 {
+      return Err(invalid_packet("OBU without a size field in packet"));
+    }
+    let (size, size_len) =
+      read_leb128(data.get(pos + header_len..).unwrap_or_default())?;
+    let payload_start = pos + header_len + size_len;
+    let payload = data
+      .get(payload_start..payload_start + size)
+      .ok_or_else(|| invalid_packet("Truncated OBU in packet"))?;
+
+    let is_frame = obu_type == OBU_FRAME_HEADER || obu_type == OBU_FRAME;
+    if frame_units.is_empty() || (is_frame && has_frame) {
+      frame_units.push(Vec::new());
+      has_frame = false;
+    }
+    has_frame |= is_frame;
+
+    let unit = frame_units.last_mut().unwrap();
+    put_leb128(unit, header_len + size);
+    unit.push(header & !OBU_HAS_SIZE_FIELD);
+    unit.extend_from_slice(&data[pos + 1..pos + header_len]);
+    unit.extend_from_slice(payload);
+    pos = payload_start + size;
+  }
+
+  let mut units = Vec::new();
+  for unit in frame_units {
+    put_leb128(&mut units, unit.len());
+    units.extend_from_slice(&unit);
+  }
+  let mut buf = Vec::new();
+  put_leb128(&mut buf, units.len());
+  buf.extend_from_slice(&units);
+  Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn leb128_round_trip() {
+    for value in [0, 1, 127, 128, 300, 16383, 16384, 1 << 30] {
+      let mut buf = Vec::new();
+      put_leb128(&mut buf, value);
+      assert_eq!(read_leb128(&buf).unwrap(), (value, buf.len()));
+    }
+  }
+
+  #[test]
+  fn annex_b_frame_units() {
+    let packet = [
+      0x12, 0x00, // temporal delimiter
+      0x0A, 0x02, 0xAA, 0xBB, // sequence header
+      0x1A, 0x01, 0xCC, // frame header
+      0x22, 0x81, 0x00, // tile group, with a padded size
+      0xDD, // (tile group payload)
+      0x1A, 0x01, 0xEE, // frame header showing an existing frame
+    ];
+    assert_eq!(
+      temporal_unit(&packet).unwrap(),
+      [
+        17, // temporal unit size
+        12, // first frame unit size
+        1, 0x10, // temporal delimiter
+        3, 0x08, 0xAA, 0xBB, // sequence header
+        2, 0x18, 0xCC, // frame header
+        2, 0x20, 0xDD, // tile group
+        3,    // second frame unit size
+        2, 0x18, 0xEE, // frame header
+      ]
+    );
+  }
+
+  #[test]
+  fn annex_b_invalid_packets() {
+    // No size field
+    assert!(temporal_unit(&[0x10]).is_err());
+    // Unterminated size field
+    assert!(temporal_unit(&[0x12, 0x80]).is_err());
+    // Payload shorter than its size field
+    assert!(temporal_unit(&[0x0A, 0x03, 0xAA]).is_err());
+  }
+}
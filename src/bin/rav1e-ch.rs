@@ -95,9 +95,9 @@ impl<D: Decoder> Source<D> {
 }
 
 fn do_encode<T: Pixel, D: Decoder>(
-  cfg: Config, verbose: Verboseness, mut progress: ProgressInfo,
-  output: &mut dyn Muxer, mut source: Source<D>, pass1file: Option<File>,
-  pass2file: Option<File>,
+  cfg: Config, time_base: Rational, verbose: Verboseness,
+  mut progress: ProgressInfo, output: &mut dyn Muxer, mut source: Source<D>,
+  pass1file: Option<File>, pass2file: Option<File>,
   mut y4m_enc: Option<y4m::Encoder<Box<dyn Write + Send>>>,
   metrics_enabled: MetricsEnabled,
 ) -> Result<(), CliError> {
@@ -129,6 +129,13 @@ fn do_encode<T: Pixel, D: Decoder>(
     };
 
   let y4m_details = source.input.get_video_details();
+  output.write_header(
+    y4m_details.width,
+    y4m_details.height,
+    time_base.den as usize,
+    time_base.num as usize,
+    &receive_packet.container_sequence_header(),
+  );
 
   crossbeam::thread::scope(move |s| -> Result<(), CliError> {
     // Receive pass data
@@ -229,11 +236,14 @@ fn do_encode<T: Pixel, D: Decoder>(
     // Receive Packets
     let receive_packets = s.spawn(move |_| -> Result<(), CliError> {
       for pkt in receive_packet.iter() {
-        output.write_frame(
-          pkt.input_frameno as u64,
-          pkt.data.as_ref(),
-          pkt.frame_type,
-        );
+        output
+          .write_frame(
+            pkt.pts,
+            pkt.duration,
+            pkt.data.as_ref(),
+            pkt.frame_type,
+          )
+          .map_err(|e| e.context("Unable to write the output"))?;
         output.flush().unwrap();
         if let (Some(ref mut y4m_enc_uw), Some(ref rec)) =
           (y4m_enc.as_mut(), &pkt.rec)
@@ -257,6 +267,9 @@ fn do_encode<T: Pixel, D: Decoder>(
           };
         }
       }
+      output
+        .finish()
+        .map_err(|e| e.context("Unable to finish the output file"))?;
 
       if verbose != Verboseness::Quiet {
         if verbose == Verboseness::Verbose {
@@ -515,13 +528,6 @@ fn run() -> Result<(), error::CliError> {
     }
   }
 
-  cli.io.output.write_header(
-    video_info.width,
-    video_info.height,
-    cli.enc.time_base.den as usize,
-    cli.enc.time_base.num as usize,
-  );
-
   let tiling =
     cfg.tiling_info().map_err(|e| e.context("Invalid configuration"))?;
   if cli.verbose != Verboseness::Quiet {
@@ -565,11 +571,19 @@ fn run() -> Result<(), error::CliError> {
     };
   }
 
+  if cli.alpha.is_some() {
+    return Err(CliError::new(
+      "An alpha channel is not supported by rav1e-ch",
+    ));
+  }
+  cli.io.output.set_color_config(&cli.enc);
+
   let source = Source::new(cli.limit, y4m_dec);
 
   if video_info.bit_depth == 8 && !cli.force_highbitdepth {
     do_encode::<u8, y4m::Decoder<Box<dyn Read + Send>>>(
       cfg,
+      cli.enc.time_base,
       cli.verbose,
       progress,
       &mut *cli.io.output,
@@ -582,6 +596,7 @@ fn run() -> Result<(), error::CliError> {
   } else {
     do_encode::<u16, y4m::Decoder<Box<dyn Read + Send>>>(
       cfg,
+      cli.enc.time_base,
       cli.verbose,
       progress,
       &mut *cli.io.output,
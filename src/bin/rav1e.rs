@@ -170,11 +170,9 @@ where
   let pkt_wrapped = ctx.receive_packet();
   let (ret, emit_pass_data) = match pkt_wrapped {
     Ok(pkt) => {
-      output_file.write_frame(
-        pkt.input_frameno,
-        pkt.data.as_ref(),
-        pkt.frame_type,
-      );
+      output_file
+        .write_frame(pkt.pts, pkt.duration, pkt.data.as_ref(), pkt.frame_type)
+        .map_err(|e| e.context("Unable to write the output"))?;
       if let (Some(ref mut y4m_enc_uw), Some(ref rec)) =
         (y4m_enc.as_mut(), &pkt.rec)
       {
@@ -244,9 +242,9 @@ where
 }
 
 fn do_encode<T: Pixel, D: Decoder>(
-  cfg: Config, verbose: Verboseness, mut progress: ProgressInfo,
-  output: &mut dyn Muxer, mut source: Source<D>, mut pass1file: Option<File>,
-  mut pass2file: Option<File>,
+  cfg: Config, time_base: Rational, verbose: Verboseness,
+  mut progress: ProgressInfo, output: &mut dyn Muxer, mut source: Source<D>,
+  mut pass1file: Option<File>, mut pass2file: Option<File>,
   mut y4m_enc: Option<y4m::Encoder<Box<dyn Write + Send>>>,
   metrics_enabled: MetricsEnabled,
 ) -> Result<(), CliError>
@@ -262,6 +260,15 @@ where
   let mut ctx: Context<T> =
     cfg.new_context().map_err(|e| e.context("Invalid encoder settings"))?;
 
+  let video_info = source.input.get_video_details();
+  output.write_header(
+    video_info.width,
+    video_info.height,
+    time_base.den as usize,
+    time_base.num as usize,
+    &ctx.container_sequence_header(),
+  );
+
   // Let's write down a placeholder.
   if let Some(passfile) = pass1file.as_mut() {
     let len = ctx.rc_summary_size();
@@ -299,6 +306,9 @@ where
       output.flush().unwrap();
     }
   }
+  output
+    .finish()
+    .map_err(|e| e.context("Unable to finish the output file"))?;
   if verbose != Verboseness::Quiet {
     if verbose == Verboseness::Verbose {
       // Clear out the temporary progress indicator
@@ -309,6 +319,56 @@ where
   Ok(())
 }
 
+/// Encodes the luma plane of the first frame of `input` as a monochrome
+/// still picture, returning its sequence header and data.
+fn encode_alpha<T: Pixel>(
+  primary: &EncoderConfig, threads: usize,
+  mut input: y4m::Decoder<Box<dyn Read + Send>>,
+) -> Result<(Vec<u8>, Vec<u8>), CliError>
+where
+  u32: CastFromPrimitive<T::Coeff>,
+  i32: CastFromPrimitive<T::Coeff>,
+  T::Coeff: num_traits::AsPrimitive<u8>,
+  i32: CastFromPrimitive<T>,
+  u32: CastFromPrimitive<T>,
+  i16: CastFromPrimitive<T>,
+  i16: CastFromPrimitive<T::Coeff>,
+{
+  let mut video_info = input.get_video_details();
+  video_info.chroma_sampling = ChromaSampling::Cs400;
+  // Only the size, timing, speed and quantizer of the primary image carry
+  // over. Alpha is always full range, and has no color or grain.
+  let enc = EncoderConfig {
+    width: primary.width,
+    height: primary.height,
+    sample_aspect_ratio: primary.sample_aspect_ratio,
+    time_base: primary.time_base,
+    bit_depth: primary.bit_depth,
+    chroma_sampling: ChromaSampling::Cs400,
+    pixel_range: PixelRange::Full,
+    still_picture: primary.still_picture,
+    quantizer: primary.quantizer,
+    speed_settings: primary.speed_settings,
+    ..Default::default()
+  };
+
+  let cfg = Config::new().with_encoder_config(enc).with_threads(threads);
+  let mut ctx: Context<T> =
+    cfg.new_context().map_err(|e| e.context("Invalid alpha settings"))?;
+  let frame = Decoder::read_frame(&mut input, &ctx, &video_info)
+    .map_err(|_| CliError::new("Could not read the alpha input"))?;
+  let _ = ctx.send_frame(Some(Arc::new(frame)));
+  ctx.flush();
+
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => return Ok((ctx.container_sequence_header(), pkt.data)),
+      Err(EncoderStatus::Encoded) => {}
+      Err(e) => return Err(e.context("Unable to encode the alpha channel")),
+    }
+  }
+}
+
 fn main() {
   init_logger();
 
@@ -535,13 +595,6 @@ fn run() -> Result<(), error::CliError> {
     }
   }
 
-  cli.io.output.write_header(
-    video_info.width,
-    video_info.height,
-    cli.enc.time_base.den as usize,
-    cli.enc.time_base.num as usize,
-  );
-
   let tiling =
     cfg.tiling_info().map_err(|e| e.context("Invalid configuration"))?;
   if cli.verbose != Verboseness::Quiet {
@@ -585,11 +638,38 @@ fn run() -> Result<(), error::CliError> {
     };
   }
 
+  if let Some(alpha) = cli.alpha.as_ref() {
+    let alpha = File::open(alpha)
+      .map_err(|e| e.context("Cannot open the alpha input file"))?;
+    let alpha_dec = y4m::Decoder::new_with_limits(
+      Box::new(alpha) as Box<dyn Read + Send>,
+      limit,
+    )
+    .map_err(|_| CliError::new("Could not parse the alpha input"))?;
+    let alpha_info = alpha_dec.get_video_details();
+    if (alpha_info.width, alpha_info.height, alpha_info.bit_depth)
+      != (video_info.width, video_info.height, video_info.bit_depth)
+    {
+      return Err(CliError::new(
+        "The alpha input must match the size and bit depth of the input",
+      ));
+    }
+    let (sequence_header, data) =
+      if video_info.bit_depth == 8 && !cli.force_highbitdepth {
+        encode_alpha::<u8>(&cli.enc, cli.threads, alpha_dec)?
+      } else {
+        encode_alpha::<u16>(&cli.enc, cli.threads, alpha_dec)?
+      };
+    cli.io.output.write_alpha(&sequence_header, &data)?;
+  }
+  cli.io.output.set_color_config(&cli.enc);
+
   let source = Source::new(cli.limit, y4m_dec);
 
   if video_info.bit_depth == 8 && !cli.force_highbitdepth {
     do_encode::<u8, y4m::Decoder<Box<dyn Read + Send>>>(
       cfg,
+      cli.enc.time_base,
       cli.verbose,
       progress,
       &mut *cli.io.output,
@@ -602,6 +682,7 @@ fn run() -> Result<(), error::CliError> {
   } else {
     do_encode::<u16, y4m::Decoder<Box<dyn Read + Send>>>(
       cfg,
+      cli.enc.time_base,
       cli.verbose,
       progress,
       &mut *cli.io.output,
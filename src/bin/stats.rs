@@ -27,6 +27,8 @@ pub struct FrameSummary {
   pub metrics: QualityMetrics,
   /// QP selected for the frame.
   pub qp: u8,
+  /// Whether the frame was coded with the screen content tools.
+  pub screen_content: bool,
   /// Block-level encoding stats for the frame
   pub enc_stats: EncoderStats,
 }
@@ -51,6 +53,7 @@ pub fn build_frame_summary<T: Pixel>(
     frame_type: packets.frame_type,
     metrics: encode_metrics,
     qp: packets.qp,
+    screen_content: packets.screen_content,
     enc_stats: packets.enc_stats,
   }
 }
@@ -59,9 +62,10 @@ impl fmt::Display for FrameSummary {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(
       f,
-      "Input Frame {} - {} - {} bytes{}",
+      "Input Frame {} - {}{} - {} bytes{}",
       self.input_frameno,
       self.frame_type,
+      if self.screen_content { " (screen content)" } else { "" },
       self.size,
       if let Some(psnr) = self.metrics.psnr {
         format!(
@@ -326,6 +330,11 @@ impl ProgressInfo {
     self.print_frame_type_summary(FrameType::INTER);
     self.print_frame_type_summary(FrameType::INTRA_ONLY);
     self.print_frame_type_summary(FrameType::SWITCH);
+    let screen_content =
+      self.frame_info.iter().filter(|frame| frame.screen_content).count();
+    if screen_content > 0 {
+      info!("{:17} {:>6}", "Screen content:", screen_content);
+    }
     if verbose {
       self.print_block_type_summary();
       self.print_transform_type_summary();
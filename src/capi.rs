@@ -210,6 +210,7 @@ impl EncContext {
       frame_type_override: frame_type,
       opaque,
       t35_metadata,
+      ..Default::default()
     };
     if let Some(frame) = frame {
       match (self, frame) {
@@ -245,7 +246,14 @@ impl EncContext {
         });
         let p = std::mem::ManuallyDrop::into_inner(p);
         let rav1e::Packet {
-          data, rec, source, input_frameno, frame_type, ..
+          data,
+          rec,
+          source,
+          input_frameno,
+          frame_type,
+          pts,
+          duration,
+          ..
         } = p;
         let len = data.len();
         let data = Box::into_raw(data.into_boxed_slice()) as *const u8;
@@ -271,7 +279,17 @@ impl EncContext {
         } else {
           std::ptr::null_mut()
         };
-        Packet { data, rec, source, len, input_frameno, frame_type, opaque }
+        Packet {
+          data,
+          rec,
+          source,
+          len,
+          input_frameno,
+          frame_type,
+          opaque,
+          pts,
+          duration,
+        }
       })
     }
     match self {
@@ -384,6 +402,10 @@ pub struct Packet {
   /// The Reference Frame
   /// This is freed automatically by `rav1e_packet_unref()`.
   pub source: *mut Frame,
+  /// Presentation timestamp of the shown frame, in units of the time base
+  pub pts: u64,
+  /// Display duration of the shown frame, in units of the time base
+  pub duration: u64,
 }
 
 /// Version information as presented in `[package]` `version`.
@@ -688,9 +710,42 @@ unsafe fn option_match(
     "tile_cols" => enc.tile_cols = value.parse().map_err(|_| ())?,
 
     "tune" => enc.tune = value.parse().map_err(|_| ())?,
+    "screen_content" => enc.screen_content = value.parse().map_err(|_| ())?,
+    "superres_mode" => enc.superres_mode = value.parse().map_err(|_| ())?,
+    "superres_denom" => enc.superres_denom = value.parse().map_err(|_| ())?,
+    "superres_qthresh" => {
+      enc.superres_qthresh = value.parse().map_err(|_| ())?
+    }
+    "superblock_size" => {
+      enc.superblock_size = value.parse().map_err(|_| ())?
+    }
     "quantizer" => enc.quantizer = value.parse().map_err(|_| ())?,
+    "lossless" => enc.lossless = value.parse().map_err(|_| ())?,
     "min_quantizer" => enc.min_quantizer = value.parse().map_err(|_| ())?,
     "bitrate" => enc.bitrate = value.parse().map_err(|_| ())?,
+    "max_bitrate" => enc.max_bitrate = value.parse().map_err(|_| ())?,
+    "target_quality" => {
+      enc.target_quality = Some(value.parse().map_err(|_| ())?)
+    }
+    "luma_dc_delta_q" => {
+      enc.luma_dc_delta_q = value.parse().map_err(|_| ())?
+    }
+    "chroma_dc_delta_q_u" => {
+      enc.chroma_dc_delta_q[0] = value.parse().map_err(|_| ())?
+    }
+    "chroma_dc_delta_q_v" => {
+      enc.chroma_dc_delta_q[1] = value.parse().map_err(|_| ())?
+    }
+    "chroma_ac_delta_q_u" => {
+      enc.chroma_ac_delta_q[0] = value.parse().map_err(|_| ())?
+    }
+    "chroma_ac_delta_q_v" => {
+      enc.chroma_ac_delta_q[1] = value.parse().map_err(|_| ())?
+    }
+    "buffer_size" => enc.buffer_size = value.parse().map_err(|_| ())?,
+    "buffer_initial_fullness" => {
+      enc.buffer_initial_fullness = Some(value.parse().map_err(|_| ())?)
+    }
 
     "key_frame_interval" => {
       enc.set_key_frame_interval(
@@ -715,6 +770,7 @@ unsafe fn option_match(
         value.parse().map_err(|_| ())?
     }
     "low_latency" => enc.low_latency = value.parse().map_err(|_| ())?,
+    "pyramid_depth" => enc.pyramid_depth = value.parse().map_err(|_| ())?,
     "enable_timing_info" => {
       enc.enable_timing_info = value.parse().map_err(|_| ())?
     }
@@ -754,18 +810,32 @@ unsafe fn option_match(
 /// - `"speed"`: 0-10, default `6`
 /// - `"threads"`: maximum number of threads to be used, default auto
 /// - `"tune"`: `"psnr"` or `"psychovisual"`, default `"psychovisual"`
+/// - `"screen_content"`: `"off"`, `"on"` or `"auto"`, default `"off"`
+/// - `"superres_mode"`: `"off"`, `"fixed"`, `"random"` or `"dynamic"`, default `"off"`
+/// - `"superres_denom"`: 9-16, width denominator in fixed superres mode, default `12`
+/// - `"superres_qthresh"`: 0-255, quantizer threshold of dynamic superres mode, default `160`
+/// - `"superblock_size"`: `"auto"`, `"64"` or `"128"`, default `"auto"`
 /// - `"quantizer"`: 0-255, default `100`
+/// - `"lossless"`: flag for lossless mode, default `false`
 /// - `"tiles"`: total number of tiles desired (0 denotes auto), default `0`
 /// - `"tile_rows"`: number of tiles horizontally (must be a power of two, overridden by tiles if present), default `0`
 /// - `"tile_cols"`: number of tiles vertically (must be a power of two, overridden by tiles if present), default `0`
 /// - `"min_quantizer"`: minimum allowed base quantizer to use in bitrate mode, default `0`
 /// - `"bitrate"`: target bitrate for the bitrate mode (required for two pass mode), default `0`
+/// - `"max_bitrate"`: maximum bitrate for the capped quality mode, which keeps `"quantizer"` unless exceeding it, enforced by a reservoir of `"reservoir_frame_delay"` rather than a sliding window (non-negative, incompatible with `"bitrate"`), default `0` (disabled)
+/// - `"target_quality"`: SSIM between 0 and 1 that every frame should reach, with its quantizer searched starting from `"quantizer"` (incompatible with `"bitrate"` and `"max_bitrate"`), default `None`
+/// - `"luma_dc_delta_q"`: offset from -63 to 63 of the luma DC quantizer index (there is no luma AC offset, the luma AC quantizer index being the base one), default `0`
+/// - `"chroma_dc_delta_q_u"`, `"chroma_dc_delta_q_v"`: offsets from -63 to 63 of the Cb and Cr DC quantizer indices, default `0`
+/// - `"chroma_ac_delta_q_u"`, `"chroma_ac_delta_q_v"`: offsets from -63 to 63 of the Cb and Cr AC quantizer indices, default `0`
+/// - `"buffer_size"`: size in bits of the decoder buffer for the constant bitrate mode (requires a bitrate), default `0` (disabled)
+/// - `"buffer_initial_fullness"`: initial fullness in bits of the decoder buffer, default 90% of `"buffer_size"`
 /// - `"key_frame_interval"`: maximum interval between two keyframes, default `240`
 /// - `"min_key_frame_interval"`: minimum interval between two keyframes, default `12`
 /// - `"switch_frame_interval"`: interval between switch frames, default `0`
 /// - `"reservoir_frame_delay"`: number of temporal units over which to distribute the reservoir usage, default `None`
 /// - `"rdo_lookahead_frames"`: number of frames to read ahead for the RDO lookahead computation, default `40`
 /// - `"low_latency"`: flag to enable low latency mode, default `false`
+/// - `"pyramid_depth"`: 1-4, depth of the pyramid of reordered frames, default `2`
 /// - `"enable_timing_info"`: flag to enable signaling timing info in the bitstream, default `false`
 /// - `"still_picture"`: flag for still picture mode, default `false`
 /// - `"no_scene_detection"`: flag to disable scene detection, default `false`
@@ -1406,6 +1476,9 @@ mod test {
             1,
           );
           assert_eq!(source, vec![42; 64 * 64]);
+          // Frames without timestamps are shown at a constant frame rate.
+          assert_eq!((*p).pts, (*p).input_frameno);
+          assert!((*p).duration > 0);
           let v = Box::from_raw((*p).opaque as *mut u8);
           eprintln!("Opaque {}", v);
         }
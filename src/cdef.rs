@@ -439,12 +439,36 @@ pub fn cdef_filter_superblock<T: Pixel>(
   cdef_dirs: &CdefDirections,
 ) where
   i32: util::math::CastFromPrimitive<T>,
+{
+  cdef_filter_superblock_with_strengths(
+    fi,
+    input,
+    output,
+    blocks,
+    tile_sbo,
+    fi.cdef_y_strengths[cdef_index as usize],
+    fi.cdef_uv_strengths[cdef_index as usize],
+    cdef_dirs,
+  );
+}
+
+/// Like [`cdef_filter_superblock`], but with the luma and chroma
+/// strengths given directly rather than through an index into the
+/// strengths of the frame, so that the strengths can be searched.
+///
+/// # Panics
+///
+/// - If called with invalid parameters
+pub fn cdef_filter_superblock_with_strengths<T: Pixel>(
+  fi: &FrameInvariants<T>, input: &Frame<T>, output: &mut TileMut<'_, T>,
+  blocks: &TileBlocks<'_>, tile_sbo: TileSuperBlockOffset,
+  cdef_y_strength: u8, cdef_uv_strength: u8, cdef_dirs: &CdefDirections,
+) where
+  i32: util::math::CastFromPrimitive<T>,
 {
   let bit_depth = fi.sequence.bit_depth;
   let coeff_shift = fi.sequence.bit_depth as i32 - 8;
   let cdef_damping = fi.cdef_damping as i32;
-  let cdef_y_strength = fi.cdef_y_strengths[cdef_index as usize];
-  let cdef_uv_strength = fi.cdef_uv_strengths[cdef_index as usize];
   let cdef_pri_y_strength = (cdef_y_strength / CDEF_SEC_STRENGTHS) as i32;
   let mut cdef_sec_y_strength = (cdef_y_strength % CDEF_SEC_STRENGTHS) as i32;
   let cdef_pri_uv_strength = (cdef_uv_strength / CDEF_SEC_STRENGTHS) as i32;
@@ -647,7 +671,8 @@ pub fn cdef_filter_tile<T: Pixel>(
   i32: util::math::CastFromPrimitive<T>,
 {
   // Each filter block is 64x64, except right and/or bottom for non-multiple-of-64 sizes.
-  // FIXME: 128x128 SB support will break this, we need FilterBlockOffset etc.
+  // Superblock offsets count 64x64 units even with 128x128 superblocks, so
+  // they address filter blocks directly.
 
   // No need to guard against having fewer actual coded blocks than
   // the output.rect() area.  Inner code already guards this case.
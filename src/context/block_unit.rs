@@ -10,8 +10,17 @@
 use std::mem::MaybeUninit;
 
 use super::*;
-use crate::predict::PredictionMode;
+use crate::global_motion::{global_mv, lower_mv_precision};
+use crate::intrabc::INTRABC_DELAY_PIXELS;
+use crate::mc::{
+  find_affine, wedge_allowed, FilterMode, LEAST_SQUARES_SAMPLES_MAX,
+};
+use crate::palette::*;
+use crate::predict::{
+  CompoundPrediction, FilterIntraMode, MotionMode, PredictionMode,
+};
 use crate::util::{self, CastFromPrimitive};
+use itertools::Itertools;
 use v_frame::chroma::ChromaSubsampling;
 
 pub const MAX_PLANES: usize = 3;
@@ -84,7 +93,7 @@ impl BlockOffset {
 
   #[inline]
   const fn y_in_sb(self) -> usize {
-    self.y % MIB_SIZE
+    self.y % MAX_MIB_SIZE
   }
 
   #[inline]
@@ -169,6 +178,11 @@ pub struct Block {
   pub skip: bool,
   pub ref_frames: [RefType; 2],
   pub mv: [MotionVector; 2],
+  pub motion_mode: MotionMode,
+  pub compound: CompoundPrediction,
+  /// Vertical and horizontal interpolation filters, in the order they are
+  /// coded.
+  pub interp_filter: [FilterMode; 2],
   // note: indexes are reflist index, NOT the same as libaom
   pub neighbors_ref_counts: [u8; INTER_REFS_PER_FRAME],
   pub cdef_index: u8,
@@ -181,6 +195,8 @@ pub struct Block {
   // deltas
   pub deblock_deltas: [i8; FRAME_LF_COUNT],
   pub segmentation_idx: u8,
+  pub palette: PaletteParams,
+  pub filter_intra_mode: Option<FilterIntraMode>,
 }
 
 impl Block {
@@ -190,6 +206,11 @@ impl Block {
   pub fn has_second_ref(&self) -> bool {
     self.ref_frames[1] != INTRA_FRAME && self.ref_frames[1] != NONE_FRAME
   }
+  /// Whether the block is predicted by intra block copy, i.e. from the
+  /// current frame using a displacement vector.
+  pub fn is_intrabc(&self) -> bool {
+    self.is_inter() && self.ref_frames[0] == INTRA_FRAME
+  }
 }
 
 impl Default for Block {
@@ -200,6 +221,9 @@ impl Default for Block {
       skip: false,
       ref_frames: [INTRA_FRAME; 2],
       mv: [MotionVector::default(); 2],
+      motion_mode: MotionMode::SIMPLE_TRANSLATION,
+      compound: CompoundPrediction::Average,
+      interp_filter: [FilterMode::REGULAR; 2],
       neighbors_ref_counts: [0; INTER_REFS_PER_FRAME],
       cdef_index: 0,
       bsize: BLOCK_64X64,
@@ -208,35 +232,91 @@ impl Default for Block {
       txsize: TX_64X64,
       deblock_deltas: [0, 0, 0, 0],
       segmentation_idx: 0,
+      palette: PaletteParams::default(),
+      filter_intra_mode: None,
     }
   }
 }
 
+const PALETTE_NUM_NEIGHBORS: usize = 3;
+const PALETTE_COLOR_HASH_MULTIPLIERS: [u8; PALETTE_NUM_NEIGHBORS] = [1, 2, 2];
+const PALETTE_COLOR_CONTEXT: [usize; 9] = [0, 0, 0, 0, 0, 4, 3, 2, 1];
+
+/// Returns the context for coding the color index at row `r` and column `c`
+/// of a palette color map, and the order of the colors by their score in
+/// the already coded neighborhood.
+fn palette_color_context(
+  map: &[u8], stride: usize, r: usize, c: usize, n: usize,
+) -> (usize, [u8; PALETTE_MAX_SIZE]) {
+  let mut scores = [0u8; PALETTE_MAX_SIZE];
+  let mut order = [0, 1, 2, 3, 4, 5, 6, 7];
+  if c > 0 {
+    scores[map[r * stride + c - 1] as usize] += 2;
+  }
+  if r > 0 && c > 0 {
+    scores[map[(r - 1) * stride + c - 1] as usize] += 1;
+  }
+  if r > 0 {
+    scores[map[(r - 1) * stride + c] as usize] += 2;
+  }
+  // Move the highest scoring colors to the front, keeping the order stable.
+  for i in 0..PALETTE_NUM_NEIGHBORS {
+    let mut max_idx = i;
+    for j in (i + 1)..n {
+      if scores[j] > scores[max_idx] {
+        max_idx = j;
+      }
+    }
+    scores[i..=max_idx].rotate_right(1);
+    order[i..=max_idx].rotate_right(1);
+  }
+  let hash: u8 = scores
+    .iter()
+    .zip(PALETTE_COLOR_HASH_MULTIPLIERS)
+    .map(|(&s, m)| s * m)
+    .sum();
+  (PALETTE_COLOR_CONTEXT[hash as usize], order)
+}
+
 #[derive(Clone)]
 pub struct BlockContextCheckpoint {
   x: usize,
   chroma_sampling: ChromaSubsampling,
   cdef_coded: bool,
-  above_partition_context: [u8; MIB_SIZE >> 1],
+  cdef_units: ArrayVec<CdefUnit, 4>,
+  above_partition_context: [u8; MAX_MIB_SIZE >> 1],
   // left context is also at 8x8 granularity
-  left_partition_context: [u8; MIB_SIZE >> 1],
-  above_tx_context: [u8; MIB_SIZE],
-  left_tx_context: [u8; MIB_SIZE],
-  above_coeff_context: [[u8; MIB_SIZE]; MAX_PLANES],
-  left_coeff_context: [[u8; MIB_SIZE]; MAX_PLANES],
+  left_partition_context: [u8; MAX_MIB_SIZE >> 1],
+  above_tx_context: [u8; MAX_MIB_SIZE],
+  left_tx_context: [u8; MAX_MIB_SIZE],
+  above_coeff_context: [[u8; MAX_MIB_SIZE]; MAX_PLANES],
+  left_coeff_context: [[u8; MAX_MIB_SIZE]; MAX_PLANES],
+}
+
+/// A 64x64 unit of the superblock being coded that codes a CDEF index.
+#[derive(Clone)]
+pub struct CdefUnit {
+  pub sbo: TileSuperBlockOffset,
+  /// Position of the index in the symbols following the first CDEF index
+  /// of the superblock.  `None` for the first unit, whose index is coded
+  /// between the two halves of the superblock symbols.
+  pub position: Option<WriterCheckpoint>,
 }
 
 pub struct BlockContext<'a> {
   pub cdef_coded: bool,
+  /// Units of the current superblock with a coded CDEF index, in coding
+  /// order.  64x64 superblocks code at most one.
+  pub cdef_units: ArrayVec<CdefUnit, 4>,
   pub code_deltas: bool,
   pub update_seg: bool,
   pub preskip_segid: bool,
   pub above_partition_context: [u8; PARTITION_CONTEXT_MAX_WIDTH],
-  pub left_partition_context: [u8; MIB_SIZE >> 1],
+  pub left_partition_context: [u8; MAX_MIB_SIZE >> 1],
   pub above_tx_context: [u8; COEFF_CONTEXT_MAX_WIDTH],
-  pub left_tx_context: [u8; MIB_SIZE],
+  pub left_tx_context: [u8; MAX_MIB_SIZE],
   pub above_coeff_context: [[u8; COEFF_CONTEXT_MAX_WIDTH]; MAX_PLANES],
-  pub left_coeff_context: [[u8; MIB_SIZE]; MAX_PLANES],
+  pub left_coeff_context: [[u8; MAX_MIB_SIZE]; MAX_PLANES],
   pub blocks: &'a mut TileBlocksMut<'a>,
 }
 
@@ -244,19 +324,20 @@ impl<'a> BlockContext<'a> {
   pub fn new(blocks: &'a mut TileBlocksMut<'a>) -> Self {
     BlockContext {
       cdef_coded: false,
+      cdef_units: ArrayVec::new(),
       code_deltas: false,
       update_seg: false,
       preskip_segid: false,
       above_partition_context: [0; PARTITION_CONTEXT_MAX_WIDTH],
-      left_partition_context: [0; MIB_SIZE >> 1],
+      left_partition_context: [0; MAX_MIB_SIZE >> 1],
       above_tx_context: [0; COEFF_CONTEXT_MAX_WIDTH],
-      left_tx_context: [0; MIB_SIZE],
+      left_tx_context: [0; MAX_MIB_SIZE],
       above_coeff_context: [
         [0; COEFF_CONTEXT_MAX_WIDTH],
         [0; COEFF_CONTEXT_MAX_WIDTH],
         [0; COEFF_CONTEXT_MAX_WIDTH],
       ],
-      left_coeff_context: [[0; MIB_SIZE]; MAX_PLANES],
+      left_coeff_context: [[0; MAX_MIB_SIZE]; MAX_PLANES],
       blocks,
     }
   }
@@ -264,24 +345,25 @@ impl<'a> BlockContext<'a> {
   pub fn checkpoint(
     &self, tile_bo: &TileBlockOffset, chroma_sampling: ChromaSubsampling,
   ) -> BlockContextCheckpoint {
-    let x = tile_bo.0.x & (COEFF_CONTEXT_MAX_WIDTH - MIB_SIZE);
+    let x = tile_bo.0.x & (COEFF_CONTEXT_MAX_WIDTH - MAX_MIB_SIZE);
     let mut checkpoint = BlockContextCheckpoint {
       x,
       chroma_sampling,
       cdef_coded: self.cdef_coded,
-      above_partition_context: [0; MIB_SIZE >> 1],
+      cdef_units: self.cdef_units.clone(),
+      above_partition_context: [0; MAX_MIB_SIZE >> 1],
       left_partition_context: self.left_partition_context,
-      above_tx_context: [0; MIB_SIZE],
+      above_tx_context: [0; MAX_MIB_SIZE],
       left_tx_context: self.left_tx_context,
-      above_coeff_context: [[0; MIB_SIZE]; MAX_PLANES],
+      above_coeff_context: [[0; MAX_MIB_SIZE]; MAX_PLANES],
       left_coeff_context: self.left_coeff_context,
     };
     checkpoint.above_partition_context.copy_from_slice(
-      &self.above_partition_context[(x >> 1)..][..(MIB_SIZE >> 1)],
+      &self.above_partition_context[(x >> 1)..][..(MAX_MIB_SIZE >> 1)],
     );
     checkpoint
       .above_tx_context
-      .copy_from_slice(&self.above_tx_context[x..][..MIB_SIZE]);
+      .copy_from_slice(&self.above_tx_context[x..][..MAX_MIB_SIZE]);
     let num_planes =
       if chroma_sampling == ChromaSubsampling::Monochrome { 1 } else { 3 };
     for (p, (dst, src)) in checkpoint
@@ -293,18 +375,19 @@ impl<'a> BlockContext<'a> {
     {
       let xdec =
         (p > 0 && chroma_sampling != ChromaSubsampling::Yuv444) as usize;
-      dst.copy_from_slice(&src[(x >> xdec)..][..MIB_SIZE]);
+      dst.copy_from_slice(&src[(x >> xdec)..][..MAX_MIB_SIZE]);
     }
     checkpoint
   }
 
   pub fn rollback(&mut self, checkpoint: &BlockContextCheckpoint) {
-    let x = checkpoint.x & (COEFF_CONTEXT_MAX_WIDTH - MIB_SIZE);
+    let x = checkpoint.x & (COEFF_CONTEXT_MAX_WIDTH - MAX_MIB_SIZE);
     self.cdef_coded = checkpoint.cdef_coded;
-    self.above_partition_context[(x >> 1)..][..(MIB_SIZE >> 1)]
+    self.cdef_units.clone_from(&checkpoint.cdef_units);
+    self.above_partition_context[(x >> 1)..][..(MAX_MIB_SIZE >> 1)]
       .copy_from_slice(&checkpoint.above_partition_context);
     self.left_partition_context = checkpoint.left_partition_context;
-    self.above_tx_context[x..][..MIB_SIZE]
+    self.above_tx_context[x..][..MAX_MIB_SIZE]
       .copy_from_slice(&checkpoint.above_tx_context);
     self.left_tx_context = checkpoint.left_tx_context;
     let num_planes =
@@ -323,7 +406,7 @@ impl<'a> BlockContext<'a> {
       let xdec = (p > 0
         && checkpoint.chroma_sampling != ChromaSubsampling::Yuv444)
         as usize;
-      dst[(x >> xdec)..][..MIB_SIZE].copy_from_slice(src);
+      dst[(x >> xdec)..][..MAX_MIB_SIZE].copy_from_slice(src);
     }
     self.left_coeff_context = checkpoint.left_coeff_context;
   }
@@ -624,6 +707,19 @@ pub struct CandidateMV {
   pub weight: u32,
 }
 
+/// A neighbor above or left of a block, whose motion is extended over
+/// their shared edge with overlapped block motion compensation.
+#[derive(Clone, Copy, Debug)]
+pub struct ObmcNeighbor {
+  /// Start of the overlap along the edge, in 4x4 units.
+  pub offset: usize,
+  /// Length of the overlap along the edge, in 4x4 units.
+  pub len: usize,
+  pub ref_frame: RefType,
+  pub mv: MotionVector,
+  pub interp_filter: [FilterMode; 2],
+}
+
 #[derive(Clone)]
 pub struct FrameBlocks {
   blocks: Box<[Block]>,
@@ -684,47 +780,55 @@ impl IndexMut<PlaneBlockOffset> for FrameBlocks {
 }
 
 impl ContextWriter<'_> {
-  pub fn get_cdf_intra_mode_kf(
-    &self, bo: TileBlockOffset,
-  ) -> &[u16; INTRA_MODES] {
+  fn get_kf_y_mode_ctx(&self, bo: TileBlockOffset) -> (usize, usize) {
     static intra_mode_context: [usize; INTRA_MODES] =
       [0, 1, 2, 3, 4, 4, 4, 4, 3, 0, 1, 2, 0];
+    // Intra block copy counts as DC prediction for its neighbors.
+    let mode_of = |blk: &Block| {
+      if blk.is_intrabc() {
+        PredictionMode::DC_PRED
+      } else {
+        blk.mode
+      }
+    };
     let above_mode = if bo.0.y > 0 {
-      self.bc.blocks.above_of(bo).mode
+      mode_of(self.bc.blocks.above_of(bo))
     } else {
       PredictionMode::DC_PRED
     };
     let left_mode = if bo.0.x > 0 {
-      self.bc.blocks.left_of(bo).mode
+      mode_of(self.bc.blocks.left_of(bo))
     } else {
       PredictionMode::DC_PRED
     };
-    let above_ctx = intra_mode_context[above_mode as usize];
-    let left_ctx = intra_mode_context[left_mode as usize];
+    (
+      intra_mode_context[above_mode as usize],
+      intra_mode_context[left_mode as usize],
+    )
+  }
+
+  pub fn get_cdf_intra_mode_kf(
+    &self, bo: TileBlockOffset,
+  ) -> &[u16; INTRA_MODES] {
+    let (above_ctx, left_ctx) = self.get_kf_y_mode_ctx(bo);
     &self.fc.kf_y_cdf[above_ctx][left_ctx]
   }
 
   pub fn write_intra_mode_kf<W: Writer>(
     &mut self, w: &mut W, bo: TileBlockOffset, mode: PredictionMode,
   ) {
-    static intra_mode_context: [usize; INTRA_MODES] =
-      [0, 1, 2, 3, 4, 4, 4, 4, 3, 0, 1, 2, 0];
-    let above_mode = if bo.0.y > 0 {
-      self.bc.blocks.above_of(bo).mode
-    } else {
-      PredictionMode::DC_PRED
-    };
-    let left_mode = if bo.0.x > 0 {
-      self.bc.blocks.left_of(bo).mode
-    } else {
-      PredictionMode::DC_PRED
-    };
-    let above_ctx = intra_mode_context[above_mode as usize];
-    let left_ctx = intra_mode_context[left_mode as usize];
+    let (above_ctx, left_ctx) = self.get_kf_y_mode_ctx(bo);
     let cdf = &self.fc.kf_y_cdf[above_ctx][left_ctx];
     symbol_with_update!(self, w, mode as u32, cdf);
   }
 
+  pub fn write_use_intrabc<W: Writer>(
+    &mut self, w: &mut W, use_intrabc: bool,
+  ) {
+    let cdf = &self.fc.intrabc_cdf;
+    symbol_with_update!(self, w, use_intrabc as u32, cdf);
+  }
+
   pub fn get_cdf_intra_mode(&self, bsize: BlockSize) -> &[u16; INTRA_MODES] {
     &self.fc.y_mode_cdf[size_group_lookup[bsize as usize] as usize]
   }
@@ -740,9 +844,9 @@ impl ContextWriter<'_> {
   #[inline]
   pub fn write_intra_uv_mode<W: Writer>(
     &mut self, w: &mut W, uv_mode: PredictionMode, y_mode: PredictionMode,
-    bs: BlockSize,
+    cfl_allowed: bool,
   ) {
-    if bs.cfl_allowed() {
+    if cfl_allowed {
       let cdf = &self.fc.uv_mode_cfl_cdf[y_mode as usize];
       symbol_with_update!(self, w, uv_mode as u32, cdf);
     } else {
@@ -764,38 +868,238 @@ impl ContextWriter<'_> {
     );
   }
 
-  pub fn write_use_filter_intra<W: Writer>(
-    &mut self, w: &mut W, enable: bool, block_size: BlockSize,
+  pub fn write_filter_intra_mode_info<W: Writer>(
+    &mut self, w: &mut W, filter_intra_mode: Option<FilterIntraMode>,
+    block_size: BlockSize,
   ) {
     let cdf = &self.fc.filter_intra_cdfs[block_size as usize];
-    symbol_with_update!(self, w, enable as u32, cdf);
+    symbol_with_update!(self, w, filter_intra_mode.is_some() as u32, cdf);
+    if let Some(mode) = filter_intra_mode {
+      let cdf = &self.fc.filter_intra_mode_cdf;
+      symbol_with_update!(self, w, mode as u32, cdf);
+    }
   }
 
-  /// # Panics
-  ///
-  /// - If called with `enable: true` (not yet implemented
-  pub fn write_use_palette_mode<W: Writer>(
-    &mut self, w: &mut W, enable: bool, bsize: BlockSize, bo: TileBlockOffset,
-    luma_mode: PredictionMode, chroma_mode: PredictionMode, xdec: usize,
-    ydec: usize, cs: ChromaSubsampling,
+  pub fn write_palette_mode_info<W: Writer>(
+    &mut self, w: &mut W, palette: &PaletteParams, bsize: BlockSize,
+    bo: TileBlockOffset, luma_mode: PredictionMode,
+    chroma_mode: PredictionMode, xdec: usize, ydec: usize,
+    cs: ChromaSubsampling, bit_depth: usize,
   ) {
-    if enable {
-      unimplemented!(); // TODO
-    }
-
-    let (ctx_luma, ctx_chroma) = (0, 0); // TODO: increase based on surrounding block info
+    let bsize_ctx = bsize.width_mi_log2() + bsize.height_mi_log2() - 2;
 
     if luma_mode == PredictionMode::DC_PRED {
-      let bsize_ctx = bsize.width_mi_log2() + bsize.height_mi_log2() - 2;
+      let ctx_luma = (bo.0.y > 0
+        && self.bc.blocks.above_of(bo).palette.size[0] > 0)
+        as usize
+        + (bo.0.x > 0 && self.bc.blocks.left_of(bo).palette.size[0] > 0)
+          as usize;
+      let n = palette.size[0] as usize;
       let cdf = &self.fc.palette_y_mode_cdfs[bsize_ctx][ctx_luma];
-      symbol_with_update!(self, w, enable as u32, cdf);
+      symbol_with_update!(self, w, (n > 0) as u32, cdf);
+      if n > 0 {
+        let cdf = &self.fc.palette_y_size_cdf[bsize_ctx];
+        symbol_with_update!(self, w, (n - PALETTE_MIN_SIZE) as u32, cdf);
+        self.write_palette_colors(
+          w,
+          bo,
+          0,
+          &palette.colors[0][..n],
+          bit_depth,
+        );
+      }
+    } else {
+      debug_assert!(palette.size[0] == 0);
     }
 
     if has_chroma(bo, bsize, xdec, ydec, cs)
       && chroma_mode == PredictionMode::DC_PRED
     {
-      let cdf = &self.fc.palette_uv_mode_cdfs[ctx_chroma];
-      symbol_with_update!(self, w, enable as u32, cdf);
+      let n = palette.size[1] as usize;
+      let cdf = &self.fc.palette_uv_mode_cdfs[(palette.size[0] > 0) as usize];
+      symbol_with_update!(self, w, (n > 0) as u32, cdf);
+      if n > 0 {
+        let cdf = &self.fc.palette_uv_size_cdf[bsize_ctx];
+        symbol_with_update!(self, w, (n - PALETTE_MIN_SIZE) as u32, cdf);
+        self.write_palette_colors(
+          w,
+          bo,
+          1,
+          &palette.colors[1][..n],
+          bit_depth,
+        );
+        Self::write_palette_colors_v(w, &palette.colors[2][..n], bit_depth);
+      }
+    } else {
+      debug_assert!(palette.size[1] == 0);
+    }
+  }
+
+  /// Merges the sorted palette colors of the above and left blocks.
+  fn get_palette_cache(
+    &self, bo: TileBlockOffset, plane_type: usize,
+  ) -> ArrayVec<u16, { 2 * PALETTE_MAX_SIZE }> {
+    let mut cache = ArrayVec::<_, { 2 * PALETTE_MAX_SIZE }>::new();
+    // The above block is not used across 64x64 boundaries to avoid storing
+    // an extra line of palette colors.
+    if (bo.0.y * MI_SIZE) % 64 != 0 {
+      let palette = &self.bc.blocks.above_of(bo).palette;
+      let n = palette.size[plane_type] as usize;
+      cache.try_extend_from_slice(&palette.colors[plane_type][..n]).unwrap();
+    }
+    if bo.0.x > 0 {
+      let palette = &self.bc.blocks.left_of(bo).palette;
+      let n = palette.size[plane_type] as usize;
+      cache.try_extend_from_slice(&palette.colors[plane_type][..n]).unwrap();
+    }
+    cache.sort_unstable();
+    cache.into_iter().dedup().collect()
+  }
+
+  /// Writes the luma or U palette colors, in ascending order.
+  fn write_palette_colors<W: Writer>(
+    &mut self, w: &mut W, bo: TileBlockOffset, plane_type: usize,
+    colors: &[u16], bit_depth: usize,
+  ) {
+    let cache = self.get_palette_cache(bo, plane_type);
+
+    // Signal which cached colors are reused; the others are coded below.
+    let mut in_cache = [false; PALETTE_MAX_SIZE];
+    let mut n_in_cache = 0;
+    for &c in &cache {
+      if n_in_cache == colors.len() {
+        break;
+      }
+      let found = colors
+        .iter()
+        .zip(&in_cache)
+        .position(|(&color, &used)| color == c && !used);
+      w.bit(found.is_some() as u16);
+      if let Some(i) = found {
+        in_cache[i] = true;
+        n_in_cache += 1;
+      }
+    }
+    let rest: ArrayVec<u16, PALETTE_MAX_SIZE> = colors
+      .iter()
+      .zip(&in_cache)
+      .filter(|(_, &used)| !used)
+      .map(|(&c, _)| c)
+      .collect();
+
+    if rest.is_empty() {
+      return;
+    }
+    w.literal(bit_depth as u8, rest[0] as u32);
+    if rest.len() == 1 {
+      return;
+    }
+
+    // Luma colors are distinct, so their deltas are coded minus one.
+    let min_delta = (plane_type == 0) as usize;
+    let min_bits = bit_depth - 3;
+    let max_delta =
+      rest.windows(2).map(|c| (c[1] - c[0]) as usize).max().unwrap();
+    let mut bits = ILog::ilog(max_delta - min_delta).max(min_bits);
+    w.literal(2, (bits - min_bits) as u32);
+    let mut range = (1 << bit_depth) - rest[0] as usize - min_delta;
+    for c in rest.windows(2) {
+      let delta = (c[1] - c[0]) as usize;
+      w.literal(bits as u8, (delta - min_delta) as u32);
+      range -= delta;
+      bits = bits.min(ILog::ilog(range.max(1) - 1));
+    }
+  }
+
+  /// Writes the V palette colors, either delta coded or as raw values.
+  fn write_palette_colors_v<W: Writer>(
+    w: &mut W, colors: &[u16], bit_depth: usize,
+  ) {
+    let n = colors.len();
+    let max_val = 1 << bit_depth;
+    let min_bits = bit_depth - 4;
+    // Deltas wrap around, so code the shorter way around the range.
+    let deltas = || {
+      colors.windows(2).map(move |c| {
+        let delta = (c[1] as i32 - c[0] as i32).unsigned_abs() as usize;
+        (delta, c[1] < c[0])
+      })
+    };
+    let max_d =
+      deltas().map(|(d, _)| d.min(max_val - d)).max().unwrap_or_default();
+    let zero_count = deltas().filter(|&(d, _)| d == 0).count();
+    let bits = ILog::ilog(max_d).max(min_bits);
+    let rate_delta = 2 + bit_depth + (bits + 1) * (n - 1) - zero_count;
+    let rate_raw = bit_depth * n;
+
+    if rate_delta < rate_raw && bits - min_bits < 4 {
+      w.bit(1);
+      w.literal(2, (bits - min_bits) as u32);
+      w.literal(bit_depth as u8, colors[0] as u32);
+      for (delta, negative) in deltas() {
+        if delta == 0 {
+          w.literal(bits as u8, 0);
+        } else if delta <= max_val - delta {
+          w.literal(bits as u8, delta as u32);
+          w.bit(negative as u16);
+        } else {
+          w.literal(bits as u8, (max_val - delta) as u32);
+          w.bit(!negative as u16);
+        }
+      }
+    } else {
+      w.bit(0);
+      for &c in colors {
+        w.literal(bit_depth as u8, c as u32);
+      }
+    }
+  }
+
+  /// Writes the color index map of a block, for the visible `width`x`height`
+  /// area of a `stride`-wide map, in wavefront order.
+  ///
+  /// # Panics
+  ///
+  /// - If the map contains an index outside of the `n` palette colors.
+  pub fn write_palette_color_map<W: Writer>(
+    &mut self, w: &mut W, plane_type: usize, map: &[u8], stride: usize,
+    width: usize, height: usize, n: usize,
+  ) {
+    w.write_quniform(n as u32, map[0] as u32);
+    for i in 1..(width + height - 1) {
+      for j in ((i + 1).saturating_sub(height)..=i.min(width - 1)).rev() {
+        let (ctx, order) = palette_color_context(map, stride, i - j, j, n);
+        let color = map[(i - j) * stride + j];
+        let s = order[..n].iter().position(|&c| c == color).unwrap();
+        self.write_palette_color_index(w, plane_type, n, ctx, s as u32);
+      }
+    }
+  }
+
+  fn write_palette_color_index<W: Writer>(
+    &mut self, w: &mut W, plane_type: usize, n: usize, ctx: usize, s: u32,
+  ) {
+    let cdf = if plane_type == 0 {
+      &self.fc.palette_y_color_index_cdf[n - PALETTE_MIN_SIZE][ctx]
+    } else {
+      &self.fc.palette_uv_color_index_cdf[n - PALETTE_MIN_SIZE][ctx]
+    };
+    // Palettes of N colors use the first N entries of the CDF.
+    macro_rules! write_color_index {
+      ($n:literal) => {{
+        let cdf: &[u16; $n] = cdf[..$n].try_into().unwrap();
+        symbol_with_update!(self, w, s, cdf);
+      }};
+    }
+    match n {
+      2 => write_color_index!(2),
+      3 => write_color_index!(3),
+      4 => write_color_index!(4),
+      5 => write_color_index!(5),
+      6 => write_color_index!(6),
+      7 => write_color_index!(7),
+      8 => write_color_index!(8),
+      _ => unreachable!(),
     }
   }
 
@@ -857,11 +1161,25 @@ impl ContextWriter<'_> {
     false
   }
 
+  /// `warped_global_mvs` holds, for each reference whose global motion
+  /// warps, the global MV of the current block. It replaces the MV of the
+  /// candidates predicted with that global motion.
   fn add_ref_mv_candidate(
     ref_frames: [RefType; 2], blk: &Block,
     mv_stack: &mut ArrayVec<CandidateMV, 9>, weight: u32,
     newmv_count: &mut usize, is_compound: bool,
+    warped_global_mvs: [Option<MotionVector>; 2],
   ) -> bool {
+    let is_global_mv_block = matches!(
+      blk.mode,
+      PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV
+    ) && blk.bsize.width().min(blk.bsize.height())
+      >= 8;
+    let candidate_mv = |list: usize, i: usize| match warped_global_mvs[list] {
+      Some(mv) if is_global_mv_block => mv,
+      _ => blk.mv[i],
+    };
+
     if !blk.is_inter() {
       /* For intrabc */
       false
@@ -869,13 +1187,13 @@ impl ContextWriter<'_> {
       if blk.ref_frames[0] == ref_frames[0]
         && blk.ref_frames[1] == ref_frames[1]
       {
-        let found_match = Self::find_matching_comp_mv_and_update_weight(
-          blk.mv, mv_stack, weight,
-        );
+        let mvs = [candidate_mv(0, 0), candidate_mv(1, 1)];
+        let found_match =
+          Self::find_matching_comp_mv_and_update_weight(mvs, mv_stack, weight);
 
         if !found_match && mv_stack.len() < MAX_REF_MV_STACK_SIZE {
           let mv_cand =
-            CandidateMV { this_mv: blk.mv[0], comp_mv: blk.mv[1], weight };
+            CandidateMV { this_mv: mvs[0], comp_mv: mvs[1], weight };
 
           mv_stack.push(mv_cand);
         }
@@ -892,13 +1210,13 @@ impl ContextWriter<'_> {
       let mut found = false;
       for i in 0..2 {
         if blk.ref_frames[i] == ref_frames[0] {
-          let found_match = Self::find_matching_mv_and_update_weight(
-            blk.mv[i], mv_stack, weight,
-          );
+          let mv = candidate_mv(0, i);
+          let found_match =
+            Self::find_matching_mv_and_update_weight(mv, mv_stack, weight);
 
           if !found_match && mv_stack.len() < MAX_REF_MV_STACK_SIZE {
             let mv_cand = CandidateMV {
-              this_mv: blk.mv[i],
+              this_mv: mv,
               comp_mv: MotionVector::default(),
               weight,
             };
@@ -976,6 +1294,7 @@ impl ContextWriter<'_> {
     processed_rows: &mut isize, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, newmv_count: &mut usize,
     bsize: BlockSize, is_compound: bool,
+    warped_global_mvs: [Option<MotionVector>; 2],
   ) -> bool {
     let bc = &self.bc;
     let target_n4_w = bsize.width_mi();
@@ -1027,6 +1346,7 @@ impl ContextWriter<'_> {
         len as u32 * weight,
         newmv_count,
         is_compound,
+        warped_global_mvs,
       ) {
         found_match = true;
       }
@@ -1042,6 +1362,7 @@ impl ContextWriter<'_> {
     processed_cols: &mut isize, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, newmv_count: &mut usize,
     bsize: BlockSize, is_compound: bool,
+    warped_global_mvs: [Option<MotionVector>; 2],
   ) -> bool {
     let bc = &self.bc;
 
@@ -1093,6 +1414,7 @@ impl ContextWriter<'_> {
         len as u32 * weight,
         newmv_count,
         is_compound,
+        warped_global_mvs,
       ) {
         found_match = true;
       }
@@ -1106,7 +1428,7 @@ impl ContextWriter<'_> {
   fn scan_blk_mbmi(
     &self, bo: TileBlockOffset, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, newmv_count: &mut usize,
-    is_compound: bool,
+    is_compound: bool, warped_global_mvs: [Option<MotionVector>; 2],
   ) -> bool {
     if bo.0.x >= self.bc.blocks.cols() || bo.0.y >= self.bc.blocks.rows() {
       return false;
@@ -1121,6 +1443,7 @@ impl ContextWriter<'_> {
       weight,
       newmv_count,
       is_compound,
+      warped_global_mvs,
     )
   }
 
@@ -1138,6 +1461,15 @@ impl ContextWriter<'_> {
   ) -> usize {
     let (_rf, _rf_num) = (INTRA_FRAME, 1);
 
+    let global_mvs = self.find_global_mvs(bo, ref_frames, bsize, fi);
+    let warped_global_mvs = [0, 1].map(|i| {
+      (ref_frames[i] != INTRA_FRAME
+        && ref_frames[i] != NONE_FRAME
+        && fi.globalmv_transformation_type[ref_frames[i].to_index()]
+          > GlobalMVMode::TRANSLATION)
+        .then_some(global_mvs[i])
+    });
+
     let target_n4_h = bsize.height_mi();
     let target_n4_w = bsize.width_mi();
 
@@ -1194,6 +1526,7 @@ impl ContextWriter<'_> {
         &mut newmv_count,
         bsize,
         is_compound,
+        warped_global_mvs,
       );
       row_match |= found_match;
     }
@@ -1208,16 +1541,18 @@ impl ContextWriter<'_> {
         &mut newmv_count,
         bsize,
         is_compound,
+        warped_global_mvs,
       );
       col_match |= found_match;
     }
-    if has_tr(bo, bsize) && bo.0.y > 0 {
+    if has_tr(bo, bsize, fi.sb_size_log2()) && bo.0.y > 0 {
       let found_match = self.scan_blk_mbmi(
         bo.with_offset(target_n4_w as isize, -1),
         ref_frames,
         mv_stack,
         &mut newmv_count,
         is_compound,
+        warped_global_mvs,
       );
       row_match |= found_match;
     }
@@ -1237,6 +1572,7 @@ impl ContextWriter<'_> {
         mv_stack,
         &mut far_newmv_count,
         is_compound,
+        warped_global_mvs,
       );
     row_match |= found_match;
 
@@ -1257,6 +1593,7 @@ impl ContextWriter<'_> {
           &mut far_newmv_count,
           bsize,
           is_compound,
+          warped_global_mvs,
         );
         row_match |= found_match;
       }
@@ -1274,6 +1611,7 @@ impl ContextWriter<'_> {
           &mut far_newmv_count,
           bsize,
           is_compound,
+          warped_global_mvs,
         );
         col_match |= found_match;
       }
@@ -1337,7 +1675,8 @@ impl ContextWriter<'_> {
       }
 
       if is_compound {
-        let mut combined_mvs = [[MotionVector::default(); 2]; 2];
+        // Missing candidates are filled in with the global MVs.
+        let mut combined_mvs = [global_mvs; 2];
 
         for list in 0..2 {
           let mut comp_count = 0;
@@ -1389,6 +1728,13 @@ impl ContextWriter<'_> {
 
     /* TODO: Handle single reference frame extension */
 
+    // Candidates of frames with integer MVs must be whole pixels too, so
+    // that NEARESTMV and NEARMV never signal a subpixel vector.
+    for cand in mv_stack.iter_mut() {
+      cand.this_mv = lower_mv_precision(fi, cand.this_mv);
+      cand.comp_mv = lower_mv_precision(fi, cand.comp_mv);
+    }
+
     let frame_bo = PlaneBlockOffset(BlockOffset {
       x: self.bc.blocks.x() + bo.0.x,
       y: self.bc.blocks.y() + bo.0.y,
@@ -1424,6 +1770,20 @@ impl ContextWriter<'_> {
     mode_context
   }
 
+  /// Returns the MVs given to a block by the global motion of `ref_frames`.
+  /// They stand in for the candidates missing from a stack of fewer than
+  /// two.
+  pub fn find_global_mvs<T: Pixel>(
+    &self, bo: TileBlockOffset, ref_frames: [RefType; 2], bsize: BlockSize,
+    fi: &FrameInvariants<T>,
+  ) -> [MotionVector; 2] {
+    let frame_bo = PlaneBlockOffset(BlockOffset {
+      x: self.bc.blocks.x() + bo.0.x,
+      y: self.bc.blocks.y() + bo.0.y,
+    });
+    ref_frames.map(|r| global_mv(fi, r, frame_bo, bsize))
+  }
+
   /// # Panics
   ///
   /// - If the first ref frame is not set (`NONE_FRAME`)
@@ -1433,21 +1793,292 @@ impl ContextWriter<'_> {
     fi: &FrameInvariants<T>, is_compound: bool,
   ) -> usize {
     assert!(ref_frames[0] != NONE_FRAME);
-    if ref_frames[0] != NONE_FRAME {
-      // TODO: If ref_frames[0] != INTRA_FRAME, convert global mv to an mv;
-      // otherwise, set the global mv ref to invalid.
-    }
-
-    if ref_frames[0] != INTRA_FRAME {
-      /* TODO: Set zeromv ref to the converted global motion vector */
-    } else {
-      /* TODO: Set the zeromv ref to 0 */
+    if ref_frames[0] == INTRA_FRAME {
       return 0;
     }
 
     self.setup_mvref_list(bo, ref_frames, mv_stack, bsize, fi, is_compound)
   }
 
+  /// Whether a block above or left of the block is inter predicted, which
+  /// allows the block to select a motion mode.
+  fn has_overlappable_candidates(
+    &self, bo: TileBlockOffset, bsize: BlockSize,
+  ) -> bool {
+    let blocks = &self.bc.blocks;
+    let (cols, rows) = (blocks.cols(), blocks.rows());
+    let above = (bo.0.y > 0).then(|| {
+      (bo.0.x..cols.min(bo.0.x + bsize.width_mi()))
+        .step_by(2)
+        .map(|x| blocks[bo.0.y - 1][(x | 1).min(cols - 1)])
+    });
+    let left = (bo.0.x > 0).then(|| {
+      (bo.0.y..rows.min(bo.0.y + bsize.height_mi()))
+        .step_by(2)
+        .map(|y| blocks[(y | 1).min(rows - 1)][bo.0.x - 1])
+    });
+    above
+      .into_iter()
+      .flatten()
+      .chain(left.into_iter().flatten())
+      .any(|block| block.ref_frames[0] > INTRA_FRAME)
+  }
+
+  /// Returns the inter predicted neighbors above and left of the block,
+  /// whose predictions are blended into its own with OBMC.
+  pub fn obmc_neighbors(
+    &self, bo: TileBlockOffset, bsize: BlockSize,
+  ) -> (ArrayVec<ObmcNeighbor, 4>, ArrayVec<ObmcNeighbor, 4>) {
+    let blocks = &self.bc.blocks;
+    let (cols, rows) = (blocks.cols(), blocks.rows());
+    let (w4, h4) = (bsize.width_mi(), bsize.height_mi());
+
+    let mut above = ArrayVec::new();
+    if bo.0.y > 0 {
+      let limit = bsize.width_mi_log2().min(4);
+      let mut x4 = 0;
+      while above.len() < limit && x4 < w4.min(cols - bo.0.x) {
+        let cand = &blocks[bo.0.y - 1][((bo.0.x + x4) | 1).min(cols - 1)];
+        let step4 = cand.bsize.width_mi().clamp(2, 16);
+        if cand.ref_frames[0] > INTRA_FRAME {
+          above.push(ObmcNeighbor {
+            offset: x4,
+            len: w4.min(step4),
+            ref_frame: cand.ref_frames[0],
+            mv: cand.mv[0],
+            interp_filter: cand.interp_filter,
+          });
+        }
+        x4 += step4;
+      }
+    }
+
+    let mut left = ArrayVec::new();
+    if bo.0.x > 0 {
+      let limit = bsize.height_mi_log2().min(4);
+      let mut y4 = 0;
+      while left.len() < limit && y4 < h4.min(rows - bo.0.y) {
+        let cand = &blocks[((bo.0.y + y4) | 1).min(rows - 1)][bo.0.x - 1];
+        let step4 = cand.bsize.height_mi().clamp(2, 16);
+        if cand.ref_frames[0] > INTRA_FRAME {
+          left.push(ObmcNeighbor {
+            offset: y4,
+            len: h4.min(step4),
+            ref_frame: cand.ref_frames[0],
+            mv: cand.mv[0],
+            interp_filter: cand.interp_filter,
+          });
+        }
+        y4 += step4;
+      }
+    }
+
+    (above, left)
+  }
+
+  /// Collects the motion of the neighbors of the block that are predicted
+  /// from `ref_frame` alone, from which its local warp is estimated with
+  /// [`find_affine`]. A sample is kept only if its motion vector is close
+  /// to `mv`, but the first one scanned is returned if none is.
+  pub fn find_warp_samples<T: Pixel>(
+    &self, bo: TileBlockOffset, bsize: BlockSize, ref_frame: RefType,
+    mv: MotionVector, fi: &FrameInvariants<T>,
+  ) -> ArrayVec<[i32; 4], LEAST_SQUARES_SAMPLES_MAX> {
+    let blocks = &self.bc.blocks;
+    let (cols, rows) = (blocks.cols(), blocks.rows());
+    let (w4, h4) = (bsize.width_mi(), bsize.height_mi());
+    let threshold = bsize.width().max(bsize.height()).clamp(16, 112) as i32;
+
+    let mut samples = ArrayVec::new();
+    let mut first = None;
+    let mut scanned = 0;
+    let mut add_sample = |x: usize, y: usize| {
+      if scanned >= LEAST_SQUARES_SAMPLES_MAX || x >= cols || y >= rows {
+        return;
+      }
+      let cand = &blocks[y][x];
+      if cand.ref_frames[0] != ref_frame
+        || cand.ref_frames[1] != NONE_FRAME
+        || cand.compound.is_interintra()
+      {
+        return;
+      }
+      // The sample is taken at the center of the candidate block, which
+      // is aligned to its size within the tile as within the frame.
+      let cand_y = blocks.y() + (y & !(cand.bsize.height_mi() - 1));
+      let cand_x = blocks.x() + (x & !(cand.bsize.width_mi() - 1));
+      let mid_y = (cand_y * MI_SIZE + cand.bsize.height() / 2) as i32 - 1;
+      let mid_x = (cand_x * MI_SIZE + cand.bsize.width() / 2) as i32 - 1;
+      let cand_mv = cand.mv[0];
+      let sample = [
+        mid_y * 8,
+        mid_x * 8,
+        mid_y * 8 + cand_mv.row as i32,
+        mid_x * 8 + cand_mv.col as i32,
+      ];
+      scanned += 1;
+      let diff_row = (cand_mv.row as i32 - mv.row as i32).abs();
+      let diff_col = (cand_mv.col as i32 - mv.col as i32).abs();
+      if diff_row + diff_col <= threshold {
+        samples.push(sample);
+      } else if scanned == 1 {
+        first = Some(sample);
+      }
+    };
+
+    let mut do_top_left = true;
+    let mut do_top_right = true;
+    if bo.0.y > 0 {
+      let src_w = blocks[bo.0.y - 1][bo.0.x].bsize.width_mi();
+      if w4 <= src_w {
+        let col_offset = bo.0.x & (src_w - 1);
+        do_top_left &= col_offset == 0;
+        do_top_right &= src_w <= w4 + col_offset;
+        add_sample(bo.0.x, bo.0.y - 1);
+      } else {
+        let mut i = 0;
+        while i < w4.min(cols - bo.0.x) {
+          let src_w = blocks[bo.0.y - 1][bo.0.x + i].bsize.width_mi();
+          add_sample(bo.0.x + i, bo.0.y - 1);
+          i += src_w;
+        }
+      }
+    }
+    if bo.0.x > 0 {
+      let src_h = blocks[bo.0.y][bo.0.x - 1].bsize.height_mi();
+      if h4 <= src_h {
+        do_top_left &= bo.0.y & (src_h - 1) == 0;
+        add_sample(bo.0.x - 1, bo.0.y);
+      } else {
+        let mut i = 0;
+        while i < h4.min(rows - bo.0.y) {
+          let src_h = blocks[bo.0.y + i][bo.0.x - 1].bsize.height_mi();
+          add_sample(bo.0.x - 1, bo.0.y + i);
+          i += src_h;
+        }
+      }
+    }
+    if do_top_left && bo.0.x > 0 && bo.0.y > 0 {
+      add_sample(bo.0.x - 1, bo.0.y - 1);
+    }
+    if do_top_right
+      && w4.max(h4) <= BLOCK_64X64.width_mi()
+      && bo.0.y > 0
+      && has_tr(bo, bsize, fi.sb_size_log2())
+    {
+      add_sample(bo.0.x + w4, bo.0.y - 1);
+    }
+
+    if samples.is_empty() {
+      samples.extend(first);
+    }
+    samples
+  }
+
+  /// Returns the motion modes that can be signaled for a single reference
+  /// block predicted with `mode` and `mv`. A single mode is not coded, and
+  /// the choice between two is coded as a `use_obmc` flag.
+  pub fn motion_modes<T: Pixel>(
+    &self, fi: &FrameInvariants<T>, bo: TileBlockOffset, bsize: BlockSize,
+    mode: PredictionMode, ref_frames: [RefType; 2], mv: MotionVector,
+  ) -> ArrayVec<MotionMode, 3> {
+    let mut modes = ArrayVec::new();
+    modes.push(MotionMode::SIMPLE_TRANSLATION);
+    let global_warp = fi.force_integer_mv == 0
+      && (mode == PredictionMode::GLOBALMV
+        || mode == PredictionMode::GLOBAL_GLOBALMV)
+      && fi.globalmv_transformation_type[ref_frames[0].to_index()]
+        > GlobalMVMode::TRANSLATION;
+    if !fi.is_motion_mode_switchable
+      || bsize.width().min(bsize.height()) < 8
+      || global_warp
+      || ref_frames[1] != NONE_FRAME
+      || !self.has_overlappable_candidates(bo, bsize)
+    {
+      return modes;
+    }
+    modes.push(MotionMode::OBMC_CAUSAL);
+    if fi.force_integer_mv == 0
+      && fi.allow_warped_motion
+      && !fi.is_scaled(ref_frames[0])
+      && !self.find_warp_samples(bo, bsize, ref_frames[0], mv, fi).is_empty()
+    {
+      modes.push(MotionMode::WARPED_CAUSAL);
+    }
+    modes
+  }
+
+  /// Returns whether the interpolation filters of an inter block are coded
+  /// in a frame with switchable filters (`needs_interp_filter` in the AV1
+  /// specification). They are not used by warped blocks.
+  pub fn needs_interp_filter<T: Pixel>(
+    fi: &FrameInvariants<T>, bsize: BlockSize, mode: PredictionMode,
+    ref_frames: [RefType; 2], motion_mode: MotionMode,
+  ) -> bool {
+    if motion_mode == MotionMode::WARPED_CAUSAL {
+      return false;
+    }
+    let global_warp = bsize.width().min(bsize.height()) >= 8
+      && (mode == PredictionMode::GLOBALMV
+        || mode == PredictionMode::GLOBAL_GLOBALMV)
+      && ref_frames.iter().filter(|&&r| r != NONE_FRAME).all(|r| {
+        fi.globalmv_transformation_type[r.to_index()]
+          != GlobalMVMode::TRANSLATION
+      });
+    !global_warp
+  }
+
+  /// Estimates the local warp of a block predicted from `ref_frame` with
+  /// `mv` from the motion of its neighbors, or returns `None` if the
+  /// resulting model cannot be used.
+  pub fn find_local_warp<T: Pixel>(
+    &self, bo: TileBlockOffset, bsize: BlockSize, ref_frame: RefType,
+    mv: MotionVector, fi: &FrameInvariants<T>,
+  ) -> Option<[i32; 6]> {
+    let samples = self.find_warp_samples(bo, bsize, ref_frame, mv, fi);
+    let frame_bo = PlaneBlockOffset(BlockOffset {
+      x: self.bc.blocks.x() + bo.0.x,
+      y: self.bc.blocks.y() + bo.0.y,
+    });
+    find_affine(&samples, frame_bo, bsize, mv)
+  }
+
+  /// Returns the predicted displacement vector of an intra block copy block,
+  /// from which its displacement vector is coded.
+  pub fn find_ref_dv<T: Pixel>(
+    &self, bo: TileBlockOffset, bsize: BlockSize, fi: &FrameInvariants<T>,
+  ) -> MotionVector {
+    let mut mv_stack = ArrayVec::new();
+    self.setup_mvref_list(
+      bo,
+      [INTRA_FRAME, NONE_FRAME],
+      &mut mv_stack,
+      bsize,
+      fi,
+      false,
+    );
+    if let Some(cand) = mv_stack
+      .iter()
+      .take(2)
+      .find(|cand| cand.this_mv != MotionVector::default())
+    {
+      return cand.this_mv;
+    }
+
+    // Without a neighboring vector, point to the previous superblock row
+    // or, in the first one, far enough to the left.
+    let sb_size =
+      if fi.sequence.use_128x128_superblock { 128 } else { 64 } as i16;
+    if bo.0.y * MI_SIZE < sb_size as usize {
+      MotionVector {
+        row: 0,
+        col: -(sb_size + INTRABC_DELAY_PIXELS as i16) * 8,
+      }
+    } else {
+      MotionVector { row: -sb_size * 8, col: 0 }
+    }
+  }
+
   pub fn fill_neighbours_ref_counts(&mut self, bo: TileBlockOffset) {
     let mut ref_counts = [0; INTER_REFS_PER_FRAME];
 
@@ -1726,12 +2357,192 @@ impl ContextWriter<'_> {
     symbol_with_update!(self, w, drl_mode as u32, cdf);
   }
 
+  /// Writes the motion mode of a block, out of the `modes` it can select
+  /// as returned by [`motion_modes`](Self::motion_modes).
+  pub fn write_motion_mode<W: Writer>(
+    &mut self, w: &mut W, motion_mode: MotionMode, modes: &[MotionMode],
+    bsize: BlockSize,
+  ) {
+    debug_assert!(modes.contains(&motion_mode));
+    match modes.len() {
+      1 => {}
+      2 => {
+        let cdf = &self.fc.obmc_cdf[bsize as usize];
+        symbol_with_update!(
+          self,
+          w,
+          (motion_mode == MotionMode::OBMC_CAUSAL) as u32,
+          cdf
+        );
+      }
+      _ => {
+        let cdf = &self.fc.motion_mode_cdf[bsize as usize];
+        symbol_with_update!(self, w, motion_mode as u32, cdf);
+      }
+    }
+  }
+
+  /// Writes whether a single reference block is blended with an intra
+  /// prediction, and how.
+  pub fn write_interintra_mode<W: Writer>(
+    &mut self, w: &mut W, compound: CompoundPrediction, bsize: BlockSize,
+  ) {
+    debug_assert!(bsize.interintra_allowed());
+    let ctx = size_group_lookup[bsize as usize] as usize;
+    let cdf = &self.fc.interintra_cdf[ctx];
+    symbol_with_update!(self, w, compound.is_interintra() as u32, cdf);
+    if let CompoundPrediction::InterIntra { mode, wedge_index } = compound {
+      let cdf = &self.fc.interintra_mode_cdf[ctx];
+      symbol_with_update!(self, w, mode as u32, cdf);
+      let cdf = &self.fc.wedge_interintra_cdf[bsize as usize];
+      symbol_with_update!(self, w, wedge_index.is_some() as u32, cdf);
+      if let Some(index) = wedge_index {
+        let cdf = &self.fc.wedge_idx_cdf[bsize as usize];
+        symbol_with_update!(self, w, index as u32, cdf);
+      }
+    }
+  }
+
+  fn get_comp_group_idx_ctx(&self, bo: TileBlockOffset) -> usize {
+    let neighbor_ctx = |block: &Block| {
+      if block.has_second_ref() {
+        block.compound.comp_group_idx()
+      } else if block.ref_frames[0] == ALTREF_FRAME {
+        3
+      } else {
+        0
+      }
+    };
+    let above = if bo.0.y > 0 {
+      neighbor_ctx(&self.bc.blocks[bo.with_offset(0, -1)])
+    } else {
+      0
+    };
+    let left = if bo.0.x > 0 {
+      neighbor_ctx(&self.bc.blocks[bo.with_offset(-1, 0)])
+    } else {
+      0
+    };
+    (above + left).min(5)
+  }
+
+  fn get_compound_idx_ctx<T: Pixel>(
+    &self, fi: &FrameInvariants<T>, bo: TileBlockOffset,
+  ) -> usize {
+    let ref_frames = self.bc.blocks[bo].ref_frames;
+    let offset = (fi.ref_frame_distance(ref_frames[0])
+      == fi.ref_frame_distance(ref_frames[1])) as usize;
+    let neighbor_ctx = |block: &Block| {
+      if block.has_second_ref() {
+        block.compound.compound_idx()
+      } else {
+        (block.ref_frames[0] == ALTREF_FRAME) as usize
+      }
+    };
+    let above = if bo.0.y > 0 {
+      neighbor_ctx(&self.bc.blocks[bo.with_offset(0, -1)])
+    } else {
+      0
+    };
+    let left = if bo.0.x > 0 {
+      neighbor_ctx(&self.bc.blocks[bo.with_offset(-1, 0)])
+    } else {
+      0
+    };
+    above + left + 3 * offset
+  }
+
+  /// Writes how the predictions of the two references of a compound block
+  /// are combined.
+  pub fn write_compound_type<T: Pixel, W: Writer>(
+    &mut self, w: &mut W, fi: &FrameInvariants<T>,
+    compound: CompoundPrediction, bo: TileBlockOffset, bsize: BlockSize,
+  ) {
+    debug_assert!(!compound.is_interintra());
+    if fi.sequence.enable_masked_compound {
+      let ctx = self.get_comp_group_idx_ctx(bo);
+      let cdf = &self.fc.comp_group_idx_cdf[ctx];
+      symbol_with_update!(self, w, compound.comp_group_idx() as u32, cdf);
+    }
+    if compound.comp_group_idx() == 0 {
+      if fi.sequence.enable_jnt_comp {
+        let ctx = self.get_compound_idx_ctx(fi, bo);
+        let cdf = &self.fc.compound_idx_cdf[ctx];
+        symbol_with_update!(self, w, compound.compound_idx() as u32, cdf);
+      }
+      return;
+    }
+    if wedge_allowed(bsize) {
+      let cdf = &self.fc.compound_type_cdf[bsize as usize];
+      let compound_type = compound.compound_type() as u32;
+      symbol_with_update!(self, w, compound_type - 1, cdf);
+    }
+    match compound {
+      CompoundPrediction::Wedge { index, sign } => {
+        let cdf = &self.fc.wedge_idx_cdf[bsize as usize];
+        symbol_with_update!(self, w, index as u32, cdf);
+        w.bit(sign as u16);
+      }
+      CompoundPrediction::DiffWeighted { inverse } => w.bit(inverse as u16),
+      _ => unreachable!(),
+    }
+  }
+
+  fn get_interp_filter_ctx(&self, bo: TileBlockOffset, dir: usize) -> usize {
+    let ref_frames = self.bc.blocks[bo].ref_frames;
+    let ctx = (dir * 2 + (ref_frames[1] != NONE_FRAME) as usize) * 4;
+    // Neighbors not predicted from the first reference of the block count
+    // as a fourth filter type.
+    let neighbor_type = |block: &Block| {
+      if block.ref_frames[0] == ref_frames[0]
+        || block.ref_frames[1] == ref_frames[0]
+      {
+        block.interp_filter[dir] as usize
+      } else {
+        3
+      }
+    };
+    let above = if bo.0.y > 0 {
+      neighbor_type(&self.bc.blocks[bo.with_offset(0, -1)])
+    } else {
+      3
+    };
+    let left = if bo.0.x > 0 {
+      neighbor_type(&self.bc.blocks[bo.with_offset(-1, 0)])
+    } else {
+      3
+    };
+    ctx
+      + if left == above || above == 3 {
+        left
+      } else if left == 3 {
+        above
+      } else {
+        3
+      }
+  }
+
+  /// Writes the interpolation filters of an inter block, in a frame with
+  /// switchable filters. Only the first one is coded without dual filters.
+  pub fn write_interp_filter<T: Pixel, W: Writer>(
+    &mut self, w: &mut W, fi: &FrameInvariants<T>, bo: TileBlockOffset,
+    interp_filter: [FilterMode; 2],
+  ) {
+    debug_assert!(fi.is_filter_switchable);
+    let dirs = if fi.sequence.enable_dual_filter { 2 } else { 1 };
+    for (dir, &filter) in interp_filter.iter().enumerate().take(dirs) {
+      let ctx = self.get_interp_filter_ctx(bo, dir);
+      let cdf = &self.fc.switchable_interp_cdf[ctx];
+      symbol_with_update!(self, w, filter as u32, cdf);
+    }
+  }
+
   /// # Panics
   ///
   /// - If the MV is invalid
   pub fn write_mv<W: Writer>(
     &mut self, w: &mut W, mv: MotionVector, ref_mv: MotionVector,
-    mv_precision: MvSubpelPrecision,
+    mv_precision: MvSubpelPrecision, ctx: usize,
   ) {
     // <https://aomediacodec.github.io/av1-spec/#assign-mv-semantics>
     assert!(mv.is_valid());
@@ -1740,14 +2551,14 @@ impl ContextWriter<'_> {
       MotionVector { row: mv.row - ref_mv.row, col: mv.col - ref_mv.col };
     let j: MvJointType = av1_get_mv_joint(diff);
 
-    let cdf = &self.fc.nmv_context.joints_cdf;
+    let cdf = &self.fc.nmv_context[ctx].joints_cdf;
     symbol_with_update!(self, w, j as u32, cdf);
 
     if mv_joint_vertical(j) {
-      self.encode_mv_component(w, diff.row as i32, 0, mv_precision);
+      self.encode_mv_component(w, diff.row as i32, ctx, 0, mv_precision);
     }
     if mv_joint_horizontal(j) {
-      self.encode_mv_component(w, diff.col as i32, 1, mv_precision);
+      self.encode_mv_component(w, diff.col as i32, ctx, 1, mv_precision);
     }
   }
 
@@ -1794,7 +2605,7 @@ impl ContextWriter<'_> {
     &mut self, w: &mut W, plane: usize, bo: TileBlockOffset, coeffs_in: &[T],
     eob: u16, pred_mode: PredictionMode, tx_size: TxSize, tx_type: TxType,
     plane_bsize: BlockSize, xdec: usize, ydec: usize,
-    use_reduced_tx_set: bool, frame_clipped_txw: usize,
+    use_reduced_tx_set: bool, lossless: bool, frame_clipped_txw: usize,
     frame_clipped_txh: usize,
   ) -> bool
   where
@@ -1850,8 +2661,8 @@ impl ContextWriter<'_> {
     let tx_class = tx_type_to_class[tx_type as usize];
     let plane_type = usize::from(plane != 0);
 
-    // Signal tx_type for luma plane only
-    if plane == 0 {
+    // Signal tx_type for luma plane only, lossless blocks always use WHT_WHT
+    if plane == 0 && !lossless {
       self.write_tx_type(
         w,
         tx_size,
@@ -2035,3 +2846,135 @@ impl ContextWriter<'_> {
     new_cul_level
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::mc::FilterMode::*;
+
+  fn neighbor(
+    ref_frames: [RefType; 2], interp_filter: [FilterMode; 2],
+  ) -> Block {
+    Block { ref_frames, interp_filter, ..Default::default() }
+  }
+
+  /// Returns the interpolation filter context of the bottom right block of
+  /// a 2x2 grid, given its references and its above and left neighbors.
+  fn interp_filter_ctx(
+    dir: usize, ref_frames: [RefType; 2], above: Block, left: Block,
+  ) -> usize {
+    let mut fb = FrameBlocks::new(2, 2);
+    fb[0][1] = above;
+    fb[1][0] = left;
+    fb[1][1].ref_frames = ref_frames;
+    let mut tb = fb.as_tile_blocks_mut();
+    let mut fc = CDFContext::new(0);
+    let cw = ContextWriter::new(&mut fc, BlockContext::new(&mut tb));
+    cw.get_interp_filter_ctx(TileBlockOffset(BlockOffset { x: 1, y: 1 }), dir)
+  }
+
+  #[test]
+  fn interp_filter_ctx_neighbors() {
+    const SINGLE: [RefType; 2] = [LAST_FRAME, NONE_FRAME];
+    const COMPOUND: [RefType; 2] = [LAST_FRAME, ALTREF_FRAME];
+    const OTHER: [RefType; 2] = [GOLDEN_FRAME, NONE_FRAME];
+    let cases = [
+      // Matching neighbors give their filter.
+      (
+        0,
+        SINGLE,
+        neighbor(SINGLE, [SMOOTH, SHARP]),
+        neighbor(SINGLE, [SMOOTH, REGULAR]),
+        1,
+      ),
+      (
+        1,
+        SINGLE,
+        neighbor(SINGLE, [SMOOTH, SHARP]),
+        neighbor(SINGLE, [REGULAR, SHARP]),
+        4 * 2 + 2,
+      ),
+      // A neighbor using another reference counts as a fourth filter type.
+      (
+        0,
+        SINGLE,
+        neighbor(OTHER, [REGULAR, REGULAR]),
+        neighbor(SINGLE, [SHARP, SHARP]),
+        2,
+      ),
+      (
+        0,
+        SINGLE,
+        neighbor(SINGLE, [REGULAR, REGULAR]),
+        neighbor(OTHER, [SHARP, SHARP]),
+        0,
+      ),
+      (
+        1,
+        SINGLE,
+        neighbor(OTHER, [SMOOTH, SMOOTH]),
+        neighbor(OTHER, [SMOOTH, SMOOTH]),
+        4 * 2 + 3,
+      ),
+      // The first reference of the block may be the second of a neighbor.
+      (
+        0,
+        SINGLE,
+        neighbor([GOLDEN_FRAME, LAST_FRAME], [SHARP, SHARP]),
+        neighbor(OTHER, [SMOOTH, SMOOTH]),
+        2,
+      ),
+      // Differing neighbors give the fourth filter type.
+      (
+        0,
+        SINGLE,
+        neighbor(SINGLE, [SMOOTH, SMOOTH]),
+        neighbor(SINGLE, [SHARP, SHARP]),
+        3,
+      ),
+      // Compound blocks use their own contexts.
+      (
+        0,
+        COMPOUND,
+        neighbor(COMPOUND, [SMOOTH, SMOOTH]),
+        neighbor(SINGLE, [SMOOTH, SMOOTH]),
+        4 + 1,
+      ),
+      (
+        1,
+        COMPOUND,
+        neighbor(COMPOUND, [REGULAR, SHARP]),
+        neighbor(OTHER, [SMOOTH, SMOOTH]),
+        4 * 3 + 2,
+      ),
+      (
+        1,
+        COMPOUND,
+        neighbor(SINGLE, [REGULAR, SMOOTH]),
+        neighbor(COMPOUND, [REGULAR, SHARP]),
+        4 * 3 + 3,
+      ),
+    ];
+    for (i, (dir, ref_frames, above, left, expected)) in
+      cases.into_iter().enumerate()
+    {
+      assert_eq!(
+        interp_filter_ctx(dir, ref_frames, above, left),
+        expected,
+        "case {i}"
+      );
+    }
+  }
+
+  #[test]
+  fn interp_filter_ctx_frame_corner() {
+    let mut fb = FrameBlocks::new(1, 1);
+    fb[0][0].ref_frames = [LAST_FRAME, ALTREF_FRAME];
+    let mut tb = fb.as_tile_blocks_mut();
+    let mut fc = CDFContext::new(0);
+    let cw = ContextWriter::new(&mut fc, BlockContext::new(&mut tb));
+    let bo = TileBlockOffset(BlockOffset { x: 0, y: 0 });
+    assert_eq!(cw.get_interp_filter_ctx(bo, 0), 4 + 3);
+    assert_eq!(cw.get_interp_filter_ctx(bo, 1), 4 * 3 + 3);
+  }
+}
@@ -8,6 +8,11 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use super::*;
+use crate::mc::WEDGE_TYPES;
+use crate::predict::{
+  CompoundType, FilterIntraMode, InterIntraMode, MotionMode, PaletteColor,
+  PaletteSize,
+};
 use std::marker::PhantomData;
 use v_frame::chroma::ChromaSubsampling;
 
@@ -23,18 +28,25 @@ pub struct CDFContextCheckpoint {
 #[repr(C)]
 pub struct CDFContext {
   pub comp_bwd_ref_cdf: [[[u16; 2]; BWD_REFS - 1]; REF_CONTEXTS],
+  pub comp_group_idx_cdf: [[u16; 2]; COMP_GROUP_IDX_CONTEXTS],
   pub comp_mode_cdf: [[u16; 2]; COMP_INTER_CONTEXTS],
   pub comp_ref_cdf: [[[u16; 2]; FWD_REFS - 1]; REF_CONTEXTS],
   pub comp_ref_type_cdf: [[u16; 2]; COMP_REF_TYPE_CONTEXTS],
+  pub compound_idx_cdf: [[u16; 2]; COMP_INDEX_CONTEXTS],
+  pub compound_type_cdf: [[u16; CompoundType::COMPOUND_TYPES as usize - 1];
+    BlockSize::BLOCK_SIZES_ALL],
   pub dc_sign_cdf: [[[u16; 2]; DC_SIGN_CONTEXTS]; PLANE_TYPES],
   pub drl_cdfs: [[u16; 2]; DRL_MODE_CONTEXTS],
   pub eob_extra_cdf:
     [[[[u16; 2]; EOB_COEF_CONTEXTS]; PLANE_TYPES]; TxSize::TX_SIZES],
   pub filter_intra_cdfs: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
+  pub interintra_cdf: [[u16; 2]; BLOCK_SIZE_GROUPS],
   pub intra_inter_cdfs: [[u16; 2]; INTRA_INTER_CONTEXTS],
+  pub intrabc_cdf: [u16; 2],
   pub lrf_sgrproj_cdf: [u16; 2],
   pub lrf_wiener_cdf: [u16; 2],
   pub newmv_cdf: [[u16; 2]; NEWMV_MODE_CONTEXTS],
+  pub obmc_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
   pub palette_uv_mode_cdfs: [[u16; 2]; PALETTE_UV_MODE_CONTEXTS],
   pub palette_y_mode_cdfs:
     [[[u16; 2]; PALETTE_Y_MODE_CONTEXTS]; PALETTE_BSIZE_CTXS],
@@ -43,6 +55,7 @@ pub struct CDFContext {
   pub skip_cdfs: [[u16; 2]; SKIP_CONTEXTS],
   pub txb_skip_cdf: [[[u16; 2]; TXB_SKIP_CONTEXTS]; TxSize::TX_SIZES],
   pub txfm_partition_cdf: [[u16; 2]; TXFM_PARTITION_CONTEXTS],
+  pub wedge_interintra_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
   pub zeromv_cdf: [[u16; 2]; GLOBALMV_MODE_CONTEXTS],
   pub tx_size_8x8_cdf: [[u16; MAX_TX_DEPTH]; TX_SIZE_CONTEXTS],
   pub inter_tx_3_cdf: [[u16; 2]; TX_SIZE_SQR_CONTEXTS],
@@ -50,6 +63,10 @@ pub struct CDFContext {
   pub coeff_base_eob_cdf:
     [[[[u16; 3]; SIG_COEF_CONTEXTS_EOB]; PLANE_TYPES]; TxSize::TX_SIZES],
   pub lrf_switchable_cdf: [u16; 3],
+  pub motion_mode_cdf:
+    [[u16; MotionMode::MOTION_MODES as usize]; BlockSize::BLOCK_SIZES_ALL],
+  pub switchable_interp_cdf:
+    [[u16; SWITCHABLE_FILTERS]; SWITCHABLE_FILTER_CONTEXTS],
   pub tx_size_cdf: [[[u16; MAX_TX_DEPTH + 1]; TX_SIZE_CONTEXTS]; BIG_TX_CATS],
 
   pub coeff_base_cdf:
@@ -58,22 +75,37 @@ pub struct CDFContext {
     [[[[u16; BR_CDF_SIZE]; LEVEL_CONTEXTS]; PLANE_TYPES]; TxSize::TX_SIZES],
   pub deblock_delta_cdf: [u16; DELTA_LF_PROBS + 1],
   pub deblock_delta_multi_cdf: [[u16; DELTA_LF_PROBS + 1]; FRAME_LF_COUNT],
+  pub interintra_mode_cdf:
+    [[u16; InterIntraMode::INTERINTRA_MODES as usize]; BLOCK_SIZE_GROUPS],
   pub partition_w8_cdf: [[u16; 4]; PARTITION_TYPES],
 
   pub eob_flag_cdf16: [[[u16; 5]; 2]; PLANE_TYPES],
   pub intra_tx_2_cdf: [[[u16; 5]; INTRA_MODES]; TX_SIZE_SQR_CONTEXTS],
+  pub filter_intra_mode_cdf:
+    [u16; FilterIntraMode::FILTER_INTRA_MODES as usize],
 
   pub eob_flag_cdf32: [[[u16; 6]; 2]; PLANE_TYPES],
 
   pub angle_delta_cdf: [[u16; 2 * MAX_ANGLE_DELTA + 1]; DIRECTIONAL_MODES],
   pub eob_flag_cdf64: [[[u16; 7]; 2]; PLANE_TYPES],
   pub intra_tx_1_cdf: [[[u16; 7]; INTRA_MODES]; TX_SIZE_SQR_CONTEXTS],
+  pub palette_y_size_cdf:
+    [[u16; PaletteSize::PALETTE_SIZES as usize]; PALETTE_BSIZE_CTXS],
+  pub palette_uv_size_cdf:
+    [[u16; PaletteSize::PALETTE_SIZES as usize]; PALETTE_BSIZE_CTXS],
 
   pub cfl_sign_cdf: [u16; CFL_JOINT_SIGNS],
   pub compound_mode_cdf: [[u16; INTER_COMPOUND_MODES]; INTER_MODE_CONTEXTS],
   pub eob_flag_cdf128: [[[u16; 8]; 2]; PLANE_TYPES],
   pub spatial_segmentation_cdfs: [[u16; 8]; 3],
   pub partition_w128_cdf: [[u16; 8]; PARTITION_TYPES],
+  // Only the first N entries are used for palettes of size N.
+  pub palette_y_color_index_cdf: [[[u16; PaletteColor::PALETTE_COLORS as usize];
+    PALETTE_COLOR_INDEX_CONTEXTS];
+    PaletteSize::PALETTE_SIZES as usize],
+  pub palette_uv_color_index_cdf:
+    [[[u16; PaletteColor::PALETTE_COLORS as usize];
+      PALETTE_COLOR_INDEX_CONTEXTS]; PaletteSize::PALETTE_SIZES as usize],
 
   pub eob_flag_cdf256: [[[u16; 9]; 2]; PLANE_TYPES],
 
@@ -92,8 +124,9 @@ pub struct CDFContext {
 
   pub cfl_alpha_cdf: [[u16; CFL_ALPHABET_SIZE]; CFL_ALPHA_CONTEXTS],
   pub inter_tx_1_cdf: [[u16; TX_TYPES]; TX_SIZE_SQR_CONTEXTS],
+  pub wedge_idx_cdf: [[u16; WEDGE_TYPES]; BlockSize::BLOCK_SIZES_ALL],
 
-  pub nmv_context: NMVContext,
+  pub nmv_context: [NMVContext; MV_CONTEXTS],
 }
 
 pub struct CDFOffset<const CDF_LEN: usize> {
@@ -120,6 +153,9 @@ impl CDFContext {
       cfl_sign_cdf: default_cfl_sign_cdf,
       cfl_alpha_cdf: default_cfl_alpha_cdf,
       newmv_cdf: default_newmv_cdf,
+      obmc_cdf: default_obmc_cdf,
+      motion_mode_cdf: default_motion_mode_cdf,
+      switchable_interp_cdf: default_switchable_interp_cdf,
       zeromv_cdf: default_zeromv_cdf,
       refmv_cdf: default_refmv_cdf,
       intra_tx_2_cdf: default_intra_tx_2_cdf,
@@ -132,10 +168,16 @@ impl CDFContext {
       txfm_partition_cdf: default_txfm_partition_cdf,
       skip_cdfs: default_skip_cdfs,
       intra_inter_cdfs: default_intra_inter_cdf,
+      intrabc_cdf: default_intrabc_cdf,
       angle_delta_cdf: default_angle_delta_cdf,
       filter_intra_cdfs: default_filter_intra_cdfs,
+      filter_intra_mode_cdf: default_filter_intra_mode_cdf,
       palette_y_mode_cdfs: default_palette_y_mode_cdfs,
       palette_uv_mode_cdfs: default_palette_uv_mode_cdfs,
+      palette_y_size_cdf: default_palette_y_size_cdf,
+      palette_uv_size_cdf: default_palette_uv_size_cdf,
+      palette_y_color_index_cdf: default_palette_y_color_index_cdf,
+      palette_uv_color_index_cdf: default_palette_uv_color_index_cdf,
       comp_mode_cdf: default_comp_mode_cdf,
       comp_ref_type_cdf: default_comp_ref_type_cdf,
       comp_ref_cdf: default_comp_ref_cdf,
@@ -143,7 +185,14 @@ impl CDFContext {
       single_ref_cdfs: default_single_ref_cdf,
       drl_cdfs: default_drl_cdf,
       compound_mode_cdf: default_compound_mode_cdf,
-      nmv_context: default_nmv_context,
+      interintra_cdf: default_interintra_cdf,
+      interintra_mode_cdf: default_interintra_mode_cdf,
+      wedge_interintra_cdf: default_wedge_interintra_cdf,
+      wedge_idx_cdf: default_wedge_idx_cdf,
+      comp_group_idx_cdf: default_comp_group_idx_cdfs,
+      compound_idx_cdf: default_compound_idx_cdfs,
+      compound_type_cdf: default_compound_type_cdf,
+      nmv_context: [default_nmv_context; MV_CONTEXTS],
       deblock_delta_multi_cdf: default_delta_lf_multi_cdf,
       deblock_delta_cdf: default_delta_lf_cdf,
       spatial_segmentation_cdfs: default_spatial_pred_seg_tree_cdf,
@@ -211,6 +260,9 @@ impl CDFContext {
     reset_1d!(self.cfl_sign_cdf);
     reset_2d!(self.cfl_alpha_cdf);
     reset_2d!(self.newmv_cdf);
+    reset_2d!(self.obmc_cdf);
+    reset_2d!(self.motion_mode_cdf);
+    reset_2d!(self.switchable_interp_cdf);
     reset_2d!(self.zeromv_cdf);
     reset_2d!(self.refmv_cdf);
 
@@ -228,10 +280,25 @@ impl CDFContext {
 
     reset_2d!(self.skip_cdfs);
     reset_2d!(self.intra_inter_cdfs);
+    reset_1d!(self.intrabc_cdf);
     reset_2d!(self.angle_delta_cdf);
     reset_2d!(self.filter_intra_cdfs);
+    reset_1d!(self.filter_intra_mode_cdf);
     reset_3d!(self.palette_y_mode_cdfs);
     reset_2d!(self.palette_uv_mode_cdfs);
+    reset_2d!(self.palette_y_size_cdf);
+    reset_2d!(self.palette_uv_size_cdf);
+    // The color index CDFs for N colors keep their counter in entry N - 1.
+    for (i, (y, uv)) in self
+      .palette_y_color_index_cdf
+      .iter_mut()
+      .zip(self.palette_uv_color_index_cdf.iter_mut())
+      .enumerate()
+    {
+      for cdf in y.iter_mut().chain(uv.iter_mut()) {
+        cdf[i + 1] = 0;
+      }
+    }
     reset_2d!(self.comp_mode_cdf);
     reset_2d!(self.comp_ref_type_cdf);
     reset_3d!(self.comp_ref_cdf);
@@ -239,6 +306,13 @@ impl CDFContext {
     reset_3d!(self.single_ref_cdfs);
     reset_2d!(self.drl_cdfs);
     reset_2d!(self.compound_mode_cdf);
+    reset_2d!(self.interintra_cdf);
+    reset_2d!(self.interintra_mode_cdf);
+    reset_2d!(self.wedge_interintra_cdf);
+    reset_2d!(self.wedge_idx_cdf);
+    reset_2d!(self.comp_group_idx_cdf);
+    reset_2d!(self.compound_idx_cdf);
+    reset_2d!(self.compound_type_cdf);
     reset_2d!(self.deblock_delta_multi_cdf);
     reset_1d!(self.deblock_delta_cdf);
     reset_2d!(self.spatial_segmentation_cdfs);
@@ -246,16 +320,18 @@ impl CDFContext {
     reset_1d!(self.lrf_sgrproj_cdf);
     reset_1d!(self.lrf_wiener_cdf);
 
-    reset_1d!(self.nmv_context.joints_cdf);
-    for i in 0..2 {
-      reset_1d!(self.nmv_context.comps[i].classes_cdf);
-      reset_2d!(self.nmv_context.comps[i].class0_fp_cdf);
-      reset_1d!(self.nmv_context.comps[i].fp_cdf);
-      reset_1d!(self.nmv_context.comps[i].sign_cdf);
-      reset_1d!(self.nmv_context.comps[i].class0_hp_cdf);
-      reset_1d!(self.nmv_context.comps[i].hp_cdf);
-      reset_1d!(self.nmv_context.comps[i].class0_cdf);
-      reset_2d!(self.nmv_context.comps[i].bits_cdf);
+    for nmv_context in self.nmv_context.iter_mut() {
+      reset_1d!(nmv_context.joints_cdf);
+      for i in 0..2 {
+        reset_1d!(nmv_context.comps[i].classes_cdf);
+        reset_2d!(nmv_context.comps[i].class0_fp_cdf);
+        reset_1d!(nmv_context.comps[i].fp_cdf);
+        reset_1d!(nmv_context.comps[i].sign_cdf);
+        reset_1d!(nmv_context.comps[i].class0_hp_cdf);
+        reset_1d!(nmv_context.comps[i].hp_cdf);
+        reset_1d!(nmv_context.comps[i].class0_cdf);
+        reset_2d!(nmv_context.comps[i].bits_cdf);
+      }
     }
 
     // lv_map
@@ -315,6 +391,16 @@ impl CDFContext {
       cfl_alpha_cdf_start + size_of_val(&self.cfl_alpha_cdf);
     let newmv_cdf_start = self.newmv_cdf.first().unwrap().as_ptr() as usize;
     let newmv_cdf_end = newmv_cdf_start + size_of_val(&self.newmv_cdf);
+    let obmc_cdf_start = self.obmc_cdf.first().unwrap().as_ptr() as usize;
+    let obmc_cdf_end = obmc_cdf_start + size_of_val(&self.obmc_cdf);
+    let motion_mode_cdf_start =
+      self.motion_mode_cdf.first().unwrap().as_ptr() as usize;
+    let motion_mode_cdf_end =
+      motion_mode_cdf_start + size_of_val(&self.motion_mode_cdf);
+    let switchable_interp_cdf_start =
+      self.switchable_interp_cdf.first().unwrap().as_ptr() as usize;
+    let switchable_interp_cdf_end =
+      switchable_interp_cdf_start + size_of_val(&self.switchable_interp_cdf);
     let zeromv_cdf_start = self.zeromv_cdf.first().unwrap().as_ptr() as usize;
     let zeromv_cdf_end = zeromv_cdf_start + size_of_val(&self.zeromv_cdf);
     let refmv_cdf_start = self.refmv_cdf.first().unwrap().as_ptr() as usize;
@@ -356,6 +442,8 @@ impl CDFContext {
       self.intra_inter_cdfs.first().unwrap().as_ptr() as usize;
     let intra_inter_cdfs_end =
       intra_inter_cdfs_start + size_of_val(&self.intra_inter_cdfs);
+    let intrabc_cdf_start = self.intrabc_cdf.as_ptr() as usize;
+    let intrabc_cdf_end = intrabc_cdf_start + size_of_val(&self.intrabc_cdf);
     let angle_delta_cdf_start =
       self.angle_delta_cdf.first().unwrap().as_ptr() as usize;
     let angle_delta_cdf_end =
@@ -364,6 +452,10 @@ impl CDFContext {
       self.filter_intra_cdfs.first().unwrap().as_ptr() as usize;
     let filter_intra_cdfs_end =
       filter_intra_cdfs_start + size_of_val(&self.filter_intra_cdfs);
+    let filter_intra_mode_cdf_start =
+      self.filter_intra_mode_cdf.as_ptr() as usize;
+    let filter_intra_mode_cdf_end =
+      filter_intra_mode_cdf_start + size_of_val(&self.filter_intra_mode_cdf);
     let palette_y_mode_cdfs_start =
       self.palette_y_mode_cdfs.first().unwrap().as_ptr() as usize;
     let palette_y_mode_cdfs_end =
@@ -372,6 +464,22 @@ impl CDFContext {
       self.palette_uv_mode_cdfs.first().unwrap().as_ptr() as usize;
     let palette_uv_mode_cdfs_end =
       palette_uv_mode_cdfs_start + size_of_val(&self.palette_uv_mode_cdfs);
+    let palette_y_size_cdf_start =
+      self.palette_y_size_cdf.first().unwrap().as_ptr() as usize;
+    let palette_y_size_cdf_end =
+      palette_y_size_cdf_start + size_of_val(&self.palette_y_size_cdf);
+    let palette_uv_size_cdf_start =
+      self.palette_uv_size_cdf.first().unwrap().as_ptr() as usize;
+    let palette_uv_size_cdf_end =
+      palette_uv_size_cdf_start + size_of_val(&self.palette_uv_size_cdf);
+    let palette_y_color_index_cdf_start =
+      self.palette_y_color_index_cdf.first().unwrap().as_ptr() as usize;
+    let palette_y_color_index_cdf_end = palette_y_color_index_cdf_start
+      + size_of_val(&self.palette_y_color_index_cdf);
+    let palette_uv_color_index_cdf_start =
+      self.palette_uv_color_index_cdf.first().unwrap().as_ptr() as usize;
+    let palette_uv_color_index_cdf_end = palette_uv_color_index_cdf_start
+      + size_of_val(&self.palette_uv_color_index_cdf);
     let comp_mode_cdf_start =
       self.comp_mode_cdf.first().unwrap().as_ptr() as usize;
     let comp_mode_cdf_end =
@@ -398,7 +506,35 @@ impl CDFContext {
       self.compound_mode_cdf.first().unwrap().as_ptr() as usize;
     let compound_mode_cdf_end =
       compound_mode_cdf_start + size_of_val(&self.compound_mode_cdf);
-    let nmv_context_start = &self.nmv_context as *const NMVContext as usize;
+    let interintra_cdf_start =
+      self.interintra_cdf.first().unwrap().as_ptr() as usize;
+    let interintra_cdf_end =
+      interintra_cdf_start + size_of_val(&self.interintra_cdf);
+    let interintra_mode_cdf_start =
+      self.interintra_mode_cdf.first().unwrap().as_ptr() as usize;
+    let interintra_mode_cdf_end =
+      interintra_mode_cdf_start + size_of_val(&self.interintra_mode_cdf);
+    let wedge_interintra_cdf_start =
+      self.wedge_interintra_cdf.first().unwrap().as_ptr() as usize;
+    let wedge_interintra_cdf_end =
+      wedge_interintra_cdf_start + size_of_val(&self.wedge_interintra_cdf);
+    let wedge_idx_cdf_start =
+      self.wedge_idx_cdf.first().unwrap().as_ptr() as usize;
+    let wedge_idx_cdf_end =
+      wedge_idx_cdf_start + size_of_val(&self.wedge_idx_cdf);
+    let comp_group_idx_cdf_start =
+      self.comp_group_idx_cdf.first().unwrap().as_ptr() as usize;
+    let comp_group_idx_cdf_end =
+      comp_group_idx_cdf_start + size_of_val(&self.comp_group_idx_cdf);
+    let compound_idx_cdf_start =
+      self.compound_idx_cdf.first().unwrap().as_ptr() as usize;
+    let compound_idx_cdf_end =
+      compound_idx_cdf_start + size_of_val(&self.compound_idx_cdf);
+    let compound_type_cdf_start =
+      self.compound_type_cdf.first().unwrap().as_ptr() as usize;
+    let compound_type_cdf_end =
+      compound_type_cdf_start + size_of_val(&self.compound_type_cdf);
+    let nmv_context_start = self.nmv_context.as_ptr() as usize;
     let nmv_context_end = nmv_context_start + size_of_val(&self.nmv_context);
     let deblock_delta_multi_cdf_start =
       self.deblock_delta_multi_cdf.first().unwrap().as_ptr() as usize;
@@ -484,6 +620,13 @@ impl CDFContext {
       ("cfl_sign_cdf", cfl_sign_cdf_start, cfl_sign_cdf_end),
       ("cfl_alpha_cdf", cfl_alpha_cdf_start, cfl_alpha_cdf_end),
       ("newmv_cdf", newmv_cdf_start, newmv_cdf_end),
+      ("obmc_cdf", obmc_cdf_start, obmc_cdf_end),
+      ("motion_mode_cdf", motion_mode_cdf_start, motion_mode_cdf_end),
+      (
+        "switchable_interp_cdf",
+        switchable_interp_cdf_start,
+        switchable_interp_cdf_end,
+      ),
       ("zeromv_cdf", zeromv_cdf_start, zeromv_cdf_end),
       ("refmv_cdf", refmv_cdf_start, refmv_cdf_end),
       ("intra_tx_2_cdf", intra_tx_2_cdf_start, intra_tx_2_cdf_end),
@@ -496,8 +639,14 @@ impl CDFContext {
       ("txfm_partition_cdf", txfm_partition_cdf_start, txfm_partition_cdf_end),
       ("skip_cdfs", skip_cdfs_start, skip_cdfs_end),
       ("intra_inter_cdfs", intra_inter_cdfs_start, intra_inter_cdfs_end),
+      ("intrabc_cdf", intrabc_cdf_start, intrabc_cdf_end),
       ("angle_delta_cdf", angle_delta_cdf_start, angle_delta_cdf_end),
       ("filter_intra_cdfs", filter_intra_cdfs_start, filter_intra_cdfs_end),
+      (
+        "filter_intra_mode_cdf",
+        filter_intra_mode_cdf_start,
+        filter_intra_mode_cdf_end,
+      ),
       (
         "palette_y_mode_cdfs",
         palette_y_mode_cdfs_start,
@@ -508,6 +657,22 @@ impl CDFContext {
         palette_uv_mode_cdfs_start,
         palette_uv_mode_cdfs_end,
       ),
+      ("palette_y_size_cdf", palette_y_size_cdf_start, palette_y_size_cdf_end),
+      (
+        "palette_uv_size_cdf",
+        palette_uv_size_cdf_start,
+        palette_uv_size_cdf_end,
+      ),
+      (
+        "palette_y_color_index_cdf",
+        palette_y_color_index_cdf_start,
+        palette_y_color_index_cdf_end,
+      ),
+      (
+        "palette_uv_color_index_cdf",
+        palette_uv_color_index_cdf_start,
+        palette_uv_color_index_cdf_end,
+      ),
       ("comp_mode_cdf", comp_mode_cdf_start, comp_mode_cdf_end),
       ("comp_ref_type_cdf", comp_ref_type_cdf_start, comp_ref_type_cdf_end),
       ("comp_ref_cdf", comp_ref_cdf_start, comp_ref_cdf_end),
@@ -515,6 +680,21 @@ impl CDFContext {
       ("single_ref_cdfs", single_ref_cdfs_start, single_ref_cdfs_end),
       ("drl_cdfs", drl_cdfs_start, drl_cdfs_end),
       ("compound_mode_cdf", compound_mode_cdf_start, compound_mode_cdf_end),
+      ("interintra_cdf", interintra_cdf_start, interintra_cdf_end),
+      (
+        "interintra_mode_cdf",
+        interintra_mode_cdf_start,
+        interintra_mode_cdf_end,
+      ),
+      (
+        "wedge_interintra_cdf",
+        wedge_interintra_cdf_start,
+        wedge_interintra_cdf_end,
+      ),
+      ("wedge_idx_cdf", wedge_idx_cdf_start, wedge_idx_cdf_end),
+      ("comp_group_idx_cdf", comp_group_idx_cdf_start, comp_group_idx_cdf_end),
+      ("compound_idx_cdf", compound_idx_cdf_start, compound_idx_cdf_end),
+      ("compound_type_cdf", compound_type_cdf_start, compound_type_cdf_end),
       ("nmv_context", nmv_context_start, nmv_context_end),
       (
         "deblock_delta_multi_cdf",
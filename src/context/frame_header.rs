@@ -17,8 +17,25 @@ impl CDFContext {
   ) -> u32 {
     match filter {
       RestorationFilter::None => w.symbol_bits(0, &self.lrf_switchable_cdf),
-      RestorationFilter::Wiener { .. } => {
-        unreachable!() // for now, not permanently
+      RestorationFilter::Wiener { coeffs } => {
+        // Does *not* use 'RESTORE_WIENER' but rather just '1'
+        let rp = &rs.planes[pli];
+        let mut bits = w.symbol_bits(1, &self.lrf_switchable_cdf);
+        let first_coeff = if pli == 0 { 0 } else { 1 };
+        for pass in 0..2 {
+          for i in first_coeff..3 {
+            let min = WIENER_TAPS_MIN[i] as i32;
+            let max = WIENER_TAPS_MAX[i] as i32;
+            bits += w.count_signed_subexp_with_ref(
+              coeffs[pass][i] as i32,
+              min,
+              max + 1,
+              WIENER_TAPS_K[i] as u8,
+              rp.wiener_ref[pass][i] as i32,
+            );
+          }
+        }
+        bits
       }
       RestorationFilter::Sgrproj { set, xqd } => {
         // Does *not* use 'RESTORE_SGRPROJ' but rather just '2'
@@ -174,93 +191,102 @@ impl ContextWriter<'_> {
   ) {
     let rp = &mut rs.planes[pli];
     if let Some(filter) = rp.restoration_unit(sbo, true).map(|ru| ru.filter) {
-      match filter {
-        RestorationFilter::None => match rp.rp_cfg.lrf_type {
-          RESTORE_WIENER => {
-            let cdf = &self.fc.lrf_wiener_cdf;
-            symbol_with_update!(self, w, 0, cdf);
-          }
+      self.write_lrf_unit(w, rp, filter, pli);
+    }
+  }
+
+  /// Writes the `filter` of a restoration unit of plane `pli`, coded
+  /// relative to the references of `rp`, which are then updated.
+  pub fn write_lrf_unit<W: Writer>(
+    &mut self, w: &mut W, rp: &mut TileRestorationPlaneMut,
+    filter: RestorationFilter, pli: usize,
+  ) {
+    match filter {
+      RestorationFilter::None => match rp.rp_cfg.lrf_type {
+        RESTORE_WIENER => {
+          let cdf = &self.fc.lrf_wiener_cdf;
+          symbol_with_update!(self, w, 0, cdf);
+        }
+        RESTORE_SGRPROJ => {
+          let cdf = &self.fc.lrf_sgrproj_cdf;
+          symbol_with_update!(self, w, 0, cdf);
+        }
+        RESTORE_SWITCHABLE => {
+          let cdf = &self.fc.lrf_switchable_cdf;
+          symbol_with_update!(self, w, 0, cdf);
+        }
+        RESTORE_NONE => {}
+        _ => unreachable!(),
+      },
+      RestorationFilter::Sgrproj { set, xqd } => {
+        match rp.rp_cfg.lrf_type {
           RESTORE_SGRPROJ => {
             let cdf = &self.fc.lrf_sgrproj_cdf;
-            symbol_with_update!(self, w, 0, cdf);
+            symbol_with_update!(self, w, 1, cdf);
           }
           RESTORE_SWITCHABLE => {
+            // Does *not* write 'RESTORE_SGRPROJ'
             let cdf = &self.fc.lrf_switchable_cdf;
-            symbol_with_update!(self, w, 0, cdf);
+            symbol_with_update!(self, w, 2, cdf);
           }
-          RESTORE_NONE => {}
           _ => unreachable!(),
-        },
-        RestorationFilter::Sgrproj { set, xqd } => {
-          match rp.rp_cfg.lrf_type {
-            RESTORE_SGRPROJ => {
-              let cdf = &self.fc.lrf_sgrproj_cdf;
-              symbol_with_update!(self, w, 1, cdf);
-            }
-            RESTORE_SWITCHABLE => {
-              // Does *not* write 'RESTORE_SGRPROJ'
-              let cdf = &self.fc.lrf_switchable_cdf;
-              symbol_with_update!(self, w, 2, cdf);
-            }
-            _ => unreachable!(),
-          }
-          w.literal(SGRPROJ_PARAMS_BITS, set as u32);
-          for i in 0..2 {
-            let s = SGRPROJ_PARAMS_S[set as usize][i];
-            let min = SGRPROJ_XQD_MIN[i] as i32;
-            let max = SGRPROJ_XQD_MAX[i] as i32;
-            if s > 0 {
-              w.write_signed_subexp_with_ref(
-                xqd[i] as i32,
-                min,
-                max + 1,
-                SGRPROJ_PRJ_SUBEXP_K,
-                rp.sgrproj_ref[i] as i32,
-              );
-              rp.sgrproj_ref[i] = xqd[i];
+        }
+        w.literal(SGRPROJ_PARAMS_BITS, set as u32);
+        for i in 0..2 {
+          let s = SGRPROJ_PARAMS_S[set as usize][i];
+          let min = SGRPROJ_XQD_MIN[i] as i32;
+          let max = SGRPROJ_XQD_MAX[i] as i32;
+          if s > 0 {
+            w.write_signed_subexp_with_ref(
+              xqd[i] as i32,
+              min,
+              max + 1,
+              SGRPROJ_PRJ_SUBEXP_K,
+              rp.sgrproj_ref[i] as i32,
+            );
+            rp.sgrproj_ref[i] = xqd[i];
+          } else {
+            // Nothing written, just update the reference
+            if i == 0 {
+              assert!(xqd[i] == 0);
+              rp.sgrproj_ref[0] = 0;
             } else {
-              // Nothing written, just update the reference
-              if i == 0 {
-                assert!(xqd[i] == 0);
-                rp.sgrproj_ref[0] = 0;
-              } else {
-                rp.sgrproj_ref[1] = 95; // LOL at spec.  The result is always 95.
-              }
+              rp.sgrproj_ref[1] = 95; // LOL at spec.  The result is always 95.
             }
           }
         }
-        RestorationFilter::Wiener { coeffs } => {
-          match rp.rp_cfg.lrf_type {
-            RESTORE_WIENER => {
-              let cdf = &self.fc.lrf_wiener_cdf;
-              symbol_with_update!(self, w, 1, cdf);
-            }
-            RESTORE_SWITCHABLE => {
-              // Does *not* write 'RESTORE_WIENER'
-              let cdf = &self.fc.lrf_switchable_cdf;
-              symbol_with_update!(self, w, 1, cdf);
-            }
-            _ => unreachable!(),
+      }
+      RestorationFilter::Wiener { coeffs } => {
+        match rp.rp_cfg.lrf_type {
+          RESTORE_WIENER => {
+            let cdf = &self.fc.lrf_wiener_cdf;
+            symbol_with_update!(self, w, 1, cdf);
           }
-          for pass in 0..2 {
-            let first_coeff = if pli == 0 {
-              0
-            } else {
-              assert!(coeffs[pass][0] == 0);
-              1
-            };
-            for i in first_coeff..3 {
-              let min = WIENER_TAPS_MIN[i] as i32;
-              let max = WIENER_TAPS_MAX[i] as i32;
-              w.write_signed_subexp_with_ref(
-                coeffs[pass][i] as i32,
-                min,
-                max + 1,
-                (i + 1) as u8,
-                rp.wiener_ref[pass][i] as i32,
-              );
-              rp.wiener_ref[pass][i] = coeffs[pass][i];
-            }
+          RESTORE_SWITCHABLE => {
+            // Does *not* write 'RESTORE_WIENER'
+            let cdf = &self.fc.lrf_switchable_cdf;
+            symbol_with_update!(self, w, 1, cdf);
+          }
+          _ => unreachable!(),
+        }
+        for pass in 0..2 {
+          let first_coeff = if pli == 0 {
+            0
+          } else {
+            assert!(coeffs[pass][0] == 0);
+            1
+          };
+          for i in first_coeff..3 {
+            let min = WIENER_TAPS_MIN[i] as i32;
+            let max = WIENER_TAPS_MAX[i] as i32;
+            w.write_signed_subexp_with_ref(
+              coeffs[pass][i] as i32,
+              min,
+              max + 1,
+              WIENER_TAPS_K[i] as u8,
+              rp.wiener_ref[pass][i] as i32,
+            );
+            rp.wiener_ref[pass][i] = coeffs[pass][i];
           }
         }
       }
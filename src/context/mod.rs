@@ -11,7 +11,7 @@
 #![allow(dead_code)]
 #![allow(non_camel_case_types)]
 
-use crate::ec::{Writer, OD_BITRES};
+use crate::ec::{Writer, WriterCheckpoint, OD_BITRES};
 use crate::encoder::FrameInvariants;
 use crate::entropymode::*;
 use crate::frame::*;
@@ -130,6 +130,11 @@ const MV_IN_USE_BITS: usize = 14;
 pub const MV_UPP: i32 = 1 << MV_IN_USE_BITS;
 pub const MV_LOW: i32 = -(1 << MV_IN_USE_BITS);
 
+const MV_CONTEXTS: usize = 2;
+/// Motion vector context used for the displacement vectors of intra block
+/// copy.
+pub const MV_INTRABC_CONTEXT: usize = 1;
+
 #[inline(always)]
 pub const fn av1_get_mv_joint(mv: MotionVector) -> MvJointType {
   match (mv.row, mv.col) {
@@ -182,7 +187,8 @@ impl ContextWriter<'_> {
   /// - If the `comp` is 0
   /// - If the `comp` is outside the bounds of `MV_LOW` and `MV_UPP`
   pub fn encode_mv_component<W: Writer>(
-    &mut self, w: &mut W, comp: i32, axis: usize, precision: MvSubpelPrecision,
+    &mut self, w: &mut W, comp: i32, ctx: usize, axis: usize,
+    precision: MvSubpelPrecision,
   ) {
     assert!(comp != 0);
     assert!((MV_LOW..=MV_UPP).contains(&comp));
@@ -195,34 +201,34 @@ impl ContextWriter<'_> {
 
     // Sign
     {
-      let mvcomp = &self.fc.nmv_context.comps[axis];
+      let mvcomp = &self.fc.nmv_context[ctx].comps[axis];
       let cdf = &mvcomp.sign_cdf;
       symbol_with_update!(self, w, sign, cdf);
     }
 
     // Class
     {
-      let mvcomp = &self.fc.nmv_context.comps[axis];
+      let mvcomp = &self.fc.nmv_context[ctx].comps[axis];
       let cdf = &mvcomp.classes_cdf;
       symbol_with_update!(self, w, mv_class as u32, cdf);
     }
 
     // Integer bits
     if mv_class == MV_CLASS_0 {
-      let mvcomp = &self.fc.nmv_context.comps[axis];
+      let mvcomp = &self.fc.nmv_context[ctx].comps[axis];
       let cdf = &mvcomp.class0_cdf;
       symbol_with_update!(self, w, d, cdf);
     } else {
       let n = mv_class + CLASS0_BITS - 1; // number of bits
       for i in 0..n {
-        let mvcomp = &self.fc.nmv_context.comps[axis];
+        let mvcomp = &self.fc.nmv_context[ctx].comps[axis];
         let cdf = &mvcomp.bits_cdf[i];
         symbol_with_update!(self, w, (d >> i) & 1, cdf);
       }
     }
     // Fractional bits
     if precision > MvSubpelPrecision::MV_SUBPEL_NONE {
-      let mvcomp = &self.fc.nmv_context.comps[axis];
+      let mvcomp = &self.fc.nmv_context[ctx].comps[axis];
       let cdf = if mv_class == MV_CLASS_0 {
         &mvcomp.class0_fp_cdf[d as usize]
       } else {
@@ -233,7 +239,7 @@ impl ContextWriter<'_> {
 
     // High precision bit
     if precision > MvSubpelPrecision::MV_SUBPEL_LOW_PRECISION {
-      let mvcomp = &self.fc.nmv_context.comps[axis];
+      let mvcomp = &self.fc.nmv_context[ctx].comps[axis];
       let cdf = if mv_class == MV_CLASS_0 {
         &mvcomp.class0_hp_cdf
       } else {
@@ -130,7 +130,7 @@ impl CFLParams {
 
 impl ContextWriter<'_> {
   fn partition_gather_horz_alike(
-    out: &mut [u16; 2], cdf_in: &[u16], _bsize: BlockSize,
+    out: &mut [u16; 2], cdf_in: &[u16], bsize: BlockSize,
   ) {
     out[0] = 32768;
     out[0] -= ContextWriter::cdf_element_prob(
@@ -153,16 +153,19 @@ impl ContextWriter<'_> {
       cdf_in,
       PartitionType::PARTITION_VERT_A as usize,
     );
-    out[0] -= ContextWriter::cdf_element_prob(
-      cdf_in,
-      PartitionType::PARTITION_HORZ_4 as usize,
-    );
+    // 4-way partitions are not coded for 128x128 blocks
+    if bsize != BlockSize::BLOCK_128X128 {
+      out[0] -= ContextWriter::cdf_element_prob(
+        cdf_in,
+        PartitionType::PARTITION_HORZ_4 as usize,
+      );
+    }
     out[0] = 32768 - out[0];
     out[1] = 0;
   }
 
   fn partition_gather_vert_alike(
-    out: &mut [u16; 2], cdf_in: &[u16], _bsize: BlockSize,
+    out: &mut [u16; 2], cdf_in: &[u16], bsize: BlockSize,
   ) {
     out[0] = 32768;
     out[0] -= ContextWriter::cdf_element_prob(
@@ -185,10 +188,13 @@ impl ContextWriter<'_> {
       cdf_in,
       PartitionType::PARTITION_VERT_B as usize,
     );
-    out[0] -= ContextWriter::cdf_element_prob(
-      cdf_in,
-      PartitionType::PARTITION_VERT_4 as usize,
-    );
+    // 4-way partitions are not coded for 128x128 blocks
+    if bsize != BlockSize::BLOCK_128X128 {
+      out[0] -= ContextWriter::cdf_element_prob(
+        cdf_in,
+        PartitionType::PARTITION_VERT_4 as usize,
+      );
+    }
     out[0] = 32768 - out[0];
     out[1] = 0;
   }
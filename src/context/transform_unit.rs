@@ -526,6 +526,9 @@ pub struct TXB_CTX {
 }
 
 impl ContextWriter<'_> {
+  /// For intra blocks, `y_mode` is the direction selecting the CDF, which
+  /// for filter intra blocks is given by `FilterIntraMode::intra_dir`.
+  ///
   /// # Panics
   ///
   /// - If an invalid combination of `tx_type` and `tx_size` is passed
@@ -557,9 +560,6 @@ impl ContextWriter<'_> {
         }
       } else {
         let intra_dir = y_mode;
-        // TODO: Once use_filter_intra is enabled,
-        // intra_dir =
-        // fimode_to_intradir[mbmi->filter_intra_mode_info.filter_intra_mode];
 
         let s = av1_tx_ind[tx_set as usize][tx_type as usize] as u32;
         if tx_set_index == 1 {
@@ -750,8 +750,8 @@ impl ContextWriter<'_> {
     } else {
       // if txfm_split == true, split one level only
       let split_tx_size = sub_tx_size_map[tx_size as usize];
-      let bw = bsize.width_mi() / split_tx_size.width_mi();
-      let bh = bsize.height_mi() / split_tx_size.height_mi();
+      let bw = tx_size.width_mi() / split_tx_size.width_mi();
+      let bh = tx_size.height_mi() / split_tx_size.height_mi();
 
       for by in 0..bh {
         for bx in 0..bw {
@@ -765,8 +765,8 @@ impl ContextWriter<'_> {
             bsize,
             split_tx_size,
             false,
-            bx,
-            by,
+            tbx + bx,
+            tby + by,
             depth + 1,
           );
         }
@@ -1621,6 +1621,11 @@ pub fn deblock_filter_optimize<T: Pixel, U: Pixel>(
   fi: &FrameInvariants<T>, rec: &Tile<U>, input: &Tile<U>,
   blocks: &TileBlocks, crop_w: usize, crop_h: usize,
 ) -> [u8; 4] {
+  // The deblocking filter is never applied to lossless frames, nor to
+  // frames using intra block copy.
+  if fi.coded_lossless() || fi.allow_intrabc {
+    return [0; 4];
+  }
   if fi.config.speed_settings.fast_deblock {
     let q = ac_q(fi.base_q_idx, 0, fi.sequence.bit_depth).get() as i32;
     let level = clamp(
@@ -306,6 +306,42 @@ pub(crate) mod rust {
       269,  264,  260,  256,
   ];
 
+  /// The sums of the samples of two blocks, of their squares and of their
+  /// products, from which their means, variances and covariance follow.
+  #[derive(Debug, Default, Clone, Copy)]
+  pub(crate) struct BlockMoments {
+    pub sum_s: u32,
+    pub sum_d: u32,
+    pub sum_s2: u32,
+    pub sum_d2: u32,
+    pub sum_sd: u32,
+  }
+
+  /// Computes the [`BlockMoments`] of two blocks.
+  /// w and h should be <= 8.
+  #[inline(always)]
+  pub(crate) fn block_moments<T: Pixel>(
+    src: &PlaneRegion<'_, T>, dst: &PlaneRegion<'_, T>, w: usize, h: usize,
+  ) -> BlockMoments
+  where
+    u32: util::math::CastFromPrimitive<T>,
+  {
+    let mut m = BlockMoments::default();
+    for (row1, row2) in src.rows_iter().take(h).zip(dst.rows_iter()) {
+      for (s, d) in row1[..w].iter().zip(row2) {
+        let s: u32 = u32::cast_from(*s);
+        let d: u32 = u32::cast_from(*d);
+        m.sum_s += s;
+        m.sum_d += d;
+
+        m.sum_s2 += s * s;
+        m.sum_d2 += d * d;
+        m.sum_sd += s * d;
+      }
+    }
+    m
+  }
+
   /// Computes a distortion metric of the sum of squares weighted by activity.
   /// w and h should be <= 8.
   #[inline(never)]
@@ -329,23 +365,11 @@ pub(crate) mod rust {
     debug_assert!(h <= 8);
 
     // Compute the following summations.
-    let mut sum_s: u32 = 0; // sum(src_{i,j})
-    let mut sum_d: u32 = 0; // sum(dst_{i,j})
-    let mut sum_s2: u32 = 0; // sum(src_{i,j}^2)
-    let mut sum_d2: u32 = 0; // sum(dst_{i,j}^2)
-    let mut sum_sd: u32 = 0; // sum(src_{i,j} * dst_{i,j})
-    for (row1, row2) in src.rows_iter().take(h).zip(dst.rows_iter()) {
-      for (s, d) in row1[..w].iter().zip(row2) {
-        let s: u32 = u32::cast_from(*s);
-        let d: u32 = u32::cast_from(*d);
-        sum_s += s;
-        sum_d += d;
-
-        sum_s2 += s * s;
-        sum_d2 += d * d;
-        sum_sd += s * d;
-      }
-    }
+    //  sum_s = sum(src_{i,j}), sum_d = sum(dst_{i,j}),
+    //  sum_s2 = sum(src_{i,j}^2), sum_d2 = sum(dst_{i,j}^2),
+    //  sum_sd = sum(src_{i,j} * dst_{i,j})
+    let BlockMoments { sum_s, sum_d, sum_s2, sum_d2, sum_sd } =
+      block_moments(src, dst, w, h);
 
     // To get the distortion, compute sum of squared error and apply a weight
     // based on the variance of the two planes.
@@ -424,6 +424,18 @@ impl WriterBase<WriterRecorder> {
     self.s.storage.truncate(0);
     self.s.bits = 0;
   }
+
+  /// Inserts the `bits` long literal `s` where `checkpoint` was taken.
+  /// The recorded tokens do not depend on the coder state, so the ones
+  /// following the literal stay valid, but later checkpoints do not.
+  pub fn insert_literal(
+    &mut self, checkpoint: &WriterCheckpoint, bits: u8, s: u32,
+  ) {
+    let mut literal = WriterRecorder::new();
+    literal.literal(bits, s);
+    let start = checkpoint.backend_var;
+    self.s.storage.splice(start..start, literal.s.storage);
+  }
 }
 
 /// Done implementation specific to the Encoder
@@ -11,7 +11,8 @@ use crate::util::math::Fixed;
 use std::collections::VecDeque;
 use std::io::Write;
 use std::mem::MaybeUninit;
-use std::sync::Arc;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
 use std::{fmt, io, mem};
 
 use arg_enum_proc_macro::ArgEnum;
@@ -21,44 +22,43 @@ use num_traits::Zero;
 use rayon::iter::*;
 use v_frame::chroma::ChromaSubsampling;
 
+use crate::api::lookahead::ScreenContentDecision;
 use crate::api::*;
 use crate::cdef::*;
 use crate::context::*;
 use crate::deblock::*;
 use crate::ec::*;
 use crate::frame::*;
+use crate::global_motion::*;
 use crate::header::*;
+use crate::intrabc::*;
 use crate::lrf::*;
-use crate::mc::{FilterMode, MotionVector};
+use crate::mc::{ref_scale, FilterMode, MotionVector, REF_SCALE_SHIFT};
 use crate::me::*;
+use crate::palette::*;
 use crate::partition::PartitionType::*;
 use crate::partition::RefType::*;
 use crate::partition::*;
 use crate::predict::{
-  luma_ac, AngleDelta, IntraEdgeFilterParameters, IntraParam, PredictionMode,
+  blend_obmc, luma_ac, predict_inter_intra, AngleDelta, CompoundPrediction,
+  FilterIntraMode, IntraEdgeFilterParameters, IntraParam, MotionMode,
+  PredictionMode,
 };
 use crate::quantize::*;
 use crate::rate::{
-  QuantizerParameters, FRAME_SUBTYPE_I, FRAME_SUBTYPE_P, QSCALE,
+  inter_frame_subtype, QuantizerParameters, FRAME_SUBTYPE_I, QSCALE,
 };
 use crate::rdo::*;
 use crate::segmentation::*;
 use crate::serialize::{Deserialize, Serialize};
 use crate::stats::EncoderStats;
+use crate::superres::*;
 use crate::tiling::*;
 use crate::transform::*;
 use crate::util::*;
 use crate::wasm_bindgen::*;
 use crate::{activity::*, util};
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CDEFSearchMethod {
-  PickFromQ,
-  FastSearch,
-  FullSearch,
-}
-
 #[inline(always)]
 fn poly2(q: f32, a: f32, b: f32, c: f32, max: i32) -> i32 {
   clamp((q * q).mul_add(a, q.mul_add(b, c)).round() as i32, 0, max)
@@ -85,10 +85,14 @@ pub struct ReferenceFrame<T: Pixel> {
   pub frame: Arc<Frame<T>>,
   pub input_hres: Arc<Plane<T>>,
   pub input_qres: Arc<Plane<T>>,
+  /// Reconstructed luma resampled to the size of the frame referencing it,
+  /// for the full pixel motion search of frames of another size.
+  pub scaled_y_plane: Option<Arc<Plane<T>>>,
   pub cdfs: CDFContext,
   pub frame_me_stats: RefMEStats,
   pub output_frameno: u64,
   pub segmentation: SegmentationState,
+  pub globalmv_params: [[i32; 6]; INTER_REFS_PER_FRAME],
 }
 
 impl<T: Pixel> fmt::Debug for ReferenceFrame<T> {
@@ -102,14 +106,32 @@ impl<T: Pixel> fmt::Debug for ReferenceFrame<T> {
       .field("frame", &"Frame<T>")
       .field("input_hres", &"Plane<T>")
       .field("input_qres", &"Plane<T>")
+      .field(
+        "scaled_y_plane",
+        &self.scaled_y_plane.as_ref().map(|_| "Plane<T>"),
+      )
       .field("cdfs", &self.cdfs)
       .field("frame_me_stats", &self.frame_me_stats)
       .field("output_frameno", &self.output_frameno)
       .field("segmentation", &self.segmentation)
+      .field("globalmv_params", &self.globalmv_params)
       .finish()
   }
 }
 
+impl<T: Pixel> ReferenceFrame<T> {
+  /// The visible dimensions of plane `p`, without the padding of the
+  /// allocated frame.
+  pub fn plane_dimensions(&self, p: usize) -> (usize, usize) {
+    let PlaneConfig { xdec, ydec, .. } =
+      PlaneConfig::new(&self.frame.planes().nth(p).unwrap().geometry());
+    (
+      (self.width as usize + xdec) >> xdec,
+      (self.height as usize + ydec) >> ydec,
+    )
+  }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ReferenceFramesSet<T: Pixel> {
   pub frames: [Option<Arc<ReferenceFrame<T>>>; REF_FRAMES],
@@ -133,6 +155,65 @@ pub enum Tune {
   Psychovisual,
 }
 
+/// Use of the coding tools for screen content, such as palette prediction
+/// and intra block copy.
+#[wasm_bindgen]
+#[derive(
+  ArgEnum, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default,
+)]
+#[repr(C)]
+pub enum ScreenContentMode {
+  /// Never use the screen content tools.
+  #[default]
+  Off,
+  /// Use the screen content tools on every frame.
+  On,
+  /// Use the screen content tools on the frames which look like screen
+  /// content.
+  Auto,
+}
+
+/// Selection of the frames coded at a reduced width and upscaled by the
+/// decoder (superres), and of their width.
+#[wasm_bindgen]
+#[derive(
+  ArgEnum, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default,
+)]
+#[repr(C)]
+pub enum SuperresMode {
+  /// Code every frame at its full width.
+  #[default]
+  Off,
+  /// Code every frame with the configured superres denominator.
+  Fixed,
+  /// Pick a pseudo-random denominator for each frame, including full
+  /// width frames. Mostly useful for testing.
+  Random,
+  /// Reduce the width of the frames whose base quantizer exceeds the
+  /// configured threshold, the more so the higher the quantizer.
+  Dynamic,
+}
+
+/// Size of the superblocks, the largest blocks into which the frames are
+/// partitioned.
+#[wasm_bindgen]
+#[derive(
+  ArgEnum, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default,
+)]
+#[repr(C)]
+pub enum SuperblockSize {
+  /// Use 128x128 superblocks for frames larger than 1080p, when the speed
+  /// settings allow 128x128 blocks, and 64x64 superblocks otherwise.
+  #[default]
+  Auto,
+  /// Always use 64x64 superblocks.
+  #[arg_enum(alias = "64")]
+  Sb64,
+  /// Always use 128x128 superblocks.
+  #[arg_enum(alias = "128")]
+  Sb128,
+}
+
 const FRAME_ID_LENGTH: u32 = 15;
 const DELTA_FRAME_ID_LENGTH: u32 = 14;
 
@@ -250,53 +331,25 @@ impl Sequence {
       [0; MAX_NUM_OPERATING_POINTS];
 
     // Restoration filters are not useful for very small frame sizes,
-    // so disable them in that case.
-    let enable_restoration_filters = config.width >= 32 && config.height >= 32;
-    let use_128x128_superblock = false;
+    // so disable them in that case. Lossless frames are never filtered.
+    let enable_restoration_filters =
+      config.width >= 32 && config.height >= 32 && !config.lossless;
+    let enable_masked_compound =
+      config.speed_settings.prediction.masked_compound
+        && !config.still_picture;
+    let use_128x128_superblock = match config.superblock_size {
+      SuperblockSize::Auto => {
+        config.width * config.height > 1920 * 1080
+          && config.speed_settings.partition.partition_range.max
+            == BlockSize::BLOCK_128X128
+      }
+      SuperblockSize::Sb64 => false,
+      SuperblockSize::Sb128 => true,
+    };
 
-    let frame_rate = config.frame_rate();
     let sb_size_log2 = Self::sb_size_log2(use_128x128_superblock);
-
-    let mut tiling = TilingInfo::from_target_tiles(
-      sb_size_log2,
-      config.width,
-      config.height,
-      frame_rate,
-      TilingInfo::tile_log2(1, config.tile_cols).unwrap(),
-      TilingInfo::tile_log2(1, config.tile_rows).unwrap(),
-      config.chroma_sampling == ChromaSubsampling::Yuv422,
-    );
-
-    if config.tiles > 0 {
-      let mut tile_rows_log2 = 0;
-      let mut tile_cols_log2 = 0;
-      while (tile_rows_log2 < tiling.max_tile_rows_log2)
-        || (tile_cols_log2 < tiling.max_tile_cols_log2)
-      {
-        tiling = TilingInfo::from_target_tiles(
-          sb_size_log2,
-          config.width,
-          config.height,
-          frame_rate,
-          tile_cols_log2,
-          tile_rows_log2,
-          config.chroma_sampling == ChromaSubsampling::Yuv422,
-        );
-
-        if tiling.rows * tiling.cols >= config.tiles {
-          break;
-        };
-
-        if ((tiling.tile_height_sb >= tiling.tile_width_sb)
-          && (tiling.tile_rows_log2 < tiling.max_tile_rows_log2))
-          || (tile_cols_log2 >= tiling.max_tile_cols_log2)
-        {
-          tile_rows_log2 += 1;
-        } else {
-          tile_cols_log2 += 1;
-        }
-      }
-    }
+    let tiling =
+      Self::tiling(config, sb_size_log2, config.width, config.height);
 
     Sequence {
       tiling,
@@ -317,20 +370,30 @@ impl Sequence {
       delta_frame_id_length: DELTA_FRAME_ID_LENGTH,
       use_128x128_superblock,
       order_hint_bits_minus_1: 5,
-      force_screen_content_tools: if config.still_picture { 2 } else { 0 },
+      force_screen_content_tools: if config.still_picture
+        || config.screen_content != ScreenContentMode::Off
+      {
+        2
+      } else {
+        0
+      },
       force_integer_mv: 2,
       still_picture: config.still_picture,
       reduced_still_picture_hdr: config.still_picture,
-      enable_filter_intra: false,
+      enable_filter_intra: config.speed_settings.prediction.filter_intra,
       enable_intra_edge_filter: true,
-      enable_interintra_compound: false,
-      enable_masked_compound: false,
-      enable_dual_filter: false,
+      enable_interintra_compound: enable_masked_compound,
+      enable_masked_compound,
+      enable_dual_filter: config.speed_settings.prediction.switchable_filter
+        && config.speed_settings.prediction.dual_filter
+        && !config.still_picture,
       enable_order_hint: !config.still_picture,
-      enable_jnt_comp: false,
+      enable_jnt_comp: config.speed_settings.prediction.distance_compound
+        && !config.still_picture,
       enable_ref_frame_mvs: false,
-      enable_warped_motion: false,
-      enable_superres: false,
+      enable_warped_motion: config.speed_settings.motion.warped_motion
+        && !config.still_picture,
+      enable_superres: config.superres_mode != SuperresMode::Off,
       enable_cdef: config.speed_settings.cdef && enable_restoration_filters,
       enable_restoration: config.speed_settings.lrf
         && enable_restoration_filters,
@@ -421,6 +484,57 @@ impl Sequence {
     }
   }
 
+  /// Returns the tiling of the frames coded `width` by `height` samples,
+  /// following the tile settings of `config`.
+  pub(crate) fn tiling(
+    config: &EncoderConfig, sb_size_log2: usize, width: usize, height: usize,
+  ) -> TilingInfo {
+    let frame_rate = config.frame_rate();
+
+    let mut tiling = TilingInfo::from_target_tiles(
+      sb_size_log2,
+      width,
+      height,
+      frame_rate,
+      TilingInfo::tile_log2(1, config.tile_cols).unwrap(),
+      TilingInfo::tile_log2(1, config.tile_rows).unwrap(),
+      config.chroma_sampling == ChromaSubsampling::Yuv422,
+    );
+
+    if config.tiles > 0 {
+      let mut tile_rows_log2 = 0;
+      let mut tile_cols_log2 = 0;
+      while (tile_rows_log2 < tiling.max_tile_rows_log2)
+        || (tile_cols_log2 < tiling.max_tile_cols_log2)
+      {
+        tiling = TilingInfo::from_target_tiles(
+          sb_size_log2,
+          width,
+          height,
+          frame_rate,
+          tile_cols_log2,
+          tile_rows_log2,
+          config.chroma_sampling == ChromaSubsampling::Yuv422,
+        );
+
+        if tiling.rows * tiling.cols >= config.tiles {
+          break;
+        };
+
+        if ((tiling.tile_height_sb >= tiling.tile_width_sb)
+          && (tiling.tile_rows_log2 < tiling.max_tile_rows_log2))
+          || (tile_cols_log2 >= tiling.max_tile_cols_log2)
+        {
+          tile_rows_log2 += 1;
+        } else {
+          tile_cols_log2 += 1;
+        }
+      }
+    }
+
+    tiling
+  }
+
   #[inline(always)]
   const fn sb_size_log2(use_128x128_superblock: bool) -> usize {
     6 + (use_128x128_superblock as usize)
@@ -598,6 +712,18 @@ impl<T: Pixel> FrameState<T> {
     }
   }
 
+  /// Clones the state with its own copy of the motion estimation statistics,
+  /// which a plain `clone()` shares, so that encoding the clone leaves this
+  /// state untouched.
+  pub(crate) fn deep_clone(&self) -> Self {
+    let frame_me_stats =
+      self.frame_me_stats.read().expect("poisoned lock").clone();
+    Self {
+      frame_me_stats: Arc::new(RwLock::new(frame_me_stats)),
+      ..self.clone()
+    }
+  }
+
   pub fn apply_tile_state_mut<F, R>(&mut self, f: F) -> R
   where
     F: FnOnce(&mut TileStateMut<'_, T>) -> R,
@@ -707,6 +833,12 @@ pub struct FrameInvariants<T: Pixel> {
   pub config: Arc<EncoderConfig>,
   pub width: usize,
   pub height: usize,
+  /// Width of the frame once upscaled by superres (`UpscaledWidth`), which
+  /// differs from `width` while the frame is coded at a reduced width.
+  pub upscaled_width: usize,
+  /// Superres denominator of the frame, `SUPERRES_NUM` if it is coded at
+  /// its full width.
+  pub superres_denom: u8,
   pub render_width: u32,
   pub render_height: u32,
   pub frame_size_override_flag: bool,
@@ -729,6 +861,7 @@ pub struct FrameInvariants<T: Pixel> {
   pub use_prev_frame_mvs: bool,
   pub partition_range: PartitionRange,
   pub globalmv_transformation_type: [GlobalMVMode; INTER_REFS_PER_FRAME],
+  pub globalmv_params: [[i32; 6]; INTER_REFS_PER_FRAME],
   pub num_tg: usize,
   pub large_scale_tile: bool,
   pub disable_cdf_update: bool,
@@ -951,12 +1084,30 @@ impl<T: Pixel> FrameInvariants<T> {
       && config.speed_settings.transform.tx_domain_distortion;
     let use_tx_domain_rate = config.speed_settings.transform.tx_domain_rate;
 
+    // Blocks are never larger than a superblock.
+    let sb_size = if sequence.use_128x128_superblock {
+      BlockSize::BLOCK_128X128
+    } else {
+      BlockSize::BLOCK_64X64
+    };
+    let mut partition_range = config.speed_settings.partition.partition_range;
+    if partition_range.max > sb_size {
+      let min = if partition_range.min > sb_size {
+        sb_size
+      } else {
+        partition_range.min
+      };
+      partition_range = PartitionRange::new(min, sb_size);
+    }
+
     let w_in_b = 2 * config.width.align_power_of_two_and_shift(3); // MiCols, ((width+7)/8)<<3 >> MI_SIZE_LOG2
     let h_in_b = 2 * config.height.align_power_of_two_and_shift(3); // MiRows, ((height+7)/8)<<3 >> MI_SIZE_LOG2
 
     Self {
       width,
       height,
+      upscaled_width: width,
+      superres_denom: SUPERRES_NUM,
       render_width: render_width as u32,
       render_height: render_height as u32,
       frame_size_override_flag,
@@ -977,23 +1128,25 @@ impl<T: Pixel> FrameInvariants<T> {
       use_reduced_tx_set,
       reference_mode: ReferenceMode::SINGLE,
       use_prev_frame_mvs: false,
-      partition_range: config.speed_settings.partition.partition_range,
+      partition_range,
       globalmv_transformation_type: [GlobalMVMode::IDENTITY;
         INTER_REFS_PER_FRAME],
+      globalmv_params: [DEFAULT_GLOBALMV_PARAMS; INTER_REFS_PER_FRAME],
       num_tg: 1,
       large_scale_tile: false,
       disable_cdf_update: false,
-      allow_screen_content_tools: sequence.force_screen_content_tools,
+      allow_screen_content_tools: (config.screen_content
+        == ScreenContentMode::On) as u32,
       force_integer_mv: 1,
       primary_ref_frame: PRIMARY_REF_NONE,
       refresh_frame_flags: ALL_REF_FRAMES_MASK,
-      allow_intrabc: false,
+      allow_intrabc: config.screen_content == ScreenContentMode::On,
       use_ref_frame_mvs: false,
       is_filter_switchable: false,
       is_motion_mode_switchable: false, // 0: only the SIMPLE motion mode will be used.
       disable_frame_end_update_cdf: sequence.reduced_still_picture_hdr,
       allow_warped_motion: false,
-      cdef_search_method: CDEFSearchMethod::PickFromQ,
+      cdef_search_method: config.speed_settings.cdef_search_method,
       cdef_damping: 3,
       cdef_bits: 0,
       cdef_y_strengths: [
@@ -1036,11 +1189,13 @@ impl<T: Pixel> FrameInvariants<T> {
       default_filter: FilterMode::REGULAR,
       cpu_feature_level: Default::default(),
       enable_segmentation: config.speed_settings.segmentation
-        != SegmentationLevel::Disabled,
+        != SegmentationLevel::Disabled
+        && !config.lossless,
       enable_inter_txfm_split: config
         .speed_settings
         .transform
-        .enable_inter_tx_split,
+        .enable_inter_tx_split
+        && !config.lossless,
       t35_metadata: Box::new([]),
       sequence,
       config,
@@ -1052,7 +1207,9 @@ impl<T: Pixel> FrameInvariants<T> {
     config: Arc<EncoderConfig>, sequence: Arc<Sequence>,
     gop_input_frameno_start: u64, t35_metadata: Box<[T35]>,
   ) -> Self {
-    let tx_mode_select = config.speed_settings.transform.rdo_tx_decision;
+    // Lossless frames can only use 4x4 transforms.
+    let tx_mode_select =
+      config.speed_settings.transform.rdo_tx_decision && !config.lossless;
     let mut fi = Self::new(config, sequence);
     fi.input_frameno = gop_input_frameno_start;
     fi.tx_mode_select = tx_mode_select;
@@ -1080,7 +1237,12 @@ impl<T: Pixel> FrameInvariants<T> {
     // quite large lookahead data for SEFs, when it is not needed.
     let mut fi = previous_coded_fi.clone_without_coded_data();
     fi.intra_only = false;
+    fi.allow_intrabc = false;
     fi.force_integer_mv = 0; // note: should be 1 if fi.intra_only is true
+                             // Global motion is estimated in the lookahead.
+    fi.globalmv_transformation_type =
+      [GlobalMVMode::IDENTITY; INTER_REFS_PER_FRAME];
+    fi.globalmv_params = [DEFAULT_GLOBALMV_PARAMS; INTER_REFS_PER_FRAME];
     fi.idx_in_group_output =
       inter_cfg.get_idx_in_group_output(output_frameno_in_gop);
     fi.tx_mode_select = fi.enable_inter_txfm_split;
@@ -1106,21 +1268,14 @@ impl<T: Pixel> FrameInvariants<T> {
     };
     fi.error_resilient =
       if fi.frame_type == FrameType::SWITCH { true } else { error_resilient };
+    fi.allow_warped_motion =
+      fi.sequence.enable_warped_motion && !fi.error_resilient;
+    fi.is_motion_mode_switchable =
+      fi.allow_warped_motion || fi.config.speed_settings.motion.obmc;
+    fi.is_filter_switchable =
+      fi.config.speed_settings.prediction.switchable_filter;
 
-    fi.frame_size_override_flag = if fi.frame_type == FrameType::SWITCH {
-      true
-    } else if fi.sequence.reduced_still_picture_hdr {
-      false
-    } else if fi.frame_type == FrameType::INTER
-      && !fi.error_resilient
-      && fi.render_and_frame_size_different
-    {
-      // force frame_size_with_refs() code path if render size != frame size
-      true
-    } else {
-      fi.width as u32 != fi.sequence.max_frame_width
-        || fi.height as u32 != fi.sequence.max_frame_height
-    };
+    fi.frame_size_override_flag = fi.frame_size_override();
 
     // this is the slot that the current frame is going to be saved into
     let slot_idx = inter_cfg.get_slot_idx(fi.pyramid_level, fi.order_hint);
@@ -1231,6 +1386,8 @@ impl<T: Pixel> FrameInvariants<T> {
       config: self.config.clone(),
       width: self.width,
       height: self.height,
+      upscaled_width: self.upscaled_width,
+      superres_denom: self.superres_denom,
       render_width: self.render_width,
       render_height: self.render_height,
       frame_size_override_flag: self.frame_size_override_flag,
@@ -1253,6 +1410,7 @@ impl<T: Pixel> FrameInvariants<T> {
       use_prev_frame_mvs: self.use_prev_frame_mvs,
       partition_range: self.partition_range,
       globalmv_transformation_type: self.globalmv_transformation_type,
+      globalmv_params: self.globalmv_params,
       num_tg: self.num_tg,
       large_scale_tile: self.large_scale_tile,
       disable_cdf_update: self.disable_cdf_update,
@@ -1315,12 +1473,11 @@ impl<T: Pixel> FrameInvariants<T> {
     if self.frame_type == FrameType::KEY {
       FRAME_SUBTYPE_I
     } else {
-      FRAME_SUBTYPE_P + (self.pyramid_level as usize)
+      inter_frame_subtype(self.pyramid_level)
     }
   }
 
   fn pick_strength_from_q(&mut self, qps: &QuantizerParameters) {
-    self.cdef_damping = 3 + (self.base_q_idx >> 6);
     let q = bexp64(qps.log_target_q + q57(QSCALE)) as f32;
     /* These coefficients were trained on libaom. */
     let (y_f1, y_f2, uv_f1, uv_f2) = if !self.intra_only {
@@ -1346,21 +1503,356 @@ impl<T: Pixel> FrameInvariants<T> {
   pub fn set_quantizers(&mut self, qps: &QuantizerParameters) {
     self.base_q_idx = qps.ac_qi[0];
     let base_q_idx = self.base_q_idx as i32;
+    // The offsets requested by the user, on top of the ones selected by the
+    //  rate control.
+    let [dc_u, dc_v] = self.config.chroma_dc_delta_q;
+    let [ac_u, ac_v] = self.config.chroma_ac_delta_q;
+    let (dc_offsets, ac_offsets) =
+      if self.sequence.chroma_sampling == ChromaSubsampling::Monochrome {
+        ([self.config.luma_dc_delta_q, 0, 0], [0; 3])
+      } else {
+        ([self.config.luma_dc_delta_q, dc_u, dc_v], [0, ac_u, ac_v])
+      };
+    // Keep the indices within the range that the frame header can signal.
+    let delta_q = |qi: u8, offset: i8| {
+      let min_qi = (base_q_idx - 63).max(0);
+      let max_qi = (base_q_idx + 63).min(255);
+      ((qi as i32 + offset as i32).clamp(min_qi, max_qi) - base_q_idx) as i8
+    };
     for pi in 0..3 {
-      self.dc_delta_q[pi] = (qps.dc_qi[pi] as i32 - base_q_idx) as i8;
-      self.ac_delta_q[pi] = (qps.ac_qi[pi] as i32 - base_q_idx) as i8;
+      self.dc_delta_q[pi] = delta_q(qps.dc_qi[pi], dc_offsets[pi]);
+      self.ac_delta_q[pi] = delta_q(qps.ac_qi[pi], ac_offsets[pi]);
     }
-    self.lambda =
-      qps.lambda * ((1 << (2 * (self.sequence.bit_depth - 8))) as f64);
+    // The offsets and the clamping move the signaled quantizers away from
+    //  the ones the rate control selected, so scale the lambda and the
+    //  distortion weight of each plane by the ratio of the squared steps,
+    //  the product of the DC and AC steps, that are actually coded.
+    let bit_depth = self.sequence.bit_depth;
+    let mono = self.sequence.chroma_sampling == ChromaSubsampling::Monochrome;
+    let step_ratio: [f64; 3] = std::array::from_fn(|pi| {
+      if mono && pi > 0 {
+        return 1.;
+      }
+      let coded = dc_q(self.base_q_idx, self.dc_delta_q[pi], bit_depth).get()
+        as f64
+        * ac_q(self.base_q_idx, self.ac_delta_q[pi], bit_depth).get() as f64;
+      let selected = dc_q(qps.dc_qi[pi], 0, bit_depth).get() as f64
+        * ac_q(qps.ac_qi[pi], 0, bit_depth).get() as f64;
+      coded / selected
+    });
+    self.lambda = qps.lambda
+      * step_ratio[0]
+      * ((1 << (2 * (self.sequence.bit_depth - 8))) as f64);
     self.me_lambda = self.lambda.sqrt();
-    self.dist_scale = qps.dist_scale.map(DistortionScale::from);
+    self.dist_scale = std::array::from_fn(|pi| {
+      DistortionScale::from(
+        qps.dist_scale[pi] * step_ratio[0] / step_ratio[pi],
+      )
+    });
 
+    self.cdef_damping = 3 + (self.base_q_idx >> 6);
     match self.cdef_search_method {
       CDEFSearchMethod::PickFromQ => {
         self.pick_strength_from_q(qps);
       }
-      // TODO: implement FastSearch and FullSearch
-      _ => unreachable!(),
+      // The strengths are searched on the reconstruction, once the
+      // tiles of the frame have been encoded.
+      CDEFSearchMethod::FastSearch | CDEFSearchMethod::FullSearch => {}
+    }
+  }
+
+  /// Returns whether every block of the frame is coded losslessly
+  /// (`CodedLossless` in the AV1 specification).
+  ///
+  /// Segmentation is never enabled along with a base quantizer index of 0,
+  /// so this only depends on the frame quantizers.
+  #[inline]
+  pub fn coded_lossless(&self) -> bool {
+    self.base_q_idx == 0
+      && self.dc_delta_q.iter().chain(&self.ac_delta_q).all(|&dq| dq == 0)
+  }
+
+  /// Returns whether CDEF is applied to the frame. The spec disables it in
+  /// lossless frames and in frames that allow intra block copy.
+  #[inline]
+  pub fn cdef_enabled(&self) -> bool {
+    self.sequence.enable_cdef && !self.allow_intrabc && !self.coded_lossless()
+  }
+
+  /// Returns whether loop restoration is applied to the frame, which is
+  /// disabled in the same frames as CDEF.
+  #[inline]
+  pub fn restoration_enabled(&self) -> bool {
+    self.sequence.enable_restoration
+      && !self.allow_intrabc
+      && !self.coded_lossless()
+  }
+
+  /// Returns whether `ref_frame` differs in size from the frame, which is
+  /// then predicted from it by scaled motion compensation (`is_scaled` in
+  /// the AV1 specification).
+  pub fn is_scaled(&self, ref_frame: RefType) -> bool {
+    let no_scale = 1 << REF_SCALE_SHIFT;
+    self.rec_buffer.frames[self.ref_frames[ref_frame.to_index()] as usize]
+      .as_ref()
+      .is_some_and(|rec| {
+        ref_scale(rec.width as usize, self.width) != no_scale
+          || ref_scale(rec.height as usize, self.height) != no_scale
+      })
+  }
+
+  /// Returns the distance between the order hints of `ref_frame` and the
+  /// frame.
+  pub fn ref_frame_distance(&self, ref_frame: RefType) -> u32 {
+    self.rec_buffer.frames[self.ref_frames[ref_frame.to_index()] as usize]
+      .as_ref()
+      .map_or(0, |rec| {
+        self
+          .sequence
+          .get_relative_dist(rec.order_hint, self.order_hint)
+          .unsigned_abs()
+      })
+  }
+
+  /// Returns the weights, in units of 1/16, of the two references of a
+  /// compound block weighted by their distance to the frame (`FwdWeight`
+  /// and `BckWeight` in the AV1 specification). The closer reference gets
+  /// the larger weight.
+  pub fn distance_weights(&self, ref_frames: [RefType; 2]) -> [u8; 2] {
+    distance_weights(
+      self.ref_frame_distance(ref_frames[1]),
+      self.ref_frame_distance(ref_frames[0]),
+    )
+  }
+
+  /// Returns whether the size of the frame is coded in its header rather
+  /// than taken from the sequence header (`frame_size_override_flag`).
+  fn frame_size_override(&self) -> bool {
+    if self.frame_type == FrameType::SWITCH {
+      true
+    } else if self.sequence.reduced_still_picture_hdr {
+      false
+    } else if self.frame_type == FrameType::INTER
+      && !self.error_resilient
+      && self.render_and_frame_size_different
+    {
+      // force frame_size_with_refs() code path if render size != frame size
+      true
+    } else {
+      self.upscaled_width as u32 != self.sequence.max_frame_width
+        || self.height as u32 != self.sequence.max_frame_height
+    }
+  }
+
+  /// Returns the size closest to `width` by `height` that the frame can be
+  /// coded at: no larger than the sequence allows and, for inter frames,
+  /// between half and 16 times the size of each of its references.
+  pub(crate) fn clamp_frame_size(
+    &self, width: usize, height: usize,
+  ) -> (usize, usize) {
+    let max_w = self.sequence.max_frame_width as usize;
+    let max_h = self.sequence.max_frame_height as usize;
+    let mut w = width.clamp(max_w.min(16), max_w);
+    let mut h = height.clamp(max_h.min(16), max_h);
+    if !self.intra_only {
+      for &idx in self.ref_frames.iter() {
+        if let Some(rec) = self.rec_buffer.frames[idx as usize].as_ref() {
+          let (ref_w, ref_h) = (rec.width as usize, rec.height as usize);
+          w = w.min(16 * ref_w).max(ref_w.div_ceil(2));
+          h = h.min(16 * ref_h).max(ref_h.div_ceil(2));
+        }
+      }
+    }
+    (w, h)
+  }
+
+  /// Returns the smallest width the frame can be coded at with superres,
+  /// half the upscaled width of its widest reference.
+  fn min_superres_width(&self) -> usize {
+    if self.intra_only {
+      return 0;
+    }
+    self
+      .ref_frames
+      .iter()
+      .filter_map(|&idx| self.rec_buffer.frames[idx as usize].as_ref())
+      .map(|rec| (rec.width as usize).div_ceil(2))
+      .max()
+      .unwrap_or(0)
+  }
+
+  /// Chooses the superres denominator of the frame as configured by
+  /// `superres_mode`, once its quantizers are known. Frames using intra
+  /// block copy or coded losslessly keep their full width. Unless it is
+  /// fixed, the denominator is adjusted to code horizontally subsampled
+  /// chroma at an even width.
+  ///
+  /// Global motion is estimated at the full width, so it is dropped from
+  /// frames coded at a reduced width.
+  pub(crate) fn set_superres_denom(&mut self) {
+    let config = &self.config;
+    let denom = match config.superres_mode {
+      _ if self.allow_intrabc || self.coded_lossless() => SUPERRES_NUM,
+      SuperresMode::Off => SUPERRES_NUM,
+      SuperresMode::Fixed => config.superres_denom,
+      SuperresMode::Random => random_denom(self.input_frameno),
+      SuperresMode::Dynamic => {
+        dynamic_denom(self.base_q_idx, config.superres_qthresh)
+      }
+    };
+    let adjust = config.superres_mode != SuperresMode::Fixed
+      && matches!(
+        self.sequence.chroma_sampling,
+        ChromaSubsampling::Yuv420 | ChromaSubsampling::Yuv422
+      );
+    let mut denom = if adjust {
+      even_width_denom(self.upscaled_width, denom)
+    } else {
+      denom
+    };
+    // References may be at most twice as wide as the coded frame.
+    let min_width = self.min_superres_width();
+    while denom > SUPERRES_NUM
+      && superres_width(self.upscaled_width, denom) < min_width
+    {
+      denom -= 1;
+    }
+    self.superres_denom = denom;
+    if self.superres_denom != SUPERRES_NUM {
+      self.globalmv_transformation_type =
+        [GlobalMVMode::IDENTITY; INTER_REFS_PER_FRAME];
+      self.globalmv_params = [DEFAULT_GLOBALMV_PARAMS; INTER_REFS_PER_FRAME];
+    }
+  }
+
+  /// Returns the invariants of the frame coded at `width` by `height`,
+  /// with the lookahead data resampled to that size.
+  fn coded_at(&self, width: usize, height: usize) -> Self {
+    let mut fi = self.clone();
+    fi.width = width;
+    fi.height = height;
+    fi.sb_width = width.align_power_of_two_and_shift(6);
+    fi.sb_height = height.align_power_of_two_and_shift(6);
+    fi.w_in_b = 2 * width.align_power_of_two_and_shift(3);
+    fi.h_in_b = 2 * height.align_power_of_two_and_shift(3);
+    fi.sequence = Arc::new(Sequence {
+      tiling: Sequence::tiling(
+        &self.config,
+        self.sb_size_log2(),
+        width,
+        height,
+      ),
+      ..*self.sequence
+    });
+
+    if let Some(coded_data) = fi.coded_frame_data.as_mut() {
+      let (src_w, src_h) = (coded_data.w_in_imp_b, coded_data.h_in_imp_b);
+      let (dst_w, dst_h) = (fi.w_in_b / 2, fi.h_in_b / 2);
+      macro_rules! resample {
+        ($($grid:ident),+) => {
+          $(coded_data.$grid =
+            resample_grid(&coded_data.$grid, src_w, src_h, dst_w, dst_h);)+
+        };
+      }
+      resample!(
+        lookahead_intra_costs,
+        block_importances,
+        distortion_scales,
+        activity_scales,
+        spatiotemporal_scores
+      );
+      coded_data.w_in_imp_b = dst_w;
+      coded_data.h_in_imp_b = dst_h;
+    }
+    fi
+  }
+
+  /// Returns the invariants of the frame coded at `width` by `height`
+  /// rather than at the size of its input, as requested through
+  /// [`FrameParameters::frame_size`].
+  ///
+  /// Global motion is estimated at the size of the input, so it is
+  /// dropped from the resized frame.
+  ///
+  /// [`FrameParameters::frame_size`]: crate::frame::FrameParameters::frame_size
+  pub(crate) fn resized(&self, width: usize, height: usize) -> Self {
+    let mut fi = self.coded_at(width, height);
+    fi.upscaled_width = width;
+    fi.render_and_frame_size_different =
+      fi.render_width as usize != width || fi.render_height as usize != height;
+    fi.frame_size_override_flag = fi.frame_size_override();
+    fi.globalmv_transformation_type =
+      [GlobalMVMode::IDENTITY; INTER_REFS_PER_FRAME];
+    fi.globalmv_params = [DEFAULT_GLOBALMV_PARAMS; INTER_REFS_PER_FRAME];
+    fi
+  }
+
+  /// Returns the invariants of the frame as coded at its reduced superres
+  /// width, with the lookahead data and the references used by the motion
+  /// search resampled to that width. Loop restoration applies to the
+  /// upscaled frame, so it is disabled while coding the tiles.
+  fn superres_coded(&self) -> Self {
+    let width = superres_width(self.upscaled_width, self.superres_denom);
+    let mut fi = self.coded_at(width, self.height);
+    fi.sequence =
+      Arc::new(Sequence { enable_restoration: false, ..*fi.sequence });
+    fi.scale_refs();
+    fi
+  }
+
+  /// Returns whether any reference differs in size from the frame.
+  fn has_scaled_refs(&self) -> bool {
+    !self.intra_only
+      && ALL_INTER_REFS.iter().any(|&ref_frame| self.is_scaled(ref_frame))
+  }
+
+  /// Replaces the references of a different size than the frame by copies
+  /// with the luma plane and the motion statistics used by the motion
+  /// search resampled to its size.
+  fn scale_refs(&mut self) {
+    let (width, height) = (self.width, self.height);
+    // References are shared between slots, so resample each only once.
+    let mut scaled: Vec<(Arc<_>, Arc<ReferenceFrame<T>>)> = Vec::new();
+    for rec in self.rec_buffer.frames.iter_mut().flatten() {
+      if let Some((_, done)) =
+        scaled.iter().find(|(orig, _)| Arc::ptr_eq(orig, rec))
+      {
+        *rec = done.clone();
+        continue;
+      }
+      let (ref_w, ref_h) = (rec.width as usize, rec.height as usize);
+      if (ref_w, ref_h) == (width, height) {
+        continue;
+      }
+      let y_plane =
+        resampled_plane(&rec.frame.y_plane, ref_w, ref_h, width, height);
+      let frame_me_stats = {
+        let me_stats = rec.frame_me_stats.read().expect("poisoned lock");
+        Arc::new(RwLock::new(std::array::from_fn(|i| {
+          me_stats[i].resampled(self.w_in_b, self.h_in_b)
+        })))
+      };
+      let coded = Arc::new(ReferenceFrame {
+        scaled_y_plane: Some(Arc::new(y_plane)),
+        frame_me_stats,
+        ..(**rec).clone()
+      });
+      scaled.push((mem::replace(rec, coded.clone()), coded));
+    }
+  }
+
+  /// Enables or disables the screen content tools for this frame, as
+  /// decided by the screen content detector. Intra frames also use intra
+  /// block copy, while inter frames may restrict motion vectors to whole
+  /// pixels.
+  pub(crate) fn set_screen_content(
+    &mut self, decision: ScreenContentDecision,
+  ) {
+    self.allow_screen_content_tools = decision.screen_content as u32;
+    if self.intra_only {
+      self.allow_intrabc = decision.screen_content;
+    } else {
+      self.force_integer_mv = decision.integer_mv as u32;
     }
   }
 
@@ -1369,6 +1861,15 @@ impl<T: Pixel> FrameInvariants<T> {
     self.sequence.tiling.sb_size_log2
   }
 
+  #[inline(always)]
+  pub fn sb_size(&self) -> BlockSize {
+    if self.sequence.use_128x128_superblock {
+      BlockSize::BLOCK_128X128
+    } else {
+      BlockSize::BLOCK_64X64
+    }
+  }
+
   pub fn film_grain_params(&self) -> Option<&GrainTableSegment> {
     if !(self.show_frame || self.showable_frame) {
       return None;
@@ -1386,6 +1887,35 @@ impl<T: Pixel> FrameInvariants<T> {
   }
 }
 
+/// Returns `FwdWeight` and `BckWeight` of a distance-weighted compound
+/// block, given the distances `d0` of its second reference and `d1` of its
+/// first one, as in `av1_dist_wtd_comp_weight_assign()` of libaom.
+fn distance_weights(d0: u32, d1: u32) -> [u8; 2] {
+  const MAX_FRAME_DISTANCE: u32 = 31;
+  const QUANT_DIST_WEIGHT: [[u32; 2]; 3] = [[2, 3], [2, 5], [2, 7]];
+  const QUANT_DIST_LOOKUP: [[u8; 2]; 4] = [[9, 7], [11, 5], [12, 4], [13, 3]];
+
+  let d0 = d0.min(MAX_FRAME_DISTANCE);
+  let d1 = d1.min(MAX_FRAME_DISTANCE);
+  let order = (d0 <= d1) as usize;
+  let i = if d0 == 0 || d1 == 0 {
+    3
+  } else {
+    QUANT_DIST_WEIGHT
+      .iter()
+      .position(|w| {
+        let (d0_c0, d1_c1) = (d0 * w[order], d1 * w[1 - order]);
+        if order == 1 {
+          d0_c0 > d1_c1
+        } else {
+          d0_c0 < d1_c1
+        }
+      })
+      .unwrap_or(3)
+  };
+  [QUANT_DIST_LOOKUP[i][order], QUANT_DIST_LOOKUP[i][1 - order]]
+}
+
 impl<T: Pixel> fmt::Display for FrameInvariants<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(f, "Input Frame {} - {}", self.input_frameno, self.frame_type)
@@ -1400,6 +1930,37 @@ pub fn write_temporal_delimiter(packet: &mut dyn io::Write) -> io::Result<()> {
   Ok(())
 }
 
+/// Writes a padding OBU of exactly `size` bytes, which must be at least 2.
+///
+/// # Errors
+///
+/// - If the frame packet cannot be written to
+pub fn write_padding_obu(
+  packet: &mut dyn io::Write, size: usize,
+) -> io::Result<()> {
+  debug_assert!(size >= 2);
+  let mut buf = Vec::with_capacity(size);
+  {
+    let mut bw = BitWriter::endian(&mut buf, BigEndian);
+    bw.write_obu_header(ObuType::OBU_PADDING, 0)?;
+  }
+  // The payload length may use more bytes than its minimal leb128 coding,
+  //  so pick the fewest that can code what is left after them.
+  let size = size as u64;
+  let mut len_bytes = 1;
+  while size - 1 - len_bytes >= 1 << (7 * len_bytes) {
+    len_bytes += 1;
+  }
+  let payload = size - 1 - len_bytes;
+  for i in 0..len_bytes {
+    let byte = ((payload >> (7 * i)) & 0x7f) as u8;
+    // Signal that more bytes follow on all but the last byte.
+    buf.push(if i + 1 < len_bytes { byte | 0x80 } else { byte });
+  }
+  buf.resize(size as usize, 0);
+  packet.write_all(&buf)
+}
+
 fn write_key_frame_obus<T: Pixel>(
   packet: &mut dyn io::Write, fi: &FrameInvariants<T>, obu_extension: u32,
 ) -> io::Result<()> {
@@ -1591,8 +2152,48 @@ where
   let frame_bo = ts.to_frame_block_offset(tx_bo);
   let rec = &mut ts.rec.planes[p];
 
-  if mode.is_intra() {
-    let bit_depth = fi.sequence.bit_depth;
+  let palette = cw.bc.blocks[tile_partition_bo].palette;
+  let filter_intra_mode =
+    cw.bc.blocks[tile_partition_bo].filter_intra_mode.filter(|_| p == 0);
+  if mode.is_intra() && palette.plane_size(p) > 0 {
+    let n = palette.plane_size(p);
+    let (plane_xdec, plane_ydec) = if p == 0 { (0, 0) } else { (xdec, ydec) };
+    let (_, _, visible_w, visible_h) = palette_map_size(
+      bsize,
+      (ts.mi_width - tile_partition_bo.0.x) * MI_SIZE,
+      (ts.mi_height - tile_partition_bo.0.y) * MI_SIZE,
+      plane_xdec,
+      plane_ydec,
+    );
+    let block_area = Area::BlockStartingAt { bo: tile_partition_bo.0 };
+    let origin = block_area.to_rect(plane_xdec, plane_ydec, 0, 0);
+    let planes = if p == 0 { 0..1 } else { 1..3 };
+    let src: ArrayVec<_, 2> = planes
+      .clone()
+      .map(|pli| ts.input_tile.planes[pli].subregion(block_area))
+      .collect();
+    let colors: ArrayVec<_, 2> =
+      planes.map(|pli| &palette.colors[pli][..n]).collect();
+    let mut map = [0u8; 64 * 64];
+    palette_color_map(
+      &src,
+      &colors,
+      (po.x - origin.x) as usize,
+      (po.y - origin.y) as usize,
+      tx_size.width(),
+      tx_size.height(),
+      visible_w,
+      visible_h,
+      &mut map,
+    );
+    predict_palette(
+      &mut rec.subregion_mut(area),
+      &palette.colors[p][..n],
+      &map,
+      tx_size.width(),
+      tx_size.height(),
+    );
+  } else if let Some(filter_intra_mode) = filter_intra_mode {
     let mut edge_buf = Aligned::uninit_array();
     let edge_buf = get_intra_edges(
       &mut edge_buf,
@@ -1601,12 +2202,38 @@ where
       bx,
       by,
       bsize,
+      fi.sb_size_log2(),
       po,
       tx_size,
-      bit_depth,
-      Some(mode),
+      fi.sequence.bit_depth,
+      None,
       fi.sequence.enable_intra_edge_filter,
-      pred_intra_param,
+      IntraParam::None,
+    );
+
+    filter_intra_mode.predict_intra(
+      &mut rec.subregion_mut(area),
+      tx_size,
+      fi.sequence.bit_depth,
+      &edge_buf,
+    );
+  } else if mode.is_intra() {
+    let bit_depth = fi.sequence.bit_depth;
+    let mut edge_buf = Aligned::uninit_array();
+    let edge_buf = get_intra_edges(
+      &mut edge_buf,
+      &rec.as_const(),
+      tile_partition_bo,
+      bx,
+      by,
+      bsize,
+      fi.sb_size_log2(),
+      po,
+      tx_size,
+      bit_depth,
+      Some(mode),
+      fi.sequence.enable_intra_edge_filter,
+      pred_intra_param,
     );
 
     mode.predict_intra(
@@ -1661,12 +2288,23 @@ where
   // SAFETY: `diff()` inits `tx_size.area()` elements when it matches size of `subregion(area)`
   let residual = unsafe { slice_assume_init_mut(residual) };
 
+  // Lossless blocks signal DCT_DCT, which selects the scan order and
+  // coefficient contexts, but are coded with the Walsh-Hadamard transform.
+  let lossless = qidx == 0;
+  let txfm_type = if lossless {
+    debug_assert_eq!(tx_size, TxSize::TX_4X4);
+    debug_assert_eq!(tx_type, TxType::DCT_DCT);
+    TxType::WHT_WHT
+  } else {
+    tx_type
+  };
+
   forward_transform(
     residual,
     coeffs,
     tx_size.width(),
     tx_size,
-    tx_type,
+    txfm_type,
     fi.sequence.bit_depth,
     fi.cpu_feature_level,
   );
@@ -1691,13 +2329,16 @@ where
       tx_bo,
       qcoeffs,
       eob,
-      mode,
+      // Filter intra blocks take the transform type context of the
+      // direction their filter resembles.
+      filter_intra_mode.map_or(mode, FilterIntraMode::intra_dir),
       tx_size,
       tx_type,
       plane_bsize,
       xdec,
       ydec,
       fi.use_reduced_tx_set,
+      lossless,
       frame_clipped_txw,
       frame_clipped_txh,
     )
@@ -1728,7 +2369,7 @@ where
       &mut rec.subregion_mut(area),
       eob,
       tx_size,
-      tx_type,
+      txfm_type,
       fi.sequence.bit_depth,
       fi.cpu_feature_level,
     );
@@ -1780,6 +2421,34 @@ where
   (has_coeff, tx_dist)
 }
 
+/// Predicts an intra block copy block from the reconstruction of the
+/// current frame.
+fn intrabc_compensate<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>, dv: MotionVector,
+  bsize: BlockSize, tile_bo: TileBlockOffset, luma_only: bool,
+) {
+  // Smaller blocks would share their chroma block with their neighbors.
+  debug_assert!(bsize.width() >= 8 && bsize.height() >= 8);
+  let num_planes = if luma_only
+    || fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    3
+  };
+  for rec in &mut ts.rec.planes[..num_planes] {
+    let PlaneConfig { xdec, ydec, .. } = rec.plane_cfg;
+    let po = tile_bo.plane_offset(&rec.plane_cfg);
+    predict_intrabc(
+      rec,
+      po,
+      bsize.width() >> xdec,
+      bsize.height() >> ydec,
+      dv,
+    );
+  }
+}
+
 /// # Panics
 ///
 /// - If the block size is invalid for subsampling
@@ -1792,6 +2461,11 @@ pub fn motion_compensate<T: Pixel>(
 ) {
   debug_assert!(!luma_mode.is_intra());
 
+  if ref_frames[0] == INTRA_FRAME {
+    intrabc_compensate(fi, ts, mvs[0], bsize, tile_bo, luma_only);
+    return;
+  }
+
   let (u_xdec, u_ydec) = if fi.sequence.chroma_sampling
     == ChromaSubsampling::Monochrome
   {
@@ -1817,6 +2491,17 @@ pub fn motion_compensate<T: Pixel>(
       0
     };
 
+  let motion_mode = cw.bc.blocks[tile_bo].motion_mode;
+  let local_warp = if motion_mode == MotionMode::WARPED_CAUSAL {
+    cw.find_local_warp(tile_bo, bsize, ref_frames[0], mvs[0], fi)
+  } else {
+    None
+  };
+  let obmc_neighbors = (motion_mode == MotionMode::OBMC_CAUSAL)
+    .then(|| cw.obmc_neighbors(tile_bo, bsize));
+  let compound = cw.bc.blocks[tile_bo].compound;
+  let interp_filter = cw.bc.blocks[tile_bo].interp_filter;
+
   let luma_tile_rect = ts.tile_rect();
   let compound_buffer = &mut ts.inter_compound_buffers;
   for p in 0..num_planes {
@@ -1860,6 +2545,7 @@ pub fn motion_compensate<T: Pixel>(
           plane_bsize.height(),
           ref_frames,
           mvs,
+          interp_filter,
           compound_buffer,
         );
       } else {
@@ -1868,12 +2554,16 @@ pub fn motion_compensate<T: Pixel>(
         if bsize == BlockSize::BLOCK_4X4 {
           let mv0 = cw.bc.blocks[tile_bo.with_offset(-1, -1)].mv;
           let rf0 = cw.bc.blocks[tile_bo.with_offset(-1, -1)].ref_frames;
+          let filter0 =
+            cw.bc.blocks[tile_bo.with_offset(-1, -1)].interp_filter;
           let mv1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].mv;
           let rf1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].ref_frames;
+          let filter1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].interp_filter;
           let po1 = PlaneOffset { x: po.x + 2, y: po.y };
           let area1 = Area::StartingAt { x: po1.x, y: po1.y };
           let mv2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].mv;
           let rf2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].ref_frames;
+          let filter2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].interp_filter;
           let po2 = PlaneOffset { x: po.x, y: po.y + 2 };
           let area2 = Area::StartingAt { x: po2.x, y: po2.y };
           let po3 = PlaneOffset { x: po.x + 2, y: po.y + 2 };
@@ -1888,6 +2578,7 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf0,
             mv0,
+            filter0,
             compound_buffer,
           );
           luma_mode.predict_inter(
@@ -1900,6 +2591,7 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf1,
             mv1,
+            filter1,
             compound_buffer,
           );
           luma_mode.predict_inter(
@@ -1912,6 +2604,7 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf2,
             mv2,
+            filter2,
             compound_buffer,
           );
           luma_mode.predict_inter(
@@ -1924,12 +2617,14 @@ pub fn motion_compensate<T: Pixel>(
             2,
             ref_frames,
             mvs,
+            interp_filter,
             compound_buffer,
           );
         }
         if bsize == BlockSize::BLOCK_8X4 {
           let mv1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].mv;
           let rf1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].ref_frames;
+          let filter1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].interp_filter;
           luma_mode.predict_inter(
             fi,
             tile_rect,
@@ -1940,6 +2635,7 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf1,
             mv1,
+            filter1,
             compound_buffer,
           );
           let po3 = PlaneOffset { x: po.x, y: po.y + 2 };
@@ -1954,12 +2650,14 @@ pub fn motion_compensate<T: Pixel>(
             2,
             ref_frames,
             mvs,
+            interp_filter,
             compound_buffer,
           );
         }
         if bsize == BlockSize::BLOCK_4X8 {
           let mv2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].mv;
           let rf2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].ref_frames;
+          let filter2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].interp_filter;
           luma_mode.predict_inter(
             fi,
             tile_rect,
@@ -1970,6 +2668,7 @@ pub fn motion_compensate<T: Pixel>(
             4,
             rf2,
             mv2,
+            filter2,
             compound_buffer,
           );
           let po3 = PlaneOffset { x: po.x + 2, y: po.y };
@@ -1984,10 +2683,59 @@ pub fn motion_compensate<T: Pixel>(
             4,
             ref_frames,
             mvs,
+            interp_filter,
             compound_buffer,
           );
         }
       }
+    } else if let Some(params) = local_warp
+      .as_ref()
+      .filter(|_| plane_bsize.width() >= 8 && plane_bsize.height() >= 8)
+    {
+      luma_mode.predict_inter_warped(
+        fi,
+        tile_rect,
+        p,
+        po,
+        &mut rec.subregion_mut(area),
+        plane_bsize.width(),
+        plane_bsize.height(),
+        ref_frames[0],
+        params,
+      );
+    } else if let CompoundPrediction::InterIntra { mode, wedge_index } =
+      compound
+    {
+      predict_inter_intra(
+        fi,
+        tile_rect,
+        p,
+        rec,
+        tile_bo,
+        bsize,
+        luma_mode,
+        ref_frames[0],
+        mvs[0],
+        interp_filter,
+        mode,
+        wedge_index,
+      );
+    } else if compound != CompoundPrediction::Average {
+      luma_mode.predict_inter_masked(
+        fi,
+        tile_rect,
+        p,
+        po,
+        &mut rec.subregion_mut(area),
+        plane_bsize.width(),
+        plane_bsize.height(),
+        ref_frames,
+        mvs,
+        interp_filter,
+        bsize,
+        compound,
+        compound_buffer,
+      );
     } else {
       luma_mode.predict_inter(
         fi,
@@ -1999,8 +2747,21 @@ pub fn motion_compensate<T: Pixel>(
         plane_bsize.height(),
         ref_frames,
         mvs,
+        interp_filter,
         compound_buffer,
       );
+      if let Some((above, left)) = &obmc_neighbors {
+        blend_obmc(
+          fi,
+          tile_rect,
+          p,
+          po,
+          &mut rec.subregion_mut(area),
+          bsize,
+          above,
+          left,
+        );
+      }
     }
   }
 }
@@ -2021,8 +2782,8 @@ pub fn save_block_motion<T: Pixel>(
 
 #[profiling::function]
 pub fn encode_block_pre_cdef<T: Pixel, W: Writer>(
-  seq: &Sequence, ts: &TileStateMut<'_, T>, cw: &mut ContextWriter, w: &mut W,
-  bsize: BlockSize, tile_bo: TileBlockOffset, skip: bool,
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, cw: &mut ContextWriter,
+  w: &mut W, bsize: BlockSize, tile_bo: TileBlockOffset, skip: bool,
 ) -> bool {
   cw.bc.blocks.set_skip(tile_bo, bsize, skip);
   if ts.segmentation.enabled
@@ -2050,12 +2811,71 @@ pub fn encode_block_pre_cdef<T: Pixel, W: Writer>(
       ts.segmentation.last_active_segid,
     );
   }
-  if !skip && seq.enable_cdef {
+  if !skip && fi.cdef_enabled() {
+    // Each 64x64 unit codes its CDEF index at its first non-skip block.
+    let sbo = TileSuperBlockOffset(SuperBlockOffset {
+      x: tile_bo.0.x >> SUPERBLOCK_TO_BLOCK_SHIFT,
+      y: tile_bo.0.y >> SUPERBLOCK_TO_BLOCK_SHIFT,
+    });
+    if !cw.bc.cdef_units.iter().any(|unit| unit.sbo == sbo) {
+      // The indices are coded once the loop filters are decided, the first
+      // one of the superblock between its pre and post CDEF symbols and the
+      // following ones where recorded here.
+      let position = cw.bc.cdef_coded.then(|| w.checkpoint());
+      cw.bc.cdef_units.push(CdefUnit { sbo, position });
+    }
     cw.bc.cdef_coded = true;
   }
   cw.bc.cdef_coded
 }
 
+/// Whether a luma block may be predicted with the recursive intra filters.
+pub fn filter_intra_allowed<T: Pixel>(
+  fi: &FrameInvariants<T>, luma_mode: PredictionMode, palette: &PaletteParams,
+  bsize: BlockSize,
+) -> bool {
+  fi.sequence.enable_filter_intra
+    && luma_mode == PredictionMode::DC_PRED
+    && palette.size[0] == 0
+    && bsize.width() <= 32
+    && bsize.height() <= 32
+}
+
+/// Writes the color index maps of a block using palette prediction.
+fn write_palette_tokens<T: Pixel, W: Writer>(
+  ts: &TileStateMut<'_, T>, cw: &mut ContextWriter, w: &mut W,
+  palette: &PaletteParams, bsize: BlockSize, tile_bo: TileBlockOffset,
+  xdec: usize, ydec: usize,
+) {
+  let visible_w = (ts.mi_width - tile_bo.0.x) * MI_SIZE;
+  let visible_h = (ts.mi_height - tile_bo.0.y) * MI_SIZE;
+  let block_area = Area::BlockStartingAt { bo: tile_bo.0 };
+  let mut map = [0u8; 64 * 64];
+
+  for plane_type in 0..2 {
+    let n = palette.size[plane_type] as usize;
+    if n == 0 {
+      continue;
+    }
+    let (planes, xdec, ydec) =
+      if plane_type == 0 { (0..1, 0, 0) } else { (1..3, xdec, ydec) };
+    let (width, height, visible_w, visible_h) =
+      palette_map_size(bsize, visible_w, visible_h, xdec, ydec);
+    let src: ArrayVec<_, 2> = planes
+      .clone()
+      .map(|p| ts.input_tile.planes[p].subregion(block_area))
+      .collect();
+    let colors: ArrayVec<_, 2> =
+      planes.map(|p| &palette.colors[p][..n]).collect();
+    palette_color_map(
+      &src, &colors, 0, 0, width, height, visible_w, visible_h, &mut map,
+    );
+    cw.write_palette_color_map(
+      w, plane_type, &map, width, visible_w, visible_h, n,
+    );
+  }
+}
+
 /// # Panics
 ///
 /// - If chroma and luma do not match for inter modes
@@ -2066,10 +2886,12 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
   cw: &mut ContextWriter, w: &mut W, luma_mode: PredictionMode,
   chroma_mode: PredictionMode, angle_delta: AngleDelta,
   ref_frames: [RefType; 2], mvs: [MotionVector; 2], bsize: BlockSize,
-  tile_bo: TileBlockOffset, skip: bool, cfl: CFLParams, tx_size: TxSize,
-  tx_type: TxType, mode_context: usize, mv_stack: &[CandidateMV],
-  rdo_type: RDOType, need_recon_pixel: bool,
-  enc_stats: Option<&mut EncoderStats>,
+  tile_bo: TileBlockOffset, skip: bool, cfl: CFLParams,
+  palette: &PaletteParams, filter_intra_mode: Option<FilterIntraMode>,
+  motion_mode: MotionMode, compound: CompoundPrediction,
+  interp_filter: [FilterMode; 2], tx_size: TxSize, tx_type: TxType,
+  mode_context: usize, mv_stack: &[CandidateMV], rdo_type: RDOType,
+  need_recon_pixel: bool, enc_stats: Option<&mut EncoderStats>,
 ) -> (bool, ScaledDistortion)
 where
   u32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
@@ -2089,11 +2911,7 @@ where
   if is_inter {
     assert!(luma_mode == chroma_mode);
   };
-  let sb_size = if fi.sequence.use_128x128_superblock {
-    BlockSize::BLOCK_128X128
-  } else {
-    BlockSize::BLOCK_64X64
-  };
+  let sb_size = fi.sb_size();
 
   let (xdec, ydec) = if fi.sequence.chroma_sampling
     == ChromaSubsampling::Monochrome
@@ -2118,6 +2936,44 @@ where
   cw.bc.blocks.set_tx_size(tile_bo, bsize, tx_size);
   cw.bc.blocks.set_ref_frames(tile_bo, bsize, ref_frames);
   cw.bc.blocks.set_motion_vectors(tile_bo, bsize, mvs);
+  cw.bc.blocks.set_palette(tile_bo, bsize, palette);
+  cw.bc.blocks.set_filter_intra_mode(tile_bo, bsize, filter_intra_mode);
+  cw.bc.blocks.set_compound(tile_bo, bsize, compound);
+
+  // The motion modes available to the block may have changed since it was
+  // chosen, along with its neighbors.
+  let motion_modes =
+    if is_inter && ref_frames[0] != INTRA_FRAME && !compound.is_interintra() {
+      cw.motion_modes(fi, tile_bo, bsize, luma_mode, ref_frames, mvs[0])
+    } else {
+      ArrayVec::from_iter([MotionMode::SIMPLE_TRANSLATION])
+    };
+  let motion_mode = if motion_modes.contains(&motion_mode) {
+    motion_mode
+  } else {
+    MotionMode::SIMPLE_TRANSLATION
+  };
+  cw.bc.blocks.set_motion_mode(tile_bo, bsize, motion_mode);
+
+  // Blocks whose filters are not coded use the regular filter.
+  let needs_interp_filter = fi.is_filter_switchable
+    && is_inter
+    && ref_frames[0] != INTRA_FRAME
+    && ContextWriter::needs_interp_filter(
+      fi,
+      bsize,
+      luma_mode,
+      ref_frames,
+      motion_mode,
+    );
+  let interp_filter = if needs_interp_filter {
+    interp_filter
+  } else if fi.is_filter_switchable {
+    [FilterMode::REGULAR; 2]
+  } else {
+    [fi.default_filter; 2]
+  };
+  cw.bc.blocks.set_interp_filter(tile_bo, bsize, interp_filter);
 
   //write_q_deltas();
   if cw.bc.code_deltas
@@ -2136,6 +2992,12 @@ where
   if fi.frame_type.has_inter() {
     cw.write_is_inter(w, tile_bo, is_inter);
     if is_inter {
+      let whole_pixel = |mv: &MotionVector| mv.row % 8 == 0 && mv.col % 8 == 0;
+      let mv_count = 1 + luma_mode.is_compound() as usize;
+      debug_assert!(
+        fi.force_integer_mv == 0 || mvs[..mv_count].iter().all(whole_pixel),
+        "subpixel MV in a frame with integer MVs"
+      );
       cw.fill_neighbours_ref_counts(tile_bo);
       cw.write_ref_frames(w, fi, tile_bo);
 
@@ -2167,10 +3029,12 @@ where
         }
       }
 
+      // The single reference stack is completed with the global MVs.
+      let global_mvs = cw.find_global_mvs(tile_bo, ref_frames, bsize, fi);
       let ref_mvs = if num_mv_found > 0 {
         [mv_stack[ref_mv_idx].this_mv, mv_stack[ref_mv_idx].comp_mv]
       } else {
-        [MotionVector::default(); 2]
+        global_mvs
       };
 
       let mv_precision = if fi.force_integer_mv != 0 {
@@ -2185,12 +3049,12 @@ where
         || luma_mode == PredictionMode::NEW_NEWMV
         || luma_mode == PredictionMode::NEW_NEARESTMV
       {
-        cw.write_mv(w, mvs[0], ref_mvs[0], mv_precision);
+        cw.write_mv(w, mvs[0], ref_mvs[0], mv_precision, 0);
       }
       if luma_mode == PredictionMode::NEW_NEWMV
         || luma_mode == PredictionMode::NEAREST_NEWMV
       {
-        cw.write_mv(w, mvs[1], ref_mvs[1], mv_precision);
+        cw.write_mv(w, mvs[1], ref_mvs[1], mv_precision, 0);
       }
 
       if luma_mode.has_nearmv() {
@@ -2215,23 +3079,54 @@ where
           assert!(mv_stack[ref_mv_idx].this_mv.row == mvs[0].row);
           assert!(mv_stack[ref_mv_idx].this_mv.col == mvs[0].col);
         } else {
-          assert!(0 == mvs[0].row);
-          assert!(0 == mvs[0].col);
+          assert!(global_mvs[0].row == mvs[0].row);
+          assert!(global_mvs[0].col == mvs[0].col);
         }
       } else if luma_mode == PredictionMode::NEARESTMV {
         if mv_stack.is_empty() {
-          assert_eq!(mvs[0].row, 0);
-          assert_eq!(mvs[0].col, 0);
+          assert_eq!(mvs[0].row, global_mvs[0].row);
+          assert_eq!(mvs[0].col, global_mvs[0].col);
         } else {
           assert_eq!(mvs[0].row, mv_stack[0].this_mv.row);
           assert_eq!(mvs[0].col, mv_stack[0].this_mv.col);
         }
       }
+
+      if fi.sequence.enable_interintra_compound
+        && ref_frames[1] == NONE_FRAME
+        && bsize.interintra_allowed()
+      {
+        cw.write_interintra_mode(w, compound, bsize);
+      }
+      cw.write_motion_mode(w, motion_mode, &motion_modes, bsize);
+      if (fi.sequence.enable_masked_compound || fi.sequence.enable_jnt_comp)
+        && ref_frames[1] != NONE_FRAME
+      {
+        cw.write_compound_type(w, fi, compound, tile_bo, bsize);
+      }
+      if needs_interp_filter {
+        cw.write_interp_filter(w, fi, tile_bo, interp_filter);
+      }
     } else {
       cw.write_intra_mode(w, bsize, luma_mode);
     }
   } else {
-    cw.write_intra_mode_kf(w, tile_bo, luma_mode);
+    if fi.allow_intrabc {
+      cw.write_use_intrabc(w, is_inter);
+    }
+    if is_inter {
+      debug_assert!(ref_frames[0] == INTRA_FRAME);
+      let ref_dv = cw.find_ref_dv(tile_bo, bsize, fi);
+      cw.write_mv(
+        w,
+        mvs[0],
+        ref_dv,
+        MvSubpelPrecision::MV_SUBPEL_NONE,
+        MV_INTRABC_CONTEXT,
+      );
+    } else {
+      cw.write_intra_mode_kf(w, tile_bo, luma_mode);
+    }
   }
 
   if !is_inter {
@@ -2239,9 +3134,10 @@ where
       cw.write_angle_delta(w, angle_delta.y, luma_mode);
     }
     if has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling) {
-      cw.write_intra_uv_mode(w, chroma_mode, luma_mode, bsize);
+      let cfl_allowed = bsize.cfl_allowed(fi.coded_lossless(), xdec, ydec);
+      cw.write_intra_uv_mode(w, chroma_mode, luma_mode, cfl_allowed);
       if chroma_mode.is_cfl() {
-        assert!(bsize.cfl_allowed());
+        assert!(cfl_allowed);
         cw.write_cfl_alphas(w, cfl);
       }
       if chroma_mode.is_directional() && bsize >= BlockSize::BLOCK_8X8 {
@@ -2249,14 +3145,10 @@ where
       }
     }
 
-    if fi.allow_screen_content_tools > 0
-      && bsize >= BlockSize::BLOCK_8X8
-      && bsize.width() <= 64
-      && bsize.height() <= 64
-    {
-      cw.write_use_palette_mode(
+    if fi.allow_screen_content_tools > 0 && bsize.palette_allowed() {
+      cw.write_palette_mode_info(
         w,
-        false,
+        palette,
         bsize,
         tile_bo,
         luma_mode,
@@ -2264,15 +3156,18 @@ where
         xdec,
         ydec,
         fi.sequence.chroma_sampling,
+        fi.sequence.bit_depth,
       );
     }
 
-    if fi.sequence.enable_filter_intra
-      && luma_mode == PredictionMode::DC_PRED
-      && bsize.width() <= 32
-      && bsize.height() <= 32
-    {
-      cw.write_use_filter_intra(w, false, bsize); // turn off FILTER_INTRA
+    if filter_intra_allowed(fi, luma_mode, palette, bsize) {
+      cw.write_filter_intra_mode_info(w, filter_intra_mode, bsize);
+    } else {
+      debug_assert!(filter_intra_mode.is_none());
+    }
+
+    if palette.size != [0, 0] {
+      write_palette_tokens(ts, cw, w, palette, bsize, tile_bo, xdec, ydec);
     }
   }
 
@@ -2294,20 +3189,29 @@ where
 
         //TODO: "&& tx_size.block_size() < bsize" will be replaced with tx-split info for a partition
         //  once it is available.
-        let txfm_split =
-          fi.enable_inter_txfm_split && tx_size.block_size() < bsize;
-
-        // TODO: Revise write_tx_size_inter() for txfm_split = true
-        cw.write_tx_size_inter(
-          w,
-          tile_bo,
-          bsize,
-          max_tx_size,
-          txfm_split,
-          0,
-          0,
-          0,
-        );
+        let txfm_split = fi.enable_inter_txfm_split
+          && tx_size.block_size() < max_tx_size.block_size();
+
+        // Blocks larger than 64x64 code a transform tree per 64x64 unit
+        for y in 0..bsize.height_mi() / max_tx_size.height_mi() {
+          for x in 0..bsize.width_mi() / max_tx_size.width_mi() {
+            let tx_bo = TileBlockOffset(BlockOffset {
+              x: tile_bo.0.x + x * max_tx_size.width_mi(),
+              y: tile_bo.0.y + y * max_tx_size.height_mi(),
+            });
+            // TODO: Revise write_tx_size_inter() for txfm_split = true
+            cw.write_tx_size_inter(
+              w,
+              tx_bo,
+              bsize,
+              max_tx_size,
+              txfm_split,
+              x,
+              y,
+              0,
+            );
+          }
+        }
       }
     } else {
       debug_assert!(bsize == BlockSize::BLOCK_4X4 || (is_inter && skip));
@@ -2385,9 +3289,6 @@ where
   }
 }
 
-/// # Panics
-///
-/// - If attempting to encode a lossless block (not yet supported)
 pub fn write_tx_blocks<T: Pixel, W: Writer>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, w: &mut W, luma_mode: PredictionMode,
@@ -2408,11 +3309,6 @@ where
   let bh = bsize.height_mi() / tx_size.height_mi();
   let qidx = get_qidx(fi, ts, cw, tile_bo);
 
-  // TODO: Lossless is not yet supported.
-  if !skip {
-    assert_ne!(qidx, 0);
-  }
-
   let (xdec, ydec) = if fi.sequence.chroma_sampling
     == ChromaSubsampling::Monochrome
   {
@@ -2426,146 +3322,162 @@ where
   let mut partition_has_coeff: bool = false;
   let mut tx_dist = ScaledDistortion::zero();
   let do_chroma =
-    has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling);
+    has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling)
+      && !luma_only
+      && fi.sequence.chroma_sampling != ChromaSubsampling::Monochrome;
+
+  // Blocks larger than 64x64 code their residual in 64x64 luma chunks, in
+  // raster order, each chunk with all of its planes.
+  let chunk_w = bsize.width_mi().min(BlockSize::BLOCK_64X64.width_mi());
+  let chunk_h = bsize.height_mi().min(BlockSize::BLOCK_64X64.height_mi());
+
+  for chunk_y in (0..bsize.height_mi()).step_by(chunk_h) {
+    for chunk_x in (0..bsize.width_mi()).step_by(chunk_w) {
+      ts.qc.update(
+        qidx,
+        tx_size,
+        luma_mode.is_intra(),
+        fi.sequence.bit_depth,
+        fi.dc_delta_q[0],
+        0,
+      );
 
-  ts.qc.update(
-    qidx,
-    tx_size,
-    luma_mode.is_intra(),
-    fi.sequence.bit_depth,
-    fi.dc_delta_q[0],
-    0,
-  );
+      let bx0 = chunk_x / tx_size.width_mi();
+      let by0 = chunk_y / tx_size.height_mi();
+      for by in by0..by0 + chunk_h / tx_size.height_mi() {
+        for bx in bx0..bx0 + chunk_w / tx_size.width_mi() {
+          let tx_bo = TileBlockOffset(BlockOffset {
+            x: tile_bo.0.x + bx * tx_size.width_mi(),
+            y: tile_bo.0.y + by * tx_size.height_mi(),
+          });
+          if tx_bo.0.x >= ts.mi_width || tx_bo.0.y >= ts.mi_height {
+            continue;
+          }
+          let cfg = PlaneConfig::new(&ts.input.y_plane.geometry());
+          let po = tx_bo.plane_offset(&cfg);
+          let (has_coeff, dist) = encode_tx_block(
+            fi,
+            ts,
+            cw,
+            w,
+            0,
+            tile_bo,
+            bx,
+            by,
+            tx_bo,
+            luma_mode,
+            tx_size,
+            tx_type,
+            bsize,
+            po,
+            skip,
+            qidx,
+            &[],
+            IntraParam::AngleDelta(angle_delta.y),
+            rdo_type,
+            need_recon_pixel,
+          );
+          partition_has_coeff |= has_coeff;
+          tx_dist += dist;
+        }
+      }
 
-  for by in 0..bh {
-    for bx in 0..bw {
-      let tx_bo = TileBlockOffset(BlockOffset {
-        x: tile_bo.0.x + bx * tx_size.width_mi(),
-        y: tile_bo.0.y + by * tx_size.height_mi(),
-      });
-      if tx_bo.0.x >= ts.mi_width || tx_bo.0.y >= ts.mi_height {
+      if !do_chroma {
         continue;
       }
-      let cfg = PlaneConfig::new(&ts.input.y_plane.geometry());
-      let po = tx_bo.plane_offset(&cfg);
-      let (has_coeff, dist) = encode_tx_block(
-        fi,
-        ts,
-        cw,
-        w,
-        0,
-        tile_bo,
-        bx,
-        by,
-        tx_bo,
-        luma_mode,
-        tx_size,
-        tx_type,
-        bsize,
-        po,
-        skip,
-        qidx,
-        &[],
-        IntraParam::AngleDelta(angle_delta.y),
-        rdo_type,
-        need_recon_pixel,
-      );
-      partition_has_coeff |= has_coeff;
-      tx_dist += dist;
-    }
-  }
 
-  if !do_chroma
-    || luma_only
-    || fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
-  {
-    return (partition_has_coeff, tx_dist);
-  };
-  debug_assert!(has_chroma(
-    tile_bo,
-    bsize,
-    xdec,
-    ydec,
-    fi.sequence.chroma_sampling
-  ));
+      let mut uv_tx_size = bsize.largest_chroma_tx_size(xdec, ydec);
 
-  let uv_tx_size = bsize.largest_chroma_tx_size(xdec, ydec);
+      let mut bw_uv = chunk_w >> xdec;
+      let mut bh_uv = chunk_h >> ydec;
 
-  let mut bw_uv = (bw * tx_size.width_mi()) >> xdec;
-  let mut bh_uv = (bh * tx_size.height_mi()) >> ydec;
+      if bw_uv == 0 || bh_uv == 0 {
+        bw_uv = 1;
+        bh_uv = 1;
+      }
 
-  if bw_uv == 0 || bh_uv == 0 {
-    bw_uv = 1;
-    bh_uv = 1;
-  }
+      bw_uv /= uv_tx_size.width_mi();
+      bh_uv /= uv_tx_size.height_mi();
 
-  bw_uv /= uv_tx_size.width_mi();
-  bh_uv /= uv_tx_size.height_mi();
+      // Lossless blocks split the chroma residual into 4x4 transforms
+      if qidx == 0 {
+        bw_uv *= uv_tx_size.width_mi();
+        bh_uv *= uv_tx_size.height_mi();
+        uv_tx_size = TxSize::TX_4X4;
+      }
 
-  let ac_data = if chroma_mode.is_cfl() {
-    luma_ac(&mut ac.data, ts, tile_bo, bsize, tx_size, fi)
-  } else {
-    [].as_slice()
-  };
+      let bx0_uv = (chunk_x >> xdec) / uv_tx_size.width_mi();
+      let by0_uv = (chunk_y >> ydec) / uv_tx_size.height_mi();
 
-  let uv_tx_type = if uv_tx_size.width() >= 32 || uv_tx_size.height() >= 32 {
-    TxType::DCT_DCT
-  } else {
-    uv_intra_mode_to_tx_type_context(chroma_mode)
-  };
+      let ac_data = if chroma_mode.is_cfl() {
+        luma_ac(&mut ac.data, ts, tile_bo, bsize, tx_size, fi)
+      } else {
+        [].as_slice()
+      };
 
-  for p in 1..3 {
-    ts.qc.update(
-      qidx,
-      uv_tx_size,
-      true,
-      fi.sequence.bit_depth,
-      fi.dc_delta_q[p],
-      fi.ac_delta_q[p],
-    );
-    let alpha = cfl.alpha(p - 1);
-    for by in 0..bh_uv {
-      for bx in 0..bw_uv {
-        let tx_bo = TileBlockOffset(BlockOffset {
-          x: tile_bo.0.x + ((bx * uv_tx_size.width_mi()) << xdec)
-            - ((bw * tx_size.width_mi() == 1) as usize) * xdec,
-          y: tile_bo.0.y + ((by * uv_tx_size.height_mi()) << ydec)
-            - ((bh * tx_size.height_mi() == 1) as usize) * ydec,
-        });
+      let uv_tx_type = if qidx == 0
+        || uv_tx_size.width() >= 32
+        || uv_tx_size.height() >= 32
+      {
+        TxType::DCT_DCT
+      } else {
+        uv_intra_mode_to_tx_type_context(chroma_mode)
+      };
 
-        let cfg =
-          PlaneConfig::new(&ts.input.planes().nth(p).unwrap().geometry());
-        let mut po = tile_bo.plane_offset(&cfg);
-        po.x += (bx * uv_tx_size.width()) as isize;
-        po.y += (by * uv_tx_size.height()) as isize;
-        let (has_coeff, dist) = encode_tx_block(
-          fi,
-          ts,
-          cw,
-          w,
-          p,
-          tile_bo,
-          bx,
-          by,
-          tx_bo,
-          chroma_mode,
-          uv_tx_size,
-          uv_tx_type,
-          bsize,
-          po,
-          skip,
+      for p in 1..3 {
+        ts.qc.update(
           qidx,
-          ac_data,
-          if chroma_mode.is_cfl() {
-            IntraParam::Alpha(alpha)
-          } else {
-            IntraParam::AngleDelta(angle_delta.uv)
-          },
-          rdo_type,
-          need_recon_pixel,
+          uv_tx_size,
+          true,
+          fi.sequence.bit_depth,
+          fi.dc_delta_q[p],
+          fi.ac_delta_q[p],
         );
-        partition_has_coeff |= has_coeff;
-        tx_dist += dist;
+        let alpha = cfl.alpha(p - 1);
+        for by in by0_uv..by0_uv + bh_uv {
+          for bx in bx0_uv..bx0_uv + bw_uv {
+            let tx_bo = TileBlockOffset(BlockOffset {
+              x: tile_bo.0.x + ((bx * uv_tx_size.width_mi()) << xdec)
+                - ((bw * tx_size.width_mi() == 1) as usize) * xdec,
+              y: tile_bo.0.y + ((by * uv_tx_size.height_mi()) << ydec)
+                - ((bh * tx_size.height_mi() == 1) as usize) * ydec,
+            });
+
+            let cfg =
+              PlaneConfig::new(&ts.input.planes().nth(p).unwrap().geometry());
+            let mut po = tile_bo.plane_offset(&cfg);
+            po.x += (bx * uv_tx_size.width()) as isize;
+            po.y += (by * uv_tx_size.height()) as isize;
+            let (has_coeff, dist) = encode_tx_block(
+              fi,
+              ts,
+              cw,
+              w,
+              p,
+              tile_bo,
+              bx,
+              by,
+              tx_bo,
+              chroma_mode,
+              uv_tx_size,
+              uv_tx_type,
+              bsize,
+              po,
+              skip,
+              qidx,
+              ac_data,
+              if chroma_mode.is_cfl() {
+                IntraParam::Alpha(alpha)
+              } else {
+                IntraParam::AngleDelta(angle_delta.uv)
+              },
+              rdo_type,
+              need_recon_pixel,
+            );
+            partition_has_coeff |= has_coeff;
+            tx_dist += dist;
+          }
+        }
       }
     }
   }
@@ -2590,8 +3502,6 @@ where
   if skip {
     return (false, ScaledDistortion::zero());
   }
-  let bw = bsize.width_mi() / tx_size.width_mi();
-  let bh = bsize.height_mi() / tx_size.height_mi();
   let qidx = get_qidx(fi, ts, cw, tile_bo);
 
   let (xdec, ydec) = if fi.sequence.chroma_sampling
@@ -2606,141 +3516,159 @@ where
   let ac = &[0i16; 0];
   let mut partition_has_coeff: bool = false;
   let mut tx_dist = ScaledDistortion::zero();
+  let do_chroma =
+    has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling)
+      && !luma_only
+      && fi.sequence.chroma_sampling != ChromaSubsampling::Monochrome;
 
-  ts.qc.update(
-    qidx,
-    tx_size,
-    luma_mode.is_intra(),
-    fi.sequence.bit_depth,
-    fi.dc_delta_q[0],
-    0,
-  );
+  let max_tx_size = max_txsize_rect_lookup[bsize as usize];
+  debug_assert!(max_tx_size.block_size() <= BlockSize::BLOCK_64X64);
 
-  // TODO: If tx-parition more than only 1-level, this code does not work.
-  // It should recursively traverse the tx block that are split recursivelty by calling write_tx_tree(),
-  // as defined in https://aomediacodec.github.io/av1-spec/#transform-tree-syntax
-  for by in 0..bh {
-    for bx in 0..bw {
-      let tx_bo = TileBlockOffset(BlockOffset {
-        x: tile_bo.0.x + bx * tx_size.width_mi(),
-        y: tile_bo.0.y + by * tx_size.height_mi(),
-      });
-      if tx_bo.0.x >= ts.mi_width || tx_bo.0.y >= ts.mi_height {
-        continue;
-      }
+  // Blocks larger than 64x64 code their residual in 64x64 luma chunks, in
+  // raster order, each chunk with all of its planes.
+  let chunk_w = max_tx_size.width_mi();
+  let chunk_h = max_tx_size.height_mi();
 
-      let cfg = PlaneConfig::new(&ts.input.y_plane.geometry());
-      let po = tx_bo.plane_offset(&cfg);
-      let (has_coeff, dist) = encode_tx_block(
-        fi,
-        ts,
-        cw,
-        w,
-        0,
-        tile_bo,
-        0,
-        0,
-        tx_bo,
-        luma_mode,
-        tx_size,
-        tx_type,
-        bsize,
-        po,
-        skip,
+  for chunk_y in (0..bsize.height_mi()).step_by(chunk_h) {
+    for chunk_x in (0..bsize.width_mi()).step_by(chunk_w) {
+      ts.qc.update(
         qidx,
-        ac,
-        IntraParam::AngleDelta(angle_delta_y),
-        rdo_type,
-        need_recon_pixel,
+        tx_size,
+        luma_mode.is_intra(),
+        fi.sequence.bit_depth,
+        fi.dc_delta_q[0],
+        0,
       );
-      partition_has_coeff |= has_coeff;
-      tx_dist += dist;
-    }
-  }
 
-  if !has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling)
-    || luma_only
-    || fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
-  {
-    return (partition_has_coeff, tx_dist);
-  };
-  debug_assert!(has_chroma(
-    tile_bo,
-    bsize,
-    xdec,
-    ydec,
-    fi.sequence.chroma_sampling
-  ));
-
-  let max_tx_size = max_txsize_rect_lookup[bsize as usize];
-  debug_assert!(max_tx_size.block_size() <= BlockSize::BLOCK_64X64);
-  let uv_tx_size = bsize.largest_chroma_tx_size(xdec, ydec);
+      // TODO: If tx-parition more than only 1-level, this code does not work.
+      // It should recursively traverse the tx block that are split recursivelty by calling write_tx_tree(),
+      // as defined in https://aomediacodec.github.io/av1-spec/#transform-tree-syntax
+      let mut chunk_has_coeff = false;
+      let bx0 = chunk_x / tx_size.width_mi();
+      let by0 = chunk_y / tx_size.height_mi();
+      for by in by0..by0 + chunk_h / tx_size.height_mi() {
+        for bx in bx0..bx0 + chunk_w / tx_size.width_mi() {
+          let tx_bo = TileBlockOffset(BlockOffset {
+            x: tile_bo.0.x + bx * tx_size.width_mi(),
+            y: tile_bo.0.y + by * tx_size.height_mi(),
+          });
+          if tx_bo.0.x >= ts.mi_width || tx_bo.0.y >= ts.mi_height {
+            continue;
+          }
 
-  let mut bw_uv = max_tx_size.width_mi() >> xdec;
-  let mut bh_uv = max_tx_size.height_mi() >> ydec;
+          let cfg = PlaneConfig::new(&ts.input.y_plane.geometry());
+          let po = tx_bo.plane_offset(&cfg);
+          let (has_coeff, dist) = encode_tx_block(
+            fi,
+            ts,
+            cw,
+            w,
+            0,
+            tile_bo,
+            0,
+            0,
+            tx_bo,
+            luma_mode,
+            tx_size,
+            tx_type,
+            bsize,
+            po,
+            skip,
+            qidx,
+            ac,
+            IntraParam::AngleDelta(angle_delta_y),
+            rdo_type,
+            need_recon_pixel,
+          );
+          chunk_has_coeff |= has_coeff;
+          tx_dist += dist;
+        }
+      }
+      partition_has_coeff |= chunk_has_coeff;
 
-  if bw_uv == 0 || bh_uv == 0 {
-    bw_uv = 1;
-    bh_uv = 1;
-  }
+      if !do_chroma {
+        continue;
+      }
 
-  bw_uv /= uv_tx_size.width_mi();
-  bh_uv /= uv_tx_size.height_mi();
+      let mut uv_tx_size = bsize.largest_chroma_tx_size(xdec, ydec);
 
-  let uv_tx_type = if partition_has_coeff {
-    tx_type.uv_inter(uv_tx_size)
-  } else {
-    TxType::DCT_DCT
-  };
+      let mut bw_uv = chunk_w >> xdec;
+      let mut bh_uv = chunk_h >> ydec;
 
-  for p in 1..3 {
-    ts.qc.update(
-      qidx,
-      uv_tx_size,
-      false,
-      fi.sequence.bit_depth,
-      fi.dc_delta_q[p],
-      fi.ac_delta_q[p],
-    );
+      if bw_uv == 0 || bh_uv == 0 {
+        bw_uv = 1;
+        bh_uv = 1;
+      }
 
-    for by in 0..bh_uv {
-      for bx in 0..bw_uv {
-        let tx_bo = TileBlockOffset(BlockOffset {
-          x: tile_bo.0.x + ((bx * uv_tx_size.width_mi()) << xdec)
-            - (max_tx_size.width_mi() == 1) as usize * xdec,
-          y: tile_bo.0.y + ((by * uv_tx_size.height_mi()) << ydec)
-            - (max_tx_size.height_mi() == 1) as usize * ydec,
-        });
+      bw_uv /= uv_tx_size.width_mi();
+      bh_uv /= uv_tx_size.height_mi();
 
-        let cfg =
-          PlaneConfig::new(&ts.input.planes().nth(p).unwrap().geometry());
-        let mut po = tile_bo.plane_offset(&cfg);
-        po.x += (bx * uv_tx_size.width()) as isize;
-        po.y += (by * uv_tx_size.height()) as isize;
-        let (has_coeff, dist) = encode_tx_block(
-          fi,
-          ts,
-          cw,
-          w,
-          p,
-          tile_bo,
-          bx,
-          by,
-          tx_bo,
-          luma_mode,
-          uv_tx_size,
-          uv_tx_type,
-          bsize,
-          po,
-          skip,
+      // Lossless blocks split the chroma residual into 4x4 transforms
+      if qidx == 0 {
+        bw_uv *= uv_tx_size.width_mi();
+        bh_uv *= uv_tx_size.height_mi();
+        uv_tx_size = TxSize::TX_4X4;
+      }
+
+      let bx0_uv = (chunk_x >> xdec) / uv_tx_size.width_mi();
+      let by0_uv = (chunk_y >> ydec) / uv_tx_size.height_mi();
+
+      let uv_tx_type = if chunk_has_coeff && qidx != 0 {
+        tx_type.uv_inter(uv_tx_size)
+      } else {
+        TxType::DCT_DCT
+      };
+
+      for p in 1..3 {
+        ts.qc.update(
           qidx,
-          ac,
-          IntraParam::AngleDelta(angle_delta_y),
-          rdo_type,
-          need_recon_pixel,
+          uv_tx_size,
+          false,
+          fi.sequence.bit_depth,
+          fi.dc_delta_q[p],
+          fi.ac_delta_q[p],
         );
-        partition_has_coeff |= has_coeff;
-        tx_dist += dist;
+
+        for by in by0_uv..by0_uv + bh_uv {
+          for bx in bx0_uv..bx0_uv + bw_uv {
+            let tx_bo = TileBlockOffset(BlockOffset {
+              x: tile_bo.0.x + ((bx * uv_tx_size.width_mi()) << xdec)
+                - (max_tx_size.width_mi() == 1) as usize * xdec,
+              y: tile_bo.0.y + ((by * uv_tx_size.height_mi()) << ydec)
+                - (max_tx_size.height_mi() == 1) as usize * ydec,
+            });
+
+            let cfg =
+              PlaneConfig::new(&ts.input.planes().nth(p).unwrap().geometry());
+            let mut po = tile_bo.plane_offset(&cfg);
+            po.x += (bx * uv_tx_size.width()) as isize;
+            po.y += (by * uv_tx_size.height()) as isize;
+            let (has_coeff, dist) = encode_tx_block(
+              fi,
+              ts,
+              cw,
+              w,
+              p,
+              tile_bo,
+              bx,
+              by,
+              tx_bo,
+              luma_mode,
+              uv_tx_size,
+              uv_tx_type,
+              bsize,
+              po,
+              skip,
+              qidx,
+              ac,
+              IntraParam::AngleDelta(angle_delta_y),
+              rdo_type,
+              need_recon_pixel,
+            );
+            partition_has_coeff |= has_coeff;
+            tx_dist += dist;
+          }
+        }
       }
     }
   }
@@ -2774,6 +3702,15 @@ pub fn encode_block_with_modes<T: Pixel, W: Writer>(
   // Set correct segmentation ID before encoding and before
   // rdo_tx_size_type().
   cw.bc.blocks.set_segmentation_idx(tile_bo, bsize, mode_decision.sidx);
+  cw.bc.blocks.set_palette(tile_bo, bsize, &mode_decision.palette);
+  cw.bc.blocks.set_filter_intra_mode(
+    tile_bo,
+    bsize,
+    mode_decision.filter_intra_mode,
+  );
+  cw.bc.blocks.set_motion_mode(tile_bo, bsize, mode_decision.motion_mode);
+  cw.bc.blocks.set_compound(tile_bo, bsize, mode_decision.compound);
+  cw.bc.blocks.set_interp_filter(tile_bo, bsize, mode_decision.interp_filter);
 
   let mut mv_stack = ArrayVec::<CandidateMV, 9>::new();
   let is_compound = ref_frames[1] != NONE_FRAME;
@@ -2790,7 +3727,7 @@ pub fn encode_block_with_modes<T: Pixel, W: Writer>(
   };
 
   cdef_coded = encode_block_pre_cdef(
-    &fi.sequence,
+    fi,
     ts,
     cw,
     if cdef_coded { w_post_cdef } else { w_pre_cdef },
@@ -2812,6 +3749,11 @@ pub fn encode_block_with_modes<T: Pixel, W: Writer>(
     tile_bo,
     skip,
     cfl,
+    &mode_decision.palette,
+    mode_decision.filter_intra_mode,
+    mode_decision.motion_mode,
+    mode_decision.compound,
+    mode_decision.interp_filter,
     tx_size,
     tx_type,
     mode_context,
@@ -2857,9 +3799,6 @@ where
   let is_straddle_x = tile_bo.0.x + bsize.width_mi() > ts.mi_width;
   let is_straddle_y = tile_bo.0.y + bsize.height_mi() > ts.mi_height;
 
-  // TODO: Update for 128x128 superblocks
-  assert!(fi.partition_range.max <= BlockSize::BLOCK_64X64);
-
   let must_split =
     is_square && (bsize > fi.partition_range.max || !has_cols || !has_rows);
 
@@ -2894,7 +3833,9 @@ where
     let mode_decision =
       rdo_mode_decision(fi, ts, cw, bsize, tile_bo, inter_cfg);
 
-    if !mode_decision.pred_mode_luma.is_intra() {
+    if !mode_decision.pred_mode_luma.is_intra()
+      && mode_decision.ref_frames[0] != INTRA_FRAME
+    {
       // Fill the saved motion structure
       save_block_motion(
         ts,
@@ -3066,7 +4007,8 @@ where
       for mode in rdo_output.part_modes.clone() {
         assert!(subsize == mode.bsize);
 
-        if !mode.pred_mode_luma.is_intra() {
+        if !mode.pred_mode_luma.is_intra() && mode.ref_frames[0] != INTRA_FRAME
+        {
           save_block_motion(
             ts,
             mode.bsize,
@@ -3139,9 +4081,6 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
   let has_cols = tile_bo.0.x + hbs < ts.mi_width;
   let has_rows = tile_bo.0.y + hbs < ts.mi_height;
 
-  // TODO: Update for 128x128 superblocks
-  debug_assert!(fi.partition_range.max <= BlockSize::BLOCK_64X64);
-
   let must_split =
     is_square && (bsize > fi.partition_range.max || !has_cols || !has_rows);
 
@@ -3221,6 +4160,19 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
       // Set correct segmentation ID before encoding and before
       // rdo_tx_size_type().
       cw.bc.blocks.set_segmentation_idx(tile_bo, bsize, part_decision.sidx);
+      cw.bc.blocks.set_palette(tile_bo, bsize, &part_decision.palette);
+      cw.bc.blocks.set_filter_intra_mode(
+        tile_bo,
+        bsize,
+        part_decision.filter_intra_mode,
+      );
+      cw.bc.blocks.set_motion_mode(tile_bo, bsize, part_decision.motion_mode);
+      cw.bc.blocks.set_compound(tile_bo, bsize, part_decision.compound);
+      cw.bc.blocks.set_interp_filter(
+        tile_bo,
+        bsize,
+        part_decision.interp_filter,
+      );
 
       // NOTE: Cannot avoid calling rdo_tx_size_type() here again,
       // because, with top-down partition RDO, the neighboring contexts
@@ -3239,9 +4191,11 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
         fi,
         is_compound,
       );
+      let global_mvs = cw.find_global_mvs(tile_bo, ref_frames, bsize, fi);
 
       // TODO: proper remap when is_compound is true
-      if !mode_luma.is_intra() {
+      // Intra block copy is always coded as a new displacement vector.
+      if !mode_luma.is_intra() && ref_frames[0] != INTRA_FRAME {
         if is_compound && mode_luma != PredictionMode::GLOBAL_GLOBALMV {
           let match0 = mv_stack[0].this_mv.row == mvs[0].row
             && mv_stack[0].this_mv.col == mvs[0].col;
@@ -3276,10 +4230,10 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
           };
 
           if mode_luma != PredictionMode::NEAREST_NEARESTMV
-            && mvs[0].row == 0
-            && mvs[0].col == 0
-            && mvs[1].row == 0
-            && mvs[1].col == 0
+            && mvs == global_mvs
+            && !ref_frames
+              .iter()
+              .any(|&r| is_global_warp(fi, r, bsize.width(), bsize.height()))
           {
             mode_luma = PredictionMode::GLOBAL_GLOBALMV;
           }
@@ -3299,17 +4253,20 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
               mode_luma = *m;
             }
           }
-          if mode_luma == PredictionMode::NEWMV
-            && mvs[0].row == 0
-            && mvs[0].col == 0
-          {
-            mode_luma = if mv_stack.is_empty() {
-              PredictionMode::NEARESTMV
+          // A warped GLOBALMV does not predict with the global MV.
+          if mode_luma == PredictionMode::NEWMV && mvs[0] == global_mvs[0] {
+            if mv_stack.is_empty() {
+              mode_luma = PredictionMode::NEARESTMV;
             } else if mv_stack.len() == 1 {
-              PredictionMode::NEAR0MV
-            } else {
-              PredictionMode::GLOBALMV
-            };
+              mode_luma = PredictionMode::NEAR0MV;
+            } else if !is_global_warp(
+              fi,
+              ref_frames[0],
+              bsize.width(),
+              bsize.height(),
+            ) {
+              mode_luma = PredictionMode::GLOBALMV;
+            }
           }
           mode_chroma = mode_luma;
         }
@@ -3325,7 +4282,7 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
 
       // FIXME: every final block that has gone through the RDO decision process is encoded twice
       cdef_coded = encode_block_pre_cdef(
-        &fi.sequence,
+        fi,
         ts,
         cw,
         if cdef_coded { w_post_cdef } else { w_pre_cdef },
@@ -3347,6 +4304,11 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
         tile_bo,
         skip,
         cfl,
+        &part_decision.palette,
+        part_decision.filter_intra_mode,
+        part_decision.motion_mode,
+        part_decision.compound,
+        part_decision.interp_filter,
         tx_size,
         tx_type,
         mode_context,
@@ -3444,7 +4406,7 @@ fn get_initial_cdfcontext<T: Pixel>(fi: &FrameInvariants<T>) -> CDFContext {
 
 #[profiling::function]
 fn encode_tile_group<T: Pixel>(
-  fi: &FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
+  fi: &mut FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
 ) -> Vec<u8>
 where
   u32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
@@ -3461,21 +4423,56 @@ where
     3
   };
   let mut blocks = FrameBlocks::new(fi.w_in_b, fi.h_in_b);
-  let ti = &fi.sequence.tiling;
+  let ti = fi.sequence.tiling;
 
   let initial_cdf = get_initial_cdfcontext(fi);
   // dynamic allocation: once per frame
   let mut cdfs = vec![initial_cdf; ti.tile_count()];
 
-  let (raw_tiles, stats): (Vec<_>, Vec<_>) = ti
-    .tile_iter_mut(fs, &mut blocks)
-    .zip(cdfs.iter_mut())
-    .collect::<Vec<_>>()
-    .into_par_iter()
-    .map(|(mut ctx, cdf)| {
-      encode_tile(fi, &mut ctx.ts, cdf, &mut ctx.tb, inter_cfg)
-    })
-    .unzip();
+  let search_cdef =
+    fi.cdef_enabled() && fi.cdef_search_method != CDEFSearchMethod::PickFromQ;
+
+  let (raw_tiles, stats): (Vec<_>, Vec<_>) = if search_cdef {
+    // The strengths of the frame must be known before the index of any
+    // superblock is coded, so all the loop filter decisions are delayed
+    // until every tile has been encoded and the strengths searched.
+    let (lf_queues, stats): (Vec<_>, Vec<_>) = ti
+      .tile_iter_mut(fs, &mut blocks)
+      .zip(cdfs.iter_mut())
+      .collect::<Vec<_>>()
+      .into_par_iter()
+      .map(|(mut ctx, cdf)| {
+        let mut cw = ContextWriter::new(cdf, BlockContext::new(&mut ctx.tb));
+        encode_tile_superblocks(fi, &mut ctx.ts, &mut cw, inter_cfg, true)
+      })
+      .unzip();
+
+    search_cdef_strengths(fi, fs, &blocks);
+
+    let fi = &*fi;
+    let raw_tiles = ti
+      .tile_iter_mut(fs, &mut blocks)
+      .zip(cdfs.iter_mut())
+      .zip(lf_queues)
+      .collect::<Vec<_>>()
+      .into_par_iter()
+      .map(|((mut ctx, cdf), lf_queue)| {
+        let mut cw = ContextWriter::new(cdf, BlockContext::new(&mut ctx.tb));
+        flush_lf_queue(fi, &mut ctx.ts, &mut cw, lf_queue)
+      })
+      .collect();
+    (raw_tiles, stats)
+  } else {
+    let fi = &*fi;
+    ti.tile_iter_mut(fs, &mut blocks)
+      .zip(cdfs.iter_mut())
+      .collect::<Vec<_>>()
+      .into_par_iter()
+      .map(|(mut ctx, cdf)| {
+        encode_tile(fi, &mut ctx.ts, cdf, &mut ctx.tb, inter_cfg)
+      })
+      .unzip()
+  };
 
   for tile_stats in stats {
     fs.enc_stats += &tile_stats;
@@ -3484,7 +4481,6 @@ where
   /* Frame deblocking operates over a single large tile wrapping the
    * frame rather than the frame itself so that deblocking is
    * available inside RDO when needed */
-  /* TODO: Don't apply if lossless */
   let levels = fs.apply_tile_state_mut(|ts| {
     let rec = &mut ts.rec;
     deblock_filter_optimize(
@@ -3513,27 +4509,24 @@ where
     });
   }
 
-  if fi.sequence.enable_restoration {
+  if fi.restoration_enabled() {
     // Until the loop filters are better pipelined, we'll need to keep
     // around a copy of both the deblocked and cdeffed frame.
     let deblocked_frame = (*fs.rec).clone();
 
-    /* TODO: Don't apply if lossless */
-    if fi.sequence.enable_cdef {
+    if fi.cdef_enabled() {
       fs.apply_tile_state_mut(|ts| {
         let rec = &mut ts.rec;
         cdef_filter_tile(fi, &deblocked_frame, &blocks.as_tile_blocks(), rec);
       });
     }
-    /* TODO: Don't apply if lossless */
     fs.restoration.lrf_filter_frame(
       Arc::get_mut(&mut fs.rec).unwrap(),
       &deblocked_frame,
       fi,
     );
   } else {
-    /* TODO: Don't apply if lossless */
-    if fi.sequence.enable_cdef {
+    if fi.cdef_enabled() {
       let deblocked_frame = (*fs.rec).clone();
       fs.apply_tile_state_mut(|ts| {
         let rec = &mut ts.rec;
@@ -3560,7 +4553,330 @@ where
   debug_assert!(max_tile_size_bytes > 0 && max_tile_size_bytes <= 4);
   fs.max_tile_size_bytes = max_tile_size_bytes;
 
-  build_raw_tile_group(ti, &raw_tiles, max_tile_size_bytes)
+  build_raw_tile_group(&ti, &raw_tiles, max_tile_size_bytes)
+}
+
+/// Encodes the tiles of a frame coded with superres, `fi_c` and `fs_c`
+/// describing it at its coded width, and reconstructs it at the full size
+/// of `fi` and `fs`.
+///
+/// The restoration units are signaled along with the superblocks of the
+/// coded frame, but their filters apply to the upscaled frame, so they are
+/// only decided once every tile has been reconstructed. All the symbols of
+/// the tiles are held back until then.
+fn encode_superres_tile_group<T: Pixel>(
+  fi: &FrameInvariants<T>, fs: &mut FrameState<T>,
+  fi_c: &mut FrameInvariants<T>, fs_c: &mut FrameState<T>,
+  inter_cfg: &InterConfig,
+) -> Vec<u8>
+where
+  u32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
+  i32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
+  <T as util::pixel::Pixel>::Coeff: num_traits::AsPrimitive<u8>,
+  i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+  i16: util::math::CastFromPrimitive<T>,
+{
+  let planes =
+    if fi_c.sequence.chroma_sampling == ChromaSubsampling::Monochrome {
+      1
+    } else {
+      3
+    };
+  let mut blocks = FrameBlocks::new(fi_c.w_in_b, fi_c.h_in_b);
+  let ti = fi_c.sequence.tiling;
+
+  let initial_cdf = get_initial_cdfcontext(fi_c);
+  let mut cdfs = vec![initial_cdf; ti.tile_count()];
+
+  let (lf_queues, stats): (Vec<_>, Vec<_>) = {
+    let fi_c = &*fi_c;
+    ti.tile_iter_mut(fs_c, &mut blocks)
+      .zip(cdfs.iter_mut())
+      .collect::<Vec<_>>()
+      .into_par_iter()
+      .map(|(mut ctx, cdf)| {
+        let mut cw = ContextWriter::new(cdf, BlockContext::new(&mut ctx.tb));
+        encode_tile_superblocks(fi_c, &mut ctx.ts, &mut cw, inter_cfg, true)
+      })
+      .unzip()
+  };
+  for tile_stats in stats {
+    fs_c.enc_stats += &tile_stats;
+  }
+
+  if fi_c.cdef_enabled() {
+    if fi_c.cdef_search_method != CDEFSearchMethod::PickFromQ {
+      search_cdef_strengths(fi_c, fs_c, &blocks);
+    }
+    let fi_c = &*fi_c;
+    ti.tile_iter_mut(fs_c, &mut blocks)
+      .zip(cdfs.iter_mut())
+      .zip(&lf_queues)
+      .collect::<Vec<_>>()
+      .into_par_iter()
+      .for_each(|((mut ctx, cdf), lf_queue)| {
+        let mut cw = ContextWriter::new(cdf, BlockContext::new(&mut ctx.tb));
+        rdo_superres_cdef(fi_c, &mut ctx.ts, &mut cw, lf_queue);
+      });
+  }
+
+  let levels = fs_c.apply_tile_state_mut(|ts| {
+    let rec = &mut ts.rec;
+    deblock_filter_optimize(
+      fi_c,
+      &rec.as_const(),
+      &ts.input.as_tile(),
+      &blocks.as_tile_blocks(),
+      fi_c.width,
+      fi_c.height,
+    )
+  });
+  fs_c.deblock.levels = levels;
+
+  if fs_c.deblock.levels[0] != 0 || fs_c.deblock.levels[1] != 0 {
+    fs_c.apply_tile_state_mut(|ts| {
+      let rec = &mut ts.rec;
+      deblock_filter_frame(
+        ts.deblock,
+        rec,
+        &blocks.as_tile_blocks(),
+        fi_c.width,
+        fi_c.height,
+        fi_c.sequence.bit_depth,
+        planes,
+      );
+    });
+  }
+
+  let deblocked_frame = (*fs_c.rec).clone();
+  if fi_c.cdef_enabled() {
+    fs_c.apply_tile_state_mut(|ts| {
+      let rec = &mut ts.rec;
+      cdef_filter_tile(fi_c, &deblocked_frame, &blocks.as_tile_blocks(), rec);
+    });
+  }
+
+  let mut rec = Frame::new(
+    fs.rec.y_plane.width().get(),
+    fs.rec.y_plane.height().get(),
+    fi.sequence.chroma_sampling,
+  );
+  let mut pre_cdef = rec.clone();
+  let bit_depth = fi.sequence.bit_depth;
+  upscale_frame(
+    &fs_c.rec, &mut rec, fi_c.width, fi.width, fi.height, bit_depth,
+  );
+  upscale_frame(
+    &deblocked_frame,
+    &mut pre_cdef,
+    fi_c.width,
+    fi.width,
+    fi.height,
+    bit_depth,
+  );
+
+  let mut rs = RestorationState::new(fi, &fs.input);
+  if fi.restoration_enabled() {
+    rdo_lrf_upscaled(fi, &mut rs, &fs.input, &rec);
+    rs.lrf_filter_frame(&mut rec, &pre_cdef, fi);
+  }
+  fs.rec = Arc::new(rec);
+
+  fi_c.sequence = Arc::new(Sequence {
+    enable_restoration: fi.sequence.enable_restoration,
+    ..*fi_c.sequence
+  });
+  let raw_tiles: Vec<_> = {
+    let fi_c = &*fi_c;
+    let rs = &rs;
+    ti.tile_iter_mut(fs_c, &mut blocks)
+      .zip(cdfs.iter_mut())
+      .zip(lf_queues)
+      .collect::<Vec<_>>()
+      .into_par_iter()
+      .map(|((mut ctx, cdf), lf_queue)| {
+        let mut cw = ContextWriter::new(cdf, BlockContext::new(&mut ctx.tb));
+        write_superres_tile(fi_c, &ctx.ts, &mut cw, lf_queue, rs)
+      })
+      .collect()
+  };
+  fs_c.restoration = rs.clone();
+  fs.restoration = rs;
+
+  let (idx_max, max_len) = raw_tiles
+    .iter()
+    .map(Vec::len)
+    .enumerate()
+    .max_by_key(|&(_, len)| len)
+    .unwrap();
+
+  if !fi_c.disable_frame_end_update_cdf {
+    // use the biggest tile (in bytes) for CDF update
+    fs_c.context_update_tile_id = idx_max;
+    fs_c.cdfs = cdfs[idx_max];
+    fs_c.cdfs.reset_counts();
+  }
+
+  let max_tile_size_bytes = ILog::ilog(max_len).div_ceil(8) as u32;
+  debug_assert!(max_tile_size_bytes > 0 && max_tile_size_bytes <= 4);
+  fs_c.max_tile_size_bytes = max_tile_size_bytes;
+
+  build_raw_tile_group(&ti, &raw_tiles, max_tile_size_bytes)
+}
+
+/// Decides the CDEF index of the superblocks of a tile coded with
+/// superres, as `check_lf_queue` does without loop restoration.
+fn rdo_superres_cdef<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
+  cw: &mut ContextWriter, lf_queue: &LoopFilterQueue,
+) where
+  i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+{
+  let planes = if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    MAX_PLANES
+  };
+  let mut last_lru_rdoed = [-1; MAX_PLANES];
+  for qe in lf_queue.sbs_q.iter().filter(|qe| qe.cdef_coded) {
+    let already_rdoed = (0..planes).any(|pli| {
+      qe.lru_index[pli] != -1 && qe.lru_index[pli] <= last_lru_rdoed[pli]
+    });
+    if !already_rdoed {
+      rdo_loop_decision(qe.sbo, fi, ts, cw, &mut WriterCounter::new(), true);
+      for pli in 0..planes {
+        last_lru_rdoed[pli] = last_lru_rdoed[pli].max(qe.lru_index[pli]);
+      }
+    }
+  }
+}
+
+/// Returns the rows and columns of the restoration units of a plane
+/// configured by `cfg` that are coded with the superblock of size
+/// `1 << sb_size_log2` at `sbo` of a frame coded with the superres
+/// denominator `denom` (`read_lr` in the AV1 specification).
+fn superres_lr_units(
+  cfg: &RestorationPlaneConfig, sbo: PlaneSuperBlockOffset,
+  sb_size_log2: usize, xdec: usize, ydec: usize, denom: u8,
+) -> (Range<usize>, Range<usize>) {
+  let unit_size = cfg.unit_size;
+  let sb_mi = 1 << (sb_size_log2 - MI_SIZE_LOG2);
+  let (r, c) = (
+    sbo.0.y << SUPERBLOCK_TO_BLOCK_SHIFT,
+    sbo.0.x << SUPERBLOCK_TO_BLOCK_SHIFT,
+  );
+  let row_start = (r * (MI_SIZE >> ydec)).div_ceil(unit_size);
+  let row_end =
+    cfg.rows.min(((r + sb_mi) * (MI_SIZE >> ydec)).div_ceil(unit_size));
+  let num = (MI_SIZE >> xdec) * denom as usize;
+  let den = unit_size * SUPERRES_NUM as usize;
+  let col_start = (c * num).div_ceil(den);
+  let col_end = cfg.cols.min(((c + sb_mi) * num).div_ceil(den));
+  (row_start..row_end, col_start..col_end)
+}
+
+/// Codes the symbols held back in `lf_queue` for a tile of a frame coded
+/// with superres, each superblock preceded by the restoration units of the
+/// upscaled frame, `rs`, that it signals.
+fn write_superres_tile<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, cw: &mut ContextWriter,
+  lf_queue: LoopFilterQueue, rs: &RestorationState,
+) -> Vec<u8> {
+  let LoopFilterQueue { mut w, sbs_q, .. } = lf_queue;
+  let planes = if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    MAX_PLANES
+  };
+
+  // The filters are coded relative to references reset in each tile.
+  let mut rs = rs.clone();
+  let mut rps: Vec<_> = rs
+    .planes
+    .iter_mut()
+    .take(planes)
+    .map(|rp| {
+      let (cols, rows) = (rp.cfg.cols, rp.cfg.rows);
+      TileRestorationPlaneMut::new(rp, 0, 0, cols, rows)
+    })
+    .collect();
+
+  for mut qe in sbs_q {
+    if fi.restoration_enabled() {
+      let sbo = PlaneSuperBlockOffset(SuperBlockOffset {
+        x: ts.sbo.0.x + qe.sbo.0.x,
+        y: ts.sbo.0.y + qe.sbo.0.y,
+      });
+      for (pli, rp) in rps.iter_mut().enumerate() {
+        let PlaneConfig { xdec, ydec, .. } = ts.rec.planes[pli].plane_cfg;
+        let (rows, cols) = superres_lr_units(
+          rp.rp_cfg,
+          sbo,
+          fi.sb_size_log2(),
+          xdec,
+          ydec,
+          fi.superres_denom,
+        );
+        for y in rows {
+          for x in cols.clone() {
+            let filter = rp.units[y][x].filter;
+            cw.write_lrf_unit(&mut w, rp, filter, pli);
+          }
+        }
+      }
+    }
+    replay_sb(fi, cw, &mut w, &mut qe);
+  }
+  w.done()
+}
+
+/// Searches the CDEF strengths of the frame on its deblocked
+/// reconstruction, before any superblock has coded its CDEF index.
+fn search_cdef_strengths<T: Pixel>(
+  fi: &mut FrameInvariants<T>, fs: &mut FrameState<T>, blocks: &FrameBlocks,
+) where
+  i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+{
+  let planes = if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    3
+  };
+  let mut deblocked = (*fs.rec).clone();
+  let fi_ref = &*fi;
+  let (cdef_bits, y_strengths, uv_strengths) = fs.apply_tile_state_mut(|ts| {
+    let tile_blocks = blocks.as_tile_blocks();
+    let mut deblock = *ts.deblock;
+    deblock.levels = deblock_filter_optimize(
+      fi_ref,
+      &ts.rec.as_const(),
+      &ts.input.as_tile(),
+      &tile_blocks,
+      fi_ref.width,
+      fi_ref.height,
+    );
+    if deblock.levels[0] != 0 || deblock.levels[1] != 0 {
+      deblock_filter_frame(
+        &deblock,
+        &mut deblocked.as_tile_mut(),
+        &tile_blocks,
+        fi_ref.width,
+        fi_ref.height,
+        fi_ref.sequence.bit_depth,
+        planes,
+      );
+    }
+    rdo_cdef_strengths(fi_ref, ts, &tile_blocks, &deblocked)
+  });
+  fi.cdef_bits = cdef_bits;
+  fi.cdef_y_strengths = y_strengths;
+  fi.cdef_uv_strengths = uv_strengths;
 }
 
 fn build_raw_tile_group(
@@ -3589,10 +4905,33 @@ pub struct SBSQueueEntry {
   pub sbo: TileSuperBlockOffset,
   pub lru_index: [i32; MAX_PLANES],
   pub cdef_coded: bool,
+  pub cdef_units: ArrayVec<CdefUnit, 4>,
   pub w_pre_cdef: WriterBase<WriterRecorder>,
   pub w_post_cdef: WriterBase<WriterRecorder>,
 }
 
+/// Codes the symbols of a queued superblock, with the CDEF indices of its
+/// 64x64 units now that they are decided.
+fn replay_sb<T: Pixel>(
+  fi: &FrameInvariants<T>, cw: &mut ContextWriter,
+  w: &mut WriterBase<WriterEncoder>, qe: &mut SBSQueueEntry,
+) {
+  qe.w_pre_cdef.replay(w);
+  // Code CDEF into the middle of the superblock
+  if let Some((first, rest)) = qe.cdef_units.split_first() {
+    // Insert from the back, so that the earlier positions stay valid
+    for unit in rest.iter().rev() {
+      let cdef_index = cw.bc.blocks.get_cdef(unit.sbo);
+      let position = unit.position.as_ref().unwrap();
+      qe.w_post_cdef.insert_literal(position, fi.cdef_bits, cdef_index as u32);
+    }
+    let cdef_index = cw.bc.blocks.get_cdef(first.sbo);
+    cw.write_cdef(w, cdef_index, fi.cdef_bits);
+    // Code queued symbols that come after the CDEF index
+    qe.w_post_cdef.replay(w);
+  }
+}
+
 #[profiling::function]
 fn check_lf_queue<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
@@ -3623,7 +4962,7 @@ fn check_lf_queue<T: Pixel>(
       }
       if check_queue {
         // yes, this entry is ready
-        if qe.cdef_coded || fi.sequence.enable_restoration {
+        if qe.cdef_coded || fi.restoration_enabled() {
           // only RDO once for a given LRU.
 
           // One quirk worth noting: LRUs in different planes
@@ -3661,8 +5000,7 @@ fn check_lf_queue<T: Pixel>(
           }
         }
         // write LRF information
-        if !fi.allow_intrabc && fi.sequence.enable_restoration {
-          // TODO: also disallow if lossless
+        if fi.restoration_enabled() {
           for pli in 0..planes {
             if qe.lru_index[pli] != -1
               && last_lru_coded[pli] < qe.lru_index[pli]
@@ -3672,15 +5010,8 @@ fn check_lf_queue<T: Pixel>(
             }
           }
         }
-        // Now that loop restoration is coded, we can replay the initial block bits
-        qe.w_pre_cdef.replay(w);
-        // Now code CDEF into the middle of the block
-        if qe.cdef_coded {
-          let cdef_index = cw.bc.blocks.get_cdef(qe.sbo);
-          cw.write_cdef(w, cdef_index, fi.cdef_bits);
-          // Code queued symbols that come after the CDEF index
-          qe.w_post_cdef.replay(w);
-        }
+        // Now that loop restoration is coded, we can replay the block bits
+        replay_sb(fi, cw, w, qe);
         sbs_q.pop_front();
       }
     } else {
@@ -3689,6 +5020,16 @@ fn check_lf_queue<T: Pixel>(
   }
 }
 
+/// Symbols of a tile waiting for the loop filter decisions of the
+/// superblocks they belong to.
+struct LoopFilterQueue {
+  w: WriterBase<WriterEncoder>,
+  sbs_q: VecDeque<SBSQueueEntry>,
+  last_lru_ready: [i32; 3],
+  last_lru_rdoed: [i32; 3],
+  last_lru_coded: [i32; 3],
+}
+
 #[profiling::function]
 fn encode_tile<'a, T: Pixel>(
   fi: &FrameInvariants<T>, ts: &'a mut TileStateMut<'_, T>,
@@ -3700,6 +5041,28 @@ where
   i32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
   <T as util::pixel::Pixel>::Coeff: num_traits::AsPrimitive<u8>,
   i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+  i16: util::math::CastFromPrimitive<T>,
+{
+  let bc = BlockContext::new(blocks);
+  let mut cw = ContextWriter::new(fc, bc);
+  let (lf_queue, enc_stats) =
+    encode_tile_superblocks(fi, ts, &mut cw, inter_cfg, false);
+  (flush_lf_queue(fi, ts, &mut cw, lf_queue), enc_stats)
+}
+
+/// Encodes the superblocks of a tile.  Their symbols are coded as soon
+/// as their loop filters have been decided, unless `delay_lf` is set or
+/// the loop filter RDO is delayed, in which case they are all left in
+/// the returned queue.
+fn encode_tile_superblocks<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
+  cw: &mut ContextWriter, inter_cfg: &InterConfig, delay_lf: bool,
+) -> (LoopFilterQueue, EncoderStats)
+where
+  u32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
+  i32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
+  <T as util::pixel::Pixel>::Coeff: num_traits::AsPrimitive<u8>,
   i32: util::math::CastFromPrimitive<T>,
   u32: util::math::CastFromPrimitive<T>,
   i16: util::math::CastFromPrimitive<T>,
@@ -3713,18 +5076,20 @@ where
     3
   };
 
-  let bc = BlockContext::new(blocks);
-  let mut cw = ContextWriter::new(fc, bc);
   let mut sbs_q: VecDeque<SBSQueueEntry> = VecDeque::new();
   let mut last_lru_ready = [-1; 3];
   let mut last_lru_rdoed = [-1; 3];
   let mut last_lru_coded = [-1; 3];
+  // Superblock offsets count 64x64 units, a 128x128 superblock spans two
+  // of them in each direction.
+  let sb_size = fi.sb_size();
+  let sb_step = 1 << (fi.sb_size_log2() - SUPERBLOCK_TO_PLANE_SHIFT);
 
   // main loop
-  for sby in 0..ts.sb_height {
+  for sby in (0..ts.sb_height).step_by(sb_step) {
     cw.bc.reset_left_contexts(planes);
 
-    for sbx in 0..ts.sb_width {
+    for sbx in (0..ts.sb_width).step_by(sb_step) {
       cw.fc_log.clear();
 
       let tile_sbo = TileSuperBlockOffset(SuperBlockOffset { x: sbx, y: sby });
@@ -3732,18 +5097,18 @@ where
         sbo: tile_sbo,
         lru_index: [-1; MAX_PLANES],
         cdef_coded: false,
+        cdef_units: ArrayVec::new(),
         w_pre_cdef: WriterRecorder::new(),
         w_post_cdef: WriterRecorder::new(),
       };
 
       let tile_bo = tile_sbo.block_offset(0, 0);
       cw.bc.cdef_coded = false;
+      cw.bc.cdef_units.clear();
       cw.bc.code_deltas = fi.delta_q_present;
 
-      let is_straddle_sbx =
-        tile_bo.0.x + BlockSize::BLOCK_64X64.width_mi() > ts.mi_width;
-      let is_straddle_sby =
-        tile_bo.0.y + BlockSize::BLOCK_64X64.height_mi() > ts.mi_height;
+      let is_straddle_sbx = tile_bo.0.x + sb_size.width_mi() > ts.mi_width;
+      let is_straddle_sby = tile_bo.0.y + sb_size.height_mi() > ts.mi_height;
 
       // Encode SuperBlock
       if fi.config.speed_settings.partition.encode_bottomup
@@ -3753,10 +5118,10 @@ where
         encode_partition_bottomup(
           fi,
           ts,
-          &mut cw,
+          cw,
           &mut sbs_qe.w_pre_cdef,
           &mut sbs_qe.w_post_cdef,
-          BlockSize::BLOCK_64X64,
+          sb_size,
           tile_bo,
           f64::MAX,
           inter_cfg,
@@ -3766,10 +5131,10 @@ where
         encode_partition_topdown(
           fi,
           ts,
-          &mut cw,
+          cw,
           &mut sbs_qe.w_pre_cdef,
           &mut sbs_qe.w_post_cdef,
-          BlockSize::BLOCK_64X64,
+          sb_size,
           tile_bo,
           &None,
           inter_cfg,
@@ -3781,6 +5146,13 @@ where
         let mut check_queue = false;
         // queue our superblock for when the LRU is complete
         sbs_qe.cdef_coded = cw.bc.cdef_coded;
+        sbs_qe.cdef_units = cw.bc.cdef_units.clone();
+        // The superblock is complete once its bottom-right 64x64 unit in
+        // the tile is.
+        let last_sbo = TileSuperBlockOffset(SuperBlockOffset {
+          x: (sbx + sb_step - 1).min(ts.sb_width - 1),
+          y: (sby + sb_step - 1).min(ts.sb_height - 1),
+        });
         for pli in 0..planes {
           if let Some((lru_x, lru_y)) =
             ts.restoration.planes[pli].restoration_unit_index(tile_sbo, false)
@@ -3790,7 +5162,7 @@ where
               as i32;
             sbs_qe.lru_index[pli] = lru_index;
             if ts.restoration.planes[pli]
-              .restoration_unit_last_sb_for_rdo(fi, ts.sbo, tile_sbo)
+              .restoration_unit_last_sb_for_rdo(fi, ts.sbo, last_sbo)
             {
               last_lru_ready[pli] = lru_index;
               check_queue = true;
@@ -3804,11 +5176,14 @@ where
         }
         sbs_q.push_back(sbs_qe);
 
-        if check_queue && !fi.sequence.enable_delayed_loopfilter_rdo {
+        if check_queue
+          && !delay_lf
+          && !fi.sequence.enable_delayed_loopfilter_rdo
+        {
           check_lf_queue(
             fi,
             ts,
-            &mut cw,
+            cw,
             &mut w,
             &mut sbs_q,
             &mut last_lru_ready,
@@ -3821,9 +5196,44 @@ where
     }
   }
 
+  (
+    LoopFilterQueue {
+      w,
+      sbs_q,
+      last_lru_ready,
+      last_lru_rdoed,
+      last_lru_coded,
+    },
+    enc_stats,
+  )
+}
+
+/// Decides the loop filters of the superblocks left in the queue of a
+/// tile and codes their symbols, returning the coded tile.
+fn flush_lf_queue<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
+  cw: &mut ContextWriter, lf_queue: LoopFilterQueue,
+) -> Vec<u8>
+where
+  i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+{
+  let LoopFilterQueue {
+    mut w,
+    mut sbs_q,
+    mut last_lru_ready,
+    mut last_lru_rdoed,
+    mut last_lru_coded,
+  } = lf_queue;
+  let planes = if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    3
+  };
+
   if fi.sequence.enable_delayed_loopfilter_rdo {
     // Solve deblocking for just this tile
-    /* TODO: Don't apply if lossless */
     let deblock_levels = deblock_filter_optimize(
       fi,
       &ts.rec.as_const(),
@@ -3864,7 +5274,7 @@ where
       check_lf_queue(
         fi,
         ts,
-        &mut cw,
+        cw,
         &mut w,
         &mut sbs_q,
         &mut last_lru_ready,
@@ -3890,7 +5300,7 @@ where
       check_lf_queue(
         fi,
         ts,
-        &mut cw,
+        cw,
         &mut w,
         &mut sbs_q,
         &mut last_lru_ready,
@@ -3899,6 +5309,19 @@ where
         false,
       );
     }
+  } else {
+    // rdo lf and write whatever was delayed
+    check_lf_queue(
+      fi,
+      ts,
+      cw,
+      &mut w,
+      &mut sbs_q,
+      &mut last_lru_ready,
+      &mut last_lru_rdoed,
+      &mut last_lru_coded,
+      true,
+    );
   }
 
   assert!(
@@ -3907,7 +5330,7 @@ where
     ts.sbo.0.x,
     ts.sbo.0.y
   );
-  (w.done(), enc_stats)
+  w.done()
 }
 
 #[allow(unused)]
@@ -3976,21 +5399,8 @@ pub fn encode_show_existing_frame<T: Pixel>(
 
   let map_idx = fi.frame_to_show_map_idx as usize;
   if let Some(ref rec) = fi.rec_buffer.frames[map_idx] {
-    let fs_rec = Arc::get_mut(&mut fs.rec).unwrap();
-    let planes =
-      if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome {
-        1
-      } else {
-        3
-      };
-    for p in 0..planes {
-      fs_rec
-        .planes_mut()
-        .nth(p)
-        .unwrap()
-        .data_mut()
-        .copy_from_slice(&rec.frame.planes().nth(p).unwrap().data());
-    }
+    // The frame shown may have been coded at another size.
+    fs.rec = rec.frame.clone();
   }
   packet
 }
@@ -4015,7 +5425,7 @@ fn get_initial_segmentation<T: Pixel>(
 /// - If the frame packets cannot be written
 #[profiling::function]
 pub fn encode_frame<T: Pixel>(
-  fi: &FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
+  fi: &mut FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
 ) -> Vec<u8>
 where
   u32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
@@ -4026,9 +5436,16 @@ where
   i16: util::math::CastFromPrimitive<T>,
 {
   debug_assert!(!fi.is_show_existing_frame());
-  let obu_extension = 0;
+  if fi.superres_denom != SUPERRES_NUM {
+    return encode_superres_frame(fi, fs, inter_cfg);
+  }
 
-  let mut packet = Vec::new();
+  // The references scaled for the motion search are not kept.
+  let rec_buffer = fi.has_scaled_refs().then(|| {
+    let rec_buffer = fi.rec_buffer.clone();
+    fi.scale_refs();
+    rec_buffer
+  });
 
   if fi.enable_segmentation {
     fs.segmentation = get_initial_segmentation(fi);
@@ -4036,6 +5453,71 @@ where
   }
   let tile_group = encode_tile_group(fi, fs, inter_cfg);
 
+  if let Some(rec_buffer) = rec_buffer {
+    fi.rec_buffer = rec_buffer;
+  }
+  write_frame_packet(fi, fs, inter_cfg, &tile_group)
+}
+
+/// Encodes a frame at its reduced superres width. It is reconstructed,
+/// deblocked and filtered by CDEF at that width, then upscaled to the full
+/// size of `fi` and `fs`, where loop restoration is decided and applied.
+fn encode_superres_frame<T: Pixel>(
+  fi: &mut FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
+) -> Vec<u8>
+where
+  u32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
+  i32: util::math::CastFromPrimitive<<T as util::pixel::Pixel>::Coeff>,
+  <T as util::pixel::Pixel>::Coeff: num_traits::AsPrimitive<u8>,
+  i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+  i16: util::math::CastFromPrimitive<T>,
+{
+  let mut fi_c = fi.superres_coded();
+  let input =
+    resample_frame(&fs.input, fi.width, fi.height, fi_c.width, fi_c.height);
+  let mut fs_c = FrameState::new_with_frame(&fi_c, Arc::new(input));
+  fs_c.frame_me_stats = {
+    let me_stats = fs.frame_me_stats.read().expect("poisoned lock");
+    Arc::new(RwLock::new(std::array::from_fn(|i| {
+      me_stats[i].resampled(fi_c.w_in_b, fi_c.h_in_b)
+    })))
+  };
+
+  if fi_c.enable_segmentation {
+    fs_c.segmentation = get_initial_segmentation(&fi_c);
+    segmentation_optimize(&fi_c, &mut fs_c);
+  }
+  let tile_group =
+    encode_superres_tile_group(fi, fs, &mut fi_c, &mut fs_c, inter_cfg);
+  let packet = write_frame_packet(&fi_c, &fs_c, inter_cfg, &tile_group);
+
+  fi.cdef_bits = fi_c.cdef_bits;
+  fi.cdef_y_strengths = fi_c.cdef_y_strengths;
+  fi.cdef_uv_strengths = fi_c.cdef_uv_strengths;
+  {
+    let me_stats = fs_c.frame_me_stats.read().expect("poisoned lock");
+    *fs.frame_me_stats.write().expect("poisoned lock") =
+      std::array::from_fn(|i| me_stats[i].resampled(fi.w_in_b, fi.h_in_b));
+  }
+  fs.cdfs = fs_c.cdfs;
+  fs.context_update_tile_id = fs_c.context_update_tile_id;
+  fs.max_tile_size_bytes = fs_c.max_tile_size_bytes;
+  fs.deblock = fs_c.deblock;
+  fs.segmentation = fs_c.segmentation;
+  fs.enc_stats += &fs_c.enc_stats;
+  packet
+}
+
+/// Writes the packet of a frame whose tiles are coded in `tile_group`.
+fn write_frame_packet<T: Pixel>(
+  fi: &FrameInvariants<T>, fs: &FrameState<T>, inter_cfg: &InterConfig,
+  tile_group: &[u8],
+) -> Vec<u8> {
+  let obu_extension = 0;
+
+  let mut packet = Vec::new();
+
   if fi.frame_type == FrameType::KEY {
     write_key_frame_obus(&mut packet, fi, obu_extension).unwrap();
   }
@@ -4072,7 +5554,7 @@ where
   packet.write_all(&buf2).unwrap();
   buf2.clear();
 
-  packet.write_all(&tile_group).unwrap();
+  packet.write_all(tile_group).unwrap();
   packet
 }
 
@@ -4088,10 +5570,12 @@ pub fn update_rec_buffer<T: Pixel>(
     frame: fs.rec.clone(),
     input_hres: fs.input_hres.clone(),
     input_qres: fs.input_qres.clone(),
+    scaled_y_plane: None,
     cdfs: fs.cdfs,
     frame_me_stats: fs.frame_me_stats.clone(),
     output_frameno,
     segmentation: fs.segmentation,
+    globalmv_params: fi.globalmv_params,
   });
   for i in 0..REF_FRAMES {
     if (fi.refresh_frame_flags & (1 << i)) != 0 {
@@ -4112,4 +5596,50 @@ mod test {
       PartitionType::PARTITION_SPLIT
     );
   }
+
+  #[test]
+  fn superblock_size_selection() {
+    let sb128 = |width, height, speed, superblock_size| {
+      let mut config = EncoderConfig::with_speed_preset(speed);
+      config.width = width;
+      config.height = height;
+      config.superblock_size = superblock_size;
+      Sequence::new(&config).use_128x128_superblock
+    };
+    assert!(sb128(3840, 2160, 6, SuperblockSize::Auto));
+    assert!(!sb128(1920, 1080, 6, SuperblockSize::Auto));
+    // Speed 10 never codes blocks larger than 32x32
+    assert!(!sb128(3840, 2160, 10, SuperblockSize::Auto));
+    assert!(!sb128(3840, 2160, 6, SuperblockSize::Sb64));
+    assert!(sb128(64, 64, 10, SuperblockSize::Sb128));
+  }
+
+  #[test]
+  fn distance_weights_match_libaom() {
+    // (d0, d1, [FwdWeight, BckWeight]) from
+    // av1_dist_wtd_comp_weight_assign() of libaom.
+    let cases = [
+      // Equal distances favor the first reference.
+      (1, 1, [7, 9]),
+      (4, 4, [7, 9]),
+      // The comparison swaps with the order of the distances.
+      (1, 2, [5, 11]),
+      (2, 1, [11, 5]),
+      (2, 3, [5, 11]),
+      (3, 2, [11, 5]),
+      (1, 3, [4, 12]),
+      (3, 1, [12, 4]),
+      (1, 8, [3, 13]),
+      (8, 1, [13, 3]),
+      // A zero distance takes the last row of the table.
+      (0, 0, [3, 13]),
+      (0, 5, [3, 13]),
+      (5, 0, [13, 3]),
+      // Distances are clamped to 31.
+      (40, 31, [7, 9]),
+    ];
+    for (d0, d1, expected) in cases {
+      assert_eq!(distance_weights(d0, d1), expected, "d0 {d0}, d1 {d1}");
+    }
+  }
 }
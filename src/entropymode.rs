@@ -18,7 +18,7 @@ use crate::util::*;
 pub const PALETTE_BSIZE_CTXS: usize = 7;
 pub const PALETTE_Y_MODE_CONTEXTS: usize = 3;
 pub const PALETTE_UV_MODE_CONTEXTS: usize = 2;
-const PALETTE_COLOR_INDEX_CONTEXTS: usize = 5;
+pub const PALETTE_COLOR_INDEX_CONTEXTS: usize = 5;
 const RESTORE_SWITCHABLE_TYPES: usize = 3;
 pub const TX_SIZE_CONTEXTS: usize = 3;
 
@@ -513,11 +513,9 @@ pub static default_cfl_alpha_cdf: [[u16; CFL_ALPHABET_SIZE];
   ],
 ]);
 
-// This does not appear to be used in the rust project currently
-const SWITCHABLE_FILTERS: usize = 3;
-const SWITCHABLE_FILTER_CONTEXTS: usize = (SWITCHABLE_FILTERS + 1) * 4;
+pub const SWITCHABLE_FILTERS: usize = 3;
+pub const SWITCHABLE_FILTER_CONTEXTS: usize = (SWITCHABLE_FILTERS + 1) * 4;
 
-#[allow(unused)]
 pub static default_switchable_interp_cdf: [[u16; SWITCHABLE_FILTERS];
   SWITCHABLE_FILTER_CONTEXTS] = cdf_2d([
   [31935, 32720],
@@ -569,11 +567,9 @@ pub static default_compound_mode_cdf: [[u16; INTER_COMPOUND_MODES];
   [13046, 23214, 24505, 25942, 27435, 28442, 29330],
 ]);
 
-#[allow(unused)]
 pub static default_interintra_cdf: [[u16; 2]; BLOCK_SIZE_GROUPS] =
   cdf_2d([[16384], [26887], [27597], [30237]]);
 
-#[allow(unused)]
 pub static default_interintra_mode_cdf: [[u16;
   InterIntraMode::INTERINTRA_MODES as usize];
   BLOCK_SIZE_GROUPS] = cdf_2d([
@@ -583,7 +579,6 @@ pub static default_interintra_mode_cdf: [[u16;
   [4238, 11537, 25926],
 ]);
 
-#[allow(unused)]
 pub static default_wedge_interintra_cdf: [[u16; 2];
   BlockSize::BLOCK_SIZES_ALL] = cdf_2d([
   [16384],
@@ -610,7 +605,6 @@ pub static default_wedge_interintra_cdf: [[u16; 2];
   [16384],
 ]);
 
-#[allow(unused)]
 pub static default_compound_type_cdf: [[u16;
   CompoundType::COMPOUND_TYPES as usize - 1];
   BlockSize::BLOCK_SIZES_ALL] = cdf_2d([
@@ -638,7 +632,6 @@ pub static default_compound_type_cdf: [[u16;
   [16384],
 ]);
 
-#[allow(unused)]
 pub static default_wedge_idx_cdf: [[u16; 16]; BlockSize::BLOCK_SIZES_ALL] =
   cdf_2d([
     [
@@ -731,7 +724,6 @@ pub static default_wedge_idx_cdf: [[u16; 16]; BlockSize::BLOCK_SIZES_ALL] =
     ],
   ]);
 
-#[allow(unused)]
 pub static default_motion_mode_cdf: [[u16;
   MotionMode::MOTION_MODES as usize];
   BlockSize::BLOCK_SIZES_ALL] = cdf_2d([
@@ -759,7 +751,6 @@ pub static default_motion_mode_cdf: [[u16;
   [29742, 31203],
 ]);
 
-#[allow(unused)]
 pub static default_obmc_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL] =
   cdf_2d([
     [16384],
@@ -820,7 +811,6 @@ pub static default_comp_ref_cdf: [[[u16; 2]; FWD_REFS - 1]; REF_CONTEXTS] =
 pub static default_comp_bwdref_cdf: [[[u16; 2]; BWD_REFS - 1]; REF_CONTEXTS] =
   cdf_3d([[[2235], [1423]], [[17182], [15175]], [[30606], [30489]]]);
 
-#[allow(unused)]
 pub static default_palette_y_size_cdf: [[u16;
   PaletteSize::PALETTE_SIZES as usize];
   PALETTE_BSIZE_CTXS] = cdf_2d([
@@ -833,7 +823,6 @@ pub static default_palette_y_size_cdf: [[u16;
   [14940, 20797, 21678, 24186, 27033, 28999],
 ]);
 
-#[allow(unused)]
 pub static default_palette_uv_size_cdf: [[u16;
   PaletteSize::PALETTE_SIZES as usize];
   PALETTE_BSIZE_CTXS] = cdf_2d([
@@ -860,7 +849,6 @@ pub static default_palette_y_mode_cdfs: [[[u16; 2]; PALETTE_Y_MODE_CONTEXTS];
 pub static default_palette_uv_mode_cdfs: [[u16; 2]; PALETTE_UV_MODE_CONTEXTS] =
   cdf_2d([[32461], [21488]]);
 
-#[allow(unused)]
 pub static default_palette_y_color_index_cdf: [[[u16;
   PaletteColor::PALETTE_COLORS as usize];
   PALETTE_COLOR_INDEX_CONTEXTS];
@@ -910,7 +898,6 @@ pub static default_palette_y_color_index_cdf: [[[u16;
   ]),
 ];
 
-#[allow(unused)]
 pub static default_palette_uv_color_index_cdf: [[[u16;
   PaletteColor::PALETTE_COLORS as usize];
   PALETTE_COLOR_INDEX_CONTEXTS];
@@ -992,18 +979,15 @@ pub static default_skip_cdfs: [[u16; 2]; SKIP_CONTEXTS] =
 pub static default_skip_mode_cdfs: [[u16; 2]; SKIP_MODE_CONTEXTS] =
   cdf_2d([[32621], [20708], [8127]]);
 
-#[allow(unused)]
 pub static default_compound_idx_cdfs: [[u16; 2]; COMP_INDEX_CONTEXTS] =
   cdf_2d([[18244], [12865], [7053], [13259], [9334], [4644]]);
 
-#[allow(unused)]
 pub static default_comp_group_idx_cdfs: [[u16; 2]; COMP_GROUP_IDX_CONTEXTS] =
   cdf_2d([[26607], [22891], [18840], [24594], [19934], [22674]]);
 
 #[allow(unused)]
 pub static default_intrabc_cdf: [u16; 2] = cdf([30531]);
 
-#[allow(unused)]
 pub static default_filter_intra_mode_cdf: [u16;
   FilterIntraMode::FILTER_INTRA_MODES as usize] =
   cdf([8949, 12776, 17211, 29558]);
@@ -8,7 +8,7 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use crate::api::{Opaque, T35};
-use crate::context::SB_SIZE;
+use crate::context::MAX_SB_SIZE;
 use crate::mc::SUBPEL_FILTER_SIZE;
 use crate::tiling::{Tile, TileMut, TileRect};
 use crate::util::Pixel;
@@ -21,7 +21,7 @@ mod plane;
 pub use plane::{AsRegion, Plane, PlaneConfig, PlaneGeometry};
 
 const FRAME_MARGIN: usize = 16 + SUBPEL_FILTER_SIZE;
-const LUMA_PADDING: usize = SB_SIZE + FRAME_MARGIN;
+const LUMA_PADDING: usize = MAX_SB_SIZE + FRAME_MARGIN;
 
 /// Extension trait to iterate over planes in a Frame
 pub trait FrameIter<T: Pixel> {
@@ -64,7 +64,11 @@ pub enum FrameTypeOverride {
 }
 
 /// Optional per-frame encoder parameters
+///
+/// Fields may be added in future releases, so build it from
+/// `FrameParameters::default()`.
 #[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct FrameParameters {
   /// Force emitted frame to be of the type selected
   pub frame_type_override: FrameTypeOverride,
@@ -72,6 +76,24 @@ pub struct FrameParameters {
   pub opaque: Option<Opaque>,
   /// List of t35 metadata associated with this frame
   pub t35_metadata: Box<[T35]>,
+  /// Presentation timestamp of the frame, in units of the `time_base`
+  ///
+  /// Defaults to the timestamp of the previous frame plus its duration.
+  pub pts: Option<u64>,
+  /// Display duration of the frame, in units of the `time_base`
+  ///
+  /// Defaults to the difference to the timestamp of the next frame.
+  pub duration: Option<u64>,
+  /// Code the frame at this width and height instead of the configured
+  /// ones, without starting a new keyframe
+  ///
+  /// The frame is resampled to this size and decoders output it at this
+  /// size, with the configured size as its render size. Inter frames
+  /// predict from references of other sizes with scaled motion
+  /// compensation, which limits the size to between half and 16 times that
+  /// of the references. Larger requests are clamped to these limits and
+  /// to the configured size.
+  pub frame_size: Option<(usize, usize)>,
 }
 
 pub use v_frame::frame::Frame;
@@ -162,8 +162,16 @@ impl Arbitrary<'_> for ArbitraryConfig {
     enc.reservoir_frame_delay = Arbitrary::arbitrary(u)?;
     enc.low_latency = Arbitrary::arbitrary(u)?;
     enc.quantizer = Arbitrary::arbitrary(u)?;
+    enc.lossless = Arbitrary::arbitrary(u)?;
     enc.min_quantizer = Arbitrary::arbitrary(u)?;
     enc.bitrate = Arbitrary::arbitrary(u)?;
+    enc.max_bitrate = Arbitrary::arbitrary(u)?;
+    enc.target_quality = Arbitrary::arbitrary(u)?;
+    enc.luma_dc_delta_q = Arbitrary::arbitrary(u)?;
+    enc.chroma_dc_delta_q = Arbitrary::arbitrary(u)?;
+    enc.chroma_ac_delta_q = Arbitrary::arbitrary(u)?;
+    enc.buffer_size = Arbitrary::arbitrary(u)?;
+    enc.buffer_initial_fullness = Arbitrary::arbitrary(u)?;
     enc.tile_cols = Arbitrary::arbitrary(u)?;
     enc.tile_rows = Arbitrary::arbitrary(u)?;
     enc.tiles = Arbitrary::arbitrary(u)?;
@@ -226,9 +234,24 @@ impl Arbitrary<'_> for ArbitraryEncoder {
       min_key_frame_interval: u.int_in_range(0..=3)?,
       max_key_frame_interval: u.int_in_range(1..=4)?,
       low_latency: Arbitrary::arbitrary(u)?,
+      pyramid_depth: u.int_in_range(1..=4)?,
       quantizer: Arbitrary::arbitrary(u)?,
+      lossless: Arbitrary::arbitrary(u)?,
       min_quantizer: Arbitrary::arbitrary(u)?,
       bitrate: Arbitrary::arbitrary(u)?,
+      max_bitrate: Arbitrary::arbitrary(u)?,
+      target_quality: Arbitrary::arbitrary(u)?,
+      luma_dc_delta_q: u.int_in_range(-63..=63)?,
+      chroma_dc_delta_q: [
+        u.int_in_range(-63..=63)?,
+        u.int_in_range(-63..=63)?,
+      ],
+      chroma_ac_delta_q: [
+        u.int_in_range(-63..=63)?,
+        u.int_in_range(-63..=63)?,
+      ],
+      buffer_size: Arbitrary::arbitrary(u)?,
+      buffer_initial_fullness: Arbitrary::arbitrary(u)?,
       tile_cols: u.int_in_range(0..=2)?,
       tile_rows: u.int_in_range(0..=2)?,
       tiles: u.int_in_range(0..=16)?,
@@ -257,6 +280,24 @@ impl Arbitrary<'_> for ArbitraryEncoder {
       enable_timing_info: Arbitrary::arbitrary(u)?,
       switch_frame_interval: u.int_in_range(0..=3)?,
       tune: *u.choose(&[Tune::Psnr, Tune::Psychovisual])?,
+      screen_content: *u.choose(&[
+        ScreenContentMode::Off,
+        ScreenContentMode::On,
+        ScreenContentMode::Auto,
+      ])?,
+      superres_mode: *u.choose(&[
+        SuperresMode::Off,
+        SuperresMode::Fixed,
+        SuperresMode::Random,
+        SuperresMode::Dynamic,
+      ])?,
+      superres_denom: u.int_in_range(9..=16)?,
+      superres_qthresh: Arbitrary::arbitrary(u)?,
+      superblock_size: *u.choose(&[
+        SuperblockSize::Auto,
+        SuperblockSize::Sb64,
+        SuperblockSize::Sb128,
+      ])?,
       film_grain_params: None,
     };
 
@@ -314,6 +355,7 @@ pub struct DecodeTestParameters<T: Pixel> {
   tile_cols_log2: usize,
   tile_rows_log2: usize,
   still_picture: bool,
+  lossless: bool,
   pixel: PhantomData<T>,
 }
 
@@ -341,6 +383,7 @@ impl<T: Pixel> Arbitrary<'_> for DecodeTestParameters<T> {
       tile_cols_log2: u.int_in_range(0..=2)?,
       tile_rows_log2: u.int_in_range(0..=2)?,
       still_picture: bool::arbitrary(u)?,
+      lossless: bool::arbitrary(u)?,
       pixel: PhantomData,
     };
     if matches!(T::type_enum(), PixelType::U16) {
@@ -352,6 +395,9 @@ impl<T: Pixel> Arbitrary<'_> for DecodeTestParameters<T> {
     if p.still_picture {
       p.limit = 1
     }
+    if p.lossless {
+      p.bitrate = 0;
+    }
     Ok(p)
   }
 }
@@ -379,6 +425,7 @@ pub fn fuzz_encode_decode<T: Pixel>(p: DecodeTestParameters<T>) {
     p.tile_cols_log2,
     p.tile_rows_log2,
     p.still_picture,
+    p.lossless,
     None,
   );
 }
@@ -0,0 +1,683 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+//! Frame-level global motion: estimation of a warped motion model between
+//! a frame and each of its references, and derivation of the motion vectors
+//! it implies for blocks using `GLOBALMV`.
+
+use crate::api::InterConfig;
+use crate::context::{PlaneBlockOffset, MI_SIZE};
+use crate::encoder::{FrameInvariants, FrameState};
+use crate::frame::*;
+use crate::mc::{setup_shear, MotionVector, WARPEDMODEL_PREC_BITS};
+use crate::partition::{
+  BlockSize, GlobalMVMode, RefType, INTER_REFS_PER_FRAME,
+};
+use crate::util::Pixel;
+
+/// Parameters of the identity model, the default for every reference.
+pub const DEFAULT_GLOBALMV_PARAMS: [i32; 6] =
+  [0, 0, 1 << WARPEDMODEL_PREC_BITS, 0, 0, 1 << WARPEDMODEL_PREC_BITS];
+
+const GM_ABS_ALPHA_BITS: u32 = 12;
+const GM_ALPHA_PREC_BITS: u32 = 15;
+const GM_ABS_TRANS_ONLY_BITS: u32 = 9;
+const GM_TRANS_ONLY_PREC_BITS: u32 = 3;
+const GM_ABS_TRANS_BITS: u32 = 12;
+const GM_TRANS_PREC_BITS: u32 = 6;
+
+/// Returns the range and the precision, in bits, with which parameter `idx`
+/// of a model is coded in the frame header.
+pub fn global_param_bits(
+  mode: GlobalMVMode, idx: usize, allow_high_precision_mv: bool,
+) -> (u32, u32) {
+  if idx >= 2 {
+    (GM_ABS_ALPHA_BITS, GM_ALPHA_PREC_BITS)
+  } else if mode == GlobalMVMode::TRANSLATION {
+    let hp = allow_high_precision_mv as u32;
+    (GM_ABS_TRANS_ONLY_BITS - 1 + hp, GM_TRANS_ONLY_PREC_BITS - 1 + hp)
+  } else {
+    (GM_ABS_TRANS_BITS, GM_TRANS_PREC_BITS)
+  }
+}
+
+/// Returns the motion vector that the global motion of `ref_frame` gives to
+/// a block, taken at its center. Blocks coded with `GLOBALMV` carry it, and
+/// it fills the MV stack when fewer than two candidates are found.
+pub fn global_mv<T: Pixel>(
+  fi: &FrameInvariants<T>, ref_frame: RefType, bo: PlaneBlockOffset,
+  bsize: BlockSize,
+) -> MotionVector {
+  if ref_frame == RefType::INTRA_FRAME || ref_frame == RefType::NONE_FRAME {
+    return MotionVector::default();
+  }
+  let params = &fi.globalmv_params[ref_frame.to_index()];
+  let mv = match fi.globalmv_transformation_type[ref_frame.to_index()] {
+    GlobalMVMode::IDENTITY => MotionVector::default(),
+    // The spec takes the row from the horizontal translation here.
+    GlobalMVMode::TRANSLATION => MotionVector {
+      row: (params[0] >> (WARPEDMODEL_PREC_BITS - 3)) as i16,
+      col: (params[1] >> (WARPEDMODEL_PREC_BITS - 3)) as i16,
+    },
+    GlobalMVMode::ROTZOOM | GlobalMVMode::AFFINE => {
+      let x = (bo.0.x * MI_SIZE + bsize.width() / 2) as i64 - 1;
+      let y = (bo.0.y * MI_SIZE + bsize.height() / 2) as i64 - 1;
+      let p = params.map(i64::from);
+      let xc = (p[2] - (1 << WARPEDMODEL_PREC_BITS)) * x + p[3] * y + p[0];
+      let yc = p[4] * x + (p[5] - (1 << WARPEDMODEL_PREC_BITS)) * y + p[1];
+      let round = |v: i64| {
+        if fi.allow_high_precision_mv {
+          round_shift_signed(v, WARPEDMODEL_PREC_BITS - 3)
+        } else {
+          round_shift_signed(v, WARPEDMODEL_PREC_BITS - 2) * 2
+        }
+      };
+      MotionVector { row: round(yc) as i16, col: round(xc) as i16 }
+    }
+  };
+  lower_mv_precision(fi, mv)
+}
+
+/// Rounds a motion vector to the precision the frame can code, as the AV1
+/// specification does for global motion and every MV candidate.
+pub fn lower_mv_precision<T: Pixel>(
+  fi: &FrameInvariants<T>, mv: MotionVector,
+) -> MotionVector {
+  if fi.allow_high_precision_mv {
+    return mv;
+  }
+  let lower_precision = |v: i16| {
+    if fi.force_integer_mv != 0 {
+      let a = (v.abs() + 3) >> 3;
+      if v > 0 {
+        a << 3
+      } else {
+        -(a << 3)
+      }
+    } else if v & 1 != 0 {
+      v - v.signum()
+    } else {
+      v
+    }
+  };
+  MotionVector { row: lower_precision(mv.row), col: lower_precision(mv.col) }
+}
+
+/// Whether `GLOBALMV` predicts a block of the given dimensions in a plane by
+/// warping `ref_frame`, rather than by translating it with [`global_mv`].
+pub fn is_global_warp<T: Pixel>(
+  fi: &FrameInvariants<T>, ref_frame: RefType, width: usize, height: usize,
+) -> bool {
+  ref_frame != RefType::INTRA_FRAME
+    && ref_frame != RefType::NONE_FRAME
+    && fi.globalmv_transformation_type[ref_frame.to_index()]
+      > GlobalMVMode::TRANSLATION
+    && width >= 8
+    && height >= 8
+    && fi.force_integer_mv == 0
+    && !fi.is_scaled(ref_frame)
+    && setup_shear(&fi.globalmv_params[ref_frame.to_index()]).is_some()
+}
+
+#[inline(always)]
+const fn round_shift_signed(value: i64, bit: u32) -> i64 {
+  if value < 0 {
+    -((-value + (1 << bit >> 1)) >> bit)
+  } else {
+    (value + (1 << bit >> 1)) >> bit
+  }
+}
+
+/// Corners are detected on a grid of cells this size, keeping at most one
+/// per cell.
+const CORNER_CELL_SIZE: usize = 8;
+/// Upper bound on the number of corners matched in a frame.
+const MAX_CORNERS: usize = 2048;
+/// Minimum of the smaller eigenvalue of the structure tensor of a corner,
+/// as the mean over its window of squared 8-bit gradients.
+const MIN_CORNER_STRENGTH: f64 = 64.0;
+/// Radius of the window over which the structure tensor is summed.
+const CORNER_WINDOW_RADIUS: usize = 3;
+/// Radius of the patches compared when matching corners.
+const PATCH_RADIUS: usize = 6;
+/// Matching corners are looked for this far away, in half-res pixels.
+const SEARCH_RADIUS: usize = 32;
+const MIN_CORRELATION: f64 = 0.8;
+const RANSAC_ITERATIONS: usize = 256;
+/// Squared distance, in half-res pixels, below which a correspondence fits
+/// a model.
+const INLIER_THRESHOLD: f64 = 1.0;
+const MIN_INLIERS: usize = 16;
+/// A model is coded only if warping the reference with it leaves at most
+/// this fraction of the error of the unwarped reference.
+const MAX_ERROR_RATIO: f64 = 0.7;
+/// The affine model is preferred to the rotation and zoom model only if it
+/// lowers the error below this fraction of it.
+const AFFINE_ERROR_RATIO: f64 = 0.95;
+
+/// A luma plane at half resolution, scaled to 8 bits.
+struct Image {
+  width: usize,
+  height: usize,
+  data: Box<[i32]>,
+}
+
+impl Image {
+  fn new<T: Pixel>(
+    plane: &Plane<T>, width: usize, height: usize, bit_depth: usize,
+  ) -> Self {
+    let region = plane.as_region();
+    let shift = bit_depth - 8;
+    let data = (0..height)
+      .flat_map(|y| region[y][..width].iter().map(|&p| p.to_i32() >> shift))
+      .collect();
+    Image { width, height, data }
+  }
+
+  #[inline(always)]
+  fn at(&self, x: usize, y: usize) -> i32 {
+    self.data[y * self.width + x]
+  }
+
+  /// Bilinear interpolation, repeating the edge samples outside the image.
+  fn sample(&self, x: f64, y: f64) -> f64 {
+    let x = x.clamp(0., (self.width - 1) as f64);
+    let y = y.clamp(0., (self.height - 1) as f64);
+    let (x0, y0) = (x as usize, y as usize);
+    let (x1, y1) =
+      ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let top = self.at(x0, y0) as f64 * (1. - fx) + self.at(x1, y0) as f64 * fx;
+    let bottom =
+      self.at(x0, y1) as f64 * (1. - fx) + self.at(x1, y1) as f64 * fx;
+    top * (1. - fy) + bottom * fy
+  }
+
+  /// Normalized cross-correlation of the patches centered on `(x, y)` in
+  /// this image and on `(rx, ry)` in `other`.
+  fn correlation(
+    &self, x: usize, y: usize, other: &Image, rx: usize, ry: usize,
+  ) -> f64 {
+    let n = ((2 * PATCH_RADIUS + 1) * (2 * PATCH_RADIUS + 1)) as f64;
+    let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0., 0., 0., 0., 0.);
+    for dy in 0..=2 * PATCH_RADIUS {
+      for dx in 0..=2 * PATCH_RADIUS {
+        let a = self.at(x + dx - PATCH_RADIUS, y + dy - PATCH_RADIUS) as f64;
+        let b =
+          other.at(rx + dx - PATCH_RADIUS, ry + dy - PATCH_RADIUS) as f64;
+        sa += a;
+        sb += b;
+        saa += a * a;
+        sbb += b * b;
+        sab += a * b;
+      }
+    }
+    let var_a = n * saa - sa * sa;
+    let var_b = n * sbb - sb * sb;
+    if var_a <= 0. || var_b <= 0. {
+      return 0.;
+    }
+    (n * sab - sa * sb) / (var_a * var_b).sqrt()
+  }
+}
+
+/// Strongest corner of each cell of the grid, by the smaller eigenvalue of
+/// the structure tensor, or `None` if the cell has no strong enough corner.
+fn detect_corners(image: &Image) -> Vec<Option<(usize, usize, f64)>> {
+  let (w, h) = (image.width, image.height);
+  let cols = w.div_ceil(CORNER_CELL_SIZE);
+  let rows = h.div_ceil(CORNER_CELL_SIZE);
+  let mut cells = vec![None; cols * rows];
+
+  // Corners need room for their window and for the matching patches,
+  // including the subpixel refinement.
+  let margin = PATCH_RADIUS.max(CORNER_WINDOW_RADIUS + 1) + 1;
+  if w <= 2 * margin || h <= 2 * margin {
+    return cells;
+  }
+
+  // Integral images of the products of the gradients.
+  let stride = w + 1;
+  let mut integral = vec![[0i64; 3]; stride * (h + 1)];
+  for y in 0..h {
+    let mut row_sum = [0i64; 3];
+    for x in 0..w {
+      if x > 0 && x < w - 1 && y > 0 && y < h - 1 {
+        let gx = (image.at(x + 1, y) - image.at(x - 1, y)) as i64;
+        let gy = (image.at(x, y + 1) - image.at(x, y - 1)) as i64;
+        row_sum[0] += gx * gx;
+        row_sum[1] += gx * gy;
+        row_sum[2] += gy * gy;
+      }
+      let above = integral[y * stride + x + 1];
+      integral[(y + 1) * stride + x + 1] =
+        [0, 1, 2].map(|i| above[i] + row_sum[i]);
+    }
+  }
+
+  let r = CORNER_WINDOW_RADIUS;
+  let window = ((2 * r + 1) * (2 * r + 1)) as f64;
+  for y in margin..h - margin {
+    for x in margin..w - margin {
+      let (x0, y0, x1, y1) = (x - r, y - r, x + r + 1, y + r + 1);
+      let [a, b, c] = [0, 1, 2].map(|i| {
+        (integral[y1 * stride + x1][i]
+          - integral[y0 * stride + x1][i]
+          - integral[y1 * stride + x0][i]
+          + integral[y0 * stride + x0][i]) as f64
+          / window
+      });
+      let strength = (a + c - ((a - c) * (a - c) + 4. * b * b).sqrt()) / 2.;
+      let cell =
+        &mut cells[(y / CORNER_CELL_SIZE) * cols + x / CORNER_CELL_SIZE];
+      if strength >= MIN_CORNER_STRENGTH
+        && cell.is_none_or(|(_, _, s)| strength > s)
+      {
+        *cell = Some((x, y, strength));
+      }
+    }
+  }
+  cells
+}
+
+/// A corner at `(x, y)` of the frame, found at `(rx, ry)` in the reference.
+#[derive(Clone, Copy, Debug)]
+struct Correspondence {
+  x: f64,
+  y: f64,
+  rx: f64,
+  ry: f64,
+}
+
+/// Matches the corners of the frame with those of the reference by the
+/// correlation of the patches around them, refined to subpixel precision.
+fn find_correspondences(
+  image: &Image, corners: &[(usize, usize)], reference: &Image,
+  ref_corners: &[Option<(usize, usize, f64)>],
+) -> Vec<Correspondence> {
+  let cols = reference.width.div_ceil(CORNER_CELL_SIZE);
+  let rows = reference.height.div_ceil(CORNER_CELL_SIZE);
+  let reach = SEARCH_RADIUS.div_ceil(CORNER_CELL_SIZE);
+
+  corners
+    .iter()
+    .filter_map(|&(x, y)| {
+      let (cx, cy) = (x / CORNER_CELL_SIZE, y / CORNER_CELL_SIZE);
+      let mut best: Option<(usize, usize, f64)> = None;
+      for row in cy.saturating_sub(reach)..(cy + reach + 1).min(rows) {
+        for col in cx.saturating_sub(reach)..(cx + reach + 1).min(cols) {
+          let Some((rx, ry, _)) = ref_corners[row * cols + col] else {
+            continue;
+          };
+          if rx.abs_diff(x) > SEARCH_RADIUS || ry.abs_diff(y) > SEARCH_RADIUS {
+            continue;
+          }
+          let c = image.correlation(x, y, reference, rx, ry);
+          if best.is_none_or(|(_, _, b)| c > b) {
+            best = Some((rx, ry, c));
+          }
+        }
+      }
+      let (rx, ry, c) = best.filter(|&(_, _, c)| c >= MIN_CORRELATION)?;
+
+      // Fit a parabola through the correlation of the neighbors.
+      let refine = |prev: f64, next: f64| {
+        let curvature = prev - 2. * c + next;
+        if curvature < 0. {
+          (0.5 * (prev - next) / curvature).clamp(-0.5, 0.5)
+        } else {
+          0.
+        }
+      };
+      let dx = refine(
+        image.correlation(x, y, reference, rx - 1, ry),
+        image.correlation(x, y, reference, rx + 1, ry),
+      );
+      let dy = refine(
+        image.correlation(x, y, reference, rx, ry - 1),
+        image.correlation(x, y, reference, rx, ry + 1),
+      );
+      Some(Correspondence {
+        x: x as f64,
+        y: y as f64,
+        rx: rx as f64 + dx,
+        ry: ry as f64 + dy,
+      })
+    })
+    .collect()
+}
+
+/// An affine model in the layout of the coded parameters, in pixels:
+/// `(rx, ry) = (m[2] x + m[3] y + m[0], m[4] x + m[5] y + m[1])`.
+type Model = [f64; 6];
+
+fn project(m: &Model, x: f64, y: f64) -> (f64, f64) {
+  (m[2] * x + m[3] * y + m[0], m[4] * x + m[5] * y + m[1])
+}
+
+/// Solves a small linear system by Gaussian elimination.
+fn solve<const N: usize>(
+  mut a: [[f64; N]; N], mut b: [f64; N],
+) -> Option<[f64; N]> {
+  for i in 0..N {
+    let pivot =
+      (i..N).max_by(|&j, &k| a[j][i].abs().total_cmp(&a[k][i].abs())).unwrap();
+    if a[pivot][i].abs() < 1e-9 {
+      return None;
+    }
+    a.swap(i, pivot);
+    b.swap(i, pivot);
+    for j in i + 1..N {
+      let f = a[j][i] / a[i][i];
+      for k in i..N {
+        a[j][k] -= f * a[i][k];
+      }
+      b[j] -= f * b[i];
+    }
+  }
+  let mut x = [0.; N];
+  for i in (0..N).rev() {
+    let s: f64 = (i + 1..N).map(|k| a[i][k] * x[k]).sum();
+    x[i] = (b[i] - s) / a[i][i];
+  }
+  Some(x)
+}
+
+/// Least squares fit of a rotation and zoom model.
+fn fit_rotzoom(points: &[Correspondence]) -> Option<Model> {
+  let mut ata = [[0.; 4]; 4];
+  let mut atb = [0.; 4];
+  for p in points {
+    for (row, target) in
+      [([p.x, p.y, 1., 0.], p.rx), ([p.y, -p.x, 0., 1.], p.ry)]
+    {
+      for i in 0..4 {
+        for j in 0..4 {
+          ata[i][j] += row[i] * row[j];
+        }
+        atb[i] += row[i] * target;
+      }
+    }
+  }
+  let [a, b, c, d] = solve(ata, atb)?;
+  Some([c, d, a, b, -b, a])
+}
+
+/// Least squares fit of an affine model.
+fn fit_affine(points: &[Correspondence]) -> Option<Model> {
+  let mut ata = [[0.; 3]; 3];
+  let mut atbx = [0.; 3];
+  let mut atby = [0.; 3];
+  for p in points {
+    let row = [p.x, p.y, 1.];
+    for i in 0..3 {
+      for j in 0..3 {
+        ata[i][j] += row[i] * row[j];
+      }
+      atbx[i] += row[i] * p.rx;
+      atby[i] += row[i] * p.ry;
+    }
+  }
+  let [a, b, c] = solve(ata, atbx)?;
+  let [d, e, f] = solve(ata, atby)?;
+  Some([c, f, a, b, d, e])
+}
+
+fn inliers(m: &Model, points: &[Correspondence]) -> Vec<Correspondence> {
+  points
+    .iter()
+    .filter(|p| {
+      let (rx, ry) = project(m, p.x, p.y);
+      (rx - p.rx).powi(2) + (ry - p.ry).powi(2) < INLIER_THRESHOLD
+    })
+    .copied()
+    .collect()
+}
+
+/// Finds the correspondences that agree on a rotation and zoom model by
+/// random sample consensus.
+fn ransac(points: &[Correspondence]) -> Vec<Correspondence> {
+  if points.len() < MIN_INLIERS {
+    return Vec::new();
+  }
+  // A fixed seed keeps the encoder deterministic.
+  let mut state = 0x9E37_79B9_7F4A_7C15u64;
+  let mut random = |n: usize| {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    (state % n as u64) as usize
+  };
+
+  let mut best = Vec::new();
+  for _ in 0..RANSAC_ITERATIONS {
+    let i = random(points.len());
+    let j = random(points.len());
+    if i == j {
+      continue;
+    }
+    if let Some(m) = fit_rotzoom(&[points[i], points[j]]) {
+      let fit = inliers(&m, points);
+      if fit.len() > best.len() {
+        best = fit;
+      }
+    }
+  }
+  // Refit to all of the inliers, which may gather a few more.
+  if let Some(m) = fit_rotzoom(&best) {
+    let fit = inliers(&m, points);
+    if fit.len() > best.len() {
+      best = fit;
+    }
+  }
+  best
+}
+
+/// Quantizes a model found at half resolution into the coded parameters of
+/// a full resolution model.
+fn quantize_model(m: &Model, mode: GlobalMVMode) -> [i32; 6] {
+  // Half-res samples are centered between two full-res samples.
+  let full = [
+    2. * m[0] + 0.5 * (1. - m[2] - m[3]),
+    2. * m[1] + 0.5 * (1. - m[4] - m[5]),
+    m[2],
+    m[3],
+    m[4],
+    m[5],
+  ];
+  let mut params = DEFAULT_GLOBALMV_PARAMS;
+  for idx in 0..6 {
+    let (abs_bits, prec_bits) = global_param_bits(mode, idx, false);
+    let identity = if idx % 3 == 2 { 1. } else { 0. };
+    let max = 1i64 << abs_bits;
+    let v =
+      ((full[idx] - identity) * (1u64 << prec_bits) as f64).round() as i64;
+    params[idx] = (v.clamp(-max, max) << (WARPEDMODEL_PREC_BITS - prec_bits))
+      as i32
+      + DEFAULT_GLOBALMV_PARAMS[idx];
+  }
+  if mode == GlobalMVMode::ROTZOOM {
+    params[4] = -params[3];
+    params[5] = params[2];
+  }
+  params
+}
+
+/// Sum of absolute differences between the frame and the reference warped
+/// with the full resolution model `params`, over every other row.
+fn warp_error(image: &Image, reference: &Image, params: &[i32; 6]) -> f64 {
+  let scale = (1 << WARPEDMODEL_PREC_BITS) as f64;
+  let p = params.map(|v| v as f64 / scale);
+  let mut error = 0.;
+  for y in (0..image.height).step_by(2) {
+    let fy = 2. * y as f64 + 0.5;
+    for x in 0..image.width {
+      let fx = 2. * x as f64 + 0.5;
+      let (rx, ry) = project(&p, fx, fy);
+      let pred = reference.sample((rx - 0.5) / 2., (ry - 0.5) / 2.);
+      error += (image.at(x, y) as f64 - pred).abs();
+    }
+  }
+  error
+}
+
+/// Estimates the global motion between the frame and each of its references
+/// from the half-resolution luma planes of the lookahead, and keeps the
+/// models that predict the frame well enough to be worth coding.
+pub(crate) fn estimate_global_motion<T: Pixel>(
+  fi: &mut FrameInvariants<T>, fs: &FrameState<T>, inter_cfg: &InterConfig,
+) {
+  fi.globalmv_transformation_type =
+    [GlobalMVMode::IDENTITY; INTER_REFS_PER_FRAME];
+  fi.globalmv_params = [DEFAULT_GLOBALMV_PARAMS; INTER_REFS_PER_FRAME];
+  if fi.intra_only
+    || fi.force_integer_mv != 0
+    || !fi.config.speed_settings.motion.global_motion
+  {
+    return;
+  }
+
+  let (width, height) = (fi.width.div_ceil(2), fi.height.div_ceil(2));
+  let bit_depth = fi.sequence.bit_depth;
+  let image = Image::new(&fs.input_hres, width, height, bit_depth);
+  let mut corners: Vec<_> =
+    detect_corners(&image).into_iter().flatten().collect();
+  corners.sort_by(|a, b| b.2.total_cmp(&a.2));
+  corners.truncate(MAX_CORNERS);
+  let corners: Vec<_> = corners.into_iter().map(|(x, y, _)| (x, y)).collect();
+  if corners.len() < MIN_INLIERS {
+    return;
+  }
+
+  let mut estimated = Vec::new();
+  for &ref_frame in inter_cfg.allowed_ref_frames() {
+    let slot = fi.ref_frames[ref_frame.to_index()];
+    let model = match estimated.iter().find(|&&(s, _)| s == slot) {
+      Some(&(_, model)) => model,
+      None => {
+        let model =
+          fi.rec_buffer.frames[slot as usize].as_ref().and_then(|rec| {
+            let reference =
+              Image::new(&rec.input_hres, width, height, bit_depth);
+            estimate_model(&image, &corners, &reference)
+          });
+        estimated.push((slot, model));
+        model
+      }
+    };
+    if let Some((mode, params)) = model {
+      fi.globalmv_transformation_type[ref_frame.to_index()] = mode;
+      fi.globalmv_params[ref_frame.to_index()] = params;
+    }
+  }
+}
+
+fn estimate_model(
+  image: &Image, corners: &[(usize, usize)], reference: &Image,
+) -> Option<(GlobalMVMode, [i32; 6])> {
+  let ref_corners = detect_corners(reference);
+  let points = find_correspondences(image, corners, reference, &ref_corners);
+  let inliers = ransac(&points);
+  if inliers.len() < MIN_INLIERS {
+    return None;
+  }
+
+  let identity_error = warp_error(image, reference, &DEFAULT_GLOBALMV_PARAMS);
+  let candidates = [
+    (GlobalMVMode::ROTZOOM, fit_rotzoom(&inliers)),
+    (GlobalMVMode::AFFINE, fit_affine(&inliers)),
+  ];
+  let mut best: Option<(GlobalMVMode, [i32; 6], f64)> = None;
+  for (mode, model) in candidates {
+    let Some(model) = model else { continue };
+    let params = quantize_model(&model, mode);
+    if setup_shear(&params).is_none() {
+      continue;
+    }
+    let error = warp_error(image, reference, &params);
+    let threshold = match best {
+      Some((_, _, best_error)) => best_error * AFFINE_ERROR_RATIO,
+      None => identity_error * MAX_ERROR_RATIO,
+    };
+    if error < threshold {
+      best = Some((mode, params, error));
+    }
+  }
+  best.map(|(mode, params, _)| (mode, params))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn textured_image(width: usize, height: usize, m: &Model) -> Image {
+    // Smooth blobs of varying sizes give well-localized corners.
+    let texture = |x: f64, y: f64| {
+      let v = (x * 0.21).sin() * (y * 0.17).cos() * 60.
+        + (x * 0.05 + y * 0.07).sin() * 40.
+        + ((x * 0.37).sin() * (y * 0.41).sin()) * 30.;
+      128. + v
+    };
+    let data = (0..height)
+      .flat_map(|y| {
+        (0..width).map(move |x| {
+          let (rx, ry) = project(m, x as f64, y as f64);
+          texture(rx, ry).round() as i32
+        })
+      })
+      .collect();
+    Image { width, height, data }
+  }
+
+  #[test]
+  fn recovers_zoom() {
+    let identity = [0., 0., 1., 0., 0., 1.];
+    let (a, b) = (1.02, 0.01);
+    let zoom = [-1.5, 2.25, a, b, -b, a];
+    let reference = textured_image(176, 144, &identity);
+    let image = textured_image(176, 144, &zoom);
+
+    let mut corners: Vec<_> = detect_corners(&image)
+      .into_iter()
+      .flatten()
+      .map(|(x, y, _)| (x, y))
+      .collect();
+    corners.truncate(MAX_CORNERS);
+    let (mode, params) = estimate_model(&image, &corners, &reference).unwrap();
+    assert!(mode >= GlobalMVMode::ROTZOOM);
+
+    // The corners of the full resolution frame land within half a pixel of
+    // where the coded model would put them.
+    let expected = quantize_model(&zoom, GlobalMVMode::ROTZOOM);
+    let warp = |p: &[i32; 6], x: i64, y: i64| {
+      let p = p.map(i64::from);
+      (p[2] * x + p[3] * y + p[0], p[4] * x + p[5] * y + p[1])
+    };
+    for (x, y) in [(0, 0), (351, 0), (0, 287), (351, 287)] {
+      let (px, py) = warp(&params, x, y);
+      let (ex, ey) = warp(&expected, x, y);
+      let half_pixel = 1 << (WARPEDMODEL_PREC_BITS - 1);
+      assert!(
+        (px - ex).abs() < half_pixel && (py - ey).abs() < half_pixel,
+        "{:?} != {:?}",
+        params,
+        expected
+      );
+    }
+  }
+
+  #[test]
+  fn global_param_bits_match_spec() {
+    use GlobalMVMode::*;
+    assert_eq!(global_param_bits(AFFINE, 2, false), (12, 15));
+    assert_eq!(global_param_bits(ROTZOOM, 0, false), (12, 6));
+    assert_eq!(global_param_bits(TRANSLATION, 1, false), (8, 2));
+    assert_eq!(global_param_bits(TRANSLATION, 1, true), (9, 3));
+  }
+}
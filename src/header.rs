@@ -10,8 +10,11 @@
 use crate::api::*;
 use crate::context::*;
 use crate::ec::*;
+use crate::global_motion::{global_param_bits, DEFAULT_GLOBALMV_PARAMS};
 use crate::lrf::*;
+use crate::mc::WARPEDMODEL_PREC_BITS;
 use crate::partition::*;
+use crate::superres::{SUPERRES_DENOM_BITS, SUPERRES_DENOM_MIN, SUPERRES_NUM};
 use crate::tiling::MAX_TILE_WIDTH;
 use crate::util::math::Fixed;
 use crate::util::Pixel;
@@ -170,6 +173,9 @@ pub trait UncompressedHeader {
   fn write_frame_size_with_refs<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()>;
+  fn write_superres_params<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>,
+  ) -> io::Result<()>;
   fn write_deblock_filter_a<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>, deblock: &DeblockState,
   ) -> io::Result<()>;
@@ -185,6 +191,9 @@ pub trait UncompressedHeader {
   fn write_segment_data<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>, segmentation: &SegmentationState,
   ) -> io::Result<()>;
+  fn write_global_motion<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>,
+  ) -> io::Result<()>;
   fn write_delta_q(&mut self, delta_q: i8) -> io::Result<()>;
 }
 
@@ -532,10 +541,11 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
       }
     }
 
-    assert!(
-      fi.force_integer_mv
-        == u32::from(fi.frame_type == FrameType::KEY || fi.intra_only)
-    );
+    if fi.frame_type == FrameType::KEY || fi.intra_only {
+      assert!(fi.force_integer_mv == 1);
+    } else if fi.allow_screen_content_tools == 0 {
+      assert!(fi.force_integer_mv == 0);
+    }
 
     if fi.sequence.frame_id_numbers_present_flag {
       unimplemented!();
@@ -605,8 +615,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     if fi.intra_only {
       self.write_frame_size(fi)?;
       self.write_render_size(fi)?;
-      if fi.allow_screen_content_tools != 0 {
-        // TODO: && UpscaledWidth == FrameWidth.
+      if fi.allow_screen_content_tools != 0 && fi.upscaled_width == fi.width {
         self.write_bit(fi.allow_intrabc)?;
       }
     }
@@ -666,10 +675,13 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     // is a slight misnomer; it's more constrained than just a uniform
     // spacing.
     let ti = &fi.sequence.tiling;
+    // Tiles are sized in coded superblocks, which may be 128x128.
+    let sb_cols = fi.width.align_power_of_two_and_shift(ti.sb_size_log2);
+    let sb_rows = fi.height.align_power_of_two_and_shift(ti.sb_size_log2);
 
-    if fi.sb_width.align_power_of_two_and_shift(ti.tile_cols_log2)
+    if sb_cols.align_power_of_two_and_shift(ti.tile_cols_log2)
       == ti.tile_width_sb
-      && fi.sb_height.align_power_of_two_and_shift(ti.tile_rows_log2)
+      && sb_rows.align_power_of_two_and_shift(ti.tile_rows_log2)
         == ti.tile_height_sb
     {
       // yes; our actual tile width/height setting (which is always
@@ -698,27 +710,26 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
       let mut sofar = 0;
       let mut widest_tile_sb = 0;
       for _ in 0..ti.cols {
-        let max = (MAX_TILE_WIDTH
-          >> if fi.sequence.use_128x128_superblock { 7 } else { 6 })
-        .min(fi.sb_width - sofar) as u16;
-        let this_sb_width = ti.tile_width_sb.min(fi.sb_width - sofar);
+        let max =
+          (MAX_TILE_WIDTH >> ti.sb_size_log2).min(sb_cols - sofar) as u16;
+        let this_sb_width = ti.tile_width_sb.min(sb_cols - sofar);
         self.write_quniform(max, (this_sb_width - 1) as u16);
         sofar += this_sb_width;
         widest_tile_sb = widest_tile_sb.max(this_sb_width);
       }
 
       let max_tile_area_sb = if ti.min_tiles_log2 > 0 {
-        (fi.sb_height * fi.sb_width) >> (ti.min_tiles_log2 + 1)
+        (sb_rows * sb_cols) >> (ti.min_tiles_log2 + 1)
       } else {
-        fi.sb_height * fi.sb_width
+        sb_rows * sb_cols
       };
 
       let max_tile_height_sb = (max_tile_area_sb / widest_tile_sb).max(1);
 
       sofar = 0;
       for i in 0..ti.rows {
-        let max = max_tile_height_sb.min(fi.sb_height - sofar) as u16;
-        let this_sb_height = ti.tile_height_sb.min(fi.sb_height - sofar);
+        let max = max_tile_height_sb.min(sb_rows - sofar) as u16;
+        let this_sb_height = ti.tile_height_sb.min(sb_rows - sofar);
 
         self.write_quniform(max, (this_sb_height - 1) as u16);
         sofar += this_sb_height;
@@ -736,7 +747,6 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     }
 
     // quantization
-    assert!(fi.base_q_idx > 0);
     self.write::<8, u8>(fi.base_q_idx)?; // base_q_idx
     self.write_delta_q(fi.dc_delta_q[0])?;
     if fi.sequence.chroma_sampling != ChromaSubsampling::Monochrome {
@@ -757,7 +767,9 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     self.write_segment_data(fi, &fs.segmentation)?;
 
     // delta_q
-    self.write_bit(false)?; // delta_q_present_flag: no delta q
+    if fi.base_q_idx > 0 {
+      self.write_bit(false)?; // delta_q_present_flag: no delta q
+    }
 
     // delta_lf_params in the spec
     self.write_deblock_filter_a(fi, &fs.deblock)?;
@@ -773,7 +785,10 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     // loop restoration
     self.write_frame_lrf(fi, &fs.restoration)?;
 
-    self.write_bit(fi.tx_mode_select)?; // tx mode
+    // tx mode, lossless frames implicitly use ONLY_4X4
+    if !fi.coded_lossless() {
+      self.write_bit(fi.tx_mode_select)?;
+    }
 
     let mut reference_select = false;
     if !fi.intra_only {
@@ -797,43 +812,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
 
     // global motion
     if !fi.intra_only {
-      for i in 0..7 {
-        let mode = fi.globalmv_transformation_type[i];
-        self.write_bit(mode != GlobalMVMode::IDENTITY)?;
-        if mode != GlobalMVMode::IDENTITY {
-          self.write_bit(mode == GlobalMVMode::ROTZOOM)?;
-          if mode != GlobalMVMode::ROTZOOM {
-            self.write_bit(mode == GlobalMVMode::TRANSLATION)?;
-          }
-        }
-        match mode {
-          GlobalMVMode::IDENTITY => { /* Nothing to do */ }
-          GlobalMVMode::TRANSLATION => {
-            let mv_x = 0;
-            let mv_x_ref = 0;
-            let mv_y = 0;
-            let mv_y_ref = 0;
-            let bits = 12 - 6 + 3 - !fi.allow_high_precision_mv as u8;
-            let bits_diff = 12 - 3 + fi.allow_high_precision_mv as u8;
-            BCodeWriter::write_s_refsubexpfin(
-              self,
-              (1 << bits) + 1,
-              3,
-              mv_x_ref >> bits_diff,
-              mv_x >> bits_diff,
-            )?;
-            BCodeWriter::write_s_refsubexpfin(
-              self,
-              (1 << bits) + 1,
-              3,
-              mv_y_ref >> bits_diff,
-              mv_y >> bits_diff,
-            )?;
-          }
-          GlobalMVMode::ROTZOOM => unimplemented!(),
-          GlobalMVMode::AFFINE => unimplemented!(),
-        };
-      }
+      self.write_global_motion(fi)?;
     }
 
     if fi.sequence.film_grain_params_present {
@@ -947,14 +926,9 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_max_frame_size<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()> {
-    // width_bits and height_bits will have to be moved to the sequence header OBU
-    // when we add support for it.
-    let width = fi.width - 1;
-    let height = fi.height - 1;
-    let width_bits = log_in_base_2(width as u32) as u32 + 1;
-    let height_bits = log_in_base_2(height as u32) as u32 + 1;
-    assert!(width_bits <= 16);
-    assert!(height_bits <= 16);
+    let (width_bits, height_bits) = frame_size_bits(&fi.sequence);
+    let width = fi.sequence.max_frame_width - 1;
+    let height = fi.sequence.max_frame_height - 1;
     self.write::<4, u32>(width_bits - 1)?;
     self.write::<4, u32>(height_bits - 1)?;
     self.write_var(width_bits, width as u16)?;
@@ -965,20 +939,26 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_frame_size<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()> {
-    // width_bits and height_bits will have to be moved to the sequence header OBU
-    // when we add support for it.
     if fi.frame_size_override_flag {
-      let width = fi.width - 1;
+      let (width_bits, height_bits) = frame_size_bits(&fi.sequence);
+      let width = fi.upscaled_width - 1;
       let height = fi.height - 1;
-      let width_bits = log_in_base_2(width as u32) as u32 + 1;
-      let height_bits = log_in_base_2(height as u32) as u32 + 1;
-      assert!(width_bits <= 16);
-      assert!(height_bits <= 16);
       self.write_var(width_bits, width as u16)?;
       self.write_var(height_bits, height as u16)?;
     }
+    self.write_superres_params(fi)
+  }
+
+  fn write_superres_params<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>,
+  ) -> io::Result<()> {
     if fi.sequence.enable_superres {
-      unimplemented!();
+      let use_superres = fi.superres_denom != SUPERRES_NUM;
+      self.write_bit(use_superres)?;
+      if use_superres {
+        let coded_denom = fi.superres_denom - SUPERRES_DENOM_MIN;
+        self.write_var(SUPERRES_DENOM_BITS, coded_denom)?;
+      }
     }
     Ok(())
   }
@@ -1000,7 +980,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     let mut found_ref = false;
     for i in 0..INTER_REFS_PER_FRAME {
       if let Some(ref rec) = fi.rec_buffer.frames[fi.ref_frames[i] as usize] {
-        if rec.width == fi.width as u32
+        if rec.width == fi.upscaled_width as u32
           && rec.height == fi.height as u32
           && rec.render_width == fi.render_width
           && rec.render_height == fi.render_height
@@ -1018,8 +998,8 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     if !found_ref {
       self.write_frame_size(fi)?;
       self.write_render_size(fi)?;
-    } else if fi.sequence.enable_superres {
-      unimplemented!();
+    } else {
+      self.write_superres_params(fi)?;
     }
     Ok(())
   }
@@ -1042,6 +1022,9 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_deblock_filter_b<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>, deblock: &DeblockState,
   ) -> io::Result<()> {
+    if fi.coded_lossless() || fi.allow_intrabc {
+      return Ok(());
+    }
     let planes =
       if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome {
         1
@@ -1101,7 +1084,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_frame_cdef<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()> {
-    if fi.sequence.enable_cdef && !fi.allow_intrabc {
+    if fi.cdef_enabled() {
       assert!(fi.cdef_damping >= 3);
       assert!(fi.cdef_damping <= 6);
       self.write::<2, u8>(fi.cdef_damping - 3)?;
@@ -1122,8 +1105,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_frame_lrf<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>, rs: &RestorationState,
   ) -> io::Result<()> {
-    if fi.sequence.enable_restoration && !fi.allow_intrabc {
-      // && !self.lossless
+    if fi.restoration_enabled() {
       let planes =
         if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome {
           1
@@ -1200,6 +1182,63 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     Ok(())
   }
 
+  fn write_global_motion<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>,
+  ) -> io::Result<()> {
+    // Parameters are coded relative to those of the primary reference.
+    let prev_params = if fi.primary_ref_frame == PRIMARY_REF_NONE {
+      [DEFAULT_GLOBALMV_PARAMS; INTER_REFS_PER_FRAME]
+    } else {
+      let ref_frame_idx = fi.ref_frames[fi.primary_ref_frame as usize];
+      fi.rec_buffer.frames[ref_frame_idx as usize]
+        .as_ref()
+        .unwrap()
+        .globalmv_params
+    };
+
+    for (i, &mode) in fi.globalmv_transformation_type.iter().enumerate() {
+      self.write_bit(mode != GlobalMVMode::IDENTITY)?; // is_global
+      if mode != GlobalMVMode::IDENTITY {
+        self.write_bit(mode == GlobalMVMode::ROTZOOM)?; // is_rot_zoom
+        if mode != GlobalMVMode::ROTZOOM {
+          self.write_bit(mode == GlobalMVMode::TRANSLATION)?; // is_translation
+        }
+      }
+
+      let params = &fi.globalmv_params[i];
+      if mode == GlobalMVMode::ROTZOOM {
+        assert_eq!(params[4], -params[3]);
+        assert_eq!(params[5], params[2]);
+      }
+      let coded: &[usize] = match mode {
+        GlobalMVMode::IDENTITY => &[],
+        GlobalMVMode::TRANSLATION => &[0, 1],
+        GlobalMVMode::ROTZOOM => &[2, 3, 0, 1],
+        GlobalMVMode::AFFINE => &[2, 3, 4, 5, 0, 1],
+      };
+      for &idx in coded {
+        let (abs_bits, prec_bits) =
+          global_param_bits(mode, idx, fi.allow_high_precision_mv);
+        let prec_diff = WARPEDMODEL_PREC_BITS - prec_bits;
+        let (round, sub) = if idx % 3 == 2 {
+          (1 << WARPEDMODEL_PREC_BITS, 1 << prec_bits)
+        } else {
+          (0, 0)
+        };
+        let r = (prev_params[i][idx] >> prec_diff) - sub;
+        let v = (params[idx] - round) >> prec_diff;
+        BCodeWriter::write_s_refsubexpfin(
+          self,
+          (1 << abs_bits) + 1,
+          3,
+          r as i16,
+          v as i16,
+        )?;
+      }
+    }
+    Ok(())
+  }
+
   fn write_delta_q(&mut self, delta_q: i8) -> io::Result<()> {
     self.write_bit(delta_q != 0)?;
     if delta_q != 0 {
@@ -1210,6 +1249,17 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   }
 }
 
+/// Returns the bits used to code the frame width and height, enough for
+/// the largest frame of the sequence (`frame_width_bits_minus_1 + 1` and
+/// `frame_height_bits_minus_1 + 1`).
+fn frame_size_bits(sequence: &Sequence) -> (u32, u32) {
+  let width_bits = log_in_base_2(sequence.max_frame_width - 1) as u32 + 1;
+  let height_bits = log_in_base_2(sequence.max_frame_height - 1) as u32 + 1;
+  assert!(width_bits <= 16);
+  assert!(height_bits <= 16);
+  (width_bits, height_bits)
+}
+
 #[cfg(test)]
 mod tests {
   use super::ULEB128Writer;
@@ -0,0 +1,402 @@
+// Copyright (c) 2025, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use crate::context::{TileBlockOffset, MI_SIZE, MV_LOW, MV_UPP};
+use crate::frame::PlaneOffset;
+use crate::mc::MotionVector;
+use crate::partition::BlockSize;
+use crate::tiling::{Area, PlaneRegion, PlaneRegionMut};
+use crate::util::Pixel;
+use arrayvec::ArrayVec;
+use std::collections::HashMap;
+
+/// Vertical distance in pixels to the default source of a block in the
+/// first superblock row of a tile.
+pub const INTRABC_DELAY_PIXELS: usize = 256;
+/// Number of 64x64 superblocks that must be coded between a block and its
+/// source, so that decoders can run the in-loop filters behind.
+pub const INTRABC_DELAY_SB64: usize = 4;
+
+/// Side lengths of the square blocks that are hashed, as log2.
+const HASH_MIN_LOG2: usize = 3;
+const HASH_MAX_LOG2: usize = 6;
+/// Positions kept per hash value, to bound the search on repeated patterns.
+const HASH_MAX_POSITIONS: usize = 256;
+/// Valid positions compared for a block, starting with the closest ones.
+const SEARCH_MAX_CANDIDATES: usize = 16;
+
+/// Whether `dv` points a block to a source that is fully decoded and
+/// unfiltered when decoding the block, as required by the bitstream.
+///
+/// Positions are relative to the tile, which is `tile_mi_width` by
+/// `tile_mi_height` mode info units.
+pub fn is_dv_valid(
+  dv: MotionVector, bo: TileBlockOffset, bsize: BlockSize,
+  subsampled_chroma: (bool, bool), tile_mi_width: usize,
+  tile_mi_height: usize, sb128: bool,
+) -> bool {
+  if dv.row & 7 != 0 || dv.col & 7 != 0 {
+    return false;
+  }
+  let (bw, bh) = (bsize.width() as isize, bsize.height() as isize);
+  let mut src_top = (bo.0.y * MI_SIZE) as isize + (dv.row >> 3) as isize;
+  let mut src_left = (bo.0.x * MI_SIZE) as isize + (dv.col >> 3) as isize;
+  let src_bottom = src_top + bh;
+  let src_right = src_left + bw;
+  // Narrow blocks share their chroma block with the previous one.
+  if bw < 8 && subsampled_chroma.0 {
+    src_left -= 4;
+  }
+  if bh < 8 && subsampled_chroma.1 {
+    src_top -= 4;
+  }
+  if src_top < 0
+    || src_left < 0
+    || src_bottom > (tile_mi_height * MI_SIZE) as isize
+    || src_right > (tile_mi_width * MI_SIZE) as isize
+  {
+    return false;
+  }
+
+  let sb_h = if sb128 { 128 } else { 64 };
+  let delay = INTRABC_DELAY_SB64 as isize;
+  let active_sb_row = (bo.0.y * MI_SIZE) as isize / sb_h;
+  let active_sb64_col = (bo.0.x * MI_SIZE) as isize >> 6;
+  let src_sb_row = (src_bottom - 1) / sb_h;
+  let src_sb64_col = (src_right - 1) >> 6;
+  let total_sb64_per_row = (((tile_mi_width - 1) >> 4) + 1) as isize;
+  let active_sb64 = active_sb_row * total_sb64_per_row + active_sb64_col;
+  let src_sb64 = src_sb_row * total_sb64_per_row + src_sb64_col;
+  if src_sb64 >= active_sb64 - delay {
+    return false;
+  }
+  // Keep the source behind the wavefront of a decoder filtering in parallel.
+  let gradient = 1 + delay + sb128 as isize;
+  let wf_offset = gradient * (active_sb_row - src_sb_row);
+  src_sb_row <= active_sb_row
+    && src_sb64_col < active_sb64_col - delay + wf_offset
+}
+
+/// Predicts the `width`x`height` block at `po` by copying the pixels of
+/// `rec` displaced by `dv`.
+///
+/// With subsampled chroma, odd luma displacements land between two chroma
+/// pixels, which are averaged the same way as the bilinear filter does.
+pub fn predict_intrabc<T: Pixel>(
+  rec: &mut PlaneRegionMut<'_, T>, po: PlaneOffset, width: usize,
+  height: usize, dv: MotionVector,
+) {
+  let (xdec, ydec) = (rec.plane_cfg.xdec, rec.plane_cfg.ydec);
+  let (dx, dy) = ((dv.col >> 3) as isize, (dv.row >> 3) as isize);
+  let x0 = (po.x + (dx >> xdec)) as usize;
+  let y0 = (po.y + (dy >> ydec)) as usize;
+  let fx = (dx & xdec as isize) as usize;
+  let fy = (dy & ydec as isize) as usize;
+
+  let mut pred = Vec::with_capacity(width * height);
+  {
+    let src = rec.as_const();
+    for y in 0..height {
+      let (r0, r1) = (&src[y0 + y], &src[y0 + y + fy]);
+      pred.extend((x0..x0 + width).map(|x| {
+        let sum = r0[x].to_u16() as u32
+          + r0[x + fx].to_u16() as u32
+          + r1[x].to_u16() as u32
+          + r1[x + fx].to_u16() as u32;
+        T::cast_from(((sum + 2) >> 2) as i32)
+      }));
+    }
+  }
+
+  let mut dst = rec.subregion_mut(Area::StartingAt { x: po.x, y: po.y });
+  for (row, pred) in dst.rows_iter_mut().zip(pred.chunks_exact(width)) {
+    row[..width].copy_from_slice(pred);
+  }
+}
+
+#[inline]
+fn hash_combine(h: u32, v: u32) -> u32 {
+  (h ^ v).wrapping_mul(0x9e37_79b1).rotate_left(13)
+}
+
+/// Hash of the first 8 pixels of `row`, along with their value if they are
+/// all equal.
+fn hash_row<T: Pixel>(row: &[T]) -> (u32, Option<u16>) {
+  let first = row[0].to_u16();
+  let mut flat = true;
+  let h = row[..8].iter().fold(0, |h, &p| {
+    let p = p.to_u16();
+    flat &= p == first;
+    hash_combine(h, p as u32)
+  });
+  (h, flat.then_some(first))
+}
+
+/// Hash of a block from the hashes of its parts, along with its value if it
+/// is flat.
+fn hash_parts(parts: &[(u32, Option<u16>)]) -> (u32, Option<u16>) {
+  let h = parts.iter().fold(0x5bd1_e995, |h, &(p, _)| hash_combine(h, p));
+  let flat =
+    parts[0].1.filter(|_| parts.iter().all(|&(_, c)| c == parts[0].1));
+  (h, flat)
+}
+
+/// Hash of the square block of side `1 << log2` at (`x`, `y`).
+fn block_hash<T: Pixel>(
+  src: &PlaneRegion<'_, T>, x: usize, y: usize, log2: usize,
+) -> (u32, Option<u16>) {
+  if log2 == HASH_MIN_LOG2 {
+    let rows: ArrayVec<_, 8> =
+      src.rows_iter().skip(y).take(8).map(|row| hash_row(&row[x..])).collect();
+    return hash_parts(&rows);
+  }
+  let half = 1 << (log2 - 1);
+  hash_parts(&[
+    block_hash(src, x, y, log2 - 1),
+    block_hash(src, x + half, y, log2 - 1),
+    block_hash(src, x, y + half, log2 - 1),
+    block_hash(src, x + half, y + half, log2 - 1),
+  ])
+}
+
+/// Positions of the square blocks of a source tile, indexed by their hash,
+/// to find exact repeats of a block.
+///
+/// Flat blocks are left out as they are cheap to code with intra prediction
+/// and would otherwise flood the tables.
+#[derive(Debug, Default)]
+pub struct IntraBcHashes {
+  width: usize,
+  height: usize,
+  tables: [HashMap<u32, Vec<(u16, u16)>>; HASH_MAX_LOG2 - HASH_MIN_LOG2 + 1],
+}
+
+impl IntraBcHashes {
+  /// Hashes the blocks fully inside the visible `width`x`height` pixels of
+  /// the luma plane of a tile.
+  pub fn new<T: Pixel>(
+    src: &PlaneRegion<'_, T>, width: usize, height: usize,
+  ) -> Self {
+    let mut hashes = IntraBcHashes { width, height, ..Default::default() };
+
+    // Hashes at every position, computed from those of the smaller size.
+    let mut level = Vec::new();
+    for log2 in HASH_MIN_LOG2..=HASH_MAX_LOG2 {
+      let size = 1 << log2;
+      if size > width || size > height {
+        break;
+      }
+      let (cols, rows) = (width - size + 1, height - size + 1);
+      level = if log2 == HASH_MIN_LOG2 {
+        let row_hashes: Vec<_> = src
+          .rows_iter()
+          .take(height)
+          .flat_map(|row| (0..cols).map(move |x| hash_row(&row[x..])))
+          .collect();
+        (0..rows)
+          .flat_map(|y| (0..cols).map(move |x| (x, y)))
+          .map(|(x, y)| {
+            let rows: ArrayVec<_, 8> =
+              (y..y + size).map(|y| row_hashes[y * cols + x]).collect();
+            hash_parts(&rows)
+          })
+          .collect()
+      } else {
+        let (half, prev_cols) = (size >> 1, cols + (size >> 1));
+        let at = |x: usize, y: usize| level[y * prev_cols + x];
+        (0..rows)
+          .flat_map(|y| (0..cols).map(move |x| (x, y)))
+          .map(|(x, y)| {
+            hash_parts(&[
+              at(x, y),
+              at(x + half, y),
+              at(x, y + half),
+              at(x + half, y + half),
+            ])
+          })
+          .collect()
+      };
+
+      let table = &mut hashes.tables[log2 - HASH_MIN_LOG2];
+      for (i, &(h, flat)) in level.iter().enumerate() {
+        if flat.is_none() {
+          let positions = table.entry(h).or_default();
+          if positions.len() < HASH_MAX_POSITIONS {
+            positions.push(((i % cols) as u16, (i / cols) as u16));
+          }
+        }
+      }
+    }
+    hashes
+  }
+
+  /// Finds the displacement vector of the block at `bo` to the closest
+  /// exact copy of its source in the already coded part of the tile.
+  ///
+  /// The candidate whose reconstruction in `rec` is the closest to the
+  /// source is returned, or `None` if there is no valid one.
+  pub fn search<T: Pixel>(
+    &self, src: &PlaneRegion<'_, T>, rec: &PlaneRegion<'_, T>,
+    bo: TileBlockOffset, bsize: BlockSize, ref_dv: MotionVector,
+    tile_mi_width: usize, tile_mi_height: usize, sb128: bool,
+  ) -> Option<MotionVector> {
+    let size = bsize.width();
+    if !bsize.is_sqr() || !(8..=64).contains(&size) {
+      return None;
+    }
+    let (x, y) = (bo.0.x * MI_SIZE, bo.0.y * MI_SIZE);
+    if x + size > self.width || y + size > self.height {
+      return None;
+    }
+    let log2 = size.trailing_zeros() as usize;
+    let (h, flat) = block_hash(src, x, y, log2);
+    if flat.is_some() {
+      return None;
+    }
+    let positions = self.tables[log2 - HASH_MIN_LOG2].get(&h)?;
+
+    let sad = |cx: usize, cy: usize| -> u32 {
+      src
+        .rows_iter()
+        .skip(y)
+        .zip(rec.rows_iter().skip(cy))
+        .take(size)
+        .map(|(s, r)| {
+          s[x..x + size]
+            .iter()
+            .zip(&r[cx..cx + size])
+            .map(|(&s, &r)| {
+              (s.to_u16() as i32 - r.to_u16() as i32).unsigned_abs()
+            })
+            .sum::<u32>()
+        })
+        .sum()
+    };
+    let matches_source = |cx: usize, cy: usize| -> bool {
+      src
+        .rows_iter()
+        .skip(y)
+        .zip(src.rows_iter().skip(cy))
+        .take(size)
+        .all(|(a, b)| a[x..x + size] == b[cx..cx + size])
+    };
+
+    // Positions are in raster order, so the closest ones come last.
+    positions
+      .iter()
+      .rev()
+      .filter_map(|&(cx, cy)| {
+        let (cx, cy) = (cx as usize, cy as usize);
+        let dv = MotionVector {
+          row: ((cy as isize - y as isize) * 8).try_into().ok()?,
+          col: ((cx as isize - x as isize) * 8).try_into().ok()?,
+        };
+        let diff_row = dv.row as i32 - ref_dv.row as i32;
+        let diff_col = dv.col as i32 - ref_dv.col as i32;
+        let valid = (MV_LOW..=MV_UPP).contains(&diff_row)
+          && (MV_LOW..=MV_UPP).contains(&diff_col)
+          && is_dv_valid(
+            dv,
+            bo,
+            bsize,
+            (false, false),
+            tile_mi_width,
+            tile_mi_height,
+            sb128,
+          );
+        (valid && matches_source(cx, cy)).then_some((cx, cy, dv))
+      })
+      .take(SEARCH_MAX_CANDIDATES)
+      .map(|(cx, cy, dv)| (sad(cx, cy), dv))
+      .min_by_key(|&(sad, dv)| {
+        let cost = (dv.row as i32 - ref_dv.row as i32).unsigned_abs()
+          + (dv.col as i32 - ref_dv.col as i32).unsigned_abs();
+        (sad, cost)
+      })
+      .map(|(_, dv)| dv)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn dv_must_be_behind_the_delay() {
+    let bo = TileBlockOffset(crate::context::BlockOffset { x: 96, y: 32 });
+    let bsize = BlockSize::BLOCK_16X16;
+    let valid = |row: i16, col: i16| -> bool {
+      is_dv_valid(
+        MotionVector { row: row * 8, col: col * 8 },
+        bo,
+        bsize,
+        (true, true),
+        128,
+        64,
+        false,
+      )
+    };
+    // Previous superblock row, far enough to the left.
+    assert!(valid(-64, -64));
+    // Within the delay of the current superblock.
+    assert!(!valid(0, -64));
+    assert!(valid(0, -5 * 64));
+    // Not yet coded.
+    assert!(!valid(16, 0));
+    // Subpixel vectors are not allowed.
+    assert!(!is_dv_valid(
+      MotionVector { row: -512, col: -4 },
+      bo,
+      bsize,
+      (true, true),
+      128,
+      64,
+      false,
+    ));
+  }
+
+  #[test]
+  fn search_finds_repeated_block() {
+    use crate::frame::{AsRegion, Frame, FrameAlloc};
+    use v_frame::chroma::ChromaSubsampling;
+
+    // Noise repeating every 32 pixels in both directions.
+    let mut frame = Frame::<u8>::new(256, 256, ChromaSubsampling::Monochrome);
+    let p = &mut frame.y_plane;
+    {
+      let mut region = p.as_region_mut();
+      for (y, row) in region.rows_iter_mut().enumerate() {
+        for (x, px) in row.iter_mut().enumerate() {
+          let v = ((x % 32) * 73 + (y % 32) * 151) as u32;
+          *px = (v.wrapping_mul(2_654_435_761) >> 24) as u8;
+        }
+      }
+    }
+    let region = p.as_region();
+    let hashes = IntraBcHashes::new(&region, 256, 256);
+
+    let bo = TileBlockOffset(crate::context::BlockOffset { x: 32, y: 16 });
+    let bsize = BlockSize::BLOCK_16X16;
+    let dv = hashes
+      .search(
+        &region,
+        &region,
+        bo,
+        bsize,
+        MotionVector::default(),
+        64,
+        64,
+        false,
+      )
+      .unwrap();
+    assert!(is_dv_valid(dv, bo, bsize, (true, true), 64, 64, false));
+    assert_eq!(dv.row % (32 * 8), 0);
+    assert_eq!(dv.col % (32 * 8), 0);
+  }
+}
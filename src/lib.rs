@@ -86,6 +86,9 @@ mod activity;
 pub(crate) mod asm;
 mod dist;
 mod ec;
+mod global_motion;
+mod intrabc;
+mod palette;
 mod partition;
 mod predict;
 mod quantize;
@@ -108,6 +111,7 @@ mod recon_intra;
 mod scan_order;
 mod segmentation;
 mod stats;
+mod superres;
 #[doc(hidden)]
 pub mod tiling;
 mod token_cdfs;
@@ -119,7 +123,9 @@ mod header;
 /// Commonly used types and traits.
 pub mod prelude {
   pub use crate::api::*;
-  pub use crate::encoder::{Sequence, Tune};
+  pub use crate::encoder::{
+    ScreenContentMode, Sequence, SuperblockSize, SuperresMode, Tune,
+  };
   pub use crate::frame::{
     Frame, FrameParameters, FrameTypeOverride, Plane, PlaneConfig, PlanePad,
   };
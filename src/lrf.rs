@@ -14,7 +14,7 @@ use std::ops::{Index, IndexMut};
 use v_frame::chroma::ChromaSubsampling;
 
 use crate::api::SGRComplexityLevel;
-use crate::context::{MAX_PLANES, SB_SIZE};
+use crate::context::{MAX_PLANES, SB_SIZE, SUPERBLOCK_TO_PLANE_SHIFT};
 use crate::encoder::FrameInvariants;
 use crate::frame::{AsRegion, Frame, Plane, PlaneSlice};
 use crate::tiling::{Area, PlaneRegion, PlaneRegionMut, Rect};
@@ -40,9 +40,13 @@ pub const RESTORE_SGRPROJ: u8 = 3;
 pub const WIENER_TAPS_MIN: [i8; 3] = [-5, -23, -17];
 pub const WIENER_TAPS_MID: [i8; 3] = [3, -7, 15];
 pub const WIENER_TAPS_MAX: [i8; 3] = [10, 8, 46];
-#[allow(unused)]
 pub const WIENER_TAPS_K: [i8; 3] = [1, 2, 3];
 pub const WIENER_BITS: usize = 7;
+// Number of alternating vertical/horizontal refinements in wiener_solve
+const WIENER_SOLVE_ITERS: usize = 3;
+// Smallest pivot, relative to the largest diagonal element, that
+// wiener_solve_sym accepts
+const WIENER_SOLVE_MIN_PIVOT: f64 = 1e-9;
 
 pub const SGRPROJ_XQD_MIN: [i8; 2] = [-96, -32];
 pub const SGRPROJ_XQD_MID: [i8; 2] = [-32, 31];
@@ -122,7 +126,6 @@ impl IntegralImageBuffer {
   }
 }
 
-#[allow(unused)] // Wiener coming soon!
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
 pub enum RestorationFilter {
   #[default]
@@ -1171,19 +1174,18 @@ fn wiener_stripe_filter<T: Pixel>(
     let n = cmp::min(7, crop_w as isize + 3 - xi as isize);
     for yi in stripe_y - 3..stripe_y + stripe_h as isize + 4 {
       let mut acc = 0;
-      let src = if yi < stripe_y {
-        let ly = cmp::max(clamp(yi, 0, crop_h as isize - 1), stripe_y - 2);
-        deblocked.row(ly as usize).unwrap()
-      } else if yi < stripe_y + stripe_h as isize {
-        let ly = clamp(yi, 0, crop_h as isize - 1);
-        cdeffed.row(ly as usize).unwrap()
+      // clamp to the frame before deciding the source, so that the first
+      // stripe reads cdeffed rather than deblocked rows at the top edge
+      let ly = clamp(yi, 0, crop_h as isize - 1);
+      let src = if ly < stripe_y {
+        PlaneSlice::new(deblocked, 0, cmp::max(ly, stripe_y - 2))
+      } else if ly < stripe_y + stripe_h as isize {
+        PlaneSlice::new(cdeffed, 0, ly)
       } else {
-        let ly = cmp::min(
-          clamp(yi, 0, crop_h as isize - 1),
-          stripe_y + stripe_h as isize + 1,
-        );
-        deblocked.row(ly as usize).unwrap()
+        let ly = cmp::min(ly, stripe_y + stripe_h as isize + 1);
+        PlaneSlice::new(deblocked, 0, ly)
       };
+      let src = src.row(0);
       let start = src[0].to_i32();
       let end = src[crop_w - 1].to_i32();
       for i in 0..3 - xi as isize {
@@ -1223,6 +1225,248 @@ fn wiener_stripe_filter<T: Pixel>(
   }
 }
 
+// Applies a Wiener filter to the area of cdeffed starting at (x, y) and
+// writes the result to the same area of out.  Like the sgrproj RDO path,
+// there is no deblocked reconstruction available here, so the cdeffed
+// plane also stands in for the rows above and below each stripe.
+// Processing is split into superblock-high stripes to bound the
+// intermediate buffer used by wiener_stripe_filter.
+#[profiling::function]
+pub fn wiener_rdo_filter<T: Pixel>(
+  coeffs: [[i8; 3]; 2], fi: &FrameInvariants<T>, crop_w: usize, crop_h: usize,
+  x: usize, y: usize, width: usize, height: usize, cdeffed: &Plane<T>,
+  out: &mut Plane<T>,
+) {
+  for stripe_y in (y..y + height).step_by(SB_SIZE) {
+    wiener_stripe_filter(
+      coeffs,
+      fi,
+      crop_w,
+      crop_h,
+      width,
+      SB_SIZE.min(y + height - stripe_y),
+      x,
+      stripe_y as isize,
+      cdeffed,
+      cdeffed,
+      out,
+    );
+  }
+}
+
+// Expands 3 coded Wiener taps into the full symmetric 7-tap filter,
+// normalized so that the taps sum to 1.
+fn wiener_taps_from_coeffs(coeffs: [i8; 3]) -> [f64; 7] {
+  let scale = (1 << WIENER_BITS) as f64;
+  let c = coeffs.map(|c| c as f64 / scale);
+  [
+    c[0],
+    c[1],
+    c[2],
+    2.0f64.mul_add(-(c[0] + c[1] + c[2]), 1.),
+    c[2],
+    c[1],
+    c[0],
+  ]
+}
+
+// Minimizes f' * a * f - 2 * f' * s over symmetric 7-tap filters f that sum
+// to 1.  Writing f = e3 + sum(x_i * p_i) with p_i = e_i + e_(6-i) - 2 * e3
+// leaves an unconstrained system in the free taps first_tap..3, which is
+// solved by Gaussian elimination.  Returns None if the system is singular
+// or too ill-conditioned for the solution to be meaningful.
+fn wiener_solve_sym(
+  a: &[[f64; 7]; 7], s: &[f64; 7], first_tap: usize,
+) -> Option<[f64; 7]> {
+  let n = 3 - first_tap;
+  let p = |i: usize| {
+    let mut v = [0.; 7];
+    v[i] += 1.;
+    v[6 - i] += 1.;
+    v[3] -= 2.;
+    v
+  };
+  let mut m = [[0f64; 4]; 3];
+  for (row, i) in m.iter_mut().zip(first_tap..3) {
+    let pi = p(i);
+    for (k, j) in (first_tap..3).enumerate() {
+      let pj = p(j);
+      row[k] = (0..7)
+        .map(|u| pi[u] * (0..7).map(|v| a[u][v] * pj[v]).sum::<f64>())
+        .sum();
+    }
+    // Right hand side: p_i' * (s - a * e3)
+    row[n] = (0..7).map(|u| pi[u] * (s[u] - a[u][3])).sum();
+  }
+
+  // Gaussian elimination with partial pivoting. A near-zero pivot means
+  // that the statistics do not determine the filter (e.g. a flat unit).
+  let min_pivot =
+    (0..n).map(|i| m[i][i].abs()).fold(0., f64::max) * WIENER_SOLVE_MIN_PIVOT;
+  for col in 0..n {
+    let pivot = (col..n)
+      .max_by(|&r1, &r2| m[r1][col].abs().total_cmp(&m[r2][col].abs()))
+      .unwrap();
+    let pivot_abs = m[pivot][col].abs();
+    if pivot_abs.is_nan() || pivot_abs <= min_pivot {
+      return None;
+    }
+    m.swap(col, pivot);
+    for row in col + 1..n {
+      let factor = m[row][col] / m[col][col];
+      for k in col..=n {
+        m[row][k] -= factor * m[col][k];
+      }
+    }
+  }
+  let mut x = [0f64; 3];
+  for row in (0..n).rev() {
+    let acc: f64 = (row + 1..n).map(|k| m[row][k] * x[k]).sum();
+    x[row] = (m[row][n] - acc) / m[row][row];
+  }
+
+  let mut taps = [0f64; 7];
+  taps[3] = 1.;
+  for (k, i) in (first_tap..3).enumerate() {
+    let pi = p(i);
+    for u in 0..7 {
+      taps[u] += x[k] * pi[u];
+    }
+  }
+  taps.iter().all(|t| t.is_finite()).then_some(taps)
+}
+
+// Accumulates the autocorrelation (upper triangle only) and
+// cross-correlation statistics of one filter input vector.
+#[inline(always)]
+fn wiener_accumulate(
+  a: &mut [[f64; 7]; 7], s: &mut [f64; 7], v: &[f64; 7], target: f64,
+) {
+  for i in 0..7 {
+    s[i] += v[i] * target;
+    for j in i..7 {
+      a[i][j] += v[i] * v[j];
+    }
+  }
+}
+
+#[inline(always)]
+fn wiener_mirror(a: &mut [[f64; 7]; 7]) {
+  for i in 0..7 {
+    for j in 0..i {
+      a[i][j] = a[j][i];
+    }
+  }
+}
+
+// Input params follow the same rules as sgrproj_solve: data access inside
+// the cdef frame is clipped to the cdef_w x cdef_h area.
+//
+// The separable filter is found by alternating least squares, in the same
+// way as libaom: solve for the vertical taps with the horizontal taps fixed,
+// then the other way round, and repeat.  Chroma planes only code 5 taps, so
+// the outermost tap is held at zero.
+//
+// Returns None if either system cannot be solved reliably, in which case
+// the unit should not use a Wiener filter.
+#[profiling::function]
+pub fn wiener_solve<T: Pixel>(
+  input: &PlaneRegion<'_, T>, cdeffed: &PlaneSlice<T>, cdef_w: usize,
+  cdef_h: usize, chroma: bool,
+) -> Option<[[i8; 3]; 2]> {
+  const PAD: usize = 3;
+  let first_tap = usize::from(chroma);
+  let stride = cdef_w + 2 * PAD;
+  let padded_h = cdef_h + 2 * PAD;
+
+  // Work on mean-removed data for numerical stability. Since the filter
+  // taps sum to 1, this does not change the solution.
+  let mut sum = 0i64;
+  for y in 0..cdef_h {
+    sum +=
+      cdeffed.row(y)[..cdef_w].iter().map(|&v| v.to_i32() as i64).sum::<i64>();
+  }
+  let avg = sum as f64 / (cdef_w * cdef_h) as f64;
+
+  let mut dgd = vec![0f64; stride * padded_h];
+  for (py, dgd_row) in dgd.chunks_exact_mut(stride).enumerate() {
+    let sy = clamp(py as isize - PAD as isize, 0, cdef_h as isize - 1);
+    let row = cdeffed.row(sy as usize);
+    for (px, d) in dgd_row.iter_mut().enumerate() {
+      let sx = clamp(px as isize - PAD as isize, 0, cdef_w as isize - 1);
+      *d = row[sx as usize].to_i32() as f64 - avg;
+    }
+  }
+  let mut src = Vec::with_capacity(cdef_w * cdef_h);
+  for y in 0..cdef_h {
+    src.extend(input[y][..cdef_w].iter().map(|&v| v.to_i32() as f64 - avg));
+  }
+
+  let mut mid = WIENER_TAPS_MID;
+  if chroma {
+    mid[0] = 0;
+  }
+  let mut vfilter = wiener_taps_from_coeffs(mid);
+  let mut hfilter = vfilter;
+
+  // Filtered intermediate, one direction at a time
+  let mut tmp = vec![0f64; stride * padded_h];
+  for _ in 0..WIENER_SOLVE_ITERS {
+    // Update the vertical filter with the horizontal filter fixed
+    let mut a = [[0f64; 7]; 7];
+    let mut s = [0f64; 7];
+    for (dgd_row, tmp_row) in
+      dgd.chunks_exact(stride).zip(tmp.chunks_exact_mut(stride))
+    {
+      for (x, t) in tmp_row[..cdef_w].iter_mut().enumerate() {
+        *t = dgd_row[x..x + 7]
+          .iter()
+          .zip(hfilter.iter())
+          .map(|(d, f)| d * f)
+          .sum();
+      }
+    }
+    for y in 0..cdef_h {
+      for x in 0..cdef_w {
+        let v: [f64; 7] = std::array::from_fn(|i| tmp[(y + i) * stride + x]);
+        wiener_accumulate(&mut a, &mut s, &v, src[y * cdef_w + x]);
+      }
+    }
+    wiener_mirror(&mut a);
+    vfilter = wiener_solve_sym(&a, &s, first_tap)?;
+
+    // Update the horizontal filter with the vertical filter fixed
+    let mut a = [[0f64; 7]; 7];
+    let mut s = [0f64; 7];
+    for y in 0..cdef_h {
+      let tmp_row = &mut tmp[y * stride..(y + 1) * stride];
+      for (x, t) in tmp_row.iter_mut().enumerate() {
+        *t = (0..7).map(|i| dgd[(y + i) * stride + x] * vfilter[i]).sum();
+      }
+    }
+    for y in 0..cdef_h {
+      let tmp_row = &tmp[y * stride..(y + 1) * stride];
+      for x in 0..cdef_w {
+        let v: [f64; 7] = std::array::from_fn(|j| tmp_row[x + j]);
+        wiener_accumulate(&mut a, &mut s, &v, src[y * cdef_w + x]);
+      }
+    }
+    wiener_mirror(&mut a);
+    hfilter = wiener_solve_sym(&a, &s, first_tap)?;
+  }
+
+  let quantize = |taps: [f64; 7]| -> [i8; 3] {
+    let mut coeffs = [0i8; 3];
+    for i in first_tap..3 {
+      let c = (taps[i] * (1 << WIENER_BITS) as f64).round() as i32;
+      coeffs[i] =
+        clamp(c, WIENER_TAPS_MIN[i] as i32, WIENER_TAPS_MAX[i] as i32) as i8;
+    }
+    coeffs
+  };
+  Some([quantize(vfilter), quantize(hfilter)])
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct RestorationUnit {
   pub filter: RestorationFilter,
@@ -1345,7 +1589,7 @@ impl RestorationState {
 
     // stripe size is decimated in 4:2:0 (and only 4:2:0)
     let stripe_uv_decimate = usize::from(xdec > 0 && ydec > 0);
-    let y_sb_log2 = if fi.sequence.use_128x128_superblock { 7 } else { 6 };
+    let y_sb_log2 = fi.sb_size_log2();
     let uv_sb_h_log2 = y_sb_log2 - xdec;
     let uv_sb_v_log2 = y_sb_log2 - ydec;
 
@@ -1366,6 +1610,9 @@ impl RestorationState {
       } else {
         2 // small
       };
+      // Luma LRUs are never smaller than a superblock.
+      let lrf_base_shift =
+        lrf_base_shift.min(RESTORATION_TILESIZE_MAX_LOG2 - y_sb_log2);
       let lrf_chroma_shift = if stripe_uv_decimate > 0 {
         // 4:2:0 only
         if lrf_base_shift == 2 {
@@ -1394,7 +1641,7 @@ impl RestorationState {
     } else {
       // Explicit request to tie LRU size to superblock size ==
       // smallest possible LRU size
-      let lrf_y_shift = if fi.sequence.use_128x128_superblock { 1 } else { 2 };
+      let lrf_y_shift = RESTORATION_TILESIZE_MAX_LOG2 - y_sb_log2;
       (lrf_y_shift, lrf_y_shift + stripe_uv_decimate)
     };
 
@@ -1448,7 +1695,11 @@ impl RestorationState {
       uv_unit_size = y_unit_size;
     }
 
-    // derive the rest
+    // derive the rest; the restoration state counts superblocks in 64x64
+    // units whatever the coded superblock size
+    let y_sb_log2 = SUPERBLOCK_TO_PLANE_SHIFT;
+    let uv_sb_h_log2 = y_sb_log2 - xdec;
+    let uv_sb_v_log2 = y_sb_log2 - ydec;
     let y_unit_log2 = y_unit_size.ilog() - 1;
     let uv_unit_log2 = uv_unit_size.ilog() - 1;
     let y_cols = ((fi.width + (y_unit_size >> 1)) / y_unit_size).max(1);
@@ -1646,3 +1897,141 @@ impl RestorationState {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::api::EncoderConfig;
+  use crate::encoder::Sequence;
+  use crate::frame::FrameAlloc;
+  use std::sync::Arc;
+
+  #[test]
+  fn wiener_solve_recovers_filter() {
+    let (w, h) = (64, 64);
+    let config = Arc::new(EncoderConfig::default());
+    let sequence = Arc::new(Sequence::new(&config));
+    let fi = FrameInvariants::<u8>::new(config, sequence);
+
+    let mut cdeffed = Frame::<u8>::new(w, h, ChromaSubsampling::Yuv420);
+    let mut state = 1u32;
+    for row in cdeffed.y_plane.rows_mut() {
+      for p in row.iter_mut() {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        *p = 64 + (state >> 25) as u8;
+      }
+    }
+
+    // The best filter to map the cdeffed plane onto its Wiener filtered
+    // version is the filter itself.
+    let coeffs = [[-2, 5, 12], [3, -6, 20]];
+    let mut input = cdeffed.clone();
+    wiener_rdo_filter(
+      coeffs,
+      &fi,
+      w,
+      h,
+      0,
+      0,
+      w,
+      h,
+      &cdeffed.y_plane,
+      &mut input.y_plane,
+    );
+    let solved = wiener_solve(
+      &input.y_plane.as_region(),
+      &PlaneSlice::new(&cdeffed.y_plane, 0, 0),
+      w,
+      h,
+      false,
+    )
+    .unwrap();
+    for pass in 0..2 {
+      for i in 0..3 {
+        assert!((solved[pass][i] - coeffs[pass][i]).abs() <= 1, "{solved:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn wiener_solve_rejects_flat_unit() {
+    let (w, h) = (64, 64);
+    let mut cdeffed = Frame::<u8>::new(w, h, ChromaSubsampling::Yuv420);
+    for row in cdeffed.y_plane.rows_mut() {
+      row.fill(128);
+    }
+    // Every filter leaves a flat unit unchanged, so there is nothing to
+    // solve for.
+    let solved = wiener_solve(
+      &cdeffed.y_plane.as_region(),
+      &PlaneSlice::new(&cdeffed.y_plane, 0, 0),
+      w,
+      h,
+      false,
+    );
+    assert!(solved.is_none(), "{solved:?}");
+  }
+
+  #[test]
+  fn wiener_stripe_filter_reads_cdeffed_above_first_stripe() {
+    let (w, h) = (64, 64);
+    let config = Arc::new(EncoderConfig::default());
+    let sequence = Arc::new(Sequence::new(&config));
+    let fi = FrameInvariants::<u8>::new(config, sequence);
+
+    let mut cdeffed = Frame::<u8>::new(w, h, ChromaSubsampling::Yuv420);
+    let mut state = 1u32;
+    for row in cdeffed.y_plane.rows_mut() {
+      for p in row.iter_mut() {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        *p = (state >> 24) as u8;
+      }
+    }
+    let mut deblocked = cdeffed.clone();
+    for row in deblocked.y_plane.rows_mut() {
+      row.fill(0);
+    }
+
+    // The first stripe starts 8 rows above the frame, so the rows above
+    // the frame are clamped to its first row of the cdeffed plane rather
+    // than read from the deblocked plane.
+    let coeffs = [[3, -7, 15], [3, -7, 15]];
+    let stripe_h = 64 - 8;
+    let mut expected = cdeffed.clone();
+    wiener_stripe_filter(
+      coeffs,
+      &fi,
+      w,
+      h,
+      w,
+      stripe_h,
+      0,
+      0,
+      &cdeffed.y_plane,
+      &cdeffed.y_plane,
+      &mut expected.y_plane,
+    );
+    let mut out = cdeffed.clone();
+    wiener_stripe_filter(
+      coeffs,
+      &fi,
+      w,
+      h,
+      w,
+      stripe_h,
+      0,
+      0,
+      &cdeffed.y_plane,
+      &deblocked.y_plane,
+      &mut out.y_plane,
+    );
+    // The last 3 rows of the stripe filter deblocked rows below it.
+    for y in 0..stripe_h - 3 {
+      assert_eq!(
+        &out.y_plane.row(y).unwrap()[..w],
+        &expected.y_plane.row(y).unwrap()[..w],
+        "row {y}"
+      );
+    }
+  }
+}
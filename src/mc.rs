@@ -19,13 +19,18 @@ cfg_if::cfg_if! {
   }
 }
 
+use crate::context::{PlaneBlockOffset, MI_SIZE};
 use crate::cpu_features::CpuFeatureLevel;
-use crate::frame::PlaneSlice;
+use crate::frame::{AsRegion, Plane, PlaneConfig, PlaneOffset, PlaneSlice};
+use crate::partition::BlockSize;
+use crate::predict::InterIntraMode;
 use crate::tiling::*;
 use crate::util::*;
 
 use simd_helpers::cold_for_target_arch;
+use std::cmp::Ordering;
 use std::ops;
+use std::sync::OnceLock;
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct MotionVector {
@@ -237,7 +242,7 @@ pub(crate) mod rust {
       .sum::<i32>()
   }
 
-  fn get_filter(
+  pub(super) fn get_filter(
     mode: FilterMode, frac: i32, length: usize,
   ) -> [i32; SUBPEL_FILTER_SIZE] {
     let filter_idx = if mode == FilterMode::BILINEAR || length > 4 {
@@ -357,7 +362,7 @@ pub(crate) mod rust {
 
   // HBD output interval is [-20588, 36956] (10-bit), [-20602, 36983] (12-bit)
   // Subtract PREP_BIAS to ensure result fits in i16 and matches dav1d assembly
-  const PREP_BIAS: i32 = 8192;
+  pub(super) const PREP_BIAS: i32 = 8192;
 
   #[cold_for_target_arch("x86_64")]
   pub fn prep_8tap<T: Pixel>(
@@ -480,4 +485,1223 @@ pub(crate) mod rust {
       }
     }
   }
+
+  /// Filters one 8x8 unit of a warped block. `src` starts 7 rows above
+  /// and 7 columns left of the integer position of the unit center, and
+  /// `mx` and `my` hold the filter positions of its top-left sample, as
+  /// set up by [`warp`](super::warp).
+  fn warp_8x8<T: Pixel>(
+    src: &[T], src_stride: usize, abcd: &[i16; 4], mx: i32, my: i32,
+    round: usize, bit_depth: usize, mut store: impl FnMut(usize, usize, i32),
+  ) {
+    let [alpha, beta, gamma, delta] = abcd.map(|v| v as i32);
+    let round0 = if bit_depth == 12 { 5 } else { 3 };
+
+    let mut intermediate = [[0i32; 8]; 15];
+    for (r, row) in intermediate.iter_mut().enumerate() {
+      let src_row = &src[r * src_stride..];
+      for (c, dst) in row.iter_mut().enumerate() {
+        let tmx = mx + alpha * c as i32 + beta * r as i32;
+        let offset =
+          round_shift(tmx, WARPEDDIFF_PREC_BITS) + WARPEDPIXEL_PREC_SHIFTS;
+        let filter = &WARPED_FILTERS[offset as usize];
+        let sum: i32 =
+          (0..8).map(|k| filter[k] * Pixel::to_i32(src_row[c + k])).sum();
+        *dst = round_shift(sum, round0);
+      }
+    }
+
+    for r in 0..8 {
+      for c in 0..8 {
+        let tmy = my + gamma * c as i32 + delta * r as i32;
+        let offset =
+          round_shift(tmy, WARPEDDIFF_PREC_BITS) + WARPEDPIXEL_PREC_SHIFTS;
+        let filter = &WARPED_FILTERS[offset as usize];
+        let sum: i32 =
+          (0..8).map(|k| filter[k] * intermediate[r + k][c]).sum();
+        store(r, c, round_shift(sum, round));
+      }
+    }
+  }
+
+  #[cold_for_target_arch("x86_64", "aarch64")]
+  pub fn put_warp_8x8<T: Pixel>(
+    dst: &mut PlaneRegionMut<'_, T>, src: &[T], src_stride: usize,
+    abcd: &[i16; 4], mx: i32, my: i32, bit_depth: usize,
+    _cpu: CpuFeatureLevel,
+  ) {
+    let max_sample_val = (1 << bit_depth) - 1;
+    let round = if bit_depth == 12 { 9 } else { 11 };
+    warp_8x8(src, src_stride, abcd, mx, my, round, bit_depth, |r, c, v| {
+      dst[r][c] = T::cast_from(v.clamp(0, max_sample_val))
+    });
+  }
+
+  #[cold_for_target_arch("x86_64", "aarch64")]
+  pub fn prep_warp_8x8<T: Pixel>(
+    tmp: &mut [i16], tmp_stride: usize, src: &[T], src_stride: usize,
+    abcd: &[i16; 4], mx: i32, my: i32, bit_depth: usize,
+    _cpu: CpuFeatureLevel,
+  ) {
+    let prep_bias = if bit_depth == 8 { 0 } else { PREP_BIAS };
+    warp_8x8(src, src_stride, abcd, mx, my, 7, bit_depth, |r, c, v| {
+      tmp[r * tmp_stride + c] = (v - prep_bias) as i16
+    });
+  }
+
+  #[inline(always)]
+  fn blend_obmc_px<T: Pixel>(dst: T, tmp: T, mask: u8) -> T {
+    let mask = mask as i32;
+    T::cast_from(round_shift(
+      mask * Pixel::to_i32(dst) + (64 - mask) * Pixel::to_i32(tmp),
+      6,
+    ))
+  }
+
+  /// Blends the prediction of the neighbor above into the top `height`
+  /// rows of `dst`. `tmp` holds it with a stride of `width`.
+  #[cold_for_target_arch("x86_64", "aarch64")]
+  pub fn blend_obmc_above<T: Pixel>(
+    dst: &mut PlaneRegionMut<'_, T>, tmp: &[T], width: usize, height: usize,
+    _cpu: CpuFeatureLevel,
+  ) {
+    let mask = obmc_mask(height);
+    for (r, tmp_row) in tmp.chunks(width).take(height).enumerate() {
+      for (d, &t) in dst[r][..width].iter_mut().zip(tmp_row) {
+        *d = blend_obmc_px(*d, t, mask[r]);
+      }
+    }
+  }
+
+  /// Blends the prediction of the neighbor to the left into the leftmost
+  /// `width` columns of `dst`. `tmp` holds it with a stride of `width`.
+  #[cold_for_target_arch("x86_64", "aarch64")]
+  pub fn blend_obmc_left<T: Pixel>(
+    dst: &mut PlaneRegionMut<'_, T>, tmp: &[T], width: usize, height: usize,
+    _cpu: CpuFeatureLevel,
+  ) {
+    let mask = obmc_mask(width);
+    for (r, tmp_row) in tmp.chunks(width).take(height).enumerate() {
+      for ((d, &t), &m) in dst[r][..width].iter_mut().zip(tmp_row).zip(mask) {
+        *d = blend_obmc_px(*d, t, m);
+      }
+    }
+  }
+}
+
+/// Weights of the prediction of a block against that of a neighbor, in
+/// units of 1/64, by distance from their shared edge.
+const OBMC_MASK_2: [u8; 2] = [45, 64];
+const OBMC_MASK_4: [u8; 4] = [39, 50, 59, 64];
+const OBMC_MASK_8: [u8; 8] = [36, 42, 48, 53, 57, 61, 64, 64];
+const OBMC_MASK_16: [u8; 16] =
+  [34, 37, 40, 43, 46, 49, 52, 54, 56, 58, 60, 61, 64, 64, 64, 64];
+const OBMC_MASK_32: [u8; 32] = [
+  33, 35, 36, 38, 40, 41, 43, 44, 45, 47, 48, 50, 51, 52, 53, 55, 56, 57, 58,
+  59, 60, 60, 61, 62, 64, 64, 64, 64, 64, 64, 64, 64,
+];
+
+/// Returns the blending weights for an overlap of `len` samples.
+///
+/// # Panics
+///
+/// - If `len` is not a power of two from 2 to 32
+pub fn obmc_mask(len: usize) -> &'static [u8] {
+  match len {
+    2 => &OBMC_MASK_2,
+    4 => &OBMC_MASK_4,
+    8 => &OBMC_MASK_8,
+    16 => &OBMC_MASK_16,
+    32 => &OBMC_MASK_32,
+    _ => unreachable!(),
+  }
+}
+
+/// Precision of the parameters of a warped motion model, in bits.
+pub const WARPEDMODEL_PREC_BITS: u32 = 16;
+/// Precision to which the shear parameters of a warp are reduced, in bits.
+const WARP_PARAM_REDUCE_BITS: u32 = 6;
+const WARPEDDIFF_PREC_BITS: usize = 10;
+const WARPEDPIXEL_PREC_SHIFTS: i32 = 1 << 6;
+
+const DIV_LUT_BITS: u32 = 8;
+const DIV_LUT_PREC_BITS: u32 = 14;
+const DIV_LUT_NUM: usize = 257;
+
+const DIV_LUT: [i32; DIV_LUT_NUM] = [
+  16384, 16320, 16257, 16194, 16132, 16070, 16009, 15948, 15888, 15828, 15768,
+  15709, 15650, 15592, 15534, 15477, 15420, 15364, 15308, 15252, 15197, 15142,
+  15087, 15033, 14980, 14926, 14873, 14821, 14769, 14717, 14665, 14614, 14564,
+  14513, 14463, 14413, 14364, 14315, 14266, 14218, 14170, 14122, 14075, 14028,
+  13981, 13935, 13888, 13843, 13797, 13752, 13707, 13662, 13618, 13574, 13530,
+  13487, 13443, 13400, 13358, 13315, 13273, 13231, 13190, 13148, 13107, 13066,
+  13026, 12985, 12945, 12906, 12866, 12827, 12788, 12749, 12710, 12672, 12633,
+  12596, 12558, 12520, 12483, 12446, 12409, 12373, 12336, 12300, 12264, 12228,
+  12193, 12157, 12122, 12087, 12053, 12018, 11984, 11950, 11916, 11882, 11848,
+  11815, 11782, 11749, 11716, 11683, 11651, 11619, 11586, 11555, 11523, 11491,
+  11460, 11429, 11398, 11367, 11336, 11305, 11275, 11245, 11215, 11185, 11155,
+  11125, 11096, 11067, 11038, 11009, 10980, 10951, 10923, 10894, 10866, 10838,
+  10810, 10782, 10755, 10727, 10700, 10673, 10645, 10618, 10592, 10565, 10538,
+  10512, 10486, 10460, 10434, 10408, 10382, 10356, 10331, 10305, 10280, 10255,
+  10230, 10205, 10180, 10156, 10131, 10107, 10082, 10058, 10034, 10010, 9986,
+  9963, 9939, 9916, 9892, 9869, 9846, 9823, 9800, 9777, 9754, 9732, 9709,
+  9687, 9664, 9642, 9620, 9598, 9576, 9554, 9533, 9511, 9489, 9468, 9447,
+  9425, 9404, 9383, 9362, 9341, 9321, 9300, 9279, 9259, 9239, 9218, 9198,
+  9178, 9158, 9138, 9118, 9098, 9079, 9059, 9039, 9020, 9001, 8981, 8962,
+  8943, 8924, 8905, 8886, 8867, 8849, 8830, 8812, 8793, 8775, 8756, 8738,
+  8720, 8702, 8684, 8666, 8648, 8630, 8613, 8595, 8577, 8560, 8542, 8525,
+  8508, 8490, 8473, 8456, 8439, 8422, 8405, 8389, 8372, 8355, 8339, 8322,
+  8306, 8289, 8273, 8257, 8240, 8224, 8208, 8192,
+];
+
+/// Filter taps of the affine warp, indexed by the position of the sample
+/// in units of 1/64 pixel, from -1 to 2 pixels and one extra row.
+const WARPED_FILTERS: [[i32; 8]; 193] = [
+  // [-1, 0)
+  [0, 0, 127, 1, 0, 0, 0, 0],
+  [0, -1, 127, 2, 0, 0, 0, 0],
+  [1, -3, 127, 4, -1, 0, 0, 0],
+  [1, -4, 126, 6, -2, 1, 0, 0],
+  [1, -5, 126, 8, -3, 1, 0, 0],
+  [1, -6, 125, 11, -4, 1, 0, 0],
+  [1, -7, 124, 13, -4, 1, 0, 0],
+  [2, -8, 123, 15, -5, 1, 0, 0],
+  [2, -9, 122, 18, -6, 1, 0, 0],
+  [2, -10, 121, 20, -6, 1, 0, 0],
+  [2, -11, 120, 22, -7, 2, 0, 0],
+  [2, -12, 119, 25, -8, 2, 0, 0],
+  [3, -13, 117, 27, -8, 2, 0, 0],
+  [3, -13, 116, 29, -9, 2, 0, 0],
+  [3, -14, 114, 32, -10, 3, 0, 0],
+  [3, -15, 113, 35, -10, 2, 0, 0],
+  [3, -15, 111, 37, -11, 3, 0, 0],
+  [3, -16, 109, 40, -11, 3, 0, 0],
+  [3, -16, 108, 42, -12, 3, 0, 0],
+  [4, -17, 106, 45, -13, 3, 0, 0],
+  [4, -17, 104, 47, -13, 3, 0, 0],
+  [4, -17, 102, 50, -14, 3, 0, 0],
+  [4, -17, 100, 52, -14, 3, 0, 0],
+  [4, -18, 98, 55, -15, 4, 0, 0],
+  [4, -18, 96, 58, -15, 3, 0, 0],
+  [4, -18, 94, 60, -16, 4, 0, 0],
+  [4, -18, 91, 63, -16, 4, 0, 0],
+  [4, -18, 89, 65, -16, 4, 0, 0],
+  [4, -18, 87, 68, -17, 4, 0, 0],
+  [4, -18, 85, 70, -17, 4, 0, 0],
+  [4, -18, 82, 73, -17, 4, 0, 0],
+  [4, -18, 80, 75, -17, 4, 0, 0],
+  [4, -18, 78, 78, -18, 4, 0, 0],
+  [4, -17, 75, 80, -18, 4, 0, 0],
+  [4, -17, 73, 82, -18, 4, 0, 0],
+  [4, -17, 70, 85, -18, 4, 0, 0],
+  [4, -17, 68, 87, -18, 4, 0, 0],
+  [4, -16, 65, 89, -18, 4, 0, 0],
+  [4, -16, 63, 91, -18, 4, 0, 0],
+  [4, -16, 60, 94, -18, 4, 0, 0],
+  [3, -15, 58, 96, -18, 4, 0, 0],
+  [4, -15, 55, 98, -18, 4, 0, 0],
+  [3, -14, 52, 100, -17, 4, 0, 0],
+  [3, -14, 50, 102, -17, 4, 0, 0],
+  [3, -13, 47, 104, -17, 4, 0, 0],
+  [3, -13, 45, 106, -17, 4, 0, 0],
+  [3, -12, 42, 108, -16, 3, 0, 0],
+  [3, -11, 40, 109, -16, 3, 0, 0],
+  [3, -11, 37, 111, -15, 3, 0, 0],
+  [2, -10, 35, 113, -15, 3, 0, 0],
+  [3, -10, 32, 114, -14, 3, 0, 0],
+  [2, -9, 29, 116, -13, 3, 0, 0],
+  [2, -8, 27, 117, -13, 3, 0, 0],
+  [2, -8, 25, 119, -12, 2, 0, 0],
+  [2, -7, 22, 120, -11, 2, 0, 0],
+  [1, -6, 20, 121, -10, 2, 0, 0],
+  [1, -6, 18, 122, -9, 2, 0, 0],
+  [1, -5, 15, 123, -8, 2, 0, 0],
+  [1, -4, 13, 124, -7, 1, 0, 0],
+  [1, -4, 11, 125, -6, 1, 0, 0],
+  [1, -3, 8, 126, -5, 1, 0, 0],
+  [1, -2, 6, 126, -4, 1, 0, 0],
+  [0, -1, 4, 127, -3, 1, 0, 0],
+  [0, 0, 2, 127, -1, 0, 0, 0],
+  // [0, 1)
+  [0, 0, 0, 127, 1, 0, 0, 0],
+  [0, 0, -1, 127, 2, 0, 0, 0],
+  [0, 1, -3, 127, 4, -2, 1, 0],
+  [0, 1, -5, 127, 6, -2, 1, 0],
+  [0, 2, -6, 126, 8, -3, 1, 0],
+  [-1, 2, -7, 126, 11, -4, 2, -1],
+  [-1, 3, -8, 125, 13, -5, 2, -1],
+  [-1, 3, -10, 124, 16, -6, 3, -1],
+  [-1, 4, -11, 123, 18, -7, 3, -1],
+  [-1, 4, -12, 122, 20, -7, 3, -1],
+  [-1, 4, -13, 121, 23, -8, 3, -1],
+  [-2, 5, -14, 120, 25, -9, 4, -1],
+  [-1, 5, -15, 119, 27, -10, 4, -1],
+  [-1, 5, -16, 118, 30, -11, 4, -1],
+  [-2, 6, -17, 116, 33, -12, 5, -1],
+  [-2, 6, -17, 114, 35, -12, 5, -1],
+  [-2, 6, -18, 113, 38, -13, 5, -1],
+  [-2, 7, -19, 111, 41, -14, 6, -2],
+  [-2, 7, -19, 110, 43, -15, 6, -2],
+  [-2, 7, -20, 108, 46, -15, 6, -2],
+  [-2, 7, -20, 106, 49, -16, 6, -2],
+  [-2, 7, -21, 104, 51, -16, 7, -2],
+  [-2, 7, -21, 102, 54, -17, 7, -2],
+  [-2, 8, -21, 100, 56, -18, 7, -2],
+  [-2, 8, -22, 98, 59, -18, 7, -2],
+  [-2, 8, -22, 96, 62, -19, 7, -2],
+  [-2, 8, -22, 94, 64, -19, 7, -2],
+  [-2, 8, -22, 91, 67, -20, 8, -2],
+  [-2, 8, -22, 89, 69, -20, 8, -2],
+  [-2, 8, -22, 87, 72, -21, 8, -2],
+  [-2, 8, -21, 84, 74, -21, 8, -2],
+  [-2, 8, -22, 82, 77, -21, 8, -2],
+  [-2, 8, -21, 79, 79, -21, 8, -2],
+  [-2, 8, -21, 77, 82, -22, 8, -2],
+  [-2, 8, -21, 74, 84, -21, 8, -2],
+  [-2, 8, -21, 72, 87, -22, 8, -2],
+  [-2, 8, -20, 69, 89, -22, 8, -2],
+  [-2, 8, -20, 67, 91, -22, 8, -2],
+  [-2, 7, -19, 64, 94, -22, 8, -2],
+  [-2, 7, -19, 62, 96, -22, 8, -2],
+  [-2, 7, -18, 59, 98, -22, 8, -2],
+  [-2, 7, -18, 56, 100, -21, 8, -2],
+  [-2, 7, -17, 54, 102, -21, 7, -2],
+  [-2, 7, -16, 51, 104, -21, 7, -2],
+  [-2, 6, -16, 49, 106, -20, 7, -2],
+  [-2, 6, -15, 46, 108, -20, 7, -2],
+  [-2, 6, -15, 43, 110, -19, 7, -2],
+  [-2, 6, -14, 41, 111, -19, 7, -2],
+  [-1, 5, -13, 38, 113, -18, 6, -2],
+  [-1, 5, -12, 35, 114, -17, 6, -2],
+  [-1, 5, -12, 33, 116, -17, 6, -2],
+  [-1, 4, -11, 30, 118, -16, 5, -1],
+  [-1, 4, -10, 27, 119, -15, 5, -1],
+  [-1, 4, -9, 25, 120, -14, 5, -2],
+  [-1, 3, -8, 23, 121, -13, 4, -1],
+  [-1, 3, -7, 20, 122, -12, 4, -1],
+  [-1, 3, -7, 18, 123, -11, 4, -1],
+  [-1, 3, -6, 16, 124, -10, 3, -1],
+  [-1, 2, -5, 13, 125, -8, 3, -1],
+  [-1, 2, -4, 11, 126, -7, 2, -1],
+  [0, 1, -3, 8, 126, -6, 2, 0],
+  [0, 1, -2, 6, 127, -5, 1, 0],
+  [0, 1, -2, 4, 127, -3, 1, 0],
+  [0, 0, 0, 2, 127, -1, 0, 0],
+  // [1, 2)
+  [0, 0, 0, 1, 127, 0, 0, 0],
+  [0, 0, 0, -1, 127, 2, 0, 0],
+  [0, 0, 1, -3, 127, 4, -1, 0],
+  [0, 0, 1, -4, 126, 6, -2, 1],
+  [0, 0, 1, -5, 126, 8, -3, 1],
+  [0, 0, 1, -6, 125, 11, -4, 1],
+  [0, 0, 1, -7, 124, 13, -4, 1],
+  [0, 0, 2, -8, 123, 15, -5, 1],
+  [0, 0, 2, -9, 122, 18, -6, 1],
+  [0, 0, 2, -10, 121, 20, -6, 1],
+  [0, 0, 2, -11, 120, 22, -7, 2],
+  [0, 0, 2, -12, 119, 25, -8, 2],
+  [0, 0, 3, -13, 117, 27, -8, 2],
+  [0, 0, 3, -13, 116, 29, -9, 2],
+  [0, 0, 3, -14, 114, 32, -10, 3],
+  [0, 0, 3, -15, 113, 35, -10, 2],
+  [0, 0, 3, -15, 111, 37, -11, 3],
+  [0, 0, 3, -16, 109, 40, -11, 3],
+  [0, 0, 3, -16, 108, 42, -12, 3],
+  [0, 0, 4, -17, 106, 45, -13, 3],
+  [0, 0, 4, -17, 104, 47, -13, 3],
+  [0, 0, 4, -17, 102, 50, -14, 3],
+  [0, 0, 4, -17, 100, 52, -14, 3],
+  [0, 0, 4, -18, 98, 55, -15, 4],
+  [0, 0, 4, -18, 96, 58, -15, 3],
+  [0, 0, 4, -18, 94, 60, -16, 4],
+  [0, 0, 4, -18, 91, 63, -16, 4],
+  [0, 0, 4, -18, 89, 65, -16, 4],
+  [0, 0, 4, -18, 87, 68, -17, 4],
+  [0, 0, 4, -18, 85, 70, -17, 4],
+  [0, 0, 4, -18, 82, 73, -17, 4],
+  [0, 0, 4, -18, 80, 75, -17, 4],
+  [0, 0, 4, -18, 78, 78, -18, 4],
+  [0, 0, 4, -17, 75, 80, -18, 4],
+  [0, 0, 4, -17, 73, 82, -18, 4],
+  [0, 0, 4, -17, 70, 85, -18, 4],
+  [0, 0, 4, -17, 68, 87, -18, 4],
+  [0, 0, 4, -16, 65, 89, -18, 4],
+  [0, 0, 4, -16, 63, 91, -18, 4],
+  [0, 0, 4, -16, 60, 94, -18, 4],
+  [0, 0, 3, -15, 58, 96, -18, 4],
+  [0, 0, 4, -15, 55, 98, -18, 4],
+  [0, 0, 3, -14, 52, 100, -17, 4],
+  [0, 0, 3, -14, 50, 102, -17, 4],
+  [0, 0, 3, -13, 47, 104, -17, 4],
+  [0, 0, 3, -13, 45, 106, -17, 4],
+  [0, 0, 3, -12, 42, 108, -16, 3],
+  [0, 0, 3, -11, 40, 109, -16, 3],
+  [0, 0, 3, -11, 37, 111, -15, 3],
+  [0, 0, 2, -10, 35, 113, -15, 3],
+  [0, 0, 3, -10, 32, 114, -14, 3],
+  [0, 0, 2, -9, 29, 116, -13, 3],
+  [0, 0, 2, -8, 27, 117, -13, 3],
+  [0, 0, 2, -8, 25, 119, -12, 2],
+  [0, 0, 2, -7, 22, 120, -11, 2],
+  [0, 0, 1, -6, 20, 121, -10, 2],
+  [0, 0, 1, -6, 18, 122, -9, 2],
+  [0, 0, 1, -5, 15, 123, -8, 2],
+  [0, 0, 1, -4, 13, 124, -7, 1],
+  [0, 0, 1, -4, 11, 125, -6, 1],
+  [0, 0, 1, -3, 8, 126, -5, 1],
+  [0, 0, 1, -2, 6, 126, -4, 1],
+  [0, 0, 0, -1, 4, 127, -3, 1],
+  [0, 0, 0, 0, 2, 127, -1, 0],
+  // dummy (replicates the last row)
+  [0, 0, 0, 0, 2, 127, -1, 0],
+];
+
+#[inline(always)]
+const fn round_shift_signed(value: i64, bit: u32) -> i64 {
+  if value < 0 {
+    -((-value + (1 << bit >> 1)) >> bit)
+  } else {
+    (value + (1 << bit >> 1)) >> bit
+  }
+}
+
+/// Returns the shift and the factor with which multiplying and shifting
+/// right approximates a division by `d`.
+fn resolve_divisor(d: i64) -> (u32, i64) {
+  let n = d.unsigned_abs().ilog2();
+  let e = d.unsigned_abs() - (1 << n);
+  let f = if n > DIV_LUT_BITS {
+    round_shift_signed(e as i64, n - DIV_LUT_BITS) as usize
+  } else {
+    (e << (DIV_LUT_BITS - n)) as usize
+  };
+  let factor = DIV_LUT[f] as i64;
+  (n + DIV_LUT_PREC_BITS, if d < 0 { -factor } else { factor })
+}
+
+/// Decomposes an affine model into the shears `[alpha, beta, gamma, delta]`
+/// applied by the two passes of the warp filter, or returns `None` if the
+/// model shears too much to be used for prediction.
+pub fn setup_shear(params: &[i32; 6]) -> Option<[i32; 4]> {
+  let clamp_i16 = |v: i64| v.clamp(i16::MIN as i64, i16::MAX as i64);
+  let alpha = clamp_i16(params[2] as i64 - (1 << WARPEDMODEL_PREC_BITS));
+  let beta = clamp_i16(params[3] as i64);
+  let (shift, factor) = resolve_divisor(params[2] as i64);
+  let v = (params[4] as i64) << WARPEDMODEL_PREC_BITS;
+  let gamma = clamp_i16(round_shift_signed(v * factor, shift));
+  let w = params[3] as i64 * params[4] as i64;
+  let delta = clamp_i16(
+    params[5] as i64
+      - round_shift_signed(w * factor, shift)
+      - (1 << WARPEDMODEL_PREC_BITS),
+  );
+
+  let reduce = |v: i64| {
+    (round_shift_signed(v, WARP_PARAM_REDUCE_BITS) << WARP_PARAM_REDUCE_BITS)
+      as i32
+  };
+  let shear = [reduce(alpha), reduce(beta), reduce(gamma), reduce(delta)];
+  let [alpha, beta, gamma, delta] = shear.map(i32::abs);
+  if 4 * alpha + 7 * beta >= (1 << WARPEDMODEL_PREC_BITS)
+    || 4 * gamma + 4 * delta >= (1 << WARPEDMODEL_PREC_BITS)
+  {
+    None
+  } else {
+    Some(shear)
+  }
+}
+
+/// Maximum number of neighboring motion samples from which the local warp
+/// of a block is estimated.
+pub const LEAST_SQUARES_SAMPLES_MAX: usize = 8;
+
+/// Fits the local warp of the block of size `bsize` at `bo` with motion
+/// vector `mv` to the motion of its neighbors. Each of the `samples` holds
+/// the center of a neighboring block and where its motion vector moves
+/// it, as `[y, x, y + mv.row, x + mv.col]` in units of 1/8 pixel.
+///
+/// Returns `None` if the fitted model cannot be used for prediction.
+pub fn find_affine(
+  samples: &[[i32; 4]], bo: PlaneBlockOffset, bsize: BlockSize,
+  mv: MotionVector,
+) -> Option<[i32; 6]> {
+  const LS_MV_MAX: i32 = 256;
+  const NONDIAGAFFINE_CLAMP: i64 = 1 << 13;
+  const TRANS_CLAMP: i64 = 1 << 23;
+  let ls_product = |a: i32, b: i32| (((a * b) >> 2) + (a + b)) as i64;
+
+  let mid_y = (bo.0.y * MI_SIZE + bsize.height() / 2) as i32 - 1;
+  let mid_x = (bo.0.x * MI_SIZE + bsize.width() / 2) as i32 - 1;
+  let (suy, sux) = (mid_y * 8, mid_x * 8);
+  let (duy, dux) = (suy + mv.row as i32, sux + mv.col as i32);
+
+  let (mut a00, mut a01, mut a11) = (0, 0, 0);
+  let (mut bx0, mut bx1, mut by0, mut by1) = (0, 0, 0, 0);
+  for sample in samples {
+    let (sy, sx) = (sample[0] - suy, sample[1] - sux);
+    let (dy, dx) = (sample[2] - duy, sample[3] - dux);
+    if (sx - dx).abs() < LS_MV_MAX && (sy - dy).abs() < LS_MV_MAX {
+      a00 += ls_product(sx, sx) + 8;
+      a01 += ls_product(sx, sy) + 4;
+      a11 += ls_product(sy, sy) + 8;
+      bx0 += ls_product(sx, dx) + 8;
+      bx1 += ls_product(sy, dx) + 4;
+      by0 += ls_product(sx, dy) + 4;
+      by1 += ls_product(sy, dy) + 8;
+    }
+  }
+
+  let det = a00 * a11 - a01 * a01;
+  if det == 0 {
+    return None;
+  }
+  let (shift, factor) = resolve_divisor(det);
+  let (shift, factor) = match shift.checked_sub(WARPEDMODEL_PREC_BITS) {
+    Some(shift) => (shift, factor),
+    None => (0, factor << (WARPEDMODEL_PREC_BITS - shift)),
+  };
+  let divide = |v: i64| round_shift_signed(v * factor, shift);
+  let nondiag = |v: i64| {
+    divide(v).clamp(-NONDIAGAFFINE_CLAMP + 1, NONDIAGAFFINE_CLAMP - 1)
+  };
+  let diag = |v: i64| {
+    divide(v).clamp(
+      (1 << WARPEDMODEL_PREC_BITS) - NONDIAGAFFINE_CLAMP + 1,
+      (1 << WARPEDMODEL_PREC_BITS) + NONDIAGAFFINE_CLAMP - 1,
+    )
+  };
+
+  let mut params = [0; 6];
+  params[2] = diag(a11 * bx0 - a01 * bx1);
+  params[3] = nondiag(-a01 * bx0 + a00 * bx1);
+  params[4] = nondiag(a11 * by0 - a01 * by1);
+  params[5] = diag(-a01 * by0 + a00 * by1);
+  let vx = ((mv.col as i64) << (WARPEDMODEL_PREC_BITS - 3))
+    - (mid_x as i64 * (params[2] - (1 << WARPEDMODEL_PREC_BITS))
+      + mid_y as i64 * params[3]);
+  let vy = ((mv.row as i64) << (WARPEDMODEL_PREC_BITS - 3))
+    - (mid_x as i64 * params[4]
+      + mid_y as i64 * (params[5] - (1 << WARPEDMODEL_PREC_BITS)));
+  params[0] = vx.clamp(-TRANS_CLAMP, TRANS_CLAMP - 1);
+  params[1] = vy.clamp(-TRANS_CLAMP, TRANS_CLAMP - 1);
+
+  let params = params.map(|v| v as i32);
+  setup_shear(&params).map(|_| params)
+}
+
+/// Stride of the buffer into which [`warp`] copies the source samples of
+/// units whose filter taps reach past the edges of the reference plane.
+const WARP_EMU_STRIDE: usize = 32;
+
+/// Warps the reference plane `src` with the affine model `params`, one
+/// 8x8 unit of the block at `po` at a time. For every unit, `warp_8x8` is
+/// given its offset in the block, its source samples with their stride,
+/// the shears of the model and the filter positions of its top-left
+/// sample.
+///
+/// `frame_width` and `frame_height` are the visible dimensions of the
+/// reference plane, past which its edge samples are repeated.
+fn warp<T: Pixel>(
+  src: &Plane<T>, frame_width: usize, frame_height: usize, po: PlaneOffset,
+  width: usize, height: usize, params: &[i32; 6],
+  mut warp_8x8: impl FnMut(usize, usize, &[T], usize, &[i16; 4], i32, i32),
+) {
+  debug_assert!(width % 8 == 0 && height % 8 == 0);
+  let shear = setup_shear(params).unwrap();
+  let [alpha, beta, gamma, delta] = shear;
+  let abcd = shear.map(|v| v as i16);
+  let PlaneConfig { stride, xdec, ydec, .. } =
+    PlaneConfig::new(&src.geometry());
+  let data = &src.data()[src.data_origin()..];
+  let last_x = frame_width as i32 - 1;
+  let last_y = frame_height as i32 - 1;
+  let mut emu = [T::cast_from(0); WARP_EMU_STRIDE * 16];
+
+  for by in (0..height).step_by(8) {
+    for bx in (0..width).step_by(8) {
+      // The model is applied to the center of each 8x8 unit.
+      let src_x = (po.x as i64 + bx as i64 + 4) << xdec;
+      let src_y = (po.y as i64 + by as i64 + 4) << ydec;
+      let dst_x =
+        params[2] as i64 * src_x + params[3] as i64 * src_y + params[0] as i64;
+      let dst_y =
+        params[4] as i64 * src_x + params[5] as i64 * src_y + params[1] as i64;
+      let x4 = dst_x >> xdec;
+      let y4 = dst_y >> ydec;
+      let ix4 = (x4 >> WARPEDMODEL_PREC_BITS) as i32;
+      let sx4 = (x4 & ((1 << WARPEDMODEL_PREC_BITS) - 1)) as i32;
+      let iy4 = (y4 >> WARPEDMODEL_PREC_BITS) as i32;
+      let sy4 = (y4 & ((1 << WARPEDMODEL_PREC_BITS) - 1)) as i32;
+      let mx =
+        (sx4 - 4 * alpha - 7 * beta) & !((1 << WARP_PARAM_REDUCE_BITS) - 1);
+      let my =
+        (sy4 - 4 * gamma - 4 * delta) & !((1 << WARP_PARAM_REDUCE_BITS) - 1);
+
+      let (x0, y0) = (ix4 - 7, iy4 - 7);
+      if x0 < 0
+        || ix4 + 8 > frame_width as i32
+        || y0 < 0
+        || iy4 + 8 > frame_height as i32
+      {
+        for (r, row) in emu.chunks_exact_mut(WARP_EMU_STRIDE).enumerate() {
+          let y = (y0 + r as i32).clamp(0, last_y) as usize;
+          for (c, v) in row[..16].iter_mut().enumerate() {
+            let x = (x0 + c as i32).clamp(0, last_x) as usize;
+            *v = data[y * stride + x];
+          }
+        }
+        warp_8x8(bx, by, &emu, WARP_EMU_STRIDE, &abcd, mx, my);
+      } else {
+        let offset = y0 as usize * stride + x0 as usize;
+        warp_8x8(bx, by, &data[offset..], stride, &abcd, mx, my);
+      }
+    }
+  }
+}
+
+/// Predicts a block by warping the reference plane with an affine model.
+/// See [`warp`] for the meaning of the parameters.
+pub fn put_warp<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, src: &Plane<T>, frame_width: usize,
+  frame_height: usize, po: PlaneOffset, width: usize, height: usize,
+  params: &[i32; 6], bit_depth: usize, cpu: CpuFeatureLevel,
+) {
+  warp(
+    src,
+    frame_width,
+    frame_height,
+    po,
+    width,
+    height,
+    params,
+    |bx, by, src, src_stride, abcd, mx, my| {
+      put_warp_8x8(
+        &mut dst
+          .subregion_mut(Area::StartingAt { x: bx as isize, y: by as isize }),
+        src,
+        src_stride,
+        abcd,
+        mx,
+        my,
+        bit_depth,
+        cpu,
+      )
+    },
+  );
+}
+
+/// Warps a block like [`put_warp`], into the intermediate precision of
+/// compound prediction used by [`mc_avg`].
+pub fn prep_warp<T: Pixel>(
+  tmp: &mut [i16], src: &Plane<T>, frame_width: usize, frame_height: usize,
+  po: PlaneOffset, width: usize, height: usize, params: &[i32; 6],
+  bit_depth: usize, cpu: CpuFeatureLevel,
+) {
+  warp(
+    src,
+    frame_width,
+    frame_height,
+    po,
+    width,
+    height,
+    params,
+    |bx, by, src, src_stride, abcd, mx, my| {
+      prep_warp_8x8(
+        &mut tmp[by * width + bx..],
+        width,
+        src,
+        src_stride,
+        abcd,
+        mx,
+        my,
+        bit_depth,
+        cpu,
+      )
+    },
+  );
+}
+
+/// Precision of the scale factors between a frame and a reference of
+/// another size.
+pub const REF_SCALE_SHIFT: u32 = 14;
+/// Precision of the sample positions in a reference of another size.
+const SCALE_SUBPEL_BITS: u32 = 10;
+
+/// Returns the scale factor from a frame dimension of `size` samples to
+/// the same dimension of a reference of `ref_size` samples, in units of
+/// `1 << REF_SCALE_SHIFT`.
+pub const fn ref_scale(ref_size: usize, size: usize) -> i32 {
+  (((ref_size << REF_SCALE_SHIFT) + size / 2) / size) as i32
+}
+
+/// Position, in `1 << SCALE_SUBPEL_BITS` units of the reference, of the
+/// top-left sample of a block predicted from a reference of another size,
+/// and the distance between the positions of adjacent samples.
+///
+/// Superres only changes the width of the frames, so `y_step` differs from
+/// `1 << SCALE_SUBPEL_BITS` only for the references of another height left
+/// by a change of the coded frame size.
+#[derive(Clone, Copy, Debug)]
+pub struct ScaledPosition {
+  x: i32,
+  y: i32,
+  x_step: i32,
+  y_step: i32,
+}
+
+impl ScaledPosition {
+  /// Scales the position of the block at `po` displaced by `mv` in a plane
+  /// subsampled by `xdec` and `ydec`, from a frame `frame_width` by
+  /// `frame_height` to a reference `ref_width` by `ref_height`.
+  pub fn new(
+    ref_width: usize, ref_height: usize, frame_width: usize,
+    frame_height: usize, po: PlaneOffset, mv: MotionVector, xdec: usize,
+    ydec: usize,
+  ) -> Self {
+    let scale = |ref_size, size, pos: isize, mv: i16, dec| {
+      let scale = ref_scale(ref_size, size) as i64;
+      let orig = ((pos as i64) << 4) + ((2 * mv as i64) >> dec) + 8;
+      let base = orig * scale - (8 << REF_SCALE_SHIFT);
+      let pos =
+        round_shift_signed(base, REF_SCALE_SHIFT + 4 - SCALE_SUBPEL_BITS)
+          + (1 << (SCALE_SUBPEL_BITS - 4) >> 1);
+      let step =
+        round_shift_signed(scale, REF_SCALE_SHIFT - SCALE_SUBPEL_BITS);
+      (pos as i32, step as i32)
+    };
+    let (x, x_step) = scale(ref_width, frame_width, po.x, mv.col, xdec);
+    let (y, y_step) = scale(ref_height, frame_height, po.y, mv.row, ydec);
+    ScaledPosition { x, y, x_step, y_step }
+  }
+}
+
+/// Interpolates a `width` by `height` block at `pos` of the reference
+/// plane `src`, `plane_width` by `plane_height` samples, passing each
+/// output sample to `out` before its final rounding.
+fn scaled_8tap<T: Pixel>(
+  src: &Plane<T>, plane_width: usize, plane_height: usize,
+  pos: ScaledPosition, width: usize, height: usize, mode_x: FilterMode,
+  mode_y: FilterMode, bit_depth: usize,
+  mut out: impl FnMut(usize, usize, i32),
+) {
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let last_x = plane_width as i32 - 1;
+  let last_y = plane_height as i32 - 1;
+  let intermediate_height =
+    ((((height - 1) as i32 * pos.y_step + (1 << SCALE_SUBPEL_BITS) - 1)
+      >> SCALE_SUBPEL_BITS)
+      + 8) as usize;
+  let frac = |p: i32| (p >> (SCALE_SUBPEL_BITS - 4)) & 15;
+
+  let src = src.as_region();
+  let mut intermediate = vec![0i32; intermediate_height * width];
+  for (r, row) in intermediate.chunks_exact_mut(width).enumerate() {
+    let y = ((pos.y >> SCALE_SUBPEL_BITS) + r as i32 - 3).clamp(0, last_y);
+    let src_row = &src[y as usize];
+    for (c, v) in row.iter_mut().enumerate() {
+      let p = pos.x + pos.x_step * c as i32;
+      let filter = rust::get_filter(mode_x, frac(p), width);
+      let sum: i32 = filter
+        .iter()
+        .enumerate()
+        .map(|(t, &f)| {
+          let x = ((p >> SCALE_SUBPEL_BITS) + t as i32 - 3).clamp(0, last_x);
+          f * src_row[x as usize].to_i32()
+        })
+        .sum();
+      *v = round_shift(sum, 7 - intermediate_bits);
+    }
+  }
+
+  for r in 0..height {
+    let p = (pos.y & ((1 << SCALE_SUBPEL_BITS) - 1)) + pos.y_step * r as i32;
+    let filter = rust::get_filter(mode_y, frac(p), height);
+    let base = (p >> SCALE_SUBPEL_BITS) as usize;
+    for c in 0..width {
+      let sum: i32 = filter
+        .iter()
+        .enumerate()
+        .map(|(t, &f)| f * intermediate[(base + t) * width + c])
+        .sum();
+      out(r, c, sum);
+    }
+  }
+}
+
+/// Predicts a block from a reference plane of another size, `plane_width`
+/// by `plane_height` samples, at the scaled position `pos`.
+pub fn put_8tap_scaled<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, src: &Plane<T>, plane_width: usize,
+  plane_height: usize, pos: ScaledPosition, width: usize, height: usize,
+  mode_x: FilterMode, mode_y: FilterMode, bit_depth: usize,
+) {
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let max_sample_val = (1 << bit_depth) - 1;
+  scaled_8tap(
+    src,
+    plane_width,
+    plane_height,
+    pos,
+    width,
+    height,
+    mode_x,
+    mode_y,
+    bit_depth,
+    |r, c, sum| {
+      dst[r][c] = T::cast_from(
+        round_shift(sum, 7 + intermediate_bits).clamp(0, max_sample_val),
+      );
+    },
+  );
+}
+
+/// Predicts a block like [`put_8tap_scaled`], into the intermediate
+/// precision of compound prediction used by [`mc_avg`].
+pub fn prep_8tap_scaled<T: Pixel>(
+  tmp: &mut [i16], src: &Plane<T>, plane_width: usize, plane_height: usize,
+  pos: ScaledPosition, width: usize, height: usize, mode_x: FilterMode,
+  mode_y: FilterMode, bit_depth: usize,
+) {
+  let prep_bias = if bit_depth == 8 { 0 } else { rust::PREP_BIAS };
+  scaled_8tap(
+    src,
+    plane_width,
+    plane_height,
+    pos,
+    width,
+    height,
+    mode_x,
+    mode_y,
+    bit_depth,
+    |r, c, sum| {
+      tmp[r * width + c] = (round_shift(sum, 7) - prep_bias) as i16;
+    },
+  );
+}
+
+/// Number of wedges in the codebook of a block size.
+pub const WEDGE_TYPES: usize = 16;
+/// Size of the master masks from which the wedge masks are cut out.
+const MASK_MASTER_SIZE: usize = 64;
+
+/// Transition from 0 to 64 of the wedge weights across the boundary, in the
+/// even and odd rows of an oblique wedge and in every row of a vertical one.
+#[rustfmt::skip]
+const WEDGE_MASTER_OBLIQUE_EVEN: [u8; MASK_MASTER_SIZE] = [
+   0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+   0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  1,  4, 11, 27,
+  46, 58, 62, 63, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64,
+  64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64,
+];
+#[rustfmt::skip]
+const WEDGE_MASTER_OBLIQUE_ODD: [u8; MASK_MASTER_SIZE] = [
+   0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+   0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  1,  2,  6, 18,
+  37, 53, 60, 63, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64,
+  64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64,
+];
+#[rustfmt::skip]
+const WEDGE_MASTER_VERTICAL: [u8; MASK_MASTER_SIZE] = [
+   0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+   0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  2,  7, 21,
+  43, 57, 62, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64,
+  64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64, 64,
+];
+
+#[derive(Clone, Copy)]
+enum WedgeDirection {
+  Horizontal,
+  Vertical,
+  Oblique27,
+  Oblique63,
+  Oblique117,
+  Oblique153,
+}
+
+use WedgeDirection::*;
+
+const WEDGE_DIRECTIONS: usize = 6;
+
+/// A wedge of a codebook: the direction of its boundary and the position
+/// of its center, in eighths of the block width and height.
+type WedgeCode = (WedgeDirection, usize, usize);
+
+/// Codebook of the blocks taller than wide.
+const WEDGE_CODEBOOK_HGTW: [WedgeCode; WEDGE_TYPES] = [
+  (Oblique27, 4, 4),
+  (Oblique63, 4, 4),
+  (Oblique117, 4, 4),
+  (Oblique153, 4, 4),
+  (Horizontal, 4, 2),
+  (Horizontal, 4, 4),
+  (Horizontal, 4, 6),
+  (Vertical, 4, 4),
+  (Oblique27, 4, 2),
+  (Oblique27, 4, 6),
+  (Oblique153, 4, 2),
+  (Oblique153, 4, 6),
+  (Oblique63, 2, 4),
+  (Oblique63, 6, 4),
+  (Oblique117, 2, 4),
+  (Oblique117, 6, 4),
+];
+
+/// Codebook of the blocks wider than tall.
+const WEDGE_CODEBOOK_HLTW: [WedgeCode; WEDGE_TYPES] = [
+  (Oblique27, 4, 4),
+  (Oblique63, 4, 4),
+  (Oblique117, 4, 4),
+  (Oblique153, 4, 4),
+  (Vertical, 2, 4),
+  (Vertical, 4, 4),
+  (Vertical, 6, 4),
+  (Horizontal, 4, 4),
+  (Oblique27, 4, 2),
+  (Oblique27, 4, 6),
+  (Oblique153, 4, 2),
+  (Oblique153, 4, 6),
+  (Oblique63, 2, 4),
+  (Oblique63, 6, 4),
+  (Oblique117, 2, 4),
+  (Oblique117, 6, 4),
+];
+
+/// Codebook of the square blocks.
+const WEDGE_CODEBOOK_HEQW: [WedgeCode; WEDGE_TYPES] = [
+  (Oblique27, 4, 4),
+  (Oblique63, 4, 4),
+  (Oblique117, 4, 4),
+  (Oblique153, 4, 4),
+  (Horizontal, 4, 2),
+  (Horizontal, 4, 6),
+  (Vertical, 2, 4),
+  (Vertical, 6, 4),
+  (Oblique27, 4, 2),
+  (Oblique27, 4, 6),
+  (Oblique153, 4, 2),
+  (Oblique153, 4, 6),
+  (Oblique63, 2, 4),
+  (Oblique63, 6, 4),
+  (Oblique117, 2, 4),
+  (Oblique117, 6, 4),
+];
+
+/// Block sizes that can be predicted along a wedge.
+const WEDGE_BLOCK_SIZES: [BlockSize; 9] = [
+  BlockSize::BLOCK_8X8,
+  BlockSize::BLOCK_8X16,
+  BlockSize::BLOCK_16X8,
+  BlockSize::BLOCK_16X16,
+  BlockSize::BLOCK_16X32,
+  BlockSize::BLOCK_32X16,
+  BlockSize::BLOCK_32X32,
+  BlockSize::BLOCK_8X32,
+  BlockSize::BLOCK_32X8,
+];
+
+/// The masks of each wedge block size, by sign then wedge index.
+static WEDGE_MASKS: OnceLock<[Box<[u8]>; WEDGE_BLOCK_SIZES.len()]> =
+  OnceLock::new();
+
+fn init_wedge_masks() -> [Box<[u8]>; WEDGE_BLOCK_SIZES.len()] {
+  const N: usize = MASK_MASTER_SIZE;
+  let mut master = vec![[[0u8; N]; N]; WEDGE_DIRECTIONS];
+
+  let mut shift = N as isize / 4;
+  for i in (0..N).step_by(2) {
+    for j in 0..N {
+      let shifted =
+        |shift: isize| (j as isize - shift).clamp(0, N as isize - 1);
+      master[Oblique63 as usize][i][j] =
+        WEDGE_MASTER_OBLIQUE_EVEN[shifted(shift) as usize];
+      master[Oblique63 as usize][i + 1][j] =
+        WEDGE_MASTER_OBLIQUE_ODD[shifted(shift - 1) as usize];
+      master[Vertical as usize][i][j] = WEDGE_MASTER_VERTICAL[j];
+      master[Vertical as usize][i + 1][j] = WEDGE_MASTER_VERTICAL[j];
+    }
+    shift -= 1;
+  }
+  for i in 0..N {
+    for j in 0..N {
+      let msk = master[Oblique63 as usize][i][j];
+      master[Oblique27 as usize][j][i] = msk;
+      master[Oblique117 as usize][i][N - 1 - j] = 64 - msk;
+      master[Oblique153 as usize][N - 1 - j][i] = 64 - msk;
+      master[Horizontal as usize][j][i] = master[Vertical as usize][i][j];
+    }
+  }
+
+  WEDGE_BLOCK_SIZES.map(|bsize| {
+    let (w, h) = (bsize.width(), bsize.height());
+    let codebook = match w.cmp(&h) {
+      Ordering::Less => &WEDGE_CODEBOOK_HGTW,
+      Ordering::Greater => &WEDGE_CODEBOOK_HLTW,
+      Ordering::Equal => &WEDGE_CODEBOOK_HEQW,
+    };
+    let mut masks = vec![0u8; 2 * WEDGE_TYPES * w * h];
+    let (sign0, sign1) = masks.split_at_mut(WEDGE_TYPES * w * h);
+    for (index, &(dir, x, y)) in codebook.iter().enumerate() {
+      let xoff = N / 2 - ((x * w) >> 3);
+      let yoff = N / 2 - ((y * h) >> 3);
+      let master = &master[dir as usize][yoff..yoff + h];
+      // The sign is flipped so that the first prediction is weighted more
+      // along the top and left edges with sign 0.
+      let sum =
+        master[0][xoff..xoff + w].iter().map(|&m| m as usize).sum::<usize>()
+          + master[1..].iter().map(|row| row[xoff] as usize).sum::<usize>();
+      let avg = (sum + (w + h - 1) / 2) / (w + h - 1);
+      let (pos, neg) = if avg < 32 {
+        (&mut *sign1, &mut *sign0)
+      } else {
+        (&mut *sign0, &mut *sign1)
+      };
+      let range = index * w * h..(index + 1) * w * h;
+      for ((row, pos), neg) in master
+        .iter()
+        .zip(pos[range.clone()].chunks_exact_mut(w))
+        .zip(neg[range].chunks_exact_mut(w))
+      {
+        for ((&m, p), n) in row[xoff..xoff + w].iter().zip(pos).zip(neg) {
+          *p = m;
+          *n = 64 - m;
+        }
+      }
+    }
+    masks.into_boxed_slice()
+  })
+}
+
+/// Whether blocks of `bsize` can be predicted along a wedge.
+pub fn wedge_allowed(bsize: BlockSize) -> bool {
+  WEDGE_BLOCK_SIZES.contains(&bsize)
+}
+
+/// Returns the weights of the first prediction of a `bsize` block split
+/// along wedge `index`, in units of 1/64, with a stride of the block width.
+/// `sign` selects the complementary weights.
+///
+/// # Panics
+///
+/// - If `bsize` cannot be predicted along a wedge
+pub fn wedge_mask(
+  bsize: BlockSize, sign: bool, index: usize,
+) -> &'static [u8] {
+  let masks = WEDGE_MASKS.get_or_init(init_wedge_masks);
+  let i = WEDGE_BLOCK_SIZES.iter().position(|&b| b == bsize).unwrap();
+  let len = bsize.area();
+  let start = (sign as usize * WEDGE_TYPES + index) * len;
+  &masks[i][start..start + len]
+}
+
+/// Weights of the intra prediction of a smooth inter-intra block, in units
+/// of 1/64, by distance from its edge scaled to 128 samples.
+#[rustfmt::skip]
+const II_WEIGHTS_1D: [u8; 128] = [
+  60, 58, 56, 54, 52, 50, 48, 47, 45, 44, 42, 41, 39, 38, 37, 35,
+  34, 33, 32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 22, 21, 20,
+  19, 19, 18, 18, 17, 16, 16, 15, 15, 14, 14, 13, 13, 12, 12, 12,
+  11, 11, 10, 10, 10,  9,  9,  9,  8,  8,  8,  8,  7,  7,  7,  7,
+   6,  6,  6,  6,  6,  5,  5,  5,  5,  5,  4,  4,  4,  4,  4,  4,
+   4,  4,  3,  3,  3,  3,  3,  3,  3,  3,  3,  2,  2,  2,  2,  2,
+   2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  1,  1,  1,  1,  1,  1,
+   1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,
+];
+
+/// Fills `mask` with the weights of the intra prediction of a `width` by
+/// `height` block smoothly blended with its inter prediction, in units of
+/// 1/64, with a stride of `width`. The intra prediction fades away from
+/// the edges it is predicted from.
+pub fn interintra_smooth_mask(
+  mask: &mut [u8], mode: InterIntraMode, width: usize, height: usize,
+) {
+  let scale = 128 / width.max(height);
+  for (i, row) in mask.chunks_exact_mut(width).take(height).enumerate() {
+    for (j, m) in row.iter_mut().enumerate() {
+      *m = match mode {
+        InterIntraMode::II_V_PRED => II_WEIGHTS_1D[i * scale],
+        InterIntraMode::II_H_PRED => II_WEIGHTS_1D[j * scale],
+        InterIntraMode::II_SMOOTH_PRED => II_WEIGHTS_1D[i.min(j) * scale],
+        _ => 32,
+      };
+    }
+  }
+}
+
+/// Fills `mask` with the weights of the intermediate prediction `tmp1`
+/// against `tmp2`, in units of 1/64, growing with their difference. With
+/// `inverse`, the weights of `tmp2` are grown instead.
+pub fn diffwtd_mask(
+  mask: &mut [u8], tmp1: &[i16], tmp2: &[i16], width: usize, height: usize,
+  inverse: bool, bit_depth: usize,
+) {
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let shift = bit_depth - 8 + intermediate_bits;
+  for ((m, &t1), &t2) in
+    mask.iter_mut().zip(tmp1).zip(tmp2).take(width * height)
+  {
+    let diff = (t1 as i32 - t2 as i32).abs();
+    let weight = (38 + round_shift(diff, shift) / 16).min(64) as u8;
+    *m = if inverse { 64 - weight } else { weight };
+  }
+}
+
+/// Subsamples the `mask` of a luma block into `dst`, `width` by `height`,
+/// for a chroma plane decimated by `xdec` and `ydec`.
+pub fn subsample_mask(
+  dst: &mut [u8], mask: &[u8], width: usize, height: usize, xdec: usize,
+  ydec: usize,
+) {
+  let stride = width << xdec;
+  for (r, row) in dst.chunks_exact_mut(width).take(height).enumerate() {
+    for (c, d) in row.iter_mut().enumerate() {
+      let sum: u32 = (0..1 << ydec)
+        .flat_map(|y| (0..1 << xdec).map(move |x| (y, x)))
+        .map(|(y, x)| {
+          mask[((r << ydec) + y) * stride + (c << xdec) + x] as u32
+        })
+        .sum();
+      *d = round_shift(sum as i32, xdec + ydec) as u8;
+    }
+  }
+}
+
+/// Blends the intermediate predictions `tmp1` and `tmp2` into `dst`,
+/// weighting `tmp1` by `mask` in units of 1/64. `mask` has a stride of
+/// `width`.
+pub fn mc_mask<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp1: &[i16], tmp2: &[i16], mask: &[u8],
+  width: usize, height: usize, bit_depth: usize,
+) {
+  let max_sample_val = (1 << bit_depth) - 1;
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let prep_bias = if bit_depth == 8 { 0 } else { rust::PREP_BIAS * 64 };
+  for r in 0..height {
+    let dst_slice = &mut dst[r][..width];
+    let range = r * width..(r + 1) * width;
+    for (((d, &t1), &t2), &m) in dst_slice
+      .iter_mut()
+      .zip(&tmp1[range.clone()])
+      .zip(&tmp2[range.clone()])
+      .zip(&mask[range])
+    {
+      let m = m as i32;
+      *d = T::cast_from(
+        round_shift(
+          m * t1 as i32 + (64 - m) * t2 as i32 + prep_bias,
+          6 + intermediate_bits,
+        )
+        .clamp(0, max_sample_val),
+      );
+    }
+  }
+}
+
+/// Blends the intermediate predictions `tmp1` and `tmp2` into `dst`,
+/// weighting `tmp1` by `weight` in units of 1/16.
+pub fn mc_weighted_avg<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp1: &[i16], tmp2: &[i16], weight: u8,
+  width: usize, height: usize, bit_depth: usize,
+) {
+  let max_sample_val = (1 << bit_depth) - 1;
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let prep_bias = if bit_depth == 8 { 0 } else { rust::PREP_BIAS * 16 };
+  let weight = weight as i32;
+  for r in 0..height {
+    let dst_slice = &mut dst[r][..width];
+    let range = r * width..(r + 1) * width;
+    for ((d, &t1), &t2) in
+      dst_slice.iter_mut().zip(&tmp1[range.clone()]).zip(&tmp2[range])
+    {
+      *d = T::cast_from(
+        round_shift(
+          weight * t1 as i32 + (16 - weight) * t2 as i32 + prep_bias,
+          4 + intermediate_bits,
+        )
+        .clamp(0, max_sample_val),
+      );
+    }
+  }
+}
+
+/// Blends the prediction `tmp` into `dst`, weighting `dst` by `mask` in
+/// units of 1/64. `tmp` and `mask` have a stride of `width`.
+pub fn blend_mask<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp: &[T], mask: &[u8], width: usize,
+  height: usize,
+) {
+  for ((tmp_row, mask_row), r) in
+    tmp.chunks(width).zip(mask.chunks(width)).zip(0..height)
+  {
+    for ((d, &t), &m) in dst[r][..width].iter_mut().zip(tmp_row).zip(mask_row)
+    {
+      let m = m as i32;
+      *d = T::cast_from(round_shift(
+        m * Pixel::to_i32(*d) + (64 - m) * Pixel::to_i32(t),
+        6,
+      ));
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn wedge_masks_are_complementary() {
+    for bsize in WEDGE_BLOCK_SIZES {
+      for index in 0..WEDGE_TYPES {
+        let mask0 = wedge_mask(bsize, false, index);
+        let mask1 = wedge_mask(bsize, true, index);
+        assert_eq!(mask0.len(), bsize.area());
+        assert!(mask0.iter().zip(mask1).all(|(&m0, &m1)| m0 + m1 == 64));
+      }
+    }
+  }
+
+  /// Test the vertical scaling of a reference twice as tall as the frame,
+  /// from which each row is predicted halfway between two reference rows.
+  #[test]
+  fn scaled_prediction_from_taller_reference() {
+    use crate::frame::Frame;
+    use v_frame::chroma::ChromaSubsampling;
+
+    let mut src = Frame::<u8>::new(16, 32, ChromaSubsampling::Monochrome);
+    for (y, row) in src.y_plane.rows_mut().enumerate() {
+      row.fill((y * 4) as u8);
+    }
+    let pos = ScaledPosition::new(
+      16,
+      32,
+      16,
+      16,
+      PlaneOffset { x: 0, y: 0 },
+      MotionVector::default(),
+      0,
+      0,
+    );
+    assert_eq!(pos.x_step, 1 << SCALE_SUBPEL_BITS);
+    assert_eq!(pos.y_step, 2 << SCALE_SUBPEL_BITS);
+
+    let mut dst = Frame::<u8>::new(16, 16, ChromaSubsampling::Monochrome);
+    put_8tap_scaled(
+      &mut dst.y_plane.as_region_mut(),
+      &src.y_plane,
+      16,
+      32,
+      pos,
+      8,
+      8,
+      FilterMode::REGULAR,
+      FilterMode::REGULAR,
+      8,
+    );
+    // Row r lies at 2r + 0.5 in the reference, whose ramp the symmetric
+    //  half-sample filter reproduces away from the clamped top edge.
+    for r in 2..8 {
+      let row = &dst.y_plane.row(r).unwrap()[..8];
+      let expected = (8 * r + 2) as i32;
+      assert!(row.iter().all(|&v| (v as i32 - expected).abs() <= 1));
+    }
+  }
 }
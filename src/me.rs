@@ -17,6 +17,7 @@ use crate::frame::*;
 use crate::mc::MotionVector;
 use crate::partition::*;
 use crate::predict::PredictionMode;
+use crate::superres::resample_grid;
 use crate::tiling::*;
 use crate::util::ILog;
 use crate::util::{clamp, Pixel};
@@ -64,6 +65,23 @@ impl FrameMEStats {
       rows,
     }
   }
+  /// Returns the statistics resampled to a frame `cols` by `rows` blocks,
+  /// with the motion vectors scaled to its size.
+  pub fn resampled(&self, cols: usize, rows: usize) -> Self {
+    let scale = |v: i16, dst: usize, src: usize| {
+      (v as i32 * dst as i32 / src as i32) as i16
+    };
+    let mut stats =
+      resample_grid(&self.stats, self.cols, self.rows, cols, rows);
+    for s in stats.iter_mut() {
+      s.mv = MotionVector {
+        row: scale(s.mv.row, rows, self.rows),
+        col: scale(s.mv.col, cols, self.cols),
+      };
+    }
+    Self { stats, cols, rows }
+  }
+
   pub fn new_arc_array(cols: usize, rows: usize) -> RefMEStats {
     Arc::new(RwLock::new([
       FrameMEStats::new(cols, rows),
@@ -567,7 +585,7 @@ where
       (mvx_min >> ssdec, mvx_max >> ssdec, mvy_min >> ssdec, mvy_max >> ssdec);
     let po = PlaneOffset { x: po.x >> ssdec, y: po.y >> ssdec };
     let p_ref = match ssdec {
-      0 => &rec.frame.y_plane,
+      0 => rec.scaled_y_plane.as_deref().unwrap_or(&rec.frame.y_plane),
       1 => &rec.input_hres,
       2 => &rec.input_qres,
       _ => unimplemented!(),
@@ -624,10 +642,12 @@ where
         );
       }
 
-      sub_pixel_me(
-        fi, po, org_region, p_ref, lambda, pmv, mvx_min, mvx_max, mvy_min,
-        mvy_max, w, h, use_satd, &mut best, ref_frame,
-      );
+      if fi.force_integer_mv == 0 {
+        sub_pixel_me(
+          fi, po, org_region, p_ref, lambda, pmv, mvx_min, mvx_max, mvy_min,
+          mvy_max, w, h, use_satd, &mut best, ref_frame,
+        );
+      }
     }
 
     // Scale motion vectors to full res size
@@ -661,7 +681,7 @@ where
       (mvx_min >> ssdec, mvx_max >> ssdec, mvy_min >> ssdec, mvy_max >> ssdec);
     let po = PlaneOffset { x: po.x >> ssdec, y: po.y >> ssdec };
     let p_ref = match ssdec {
-      0 => &rec.frame.y_plane,
+      0 => rec.scaled_y_plane.as_deref().unwrap_or(&rec.frame.y_plane),
       1 => &rec.input_hres,
       2 => &rec.input_qres,
       _ => unimplemented!(),
@@ -1473,9 +1493,17 @@ where
     TileRect { x: 0, y: 0, width: tmp_width, height: tmp_height };
 
   PredictionMode::NEWMV.predict_inter_single(
-    fi, tile_rect, 0, po, tmp_region,
+    fi,
+    tile_rect,
+    0,
+    po,
+    tmp_region,
     // motion comp's w & h on edges can be different than distortion's
-    tmp_width, tmp_height, ref_frame, cand_mv,
+    tmp_width,
+    tmp_height,
+    ref_frame,
+    cand_mv,
+    [fi.default_filter; 2],
   );
   let plane_ref = tmp_region.as_const();
   compute_mv_rd(
@@ -0,0 +1,269 @@
+// Copyright (c) 2025, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use crate::partition::BlockSize;
+use crate::tiling::{PlaneRegion, PlaneRegionMut};
+use crate::util::{kmeans, Pixel};
+use arrayvec::ArrayVec;
+use itertools::Itertools;
+
+pub const PALETTE_MIN_SIZE: usize = 2;
+pub const PALETTE_MAX_SIZE: usize = 8;
+
+/// Blocks with more distinct colors than this are not considered for
+/// palette coding.
+const PALETTE_MAX_SOURCE_COLORS: usize = 64;
+
+/// Palette prediction parameters of a block.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaletteParams {
+  /// Number of luma and chroma palette colors, zero if palette is unused.
+  pub size: [u8; 2],
+  /// Colors of each plane, in ascending order for the Y and U planes.
+  /// The V colors are paired with the U color of the same index.
+  pub colors: [[u16; PALETTE_MAX_SIZE]; 3],
+}
+
+impl PaletteParams {
+  /// Sets the luma palette to the given colors, in ascending order.
+  pub fn set_luma(&mut self, colors: &[u16]) {
+    debug_assert!(colors.windows(2).all(|c| c[0] < c[1]));
+    self.size[0] = colors.len() as u8;
+    self.colors[0][..colors.len()].copy_from_slice(colors);
+  }
+
+  /// Sets the chroma palette to the given (U, V) pairs, in ascending order.
+  pub fn set_chroma(&mut self, colors: &[(u16, u16)]) {
+    debug_assert!(colors.windows(2).all(|c| c[0] <= c[1]));
+    self.size[1] = colors.len() as u8;
+    for (i, &(u, v)) in colors.iter().enumerate() {
+      self.colors[1][i] = u;
+      self.colors[2][i] = v;
+    }
+  }
+
+  /// Number of colors in the palette of the given plane.
+  #[inline]
+  pub const fn plane_size(&self, plane: usize) -> usize {
+    self.size[(plane > 0) as usize] as usize
+  }
+}
+
+/// Returns the dimensions of the color index map of a plane for a block,
+/// followed by the dimensions of its visible part, given the visible luma
+/// dimensions.
+pub fn palette_map_size(
+  bsize: BlockSize, visible_w: usize, visible_h: usize, xdec: usize,
+  ydec: usize,
+) -> (usize, usize, usize, usize) {
+  let (mut w, mut h) = (bsize.width() >> xdec, bsize.height() >> ydec);
+  let mut visible_w = visible_w.min(bsize.width()) >> xdec;
+  let mut visible_h = visible_h.min(bsize.height()) >> ydec;
+  // 4xN and Nx4 blocks share a chroma map with their neighbor.
+  if w < 4 {
+    w += 2;
+    visible_w += 2;
+  }
+  if h < 4 {
+    h += 2;
+    visible_h += 2;
+  }
+  (w, h, visible_w, visible_h)
+}
+
+/// Proposes luma palettes for the visible `width`x`height` pixels of `src`,
+/// from the most to the least number of colors.
+///
+/// Blocks with few distinct colors are represented exactly, others are
+/// clustered into fewer colors.
+pub fn luma_palette_candidates<T: Pixel>(
+  src: &PlaneRegion<'_, T>, width: usize, height: usize,
+) -> ArrayVec<ArrayVec<u16, PALETTE_MAX_SIZE>, 4> {
+  let mut data = Vec::with_capacity(width * height);
+  for row in src.rows_iter().take(height) {
+    data.extend(row[..width].iter().map(|&p| p.to_u16()));
+  }
+  data.sort_unstable();
+
+  let mut distinct = data.clone();
+  distinct.dedup();
+
+  let mut candidates = ArrayVec::new();
+  if distinct.len() < PALETTE_MIN_SIZE
+    || distinct.len() > PALETTE_MAX_SOURCE_COLORS
+  {
+    return candidates;
+  }
+  if distinct.len() <= PALETTE_MAX_SIZE {
+    candidates.push(distinct.iter().copied().collect());
+    return candidates;
+  }
+
+  let c: ([u16; 8], [u16; 6], [u16; 4], [u16; 2]) =
+    (kmeans(&data), kmeans(&data), kmeans(&data), kmeans(&data));
+  for centroids in [&c.0[..], &c.1[..], &c.2[..], &c.3[..]] {
+    let colors: ArrayVec<_, PALETTE_MAX_SIZE> =
+      centroids.iter().copied().dedup().collect();
+    if colors.len() >= PALETTE_MIN_SIZE {
+      candidates.push(colors);
+    }
+  }
+  candidates
+}
+
+/// Proposes a chroma palette for the visible `width`x`height` pixels of the
+/// `u` and `v` planes, as (U, V) pairs in ascending order.
+pub fn chroma_palette_candidate<T: Pixel>(
+  u: &PlaneRegion<'_, T>, v: &PlaneRegion<'_, T>, width: usize, height: usize,
+) -> Option<ArrayVec<(u16, u16), PALETTE_MAX_SIZE>> {
+  let mut data = Vec::with_capacity(width * height);
+  for (row_u, row_v) in u.rows_iter().zip(v.rows_iter()).take(height) {
+    data.extend(
+      row_u[..width]
+        .iter()
+        .zip(&row_v[..width])
+        .map(|(&u, &v)| (u.to_u16(), v.to_u16())),
+    );
+  }
+  data.sort_unstable();
+
+  let mut distinct = data.clone();
+  distinct.dedup();
+
+  if distinct.len() < PALETTE_MIN_SIZE
+    || distinct.len() > PALETTE_MAX_SOURCE_COLORS
+  {
+    return None;
+  }
+  if distinct.len() <= PALETTE_MAX_SIZE {
+    return Some(distinct.iter().copied().collect());
+  }
+
+  // Cluster the U values, then pair each cluster with its mean V value.
+  let data_u: Vec<u16> = data.iter().map(|&(u, _)| u).collect();
+  let centroids_u: [u16; PALETTE_MAX_SIZE] = kmeans(&data_u);
+  let mut sum_v = [0u32; PALETTE_MAX_SIZE];
+  let mut count = [0u32; PALETTE_MAX_SIZE];
+  for &(u, v) in &data {
+    let i = nearest_color(&[&centroids_u], &[u]) as usize;
+    sum_v[i] += v as u32;
+    count[i] += 1;
+  }
+  let mut colors: ArrayVec<_, PALETTE_MAX_SIZE> = centroids_u
+    .iter()
+    .zip(sum_v.iter().zip(&count))
+    .filter(|(_, (_, &n))| n > 0)
+    .map(|(&u, (&s, &n))| (u, ((s + n / 2) / n) as u16))
+    .collect();
+  colors.sort_unstable();
+  let colors: ArrayVec<_, PALETTE_MAX_SIZE> =
+    colors.into_iter().dedup().collect();
+  (colors.len() >= PALETTE_MIN_SIZE).then_some(colors)
+}
+
+/// Index of the palette color nearest to a pixel, in squared error over
+/// all planes. Ties are resolved towards the lower index.
+#[inline]
+fn nearest_color(colors: &[&[u16]], px: &[u16]) -> u8 {
+  let mut best = (u32::MAX, 0);
+  for i in 0..colors[0].len() {
+    let dist: u32 = colors
+      .iter()
+      .zip(px)
+      .map(|(c, &p)| (c[i] as i32 - p as i32).pow(2) as u32)
+      .sum();
+    if dist < best.0 {
+      best = (dist, i as u8);
+    }
+  }
+  best.1
+}
+
+/// Computes the color indices of the `width`x`height` pixels starting at
+/// (`x0`, `y0`) in a block, given the source planes sharing the palette
+/// starting at the block origin.
+///
+/// Pixels beyond the `visible_w`x`visible_h` area repeat the last visible
+/// column and row, the same way the decoder extends the color index map.
+pub fn palette_color_map<T: Pixel>(
+  src: &[PlaneRegion<'_, T>], colors: &[&[u16]], x0: usize, y0: usize,
+  width: usize, height: usize, visible_w: usize, visible_h: usize,
+  map: &mut [u8],
+) {
+  debug_assert!(src.len() == colors.len());
+  let mut px = [0u16; 2];
+  for y in 0..height {
+    let sy = (y0 + y).min(visible_h - 1);
+    for x in 0..width {
+      let sx = (x0 + x).min(visible_w - 1);
+      for (p, s) in px.iter_mut().zip(src) {
+        *p = s[sy][sx].to_u16();
+      }
+      map[y * width + x] = nearest_color(colors, &px[..src.len()]);
+    }
+  }
+}
+
+/// Fills a transform block with the palette colors given by its color
+/// index map.
+pub fn predict_palette<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, colors: &[u16], map: &[u8], width: usize,
+  height: usize,
+) {
+  for (row, map) in
+    dst.rows_iter_mut().zip(map.chunks_exact(width)).take(height)
+  {
+    for (p, &idx) in row[..width].iter_mut().zip(map) {
+      *p = T::cast_from(colors[idx as usize] as i32);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::frame::{AsRegion, Frame, FrameAlloc};
+  use v_frame::chroma::ChromaSubsampling;
+
+  #[test]
+  fn exact_palette_for_few_colors() {
+    let mut frame = Frame::<u8>::new(8, 8, ChromaSubsampling::Yuv420);
+    for (y, row) in frame.y_plane.rows_mut().enumerate() {
+      for (x, p) in row.iter_mut().enumerate() {
+        *p = [16, 235, 128][(x + y) % 3];
+      }
+    }
+    let candidates = luma_palette_candidates(&frame.y_plane.as_region(), 8, 8);
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(&candidates[0][..], &[16, 128, 235]);
+  }
+
+  #[test]
+  fn color_map_repeats_visible_edge() {
+    let mut frame = Frame::<u8>::new(8, 8, ChromaSubsampling::Yuv420);
+    for (y, row) in frame.y_plane.rows_mut().enumerate() {
+      for (x, p) in row.iter_mut().enumerate() {
+        *p = if x == 1 && y < 2 { 200 } else { 10 };
+      }
+    }
+    let mut map = [0; 16];
+    palette_color_map(
+      &[frame.y_plane.as_region()],
+      &[&[10, 200]],
+      0,
+      0,
+      4,
+      4,
+      2,
+      2,
+      &mut map,
+    );
+    assert_eq!(map, [0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1]);
+  }
+}
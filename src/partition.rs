@@ -225,11 +225,27 @@ impl BlockSize {
   }
 
   #[inline]
-  pub fn cfl_allowed(self) -> bool {
+  pub fn cfl_allowed(self, lossless: bool, xdec: usize, ydec: usize) -> bool {
+    if lossless {
+      // Lossless blocks only allow CfL with a single 4x4 chroma transform
+      return self
+        .subsampled_size(xdec, ydec)
+        .is_ok_and(|bs| bs == BlockSize::BLOCK_4X4);
+    }
     // TODO: fix me when enabling EXT_PARTITION_TYPES
     self <= BlockSize::BLOCK_32X32
   }
 
+  #[inline]
+  pub fn palette_allowed(self) -> bool {
+    self >= BlockSize::BLOCK_8X8 && self.width() <= 64 && self.height() <= 64
+  }
+
+  #[inline]
+  pub fn interintra_allowed(self) -> bool {
+    self >= BlockSize::BLOCK_8X8 && self <= BlockSize::BLOCK_32X32
+  }
+
   #[inline]
   pub const fn width(self) -> usize {
     1 << self.width_log2()
@@ -643,6 +659,7 @@ pub fn get_intra_edges<'a, T: Pixel>(
   bx: usize,
   by: usize,
   partition_size: BlockSize, // partition size, BlockSize
+  sb_size_log2: usize,
   po: PlaneOffset,
   tx_size: TxSize,
   bit_depth: usize,
@@ -795,6 +812,7 @@ pub fn get_intra_edges<'a, T: Pixel>(
         && has_top_right(
           scaled_partition_size,
           partition_bo,
+          sb_size_log2,
           have_top,
           right_available,
           tx_size,
@@ -840,6 +858,7 @@ pub fn get_intra_edges<'a, T: Pixel>(
         && has_bottom_left(
           scaled_partition_size,
           partition_bo,
+          sb_size_log2,
           bottom_available,
           have_left,
           tx_size,
@@ -902,10 +921,12 @@ pub fn get_intra_edges<'a, T: Pixel>(
   IntraEdge::new(edge_buf, init_left, init_above)
 }
 
-pub fn has_tr(bo: TileBlockOffset, bsize: BlockSize) -> bool {
-  let sb_mi_size = BLOCK_64X64.width_mi(); /* Assume 64x64 for now */
-  let mask_row = bo.0.y & LOCAL_BLOCK_MASK;
-  let mask_col = bo.0.x & LOCAL_BLOCK_MASK;
+pub fn has_tr(
+  bo: TileBlockOffset, bsize: BlockSize, sb_size_log2: usize,
+) -> bool {
+  let sb_mi_size = 1 << (sb_size_log2 - MI_SIZE_LOG2);
+  let mask_row = bo.0.y & (sb_mi_size - 1);
+  let mask_col = bo.0.x & (sb_mi_size - 1);
   let target_n4_w = bsize.width_mi();
   let target_n4_h = bsize.height_mi();
 
@@ -959,10 +980,12 @@ pub fn has_tr(bo: TileBlockOffset, bsize: BlockSize) -> bool {
   has_tr
 }
 
-pub fn has_bl(bo: TileBlockOffset, bsize: BlockSize) -> bool {
-  let sb_mi_size = BLOCK_64X64.width_mi(); /* Assume 64x64 for now */
-  let mask_row = bo.0.y & LOCAL_BLOCK_MASK;
-  let mask_col = bo.0.x & LOCAL_BLOCK_MASK;
+pub fn has_bl(
+  bo: TileBlockOffset, bsize: BlockSize, sb_size_log2: usize,
+) -> bool {
+  let sb_mi_size = 1 << (sb_size_log2 - MI_SIZE_LOG2);
+  let mask_row = bo.0.y & (sb_mi_size - 1);
+  let mask_col = bo.0.x & (sb_mi_size - 1);
   let target_n4_w = bsize.width_mi();
   let target_n4_h = bsize.height_mi();
 
@@ -26,9 +26,12 @@ cfg_if::cfg_if! {
 use aligned_vec::{avec, ABox};
 use v_frame::chroma::ChromaSubsampling;
 
-use crate::context::{TileBlockOffset, MAX_SB_SIZE_LOG2, MAX_TX_SIZE};
+use crate::context::{
+  ObmcNeighbor, TileBlockOffset, MAX_SB_SIZE_LOG2, MAX_TX_SIZE, MI_SIZE,
+};
 use crate::cpu_features::CpuFeatureLevel;
-use crate::encoder::FrameInvariants;
+use crate::encoder::{FrameInvariants, ReferenceFrame};
+use crate::global_motion::is_global_warp;
 use crate::mc::*;
 use crate::partition::*;
 use crate::tiling::*;
@@ -56,6 +59,21 @@ pub static RAV1E_INTRA_MODES: &[PredictionMode] = &[
   PredictionMode::D67_PRED,
 ];
 
+pub static RAV1E_FILTER_INTRA_MODES: &[FilterIntraMode] = &[
+  FilterIntraMode::FILTER_DC_PRED,
+  FilterIntraMode::FILTER_V_PRED,
+  FilterIntraMode::FILTER_H_PRED,
+  FilterIntraMode::FILTER_D157_PRED,
+  FilterIntraMode::FILTER_PAETH_PRED,
+];
+
+pub static RAV1E_INTERINTRA_MODES: &[InterIntraMode] = &[
+  InterIntraMode::II_DC_PRED,
+  InterIntraMode::II_V_PRED,
+  InterIntraMode::II_H_PRED,
+  InterIntraMode::II_SMOOTH_PRED,
+];
+
 pub static RAV1E_INTER_MODES_MINIMAL: &[PredictionMode] =
   &[PredictionMode::NEARESTMV];
 
@@ -302,8 +320,21 @@ impl PredictionMode {
     )
   }
 
+  /// Whether the block is predicted by warping `ref_frame` with its global
+  /// motion.
+  fn is_global_warp<T: Pixel>(
+    self, fi: &FrameInvariants<T>, ref_frame: RefType, width: usize,
+    height: usize,
+  ) -> bool {
+    matches!(self, PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV)
+      && is_global_warp(fi, ref_frame, width, height)
+  }
+
   /// Inter prediction with a single reference (i.e. not compound mode)
   ///
+  /// `interp_filter` holds the vertical and horizontal interpolation
+  /// filters, in the order they are coded.
+  ///
   /// # Panics
   ///
   /// - If called on an intra `PredictionMode`
@@ -311,20 +342,51 @@ impl PredictionMode {
     self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
     po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
     height: usize, ref_frame: RefType, mv: MotionVector,
+    interp_filter: [FilterMode; 2],
   ) {
     assert!(!self.is_intra());
     let frame_po = tile_rect.to_frame_plane_offset(po);
 
-    let mode = fi.default_filter;
+    let [mode_y, mode_x] = interp_filter;
 
     if let Some(ref rec) =
       fi.rec_buffer.frames[fi.ref_frames[ref_frame.to_index()] as usize]
     {
-      let (row_frac, col_frac, src) = PredictionMode::get_mv_params(
-        &rec.frame.planes().nth(p).unwrap(),
-        frame_po,
-        mv,
-      );
+      let plane = &rec.frame.planes().nth(p).unwrap();
+      if self.is_global_warp(fi, ref_frame, width, height) {
+        let (frame_width, frame_height) = rec.plane_dimensions(p);
+        put_warp(
+          dst,
+          plane,
+          frame_width,
+          frame_height,
+          frame_po,
+          width,
+          height,
+          &fi.globalmv_params[ref_frame.to_index()],
+          fi.sequence.bit_depth,
+          fi.cpu_feature_level,
+        );
+        return;
+      }
+      if fi.is_scaled(ref_frame) {
+        let (plane_width, plane_height) = rec.plane_dimensions(p);
+        put_8tap_scaled(
+          dst,
+          plane,
+          plane_width,
+          plane_height,
+          scaled_position(fi, rec, p, frame_po, mv),
+          width,
+          height,
+          mode_x,
+          mode_y,
+          fi.sequence.bit_depth,
+        );
+        return;
+      }
+      let (row_frac, col_frac, src) =
+        PredictionMode::get_mv_params(plane, frame_po, mv);
       put_8tap(
         dst,
         src,
@@ -332,8 +394,42 @@ impl PredictionMode {
         height,
         col_frac,
         row_frac,
-        mode,
-        mode,
+        mode_x,
+        mode_y,
+        fi.sequence.bit_depth,
+        fi.cpu_feature_level,
+      );
+    }
+  }
+
+  /// Inter prediction with a single reference, warped by the local
+  /// `params` fitted to the motion of the neighboring blocks.
+  ///
+  /// # Panics
+  ///
+  /// - If called on an intra `PredictionMode`
+  pub fn predict_inter_warped<T: Pixel>(
+    self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
+    po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
+    height: usize, ref_frame: RefType, params: &[i32; 6],
+  ) {
+    assert!(!self.is_intra());
+    let frame_po = tile_rect.to_frame_plane_offset(po);
+
+    if let Some(ref rec) =
+      fi.rec_buffer.frames[fi.ref_frames[ref_frame.to_index()] as usize]
+    {
+      let plane = &rec.frame.planes().nth(p).unwrap();
+      let (frame_width, frame_height) = rec.plane_dimensions(p);
+      put_warp(
+        dst,
+        plane,
+        frame_width,
+        frame_height,
+        frame_po,
+        width,
+        height,
+        params,
         fi.sequence.bit_depth,
         fi.cpu_feature_level,
       );
@@ -349,22 +445,142 @@ impl PredictionMode {
     self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
     po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
     height: usize, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+    interp_filter: [FilterMode; 2], buffer: &mut InterCompoundBuffers,
+  ) {
+    self.prep_inter_compound(
+      fi,
+      tile_rect,
+      p,
+      po,
+      width,
+      height,
+      ref_frames,
+      mvs,
+      interp_filter,
+      buffer,
+    );
+    mc_avg(
+      dst,
+      buffer.get_buffer(0),
+      buffer.get_buffer(1),
+      width,
+      height,
+      fi.sequence.bit_depth,
+      fi.cpu_feature_level,
+    );
+  }
+
+  /// Compound inter prediction weighted by the distance of the two
+  /// references to the frame, or blended along a wedge or by the difference
+  /// between their predictions.
+  ///
+  /// The mask of a chroma block is subsampled from the one of the luma
+  /// block, which must be predicted first.
+  ///
+  /// # Panics
+  ///
+  /// - If called on an intra `PredictionMode`
+  /// - If `compound` is not weighted or blended through a mask
+  pub fn predict_inter_masked<T: Pixel>(
+    self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
+    po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
+    height: usize, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+    interp_filter: [FilterMode; 2], bsize: BlockSize,
+    compound: CompoundPrediction, buffer: &mut InterCompoundBuffers,
+  ) {
+    self.prep_inter_compound(
+      fi,
+      tile_rect,
+      p,
+      po,
+      width,
+      height,
+      ref_frames,
+      mvs,
+      interp_filter,
+      buffer,
+    );
+    let bit_depth = fi.sequence.bit_depth;
+    let (tmp1, tmp2) = buffer.data.split_at(InterCompoundBuffers::BUFFER_SIZE);
+    if compound == CompoundPrediction::Distance {
+      let [weight, _] = fi.distance_weights(ref_frames);
+      mc_weighted_avg(dst, tmp1, tmp2, weight, width, height, bit_depth);
+      return;
+    }
+    let (luma_mask, chroma_mask) =
+      buffer.mask.split_at_mut(InterCompoundBuffers::BUFFER_SIZE);
+    if p == 0 {
+      match compound {
+        CompoundPrediction::Wedge { index, sign } => {
+          let mask = wedge_mask(bsize, sign, index as usize);
+          luma_mask[..mask.len()].copy_from_slice(mask);
+        }
+        CompoundPrediction::DiffWeighted { inverse } => {
+          diffwtd_mask(
+            luma_mask, tmp1, tmp2, width, height, inverse, bit_depth,
+          );
+        }
+        _ => unreachable!(),
+      }
+      mc_mask(dst, tmp1, tmp2, luma_mask, width, height, bit_depth);
+    } else {
+      let PlaneConfig { xdec, ydec, .. } = dst.plane_cfg;
+      subsample_mask(chroma_mask, luma_mask, width, height, xdec, ydec);
+      mc_mask(dst, tmp1, tmp2, chroma_mask, width, height, bit_depth);
+    }
+  }
+
+  /// Interpolates both references of a compound block into `buffer`.
+  fn prep_inter_compound<T: Pixel>(
+    self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
+    po: PlaneOffset, width: usize, height: usize, ref_frames: [RefType; 2],
+    mvs: [MotionVector; 2], interp_filter: [FilterMode; 2],
     buffer: &mut InterCompoundBuffers,
   ) {
     assert!(!self.is_intra());
     let frame_po = tile_rect.to_frame_plane_offset(po);
 
-    let mode = fi.default_filter;
+    let [mode_y, mode_x] = interp_filter;
 
     for i in 0..2 {
       if let Some(ref rec) =
         fi.rec_buffer.frames[fi.ref_frames[ref_frames[i].to_index()] as usize]
       {
-        let (row_frac, col_frac, src) = PredictionMode::get_mv_params(
-          &rec.frame.planes().nth(p).unwrap(),
-          frame_po,
-          mvs[i],
-        );
+        let plane = &rec.frame.planes().nth(p).unwrap();
+        if self.is_global_warp(fi, ref_frames[i], width, height) {
+          let (frame_width, frame_height) = rec.plane_dimensions(p);
+          prep_warp(
+            buffer.get_buffer_mut(i),
+            plane,
+            frame_width,
+            frame_height,
+            frame_po,
+            width,
+            height,
+            &fi.globalmv_params[ref_frames[i].to_index()],
+            fi.sequence.bit_depth,
+            fi.cpu_feature_level,
+          );
+          continue;
+        }
+        if fi.is_scaled(ref_frames[i]) {
+          let (plane_width, plane_height) = rec.plane_dimensions(p);
+          prep_8tap_scaled(
+            buffer.get_buffer_mut(i),
+            plane,
+            plane_width,
+            plane_height,
+            scaled_position(fi, rec, p, frame_po, mvs[i]),
+            width,
+            height,
+            mode_x,
+            mode_y,
+            fi.sequence.bit_depth,
+          );
+          continue;
+        }
+        let (row_frac, col_frac, src) =
+          PredictionMode::get_mv_params(plane, frame_po, mvs[i]);
         prep_8tap(
           buffer.get_buffer_mut(i),
           src,
@@ -372,22 +588,13 @@ impl PredictionMode {
           height,
           col_frac,
           row_frac,
-          mode,
-          mode,
+          mode_x,
+          mode_y,
           fi.sequence.bit_depth,
           fi.cpu_feature_level,
         );
       }
     }
-    mc_avg(
-      dst,
-      buffer.get_buffer(0),
-      buffer.get_buffer(1),
-      width,
-      height,
-      fi.sequence.bit_depth,
-      fi.cpu_feature_level,
-    );
   }
 
   /// Inter prediction that determines whether compound mode is being used based
@@ -396,6 +603,7 @@ impl PredictionMode {
     self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
     po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
     height: usize, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+    interp_filter: [FilterMode; 2],
     compound_buffer: &mut InterCompoundBuffers,
   ) {
     let is_compound = ref_frames[1] != RefType::INTRA_FRAME
@@ -412,6 +620,7 @@ impl PredictionMode {
         height,
         ref_frames[0],
         mvs[0],
+        interp_filter,
       )
     } else {
       self.predict_inter_compound(
@@ -424,17 +633,232 @@ impl PredictionMode {
         height,
         ref_frames,
         mvs,
+        interp_filter,
         compound_buffer,
       );
     }
   }
 }
 
+/// Blends the predictions of the `above` and `left` neighbors of a block,
+/// extended over its edges, into its own inter prediction in `dst`
+/// (`OBMC_CAUSAL`).
+pub fn blend_obmc<T: Pixel>(
+  fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize, po: PlaneOffset,
+  dst: &mut PlaneRegionMut<'_, T>, bsize: BlockSize, above: &[ObmcNeighbor],
+  left: &[ObmcNeighbor],
+) {
+  let PlaneConfig { xdec, ydec, .. } = dst.plane_cfg;
+  let mut tmp = Aligned::new([T::cast_from(0); 64 * 32]);
+  let tmp = &mut tmp.data;
+
+  if bsize.subsampled_size(xdec, ydec).unwrap() >= BlockSize::BLOCK_8X8 {
+    let overlap = (bsize.height().min(64) >> 1) >> ydec;
+    for n in above {
+      let x = (n.offset * MI_SIZE) >> xdec;
+      let width = (n.len * MI_SIZE) >> xdec;
+      let po = PlaneOffset { x: po.x + x as isize, y: po.y };
+      predict_obmc_neighbor(fi, tile_rect, p, po, n, width, overlap, tmp);
+      blend_obmc_above(
+        &mut dst.subregion_mut(Area::StartingAt { x: x as isize, y: 0 }),
+        tmp,
+        width,
+        overlap,
+        fi.cpu_feature_level,
+      );
+    }
+  }
+
+  let overlap = (bsize.width().min(64) >> 1) >> xdec;
+  for n in left {
+    let y = (n.offset * MI_SIZE) >> ydec;
+    let height = (n.len * MI_SIZE) >> ydec;
+    let po = PlaneOffset { x: po.x, y: po.y + y as isize };
+    predict_obmc_neighbor(fi, tile_rect, p, po, n, overlap, height, tmp);
+    blend_obmc_left(
+      &mut dst.subregion_mut(Area::StartingAt { x: 0, y: y as isize }),
+      tmp,
+      overlap,
+      height,
+      fi.cpu_feature_level,
+    );
+  }
+}
+
+/// Predicts the `width` by `height` samples at `po` overlapped by the
+/// neighbor `n` into `tmp`. The motion of the neighbor is extended by
+/// translation, whatever its own prediction.
+fn predict_obmc_neighbor<T: Pixel>(
+  fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize, po: PlaneOffset,
+  n: &ObmcNeighbor, width: usize, height: usize, tmp: &mut [T],
+) {
+  let Some(ref rec) =
+    fi.rec_buffer.frames[fi.ref_frames[n.ref_frame.to_index()] as usize]
+  else {
+    return;
+  };
+  let plane = &rec.frame.planes().nth(p).unwrap();
+  let PlaneConfig { xdec, ydec, .. } = PlaneConfig::new(&plane.geometry());
+  let cfg = PlaneConfig {
+    stride: width,
+    alloc_height: height,
+    width,
+    height,
+    xdec,
+    ydec,
+    xorigin: 0,
+    yorigin: 0,
+  };
+  let rect = Rect { x: 0, y: 0, width, height };
+  let mut dst = PlaneRegionMut::from_slice(tmp, cfg, rect);
+  let frame_po = tile_rect.to_frame_plane_offset(po);
+  if fi.is_scaled(n.ref_frame) {
+    let (plane_width, plane_height) = rec.plane_dimensions(p);
+    put_8tap_scaled(
+      &mut dst,
+      plane,
+      plane_width,
+      plane_height,
+      scaled_position(fi, rec, p, frame_po, n.mv),
+      width,
+      height,
+      n.interp_filter[1],
+      n.interp_filter[0],
+      fi.sequence.bit_depth,
+    );
+    return;
+  }
+  let (row_frac, col_frac, src) =
+    PredictionMode::get_mv_params(plane, frame_po, n.mv);
+  put_8tap(
+    &mut dst,
+    src,
+    width,
+    height,
+    col_frac,
+    row_frac,
+    n.interp_filter[1],
+    n.interp_filter[0],
+    fi.sequence.bit_depth,
+    fi.cpu_feature_level,
+  );
+}
+
+/// Predicts the inter-intra block at `tile_bo` into the tile plane `rec`:
+/// blends the prediction of `ref_frame` with the intra prediction from the
+/// edges of the block, smoothly or along the wedge `wedge_index`.
+pub fn predict_inter_intra<T: Pixel>(
+  fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
+  rec: &mut PlaneRegionMut<'_, T>, tile_bo: TileBlockOffset, bsize: BlockSize,
+  luma_mode: PredictionMode, ref_frame: RefType, mv: MotionVector,
+  interp_filter: [FilterMode; 2], interintra_mode: InterIntraMode,
+  wedge_index: Option<u8>,
+) {
+  let PlaneConfig { xdec, ydec, .. } = rec.plane_cfg;
+  let plane_bsize = bsize.subsampled_size(xdec, ydec).unwrap();
+  let (width, height) = (plane_bsize.width(), plane_bsize.height());
+  let po = tile_bo.plane_offset(&rec.plane_cfg);
+  let area = Area::BlockStartingAt { bo: tile_bo.0 };
+  let bit_depth = fi.sequence.bit_depth;
+
+  let mut tmp = Aligned::new([T::cast_from(0); 32 * 32]);
+  let cfg = PlaneConfig {
+    stride: width,
+    alloc_height: height,
+    width,
+    height,
+    xdec,
+    ydec,
+    xorigin: 0,
+    yorigin: 0,
+  };
+  let rect = Rect { x: 0, y: 0, width, height };
+  luma_mode.predict_inter_single(
+    fi,
+    tile_rect,
+    p,
+    po,
+    &mut PlaneRegionMut::from_slice(&mut tmp.data, cfg, rect),
+    width,
+    height,
+    ref_frame,
+    mv,
+    interp_filter,
+  );
+
+  let mode = interintra_mode.intra_mode();
+  let tx_size = plane_bsize.tx_size();
+  let mut edge_buf = Aligned::uninit_array();
+  let edge_buf = get_intra_edges(
+    &mut edge_buf,
+    &rec.as_const(),
+    tile_bo,
+    0,
+    0,
+    bsize,
+    fi.sb_size_log2(),
+    po,
+    tx_size,
+    bit_depth,
+    Some(mode),
+    fi.sequence.enable_intra_edge_filter,
+    IntraParam::None,
+  );
+  let mut dst = rec.subregion_mut(area);
+  mode.predict_intra(
+    tile_rect,
+    &mut dst,
+    tx_size,
+    bit_depth,
+    &[],
+    IntraParam::None,
+    None,
+    &edge_buf,
+    fi.cpu_feature_level,
+  );
+
+  let mut mask = [0u8; 32 * 32];
+  match wedge_index {
+    Some(index) => subsample_mask(
+      &mut mask,
+      wedge_mask(bsize, false, index as usize),
+      width,
+      height,
+      xdec,
+      ydec,
+    ),
+    None => interintra_smooth_mask(&mut mask, interintra_mode, width, height),
+  }
+  blend_mask(&mut dst, &tmp.data, &mask, width, height);
+}
+
+/// Position in plane `p` of the reference `rec`, of another size than the
+/// frame, of the block at `frame_po` displaced by `mv`.
+fn scaled_position<T: Pixel>(
+  fi: &FrameInvariants<T>, rec: &ReferenceFrame<T>, p: usize,
+  frame_po: PlaneOffset, mv: MotionVector,
+) -> ScaledPosition {
+  let PlaneConfig { xdec, ydec, .. } =
+    PlaneConfig::new(&rec.frame.planes().nth(p).unwrap().geometry());
+  ScaledPosition::new(
+    rec.width as usize,
+    rec.height as usize,
+    fi.width,
+    fi.height,
+    frame_po,
+    mv,
+    xdec,
+    ydec,
+  )
+}
+
 /// A pair of buffers holding the interpolation of two references. Use for
 /// compound inter prediction.
 #[derive(Debug)]
 pub struct InterCompoundBuffers {
   data: ABox<[i16]>,
+  /// The blending masks of the luma and of a chroma plane of a block.
+  mask: Box<[u8]>,
 }
 
 impl InterCompoundBuffers {
@@ -463,7 +887,10 @@ impl InterCompoundBuffers {
 
 impl Default for InterCompoundBuffers {
   fn default() -> Self {
-    Self { data: avec![0; 2 * Self::BUFFER_SIZE].into_boxed_slice() }
+    Self {
+      data: avec![0; 2 * Self::BUFFER_SIZE].into_boxed_slice(),
+      mask: vec![0; 2 * Self::BUFFER_SIZE].into_boxed_slice(),
+    }
   }
 }
 
@@ -476,6 +903,18 @@ pub enum InterIntraMode {
   INTERINTRA_MODES,
 }
 
+impl InterIntraMode {
+  /// The intra mode blended with the inter prediction.
+  pub const fn intra_mode(self) -> PredictionMode {
+    match self {
+      InterIntraMode::II_V_PRED => PredictionMode::V_PRED,
+      InterIntraMode::II_H_PRED => PredictionMode::H_PRED,
+      InterIntraMode::II_SMOOTH_PRED => PredictionMode::SMOOTH_PRED,
+      _ => PredictionMode::DC_PRED,
+    }
+  }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
 pub enum CompoundType {
   COMPOUND_AVERAGE,
@@ -484,6 +923,58 @@ pub enum CompoundType {
   COMPOUND_TYPES,
 }
 
+/// How the prediction of an inter block is combined with a second one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CompoundPrediction {
+  /// The average of the two references of a compound block, or the single
+  /// reference of any other block.
+  #[default]
+  Average,
+  /// The two references of a compound block, weighted by their distance to
+  /// the frame.
+  Distance,
+  /// The two references of a compound block, split along a wedge.
+  Wedge { index: u8, sign: bool },
+  /// The two references of a compound block, weighted by the difference
+  /// between their predictions.
+  DiffWeighted { inverse: bool },
+  /// The single reference blended with an intra prediction, smoothly or
+  /// along a wedge.
+  InterIntra { mode: InterIntraMode, wedge_index: Option<u8> },
+}
+
+impl CompoundPrediction {
+  /// Whether a compound block is blended through a mask.
+  pub const fn comp_group_idx(self) -> usize {
+    matches!(
+      self,
+      CompoundPrediction::Wedge { .. }
+        | CompoundPrediction::DiffWeighted { .. }
+    ) as usize
+  }
+
+  /// Whether a compound block not blended through a mask is averaged
+  /// rather than weighted by distance.
+  pub const fn compound_idx(self) -> usize {
+    !matches!(self, CompoundPrediction::Distance) as usize
+  }
+
+  pub const fn is_interintra(self) -> bool {
+    matches!(self, CompoundPrediction::InterIntra { .. })
+  }
+
+  /// The compound type of a block blended through a mask.
+  pub const fn compound_type(self) -> CompoundType {
+    match self {
+      CompoundPrediction::Wedge { .. } => CompoundType::COMPOUND_WEDGE,
+      CompoundPrediction::DiffWeighted { .. } => {
+        CompoundType::COMPOUND_DIFFWTD
+      }
+      _ => CompoundType::COMPOUND_AVERAGE,
+    }
+  }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
 pub enum MotionMode {
   SIMPLE_TRANSLATION,
@@ -527,6 +1018,42 @@ pub enum FilterIntraMode {
   FILTER_INTRA_MODES,
 }
 
+impl FilterIntraMode {
+  /// The intra direction used in place of `DC_PRED` to select the luma
+  /// transform type context of blocks predicted with this filter.
+  pub const fn intra_dir(self) -> PredictionMode {
+    match self {
+      FilterIntraMode::FILTER_V_PRED => PredictionMode::V_PRED,
+      FilterIntraMode::FILTER_H_PRED => PredictionMode::H_PRED,
+      FilterIntraMode::FILTER_D157_PRED => PredictionMode::D157_PRED,
+      _ => PredictionMode::DC_PRED,
+    }
+  }
+
+  /// Predicts a luma transform block with the recursive intra filter.
+  ///
+  /// The edges must hold the left column, the top-left pixel and the
+  /// above row of the transform block.
+  pub fn predict_intra<T: Pixel>(
+    self, dst: &mut PlaneRegionMut<'_, T>, tx_size: TxSize, bit_depth: usize,
+    edge_buf: &IntraEdge<T>,
+  ) {
+    let width = tx_size.width();
+    let height = tx_size.height();
+    let (left, top_left, above) = edge_buf.as_slices();
+    rust::pred_filter_intra(
+      dst,
+      &above[..width],
+      &left[left.len() - height..],
+      top_left[0],
+      self,
+      width,
+      height,
+      bit_depth,
+    );
+  }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum IntraParam {
   AngleDelta(i8),
@@ -605,6 +1132,62 @@ impl IntraEdgeFilterParameters {
   }
 }
 
+// Filter taps of the recursive intra filters, applied to the top-left, the
+// 4 above and the 2 left neighbors of each 4x2 sub-block.
+#[rustfmt::skip]
+static filter_intra_taps: [[[i8; 7]; 8]; FilterIntraMode::FILTER_INTRA_MODES as usize] = [
+  [
+    [ -6, 10,  0,  0,  0, 12,  0],
+    [ -5,  2, 10,  0,  0,  9,  0],
+    [ -3,  1,  1, 10,  0,  7,  0],
+    [ -3,  1,  1,  2, 10,  5,  0],
+    [ -4,  6,  0,  0,  0,  2, 12],
+    [ -3,  2,  6,  0,  0,  2,  9],
+    [ -3,  2,  2,  6,  0,  2,  7],
+    [ -3,  1,  2,  2,  6,  3,  5],
+  ],
+  [
+    [-10, 16,  0,  0,  0, 10,  0],
+    [ -6,  0, 16,  0,  0,  6,  0],
+    [ -4,  0,  0, 16,  0,  4,  0],
+    [ -2,  0,  0,  0, 16,  2,  0],
+    [-10, 16,  0,  0,  0,  0, 10],
+    [ -6,  0, 16,  0,  0,  0,  6],
+    [ -4,  0,  0, 16,  0,  0,  4],
+    [ -2,  0,  0,  0, 16,  0,  2],
+  ],
+  [
+    [ -8,  8,  0,  0,  0, 16,  0],
+    [ -8,  0,  8,  0,  0, 16,  0],
+    [ -8,  0,  0,  8,  0, 16,  0],
+    [ -8,  0,  0,  0,  8, 16,  0],
+    [ -4,  4,  0,  0,  0,  0, 16],
+    [ -4,  0,  4,  0,  0,  0, 16],
+    [ -4,  0,  0,  4,  0,  0, 16],
+    [ -4,  0,  0,  0,  4,  0, 16],
+  ],
+  [
+    [ -2,  8,  0,  0,  0, 10,  0],
+    [ -1,  3,  8,  0,  0,  6,  0],
+    [ -1,  2,  3,  8,  0,  4,  0],
+    [  0,  1,  2,  3,  8,  2,  0],
+    [ -1,  4,  0,  0,  0,  3, 10],
+    [ -1,  3,  4,  0,  0,  4,  6],
+    [ -1,  2,  3,  4,  0,  4,  4],
+    [ -1,  2,  2,  3,  4,  3,  3],
+  ],
+  [
+    [-12, 14,  0,  0,  0, 14,  0],
+    [-10,  0, 14,  0,  0, 12,  0],
+    [ -9,  0,  0, 14,  0, 11,  0],
+    [ -8,  0,  0,  0, 14, 10,  0],
+    [-10, 12,  0,  0,  0,  0, 14],
+    [ -9,  1, 12,  0,  0,  0, 12],
+    [ -8,  0,  0, 12,  0,  1, 11],
+    [ -7,  0,  0,  1, 12,  1,  9],
+  ],
+];
+
 // Weights are quadratic from '1' to '1 / block_size', scaled by 2^sm_weight_log2_scale.
 const sm_weight_log2_scale: u8 = 8;
 
@@ -910,6 +1493,54 @@ pub(crate) mod rust {
     }
   }
 
+  pub(crate) fn pred_filter_intra<T: Pixel>(
+    output: &mut PlaneRegionMut<'_, T>, above: &[T], left: &[T],
+    above_left: T, mode: FilterIntraMode, width: usize, height: usize,
+    bit_depth: usize,
+  ) {
+    let taps = &filter_intra_taps[mode as usize];
+    let sample_max = (1 << bit_depth) - 1;
+    // left pixels are ordered from bottom to top
+    let left_col = |i: usize| left[height - 1 - i].to_i32();
+
+    // Each 4x2 sub-block is predicted from the 7 pixels above and to its
+    // left, which may belong to previously predicted sub-blocks.
+    for y in (0..height).step_by(2) {
+      for x in (0..width).step_by(4) {
+        let mut p = [0i32; 7];
+        p[0] = match (x, y) {
+          (0, 0) => above_left.to_i32(),
+          (_, 0) => above[x - 1].to_i32(),
+          (0, _) => left_col(y - 1),
+          _ => output[y - 1][x - 1].to_i32(),
+        };
+        for i in 0..4 {
+          p[1 + i] = if y == 0 {
+            above[x + i].to_i32()
+          } else {
+            output[y - 1][x + i].to_i32()
+          };
+        }
+        for i in 0..2 {
+          p[5 + i] = if x == 0 {
+            left_col(y + i)
+          } else {
+            output[y + i][x - 1].to_i32()
+          };
+        }
+
+        for (i, tap) in taps.iter().enumerate() {
+          let sum: i32 =
+            tap.iter().zip(p.iter()).map(|(&t, &v)| t as i32 * v).sum();
+          let v =
+            if sum < 0 { -round_shift(-sum, 4) } else { round_shift(sum, 4) };
+          output[y + (i >> 2)][x + (i & 3)] =
+            T::cast_from(v.clamp(0, sample_max));
+        }
+      }
+    }
+  }
+
   pub(crate) fn pred_smooth<T: Pixel>(
     output: &mut PlaneRegionMut<'_, T>, above: &[T], left: &[T], width: usize,
     height: usize,
@@ -1537,6 +2168,54 @@ mod test {
   use crate::predict::rust::*;
   use num_traits::*;
 
+  #[test]
+  fn pred_filter_intra_matches_spec() {
+    let above = [10u8, 40, 80, 120, 160, 200, 230, 250];
+    // Bottom to top
+    let left = [180u8, 20, 90, 60];
+    let mut output = Plane::from_slice(&[0u8; 8 * 4], 8);
+
+    // Values from the recursive intra prediction process of the
+    // specification, which rounds negative sums with Round2Signed.
+    pred_filter_intra(
+      &mut output.as_region_mut(),
+      &above,
+      &left,
+      100,
+      FilterIntraMode::FILTER_DC_PRED,
+      8,
+      4,
+      8,
+    );
+    assert_eq!(
+      &output.data[..],
+      [
+        14, 29, 61, 88, 121, 157, 182, 213, 54, 56, 64, 81, 102, 129, 155,
+        177, 15, 25, 39, 55, 75, 99, 120, 146, 135, 115, 102, 92, 94, 105,
+        119, 132
+      ]
+    );
+
+    pred_filter_intra(
+      &mut output.as_region_mut(),
+      &above,
+      &left,
+      100,
+      FilterIntraMode::FILTER_PAETH_PRED,
+      8,
+      4,
+      8,
+    );
+    assert_eq!(
+      &output.data[..],
+      [
+        0, 18, 55, 93, 131, 170, 198, 217, 24, 42, 76, 106, 138, 172, 191,
+        215, 0, 0, 30, 60, 94, 129, 149, 173, 119, 117, 137, 147, 166, 188,
+        195, 213
+      ]
+    );
+  }
+
   #[test]
   fn pred_matches_u8() {
     let edge_buf =
@@ -1716,5 +2395,24 @@ mod test {
         assert_eq!(*v, max12bit);
       }
     }
+
+    for &mode in RAV1E_FILTER_INTRA_MODES {
+      pred_filter_intra(
+        &mut o.as_region_mut(),
+        &above[..8],
+        &left[..8],
+        above_left,
+        mode,
+        8,
+        8,
+        12,
+      );
+
+      for l in o.data.chunks(32).take(8) {
+        for v in l[..8].iter() {
+          assert_eq!(*v, max12bit);
+        }
+      }
+    }
   }
 }
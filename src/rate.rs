@@ -8,8 +8,9 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use std::cmp;
+use std::mem;
 
-use crate::api::ContextInner;
+use crate::api::{ContextInner, MatrixCoefficients};
 use crate::encoder::TEMPORAL_DELIMITER;
 use crate::quantize::{ac_q, dc_q, select_ac_qi, select_dc_qi};
 use crate::util::{
@@ -26,10 +27,21 @@ pub const FRAME_SUBTYPE_I: usize = 0;
 pub const FRAME_SUBTYPE_P: usize = 1;
 #[allow(unused)]
 pub const FRAME_SUBTYPE_B0: usize = 2;
-#[allow(unused)]
 pub const FRAME_SUBTYPE_B1: usize = 3;
 pub const FRAME_SUBTYPE_SEF: usize = 4;
 
+/// Get the sub-type of an inter frame at the given level of the re-ordering
+///  pyramid.
+/// The levels below the second one share the parameters of FRAME_SUBTYPE_B1.
+pub(crate) const fn inter_frame_subtype(pyramid_level: u64) -> usize {
+  let level = pyramid_level as usize;
+  if level < FRAME_SUBTYPE_B1 - FRAME_SUBTYPE_P {
+    FRAME_SUBTYPE_P + level
+  } else {
+    FRAME_SUBTYPE_B1
+  }
+}
+
 const PASS_SINGLE: i32 = 0;
 const PASS_1: i32 = 1;
 const PASS_2: i32 = 2;
@@ -47,6 +59,13 @@ const TWOPASS_PACKET_SZ: usize = 8;
 
 const SEF_BITS: i64 = 24;
 
+// The maximum number of trial encodes that search the base quantizer index
+//  of a frame in target quality mode.
+const QUALITY_SEARCH_NTRIALS: usize = 4;
+// How far the search may move from the base quantizer index of the previous
+//  frame of the same type.
+const QUALITY_SEARCH_RANGE: i32 = 32;
+
 // The scale of AV1 quantizer tables (relative to the pixel domain), i.e., Q3.
 pub(crate) const QSCALE: i32 = 3;
 
@@ -361,9 +380,88 @@ impl RCDeserialize {
   }
 }
 
+// Computes the target number of bits for a temporal unit lasting
+//  duration_q16/65536 time base units.
+fn tu_target_bits(
+  target_bitrate: i32, framerate_num: i64, framerate_den: i64,
+  duration_q16: i64,
+) -> i64 {
+  // Insane framerates or frame sizes mean insane bitrates.
+  // Let's not get carried away.
+  // We also subtract 16 bits from each temporal unit to account for the
+  //  temporal delimiter, whose bits are not included in the frame sizes
+  //  reported to update_state().
+  // TODO: Support constraints imposed by levels.
+  let bits = (target_bitrate as i128)
+    * (framerate_den as i128)
+    * (duration_q16 as i128)
+    / ((framerate_num as i128) << 16);
+  bits.clamp(40, 0x4000_0000_0000) as i64
+    - (TEMPORAL_DELIMITER.len() * 8) as i64
+}
+
+// Bisection of the base quantizer index of a frame in target quality mode.
+pub(crate) struct QualitySearch {
+  // The quality to reach.
+  target: f64,
+  // The range of quantizer indices left to search.
+  qi_lo: i32,
+  qi_hi: i32,
+  // The quantizer index to try next.
+  qi: i32,
+  ntrials: usize,
+  // The coarsest quantizer index tried that reached the target (or, if none
+  //  did, the one with the best quality) and its quality.
+  best: Option<(u8, f64)>,
+}
+
+impl QualitySearch {
+  // Returns the quantizer index to try next, or None if the search is done.
+  pub(crate) fn next_qi(&self) -> Option<u8> {
+    (self.ntrials < QUALITY_SEARCH_NTRIALS && self.qi_lo <= self.qi_hi)
+      .then_some(self.qi as u8)
+  }
+
+  // Records the quality reached with the quantizer index returned by
+  //  next_qi(), and returns whether it is the best one so far.
+  pub(crate) fn update(&mut self, quality: f64) -> bool {
+    let qi = self.qi as u8;
+    let reached = quality >= self.target;
+    let is_best = match self.best {
+      None => true,
+      Some((best_qi, best_quality)) => {
+        if best_quality >= self.target {
+          reached && qi > best_qi
+        } else {
+          reached || quality > best_quality
+        }
+      }
+    };
+    if is_best {
+      self.best = Some((qi, quality));
+    }
+    if reached {
+      self.qi_lo = self.qi + 1;
+    } else {
+      self.qi_hi = self.qi - 1;
+    }
+    self.qi = (self.qi_lo + self.qi_hi + 1) >> 1;
+    self.ntrials += 1;
+    is_best
+  }
+
+  // The best quantizer index found and its quality.
+  pub(crate) const fn best(&self) -> Option<(u8, f64)> {
+    self.best
+  }
+}
+
 pub struct RCState {
   // The target bit-rate in bits per second.
   target_bitrate: i32,
+  // The nominal frame rate, i.e., the inverse of the time base.
+  framerate_num: i64,
+  framerate_den: i64,
   // The number of TUs over which to distribute the reservoir usage.
   // We use TUs because in our leaky bucket model, we only add bits to the
   //  reservoir on TU boundaries.
@@ -376,12 +474,29 @@ pub struct RCState {
   maybe_ac_qi_max: Option<u8>,
   // The minimum quantizer index to allow (for the luma AC coefficients).
   ac_qi_min: u8,
+  // The quantizer index to keep in capped quality mode, in which the target
+  //  bit-rate is only a maximum that is raised to when it would be exceeded.
+  maybe_capped_qi: Option<u8>,
+  // The SSIM to reach in target quality mode, in which the base quantizer
+  //  index of every frame is searched with trial encodes.
+  maybe_target_quality: Option<f64>,
+  // The base quantizer index last selected in target quality mode for each
+  //  frame type, which the next search starts from.
+  target_quality_qi: [Option<u8>; FRAME_NSUBTYPES],
   // Will we drop frames to meet bitrate requirements?
   drop_frames: bool,
   // Do we respect the maximum reservoir fullness?
   cap_overflow: bool,
   // Can the reservoir go negative?
   cap_underflow: bool,
+  // Is the reservoir a hard constraint (constant bitrate mode)?
+  // The reservoir then models the decoder buffer: every frame must fit in
+  //  the bits it holds, and the bits that would overflow it are spent on
+  //  padding.
+  strict_buffer: bool,
+  // The padding in bytes to append to the current TU to avoid overflowing
+  //  the strict buffer.
+  padding_bytes: usize,
   // The log of the first-pass base quantizer.
   pass1_log_base_q: i64,
   // Two-pass mode state.
@@ -393,7 +508,8 @@ pub struct RCState {
   twopass_state: i32,
   // The log of the number of pixels in a frame in Q57 format.
   log_npixels: i64,
-  // The target average bits per Temporal Unit (input frame).
+  // The target average bits per Temporal Unit (input frame), based on the
+  //  estimated average TU duration.
   bits_per_tu: i64,
   // The current bit reservoir fullness (bits available to be used).
   reservoir_fullness: i64,
@@ -412,10 +528,12 @@ pub struct RCState {
   exp: [u8; FRAME_NSUBTYPES],
   // The log of an estimated scale factor used to obtain the real framerate,
   //  for VFR sources or, e.g., 12 fps content doubled to 24 fps, etc.
-  // TODO vfr: log_vfr_scale: i64,
+  // This is the log of the average TU duration in time base units, in Q57.
+  log_vfr_scale: i64,
   // Second-order lowpass filters to track scale and VFR.
   scalefilter: [IIRBessel2; FRAME_NSUBTYPES],
-  // TODO vfr: vfrfilter: IIRBessel2,
+  // The VFR filter is only created once the first frame duration is known.
+  vfrfilter: Option<IIRBessel2>,
   // The number of frames of each type we have seen, for filter adaptation
   //  purposes.
   // These are only 32 bits to guarantee that we can sum the scales over the
@@ -483,7 +601,6 @@ pub struct RCState {
   scale_window_sum: [i64; FRAME_NSUBTYPES],
 }
 
-// TODO: Separate qi values for each color plane.
 pub struct QuantizerParameters {
   // The full-precision, unmodulated log quantizer upon which our modulated
   //  quantizer indices are based.
@@ -505,26 +622,58 @@ pub struct QuantizerParameters {
 const Q57_SQUARE_EXP_SCALE: f64 =
   (2.0 * ::std::f64::consts::LN_2) / ((1i64 << 57) as f64);
 
+// Log-offset of the Cb and Cr quantizers for the given matrix coefficients
+//  relative to BT.709.
+// Cb and Cr are B'-Y' and R'-Y' divided by 2*(1-Kb) and 2*(1-Kr), so a
+//  quantizer step spans a color difference proportional to that divisor.
+// Scaling the steps by the ratio of the BT.709 divisors to those of the
+//  matrix keeps the color difference they span the same.
+fn matrix_chroma_offset(
+  matrix_coefficients: MatrixCoefficients,
+) -> (i64, i64) {
+  let (kr, kb) = match matrix_coefficients {
+    MatrixCoefficients::FCC => (0.30, 0.11),
+    MatrixCoefficients::BT470BG | MatrixCoefficients::BT601 => (0.299, 0.114),
+    MatrixCoefficients::SMPTE240 => (0.212, 0.087),
+    // The constant luminance variant uses the same Kr and Kb, and divisors
+    //  close to those of the non-constant luminance one.
+    MatrixCoefficients::BT2020NCL | MatrixCoefficients::BT2020CL => {
+      (0.2627, 0.0593)
+    }
+    // BT.709, and matrices for which no better guess is known.
+    _ => return (0, 0),
+  };
+  let offset = |k_bt709: f64, k: f64| {
+    (((1. - k_bt709) / (1. - k)).log2() * (1i64 << 57) as f64) as i64
+  };
+  (offset(0.0722, kb), offset(0.2126, kr))
+}
+
 // Daala style log-offset for chroma quantizers
-// TODO: Optimal offsets for more configurations than just BT.709
 fn chroma_offset(
   log_target_q: i64, chroma_sampling: ChromaSubsampling,
+  matrix_coefficients: MatrixCoefficients,
 ) -> (i64, i64) {
   let x = log_target_q.max(0);
-  // Gradient optimized for CIEDE2000+PSNR on subset3
+  // Gradient optimized for CIEDE2000+PSNR on subset3 (BT.709)
   let y = match chroma_sampling {
     ChromaSubsampling::Monochrome => 0,
     ChromaSubsampling::Yuv420 => (x >> 2) + (x >> 6), // 0.266
     ChromaSubsampling::Yuv422 => (x >> 3) + (x >> 4) - (x >> 7), // 0.180
     ChromaSubsampling::Yuv444 => (x >> 4) + (x >> 5) + (x >> 8), // 0.098
   };
-  (const { blog64(7) - blog64(4) } - y, const { blog64(5) - blog64(4) } - y)
+  let (matrix_u, matrix_v) = matrix_chroma_offset(matrix_coefficients);
+  (
+    const { blog64(7) - blog64(4) } - y + matrix_u,
+    const { blog64(5) - blog64(4) } - y + matrix_v,
+  )
 }
 
 impl QuantizerParameters {
   fn new_from_log_q(
     log_base_q: i64, log_target_q: i64, bit_depth: usize,
-    chroma_sampling: ChromaSubsampling, is_intra: bool,
+    chroma_sampling: ChromaSubsampling,
+    matrix_coefficients: MatrixCoefficients, is_intra: bool,
     log_isqrt_mean_scale: i64,
   ) -> QuantizerParameters {
     let scale = log_isqrt_mean_scale + q57(QSCALE + bit_depth as i32 - 8);
@@ -537,8 +686,11 @@ impl QuantizerParameters {
     }
 
     let quantizer = bexp64(log_q_y + scale);
-    let (offset_u, offset_v) =
-      chroma_offset(log_q_y + log_isqrt_mean_scale, chroma_sampling);
+    let (offset_u, offset_v) = chroma_offset(
+      log_q_y + log_isqrt_mean_scale,
+      chroma_sampling,
+      matrix_coefficients,
+    );
     let mono = chroma_sampling == ChromaSubsampling::Monochrome;
     let log_q_u = log_q_y + offset_u;
     let log_q_v = log_q_y + offset_v;
@@ -562,7 +714,7 @@ impl QuantizerParameters {
     QuantizerParameters {
       log_base_q,
       log_target_q,
-      // TODO: Allow lossless mode; i.e. qi == 0.
+      // qi == 0 is reserved for lossless mode, see select_qi().
       dc_qi: [
         clamp_qi(select_dc_qi(quantizer, bit_depth)),
         if mono { 0 } else { clamp_qi(select_dc_qi(quantizer_u, bit_depth)) },
@@ -584,7 +736,9 @@ impl RCState {
     frame_width: i32, frame_height: i32, framerate_num: i64,
     framerate_den: i64, target_bitrate: i32, maybe_ac_qi_max: Option<u8>,
     ac_qi_min: u8, max_key_frame_interval: i32,
-    maybe_reservoir_frame_delay: Option<i32>,
+    maybe_reservoir_frame_delay: Option<i32>, maybe_buffer_size: Option<i64>,
+    maybe_buffer_initial_fullness: Option<i64>, maybe_capped_qi: Option<u8>,
+    maybe_target_quality: Option<f64>,
   ) -> RCState {
     // The default buffer size is set equal to 1.5x the keyframe interval, or 240
     //  frames; whichever is smaller, with a minimum of 12.
@@ -599,20 +753,26 @@ impl RCState {
       .max(12);
     // TODO: What are the limits on these?
     let npixels = (frame_width as i64) * (frame_height as i64);
-    // Insane framerates or frame sizes mean insane bitrates.
-    // Let's not get carried away.
-    // We also subtract 16 bits from each temporal unit to account for the
-    //  temporal delimiter, whose bits are not included in the frame sizes
-    //  reported to update_state().
-    // TODO: Support constraints imposed by levels.
-    let bits_per_tu = clamp(
-      (target_bitrate as i64) * framerate_den / framerate_num,
-      40,
-      0x4000_0000_0000,
-    ) - (TEMPORAL_DELIMITER.len() * 8) as i64;
-    let reservoir_max = bits_per_tu * (reservoir_frame_delay as i64);
-    // Start with a buffer fullness and fullness target of 50%.
-    let reservoir_target = (reservoir_max + 1) >> 1;
+    // Assume every TU lasts one time base unit until we see real durations.
+    let bits_per_tu =
+      tu_target_bits(target_bitrate, framerate_num, framerate_den, 1 << 16);
+    let (reservoir_max, reservoir_target) = match maybe_buffer_size {
+      // A strict buffer starts at its initial fullness, 90% by default, and
+      //  aims to stay there.
+      Some(buffer_size) => (
+        buffer_size,
+        maybe_buffer_initial_fullness
+          .unwrap_or(buffer_size - buffer_size / 10),
+      ),
+      None => {
+        let reservoir_max = bits_per_tu * (reservoir_frame_delay as i64);
+        // Start with a buffer fullness and fullness target of 50%.
+        (reservoir_max, (reservoir_max + 1) >> 1)
+      }
+    };
+    // In capped quality mode, nothing has been spent in the window yet.
+    let reservoir_fullness =
+      if maybe_capped_qi.is_some() { reservoir_max } else { reservoir_target };
     // Pick exponents and initial scales for quantizer selection.
     let ibpp = npixels / bits_per_tu;
     // These have been derived by encoding many clips at every quantizer
@@ -649,18 +809,25 @@ impl RCState {
     // TODO: Add support for "golden" P frames.
     RCState {
       target_bitrate,
+      framerate_num,
+      framerate_den,
       reservoir_frame_delay,
       reservoir_frame_delay_is_set: maybe_reservoir_frame_delay.is_some(),
       maybe_ac_qi_max,
       ac_qi_min,
+      maybe_capped_qi,
+      maybe_target_quality,
+      target_quality_qi: [None; FRAME_NSUBTYPES],
       drop_frames: false,
       cap_overflow: true,
       cap_underflow: false,
+      strict_buffer: maybe_buffer_size.is_some(),
+      padding_bytes: 0,
       pass1_log_base_q: 0,
       twopass_state: PASS_SINGLE,
       log_npixels: blog64(npixels),
       bits_per_tu,
-      reservoir_fullness: reservoir_target,
+      reservoir_fullness,
       reservoir_target,
       reservoir_max,
       log_scale: [i_log_scale, p_log_scale, b0_log_scale, b1_log_scale],
@@ -671,7 +838,8 @@ impl RCState {
         IIRBessel2::new(INTER_DELAY_TARGET_MIN, q57_to_q24(b0_log_scale)),
         IIRBessel2::new(INTER_DELAY_TARGET_MIN, q57_to_q24(b1_log_scale)),
       ],
-      // TODO VFR
+      log_vfr_scale: 0,
+      vfrfilter: None,
       nframes: [0; FRAME_NSUBTYPES + 1],
       inter_delay: [INTER_DELAY_TARGET_MIN; FRAME_NSUBTYPES - 1],
       inter_delay_target: reservoir_frame_delay >> 1,
@@ -701,8 +869,75 @@ impl RCState {
     }
   }
 
+  // Updates the estimated average TU duration with the duration of a newly
+  //  received frame, in time base units.
+  pub(crate) fn update_vfr_scale(&mut self, duration: u64) {
+    let log_duration_q24 = q57_to_q24(blog64(duration.max(1) as i64));
+    let log_vfr_scale_q24 = match self.vfrfilter {
+      Some(ref mut f) => f.update(log_duration_q24),
+      None => {
+        self.vfrfilter =
+          Some(IIRBessel2::new(self.inter_delay_target, log_duration_q24));
+        log_duration_q24
+      }
+    };
+    self.log_vfr_scale = q24_to_q57(log_vfr_scale_q24);
+    self.bits_per_tu = self.tu_bits(None);
+    // The size of a strict buffer is given in bits rather than TUs.
+    if self.strict_buffer {
+      return;
+    }
+    self.reservoir_max =
+      self.bits_per_tu * (self.reservoir_frame_delay as i64);
+    self.reservoir_target = (self.reservoir_max + 1) >> 1;
+    // The initial fullness is only a guess based on the nominal frame rate,
+    //  so keep it at the target until we start spending bits.
+    if self.nencoded_frames == 0 && self.nsef_frames == 0 {
+      self.reservoir_fullness = if self.maybe_capped_qi.is_some() {
+        self.reservoir_max
+      } else {
+        self.reservoir_target
+      };
+    }
+  }
+
+  // The estimated average TU duration, in time base units.
+  pub(crate) fn estimated_tu_duration(&self) -> u64 {
+    ((bexp64(self.log_vfr_scale + q57(16)) + (1 << 15)) >> 16).max(1) as u64
+  }
+
+  // The target number of bits for a TU lasting the given number of time base
+  //  units, or the estimated average TU duration if it is not known.
+  fn tu_bits(&self, duration: Option<u64>) -> i64 {
+    let duration_q16 = match duration {
+      Some(d) => (d.min(i64::MAX as u64 >> 16) as i64) << 16,
+      None => bexp64(self.log_vfr_scale + q57(16)),
+    };
+    tu_target_bits(
+      self.target_bitrate,
+      self.framerate_num,
+      self.framerate_den,
+      duration_q16,
+    )
+  }
+
+  // The target number of bits for the next ntus TUs, given the durations of
+  //  the first of those TUs that are already known.
+  fn reservoir_bits(
+    &self, durations: impl Iterator<Item = u64>, ntus: i32,
+  ) -> i64 {
+    let mut bits = 0;
+    let mut nknown = 0;
+    for duration in durations.take(ntus.max(0) as usize) {
+      bits += self.tu_bits(Some(duration));
+      nknown += 1;
+    }
+    bits + (ntus - nknown).max(0) as i64 * self.bits_per_tu
+  }
+
   pub(crate) fn select_first_pass_qi(
     &self, bit_depth: usize, fti: usize, chroma_sampling: ChromaSubsampling,
+    matrix_coefficients: MatrixCoefficients,
   ) -> QuantizerParameters {
     // Adjust the quantizer for the frame type, result is Q57:
     let log_q = ((self.pass1_log_base_q + (1i64 << 11)) >> 12)
@@ -713,12 +948,12 @@ impl RCState {
       log_q,
       bit_depth,
       chroma_sampling,
+      matrix_coefficients,
       fti == 0,
       0,
     )
   }
 
-  // TODO: Separate quantizers for Cb and Cr.
   #[profiling::function]
   pub(crate) fn select_qi<T: Pixel>(
     &self, ctx: &ContextInner<T>, output_frameno: u64, fti: usize,
@@ -743,18 +978,22 @@ impl RCState {
     if self.target_bitrate <= 0 {
       // Rate control is not active.
       // Derive quantizer directly from frame type.
-      let bit_depth = ctx.config.bit_depth;
-      let chroma_sampling = ctx.config.chroma_sampling;
-      let (log_base_q, log_q) =
-        Self::calc_flat_quantizer(ctx.config.quantizer as u8, bit_depth, fti);
-      QuantizerParameters::new_from_log_q(
-        log_base_q,
-        log_q,
-        bit_depth,
-        chroma_sampling,
-        fti == 0,
+      let base_qi =
+        if ctx.config.lossless { 0 } else { ctx.config.quantizer as u8 };
+      let qps = Self::select_flat_qi(
+        base_qi,
+        ctx.config.bit_depth,
+        fti,
+        ctx.config.chroma_sampling,
+        ctx.config.matrix_coefficients(),
         log_isqrt_mean_scale,
-      )
+      );
+      if ctx.config.lossless {
+        // Lossless mode requires every quantizer index to be 0.
+        QuantizerParameters { dc_qi: [0; 3], ac_qi: [0; 3], ..qps }
+      } else {
+        qps
+      }
     } else {
       let mut nframes: [i32; FRAME_NSUBTYPES + 1] = [0; FRAME_NSUBTYPES + 1];
       let mut log_scale: [i64; FRAME_NSUBTYPES] = self.log_scale;
@@ -768,6 +1007,7 @@ impl RCState {
             ctx.config.bit_depth,
             fti,
             ctx.config.chroma_sampling,
+            ctx.config.matrix_coefficients(),
           );
         }
         // Second pass of 2-pass mode: we know exactly how much of each frame
@@ -899,17 +1139,19 @@ impl RCState {
             ctx.guess_frame_subtypes(&mut nframes, self.reservoir_frame_delay);
           reservoir_frames = guessed_reservoir_frames;
           reservoir_tus = guessed_reservoir_tus;
-          // TODO: Scale for VFR.
         }
       }
       // If we've been missing our target, add a penalty term.
       let rate_bias = (self.rate_bias / (self.nencoded_frames + 100))
         * (reservoir_frames as i64);
       // rate_total is the total bits available over the next
-      //  reservoir_tus TUs.
+      //  reservoir_tus TUs, scaled by the durations of the frames we have
+      //  already received.
       let rate_total = self.reservoir_fullness - self.reservoir_target
         + rate_bias
-        + (reservoir_tus as i64) * self.bits_per_tu;
+        + self.reservoir_bits(ctx.tu_durations(), reservoir_tus);
+      // The bits available for the TU containing the current frame.
+      let cur_tu_bits = self.tu_bits(ctx.tu_durations().next());
       // Find a target quantizer that meets our rate target for the
       //  specific mix of frame types we'll have over the next
       //  reservoir_frame frames.
@@ -987,7 +1229,7 @@ impl RCState {
         // We only want to keep these bits from being completely wasted.
         let margin = (self.reservoir_max + 31) >> 5;
         // We want to use at least this many bits next frame.
-        let soft_limit = self.reservoir_fullness + self.bits_per_tu
+        let soft_limit = self.reservoir_fullness + cur_tu_bits
           - (self.reservoir_max - margin);
         if soft_limit > 0 {
           let log_soft_limit = blog64(soft_limit);
@@ -1013,11 +1255,18 @@ impl RCState {
       //  soft target).
       if self.maybe_ac_qi_max.is_none() {
         // Compute the maximum number of bits we can use in the next frame.
-        // Allow 50% of the rate for a single frame for prediction error.
-        // This may not be enough for keyframes or sudden changes in
-        //  complexity.
-        let log_hard_limit =
-          blog64(self.reservoir_fullness + (self.bits_per_tu >> 1));
+        let hard_limit = if self.strict_buffer {
+          // The whole frame must be in the buffer when it is decoded.
+          // Keep 1/8 of it for prediction error; frames that still do not
+          //  fit are coded again, see select_reencode_qi().
+          (self.reservoir_fullness - (self.reservoir_fullness >> 3)).max(1)
+        } else {
+          // Allow 50% of the rate for a single frame for prediction error.
+          // This may not be enough for keyframes or sudden changes in
+          //  complexity.
+          self.reservoir_fullness + (cur_tu_bits >> 1)
+        };
+        let log_hard_limit = blog64(hard_limit);
         // If we're predicting we'll use more than this...
         // TODO: When using frame re-ordering, we should include the rate
         //  for all of the frames in the current TU.
@@ -1034,6 +1283,13 @@ impl RCState {
         }
       }
 
+      // In capped quality mode, only ever raise the quantizer.
+      if let Some(capped_qi) = self.maybe_capped_qi {
+        let (capped_log_base_q, capped_log_q) =
+          Self::calc_flat_quantizer(capped_qi, ctx.config.bit_depth, fti);
+        log_base_q = cmp::max(log_base_q, capped_log_base_q);
+        log_q = cmp::max(log_q, capped_log_q);
+      }
       if let Some(qi_max) = self.maybe_ac_qi_max {
         let (max_log_base_q, max_log_q) =
           Self::calc_flat_quantizer(qi_max, ctx.config.bit_depth, fti);
@@ -1051,12 +1307,94 @@ impl RCState {
         log_q,
         bit_depth,
         chroma_sampling,
+        ctx.config.matrix_coefficients(),
         fti == 0,
         log_isqrt_mean_scale,
       )
     }
   }
 
+  // Selects the quantizers of a frame from its base quantizer index, without
+  //  consideration for rate control.
+  pub(crate) fn select_flat_qi(
+    base_qi: u8, bit_depth: usize, fti: usize,
+    chroma_sampling: ChromaSubsampling,
+    matrix_coefficients: MatrixCoefficients, log_isqrt_mean_scale: i64,
+  ) -> QuantizerParameters {
+    let (log_base_q, log_q) =
+      Self::calc_flat_quantizer(base_qi, bit_depth, fti);
+    QuantizerParameters::new_from_log_q(
+      log_base_q,
+      log_q,
+      bit_depth,
+      chroma_sampling,
+      matrix_coefficients,
+      fti == FRAME_SUBTYPE_I,
+      log_isqrt_mean_scale,
+    )
+  }
+
+  // Starts the search of the base quantizer index of a frame in target
+  //  quality mode, around the one last selected for its frame type, or
+  //  returns None if that mode is not active.
+  pub(crate) fn start_quality_search(
+    &self, fti: usize, default_qi: u8,
+  ) -> Option<QualitySearch> {
+    let target = self.maybe_target_quality?;
+    let qi = self.target_quality_qi[fti].unwrap_or(default_qi) as i32;
+    Some(QualitySearch {
+      target,
+      qi_lo: (qi - QUALITY_SEARCH_RANGE).max(0),
+      qi_hi: (qi + QUALITY_SEARCH_RANGE).min(255),
+      qi,
+      ntrials: 0,
+      best: None,
+    })
+  }
+
+  // Records the base quantizer index selected for a frame in target quality
+  //  mode.
+  pub(crate) fn update_quality_qi(&mut self, fti: usize, qi: u8) {
+    self.target_quality_qi[fti] = Some(qi);
+  }
+
+  // Selects a coarser quantizer for a frame that used more bits than a
+  //  strict buffer holds, or returns None if the frame fits or the quantizer
+  //  cannot be raised any further.
+  pub(crate) fn select_reencode_qi(
+    &self, bits: i64, fti: usize, qps: &QuantizerParameters, bit_depth: usize,
+    chroma_sampling: ChromaSubsampling,
+    matrix_coefficients: MatrixCoefficients, log_isqrt_mean_scale: i64,
+  ) -> Option<QuantizerParameters> {
+    if self.fits_buffer(bits) {
+      return None;
+    }
+    // Solve the rate model for the quantizer that would bring the frame
+    //  within 7/8 of the buffer fullness, as in select_qi().
+    let limit =
+      (self.reservoir_fullness - (self.reservoir_fullness >> 3)).max(1);
+    let exp = self.exp[fti] as i64;
+    let log_q_exp =
+      ((qps.log_target_q + 32) >> 6) * exp + blog64(bits) - blog64(limit);
+    let (_, max_log_q) = Self::calc_flat_quantizer(255, bit_depth, fti);
+    // Raise the quantizer by at least 1/8 octave so every attempt counts.
+    let log_q = (((log_q_exp + (exp >> 1)) / exp) << 6)
+      .max(qps.log_target_q + (1 << 54))
+      .min(max_log_q);
+    if log_q <= qps.log_target_q {
+      return None;
+    }
+    Some(QuantizerParameters::new_from_log_q(
+      qps.log_base_q,
+      log_q,
+      bit_depth,
+      chroma_sampling,
+      matrix_coefficients,
+      fti == FRAME_SUBTYPE_I,
+      log_isqrt_mean_scale,
+    ))
+  }
+
   // Computes a quantizer directly from the frame type and base quantizer index,
   // without consideration for rate control.
   fn calc_flat_quantizer(
@@ -1085,8 +1423,8 @@ impl RCState {
 
   #[profiling::function]
   pub fn update_state(
-    &mut self, bits: i64, fti: usize, show_frame: bool, log_target_q: i64,
-    trial: bool, droppable: bool,
+    &mut self, bits: i64, fti: usize, show_frame: bool, duration: Option<u64>,
+    log_target_q: i64, trial: bool, droppable: bool,
   ) -> bool {
     if trial {
       assert!(self.needs_trial_encode(fti));
@@ -1099,6 +1437,7 @@ impl RCState {
       let mut bits = bits;
       let mut droppable = droppable;
       let mut log_scale = q57(-64);
+      let tu_bits = self.tu_bits(duration);
       // Drop frames is also disabled for now in the case of infinite-buffer
       //  two-pass mode.
       if !self.drop_frames
@@ -1207,7 +1546,7 @@ impl RCState {
             q24_to_q57(self.scalefilter[fti].update(log_scale_q24));
         }
         // If this frame busts our budget, it must be dropped.
-        if droppable && self.reservoir_fullness + self.bits_per_tu < bits {
+        if droppable && self.reservoir_fullness + tu_bits < bits {
           // TODO: Adjust VFR rate based on drop count.
           bits = 0;
           dropped = true;
@@ -1225,8 +1564,17 @@ impl RCState {
         }
         self.reservoir_fullness -= bits;
         if show_frame {
-          self.reservoir_fullness += self.bits_per_tu;
-          // TODO: Properly account for temporal delimiter bits.
+          // The temporal delimiter bits are already subtracted from tu_bits.
+          self.reservoir_fullness += tu_bits;
+        }
+        // The bits that would overflow a strict buffer are spent on padding
+        //  at the end of the TU instead, of at least the size of an empty
+        //  padding OBU.
+        let overflow = self.reservoir_fullness - self.reservoir_max;
+        if self.strict_buffer && overflow > 0 {
+          let padding_bytes = (((overflow + 7) >> 3) as usize).max(2);
+          self.padding_bytes += padding_bytes;
+          self.reservoir_fullness -= (padding_bytes * 8) as i64;
         }
         // If we're too quick filling the buffer and overflow is capped, that
         //  rate is lost forever.
@@ -1246,6 +1594,23 @@ impl RCState {
     dropped
   }
 
+  // Returns the size in bytes of the padding to append to the current TU,
+  //  and resets it.
+  pub(crate) fn take_padding_bytes(&mut self) -> usize {
+    mem::take(&mut self.padding_bytes)
+  }
+
+  // Whether a frame of the given size fits in the decoder buffer, which it
+  //  always does without a strict buffer.
+  pub(crate) const fn fits_buffer(&self, bits: i64) -> bool {
+    !self.strict_buffer || bits <= self.reservoir_fullness
+  }
+
+  // Whether frames may have to be coded again to fit in a strict buffer.
+  pub(crate) const fn has_strict_buffer(&self) -> bool {
+    self.strict_buffer
+  }
+
   pub const fn needs_trial_encode(&self, fti: usize) -> bool {
     self.target_bitrate > 0 && self.nframes[fti] == 0
   }
@@ -1416,10 +1781,13 @@ impl RCState {
       self.reservoir_frame_delay = s.ntus;
       self.scale_window_nframes = self.nframes_total;
       self.scale_window_sum = s.scale_sum;
-      self.reservoir_max =
-        self.bits_per_tu * (self.reservoir_frame_delay as i64);
-      self.reservoir_target = (self.reservoir_max + 1) >> 1;
-      self.reservoir_fullness = self.reservoir_target;
+      // A strict buffer keeps its size regardless of the planning horizon.
+      if !self.strict_buffer {
+        self.reservoir_max =
+          self.bits_per_tu * (self.reservoir_frame_delay as i64);
+        self.reservoir_target = (self.reservoir_max + 1) >> 1;
+        self.reservoir_fullness = self.reservoir_target;
+      }
     } else {
       self.reservoir_frame_delay = self.reservoir_frame_delay.min(s.ntus);
     }
@@ -11,10 +11,11 @@
 #![allow(non_camel_case_types)]
 
 use std::fmt;
+use std::iter;
 use std::mem::MaybeUninit;
 
 use arrayvec::*;
-use itertools::izip;
+use itertools::{iproduct, izip};
 
 use crate::api::*;
 use crate::cdef::*;
@@ -24,20 +25,27 @@ use crate::deblock::*;
 use crate::dist::*;
 use crate::ec::{Writer, WriterCounter, OD_BITRES};
 use crate::encode_block_with_modes;
-use crate::encoder::{FrameInvariants, IMPORTANCE_BLOCK_SIZE};
+use crate::encoder::{
+  filter_intra_allowed, FrameInvariants, IMPORTANCE_BLOCK_SIZE,
+};
 use crate::frame::*;
+use crate::global_motion::is_global_warp;
 use crate::header::ReferenceMode;
+use crate::intrabc::IntraBcHashes;
 use crate::lrf::*;
-use crate::mc::MotionVector;
+use crate::mc::{wedge_allowed, FilterMode, MotionVector, WEDGE_TYPES};
 use crate::me::estimate_motion;
 use crate::me::MVSamplingMode;
 use crate::me::MotionSearchResult;
 use crate::motion_compensate;
+use crate::palette::*;
 use crate::partition::PartitionType::*;
 use crate::partition::RefType::*;
 use crate::partition::*;
 use crate::predict::{
-  luma_ac, AngleDelta, IntraEdgeFilterParameters, IntraParam, PredictionMode,
+  luma_ac, predict_inter_intra, AngleDelta, CompoundPrediction,
+  FilterIntraMode, IntraEdgeFilterParameters, IntraParam, MotionMode,
+  PredictionMode, RAV1E_FILTER_INTRA_MODES, RAV1E_INTERINTRA_MODES,
   RAV1E_INTER_COMPOUND_MODES, RAV1E_INTER_MODES_MINIMAL, RAV1E_INTRA_MODES,
 };
 use crate::rdo_tables::*;
@@ -94,9 +102,14 @@ pub struct PartitionParameters {
   pub pred_mode_luma: PredictionMode,
   pub pred_mode_chroma: PredictionMode,
   pub pred_cfl_params: CFLParams,
+  pub palette: PaletteParams,
+  pub filter_intra_mode: Option<FilterIntraMode>,
   pub angle_delta: AngleDelta,
   pub ref_frames: [RefType; 2],
   pub mvs: [MotionVector; 2],
+  pub motion_mode: MotionMode,
+  pub compound: CompoundPrediction,
+  pub interp_filter: [FilterMode; 2],
   pub skip: bool,
   pub has_coeff: bool,
   pub tx_size: TxSize,
@@ -113,9 +126,14 @@ impl Default for PartitionParameters {
       pred_mode_luma: PredictionMode::default(),
       pred_mode_chroma: PredictionMode::default(),
       pred_cfl_params: CFLParams::default(),
+      palette: PaletteParams::default(),
+      filter_intra_mode: None,
       angle_delta: AngleDelta::default(),
       ref_frames: [RefType::INTRA_FRAME, RefType::NONE_FRAME],
       mvs: [MotionVector::default(); 2],
+      motion_mode: MotionMode::SIMPLE_TRANSLATION,
+      compound: CompoundPrediction::Average,
+      interp_filter: [FilterMode::REGULAR; 2],
       skip: false,
       has_coeff: true,
       tx_size: TxSize::TX_4X4,
@@ -763,10 +781,15 @@ where
   u32: crate::util::math::CastFromPrimitive<T>,
   i16: util::math::CastFromPrimitive<T>,
 {
+  // Lossless blocks can only use the 4x4 Walsh-Hadamard transform
+  if fi.coded_lossless() {
+    return (TxSize::TX_4X4, TxType::DCT_DCT);
+  }
+
   let is_inter = !luma_mode.is_intra();
   let mut tx_size = max_txsize_rect_lookup[bsize as usize];
 
-  if fi.enable_inter_txfm_split && is_inter && !skip {
+  if fi.enable_inter_txfm_split && fi.tx_mode_select && is_inter && !skip {
     tx_size = sub_tx_size_map[tx_size as usize]; // Always choose one level split size
   }
 
@@ -856,7 +879,9 @@ fn luma_chroma_mode_rdo<T: Pixel>(
   mvs: [MotionVector; 2], ref_frames: [RefType; 2],
   mode_set_chroma: &[PredictionMode], luma_mode_is_intra: bool,
   mode_context: usize, mv_stack: &ArrayVec<CandidateMV, 9>,
-  angle_delta: AngleDelta,
+  angle_delta: AngleDelta, palette: &PaletteParams,
+  filter_intra_mode: Option<FilterIntraMode>, motion_mode: MotionMode,
+  compound: CompoundPrediction, interp_filter: [FilterMode; 2],
 ) where
   u32: crate::util::math::CastFromPrimitive<
     <T as crate::util::pixel::Pixel>::Coeff,
@@ -882,8 +907,10 @@ fn luma_chroma_mode_rdo<T: Pixel>(
     has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling);
 
   if !luma_mode_is_intra {
-    let ref_mvs = if mv_stack.is_empty() {
-      [MotionVector::default(); 2]
+    let ref_mvs = if ref_frames[0] == INTRA_FRAME {
+      [cw.find_ref_dv(tile_bo, bsize, fi), MotionVector::default()]
+    } else if mv_stack.is_empty() {
+      cw.find_global_mvs(tile_bo, ref_frames, bsize, fi)
     } else {
       [mv_stack[0].this_mv, mv_stack[0].comp_mv]
     };
@@ -904,6 +931,12 @@ fn luma_chroma_mode_rdo<T: Pixel>(
     }
   }
 
+  cw.bc.blocks.set_palette(tile_bo, bsize, palette);
+  cw.bc.blocks.set_filter_intra_mode(tile_bo, bsize, filter_intra_mode);
+  cw.bc.blocks.set_motion_mode(tile_bo, bsize, motion_mode);
+  cw.bc.blocks.set_compound(tile_bo, bsize, compound);
+  cw.bc.blocks.set_interp_filter(tile_bo, bsize, interp_filter);
+
   // Find the best chroma prediction mode for the current luma prediction mode
   let mut chroma_rdo = |skip: bool| -> bool {
     use crate::segmentation::select_segment;
@@ -933,7 +966,7 @@ fn luma_chroma_mode_rdo<T: Pixel>(
         let need_recon_pixel =
           luma_mode_is_intra && tx_size.block_size() != bsize;
 
-        encode_block_pre_cdef(&fi.sequence, ts, cw, wr, bsize, tile_bo, skip);
+        encode_block_pre_cdef(fi, ts, cw, wr, bsize, tile_bo, skip);
         let (has_coeff, tx_dist) = encode_block_post_cdef(
           fi,
           ts,
@@ -948,6 +981,11 @@ fn luma_chroma_mode_rdo<T: Pixel>(
           tile_bo,
           skip,
           CFLParams::default(),
+          palette,
+          filter_intra_mode,
+          motion_mode,
+          compound,
+          interp_filter,
           tx_size,
           tx_type,
           mode_context,
@@ -979,9 +1017,14 @@ fn luma_chroma_mode_rdo<T: Pixel>(
           best.rd_cost = rd;
           best.pred_mode_luma = luma_mode;
           best.pred_mode_chroma = chroma_mode;
+          best.palette = *palette;
+          best.filter_intra_mode = filter_intra_mode;
           best.angle_delta = angle_delta;
           best.ref_frames = ref_frames;
           best.mvs = mvs;
+          best.motion_mode = motion_mode;
+          best.compound = compound;
+          best.interp_filter = interp_filter;
           best.skip = skip;
           best.has_coeff = has_coeff;
           best.tx_size = tx_size;
@@ -997,9 +1040,13 @@ fn luma_chroma_mode_rdo<T: Pixel>(
     zero_distortion
   };
 
-  // Don't skip when using intra modes
-  let zero_distortion =
-    if !luma_mode_is_intra { chroma_rdo(true) } else { false };
+  // Don't skip when using intra modes, or in lossless frames where skipping
+  // the residual would lose information
+  let zero_distortion = if !luma_mode_is_intra && !fi.coded_lossless() {
+    chroma_rdo(true)
+  } else {
+    false
+  };
   // early skip
   if !zero_distortion {
     chroma_rdo(false);
@@ -1083,10 +1130,17 @@ where
     );
   }
 
-  if best.pred_mode_luma.is_intra() && is_chroma_block && bsize.cfl_allowed() {
+  if best.pred_mode_luma.is_intra()
+    && is_chroma_block
+    && bsize.cfl_allowed(fi.coded_lossless(), xdec, ydec)
+  {
     cw.bc.blocks.set_segmentation_idx(tile_bo, bsize, best.sidx);
 
     let chroma_mode = PredictionMode::UV_CFL_PRED;
+    let palette =
+      PaletteParams { size: [best.palette.size[0], 0], ..best.palette };
+    cw.bc.blocks.set_palette(tile_bo, bsize, &palette);
+    cw.bc.blocks.set_filter_intra_mode(tile_bo, bsize, best.filter_intra_mode);
     let cw_checkpoint = cw.checkpoint(&tile_bo, fi.sequence.chroma_sampling);
     let mut wr = WriterCounter::new();
     let angle_delta = AngleDelta { y: best.angle_delta.y, uv: 0 };
@@ -1115,15 +1169,7 @@ where
         let mut wr = WriterCounter::new();
         let tell = wr.tell_frac();
 
-        encode_block_pre_cdef(
-          &fi.sequence,
-          ts,
-          cw,
-          &mut wr,
-          bsize,
-          tile_bo,
-          best.skip,
-        );
+        encode_block_pre_cdef(fi, ts, cw, &mut wr, bsize, tile_bo, best.skip);
         let (has_coeff, _) = encode_block_post_cdef(
           fi,
           ts,
@@ -1138,6 +1184,11 @@ where
           tile_bo,
           best.skip,
           cfl,
+          &palette,
+          best.filter_intra_mode,
+          best.motion_mode,
+          best.compound,
+          best.interp_filter,
           best.tx_size,
           best.tx_type,
           0,
@@ -1159,6 +1210,7 @@ where
           best.angle_delta = angle_delta;
           best.has_coeff = has_coeff;
           best.pred_cfl_params = cfl;
+          best.palette = palette;
         }
 
         cw.rollback(&cw_checkpoint);
@@ -1169,6 +1221,11 @@ where
   cw.bc.blocks.set_mode(tile_bo, bsize, best.pred_mode_luma);
   cw.bc.blocks.set_ref_frames(tile_bo, bsize, best.ref_frames);
   cw.bc.blocks.set_motion_vectors(tile_bo, bsize, best.mvs);
+  cw.bc.blocks.set_palette(tile_bo, bsize, &best.palette);
+  cw.bc.blocks.set_filter_intra_mode(tile_bo, bsize, best.filter_intra_mode);
+  cw.bc.blocks.set_motion_mode(tile_bo, bsize, best.motion_mode);
+  cw.bc.blocks.set_compound(tile_bo, bsize, best.compound);
+  cw.bc.blocks.set_interp_filter(tile_bo, bsize, best.interp_filter);
 
   assert!(best.rd_cost >= 0_f64);
 
@@ -1178,9 +1235,14 @@ where
     pred_mode_luma: best.pred_mode_luma,
     pred_mode_chroma: best.pred_mode_chroma,
     pred_cfl_params: best.pred_cfl_params,
+    palette: best.palette,
+    filter_intra_mode: best.filter_intra_mode,
     angle_delta: best.angle_delta,
     ref_frames: best.ref_frames,
     mvs: best.mvs,
+    motion_mode: best.motion_mode,
+    compound: best.compound,
+    interp_filter: best.interp_filter,
     rd_cost: best.rd_cost,
     skip: best.skip,
     has_coeff: best.has_coeff,
@@ -1245,6 +1307,7 @@ where
   let mut satds = ArrayVec::<u32, 20>::new();
   let mut mv_stacks = ArrayVec::<_, 20>::new();
   let mut mode_contexts = ArrayVec::<_, 7>::new();
+  let mut global_mvs_set = ArrayVec::<_, 7>::new();
 
   for (i, &ref_frames) in ref_frames_set.iter().enumerate() {
     let mut mv_stack = ArrayVec::<CandidateMV, 9>::new();
@@ -1256,8 +1319,10 @@ where
       fi,
       false,
     ));
+    let global_mvs = cw.find_global_mvs(tile_bo, ref_frames, bsize, fi);
+    global_mvs_set.push(global_mvs);
 
-    let mut pmv = [MotionVector::default(); 2];
+    let mut pmv = [global_mvs[0]; 2];
     if !mv_stack.is_empty() {
       pmv[0] = mv_stack[0].this_mv;
     }
@@ -1289,7 +1354,10 @@ where
     if !mv_stack.is_empty() {
       inter_mode_set.push((PredictionMode::NEAR0MV, i));
     }
-    if mv_stack.len() >= 2 {
+    // Otherwise GLOBALMV predicts like NEARESTMV or NEAR0MV.
+    let global_warp =
+      is_global_warp(fi, ref_frames[0], bsize.width(), bsize.height());
+    if mv_stack.len() >= 2 || global_warp {
       inter_mode_set.push((PredictionMode::GLOBALMV, i));
     }
     let include_near_mvs = fi.config.speed_settings.motion.include_near_mvs;
@@ -1309,7 +1377,7 @@ where
       .iter()
       .take(if include_near_mvs { 4 } else { 2 })
       .any(same_row_col)
-      && (mvs_from_me[i][0].row != 0 || mvs_from_me[i][0].col != 0)
+      && (mvs_from_me[i][0] != global_mvs[0] || global_warp)
     {
       inter_mode_set.push((PredictionMode::NEWMV, i));
     }
@@ -1338,6 +1406,8 @@ where
           fi,
           true,
         ));
+        global_mvs_set
+          .push(cw.find_global_mvs(tile_bo, ref_frames, bsize, fi));
         for &x in RAV1E_INTER_COMPOUND_MODES {
           // exclude any NEAR mode based on speed setting
           if fi.config.speed_settings.motion.include_near_mvs
@@ -1370,14 +1440,14 @@ where
         if !mv_stacks[i].is_empty() {
           [mv_stacks[i][0].this_mv, mv_stacks[i][0].comp_mv]
         } else {
-          [MotionVector::default(); 2]
+          global_mvs_set[i]
         }
       }
       PredictionMode::NEAR0MV | PredictionMode::NEAR_NEAR0MV => {
         if mv_stacks[i].len() > 1 {
           [mv_stacks[i][1].this_mv, mv_stacks[i][1].comp_mv]
         } else {
-          [MotionVector::default(); 2]
+          global_mvs_set[i]
         }
       }
       PredictionMode::NEAR1MV
@@ -1394,7 +1464,7 @@ where
         [mvs_from_me[i][0], mv_stacks[i][0].comp_mv]
       }
       PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV => {
-        [MotionVector::default(); 2]
+        global_mvs_set[i]
       }
       _ => {
         unimplemented!();
@@ -1420,6 +1490,7 @@ where
         bsize.height(),
         ref_frames_set[i],
         mvs,
+        [FilterMode::REGULAR; 2],
         &mut ts.inter_compound_buffers,
       );
 
@@ -1468,13 +1539,339 @@ where
         mode_contexts[i],
         &mv_stacks[i],
         AngleDelta::default(),
+        &PaletteParams::default(),
+        None,
+        MotionMode::SIMPLE_TRANSLATION,
+        CompoundPrediction::Average,
+        [FilterMode::REGULAR; 2],
       );
     },
   );
 
+  // Try the other motion modes of the best single reference block: a local
+  // warp fitted to the motion of its neighbors, or blending their motion
+  // into its prediction near the edges.
+  if best.ref_frames[0] != INTRA_FRAME && best.ref_frames[1] == NONE_FRAME {
+    let (luma_mode, mvs, ref_frames) =
+      (best.pred_mode_luma, best.mvs, best.ref_frames);
+    let motion_modes =
+      cw.motion_modes(fi, tile_bo, bsize, luma_mode, ref_frames, mvs[0]);
+    let i = ref_frames_set.iter().position(|&r| r == ref_frames);
+    for &motion_mode in motion_modes.iter().skip(1) {
+      let enabled = match motion_mode {
+        MotionMode::OBMC_CAUSAL => fi.config.speed_settings.motion.obmc,
+        MotionMode::WARPED_CAUSAL => cw
+          .find_local_warp(tile_bo, bsize, ref_frames[0], mvs[0], fi)
+          .is_some(),
+        _ => false,
+      };
+      if let Some(i) = i.filter(|_| enabled) {
+        let mode_set_chroma = ArrayVec::from([luma_mode]);
+        luma_chroma_mode_rdo(
+          luma_mode,
+          fi,
+          bsize,
+          tile_bo,
+          ts,
+          cw,
+          rdo_type,
+          cw_checkpoint,
+          &mut best,
+          mvs,
+          ref_frames,
+          &mode_set_chroma,
+          false,
+          mode_contexts[i],
+          &mv_stacks[i],
+          AngleDelta::default(),
+          &PaletteParams::default(),
+          None,
+          motion_mode,
+          CompoundPrediction::Average,
+          [FilterMode::REGULAR; 2],
+        );
+      }
+    }
+  }
+
+  // Try blending the best block with an intra prediction if it has a single
+  // reference, or weighting its two references by their distance or
+  // blending them through a mask. The best smooth and wedge blends by SATD
+  // are checked.
+  if best.ref_frames[0] != INTRA_FRAME {
+    let (luma_mode, mvs, ref_frames) =
+      (best.pred_mode_luma, best.mvs, best.ref_frames);
+    let mut compounds = ArrayVec::<_, 3>::new();
+    if ref_frames[1] == NONE_FRAME {
+      if fi.sequence.enable_interintra_compound && bsize.interintra_allowed() {
+        let smooth = best_compound_by_satd(
+          fi,
+          ts,
+          bsize,
+          tile_bo,
+          luma_mode,
+          ref_frames,
+          mvs,
+          RAV1E_INTERINTRA_MODES.iter().map(|&mode| {
+            CompoundPrediction::InterIntra { mode, wedge_index: None }
+          }),
+        );
+        compounds.push(smooth);
+        if let CompoundPrediction::InterIntra { mode, .. } = smooth {
+          compounds.push(best_compound_by_satd(
+            fi,
+            ts,
+            bsize,
+            tile_bo,
+            luma_mode,
+            ref_frames,
+            mvs,
+            (0..WEDGE_TYPES as u8).map(|index| {
+              CompoundPrediction::InterIntra { mode, wedge_index: Some(index) }
+            }),
+          ));
+        }
+      }
+    } else {
+      if fi.sequence.enable_jnt_comp {
+        compounds.push(CompoundPrediction::Distance);
+      }
+      if fi.sequence.enable_masked_compound {
+        compounds.push(best_compound_by_satd(
+          fi,
+          ts,
+          bsize,
+          tile_bo,
+          luma_mode,
+          ref_frames,
+          mvs,
+          [false, true]
+            .map(|inverse| CompoundPrediction::DiffWeighted { inverse }),
+        ));
+        if wedge_allowed(bsize) {
+          compounds.push(best_compound_by_satd(
+            fi,
+            ts,
+            bsize,
+            tile_bo,
+            luma_mode,
+            ref_frames,
+            mvs,
+            (0..WEDGE_TYPES as u8).flat_map(|index| {
+              [false, true]
+                .map(|sign| CompoundPrediction::Wedge { index, sign })
+            }),
+          ));
+        }
+      }
+    }
+    if let Some(i) = ref_frames_set.iter().position(|&r| r == ref_frames) {
+      for compound in compounds {
+        let mode_set_chroma = ArrayVec::from([luma_mode]);
+        luma_chroma_mode_rdo(
+          luma_mode,
+          fi,
+          bsize,
+          tile_bo,
+          ts,
+          cw,
+          rdo_type,
+          cw_checkpoint,
+          &mut best,
+          mvs,
+          ref_frames,
+          &mode_set_chroma,
+          false,
+          mode_contexts[i],
+          &mv_stacks[i],
+          AngleDelta::default(),
+          &PaletteParams::default(),
+          None,
+          MotionMode::SIMPLE_TRANSLATION,
+          compound,
+          [FilterMode::REGULAR; 2],
+        );
+      }
+    }
+  }
+
+  // Try the other interpolation filters of the best inter block, or pairs
+  // of them with dual filters. The best ones by SATD are checked.
+  if best.ref_frames[0] != INTRA_FRAME
+    && fi.is_filter_switchable
+    && ContextWriter::needs_interp_filter(
+      fi,
+      bsize,
+      best.pred_mode_luma,
+      best.ref_frames,
+      best.motion_mode,
+    )
+  {
+    let (luma_mode, mvs, ref_frames) =
+      (best.pred_mode_luma, best.mvs, best.ref_frames);
+    let (motion_mode, compound) = (best.motion_mode, best.compound);
+    const FILTERS: [FilterMode; 3] =
+      [FilterMode::REGULAR, FilterMode::SMOOTH, FilterMode::SHARP];
+    let interp_filters: ArrayVec<_, 9> = if fi.sequence.enable_dual_filter {
+      iproduct!(FILTERS, FILTERS).map(|(y, x)| [y, x]).collect()
+    } else {
+      FILTERS.iter().map(|&filter| [filter; 2]).collect()
+    };
+    let interp_filter = interp_filters
+      .into_iter()
+      .min_by_key(|&interp_filter| {
+        inter_luma_satd(
+          fi,
+          ts,
+          bsize,
+          tile_bo,
+          luma_mode,
+          ref_frames,
+          mvs,
+          compound,
+          interp_filter,
+        )
+      })
+      .unwrap();
+    if let Some(i) = ref_frames_set
+      .iter()
+      .position(|&r| r == ref_frames)
+      .filter(|_| interp_filter != best.interp_filter)
+    {
+      let mode_set_chroma = ArrayVec::from([luma_mode]);
+      luma_chroma_mode_rdo(
+        luma_mode,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        mvs,
+        ref_frames,
+        &mode_set_chroma,
+        false,
+        mode_contexts[i],
+        &mv_stacks[i],
+        AngleDelta::default(),
+        &PaletteParams::default(),
+        None,
+        motion_mode,
+        compound,
+        interp_filter,
+      );
+    }
+  }
+
   best
 }
 
+/// Returns the blend of the predictions of an inter block with the lowest
+/// luma SATD out of `compounds`.
+fn best_compound_by_satd<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>, bsize: BlockSize,
+  tile_bo: TileBlockOffset, luma_mode: PredictionMode,
+  ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+  compounds: impl IntoIterator<Item = CompoundPrediction>,
+) -> CompoundPrediction
+where
+  i32: util::math::CastFromPrimitive<T>,
+{
+  compounds
+    .into_iter()
+    .min_by_key(|&compound| {
+      inter_luma_satd(
+        fi,
+        ts,
+        bsize,
+        tile_bo,
+        luma_mode,
+        ref_frames,
+        mvs,
+        compound,
+        [FilterMode::REGULAR; 2],
+      )
+    })
+    .unwrap()
+}
+
+/// Returns the luma SATD of the translational prediction of an inter block
+/// blended as `compound`.
+fn inter_luma_satd<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>, bsize: BlockSize,
+  tile_bo: TileBlockOffset, luma_mode: PredictionMode,
+  ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+  compound: CompoundPrediction, interp_filter: [FilterMode; 2],
+) -> u32
+where
+  i32: util::math::CastFromPrimitive<T>,
+{
+  let tile_rect = ts.tile_rect();
+  let area = Area::BlockStartingAt { bo: tile_bo.0 };
+  let rec = &mut ts.rec.planes[0];
+  let po = tile_bo.plane_offset(&rec.plane_cfg);
+  match compound {
+    CompoundPrediction::InterIntra { mode, wedge_index } => {
+      predict_inter_intra(
+        fi,
+        tile_rect,
+        0,
+        rec,
+        tile_bo,
+        bsize,
+        luma_mode,
+        ref_frames[0],
+        mvs[0],
+        interp_filter,
+        mode,
+        wedge_index,
+      );
+    }
+    CompoundPrediction::Average => {
+      luma_mode.predict_inter(
+        fi,
+        tile_rect,
+        0,
+        po,
+        &mut rec.subregion_mut(area),
+        bsize.width(),
+        bsize.height(),
+        ref_frames,
+        mvs,
+        interp_filter,
+        &mut ts.inter_compound_buffers,
+      );
+    }
+    _ => {
+      luma_mode.predict_inter_masked(
+        fi,
+        tile_rect,
+        0,
+        po,
+        &mut rec.subregion_mut(area),
+        bsize.width(),
+        bsize.height(),
+        ref_frames,
+        mvs,
+        interp_filter,
+        bsize,
+        compound,
+        &mut ts.inter_compound_buffers,
+      );
+    }
+  }
+  get_satd(
+    &ts.input_tile.planes[0].subregion(area),
+    &ts.rec.planes[0].subregion(area),
+    bsize.width(),
+    bsize.height(),
+    fi.sequence.bit_depth,
+    fi.cpu_feature_level,
+  )
+}
+
 #[profiling::function]
 fn intra_frame_rdo_mode_decision<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
@@ -1543,6 +1940,7 @@ where
           0,
           0,
           bsize,
+          fi.sb_size_log2(),
           po,
           tx_size,
           fi.sequence.bit_depth,
@@ -1629,6 +2027,11 @@ where
       0,
       &ArrayVec::<CandidateMV, 9>::new(),
       AngleDelta::default(),
+      &PaletteParams::default(),
+      None,
+      MotionMode::SIMPLE_TRANSLATION,
+      CompoundPrediction::Average,
+      [FilterMode::REGULAR; 2],
     );
   });
 
@@ -1663,6 +2066,11 @@ where
           0,
           &mv_stack,
           AngleDelta { y, uv },
+          &PaletteParams::default(),
+          None,
+          MotionMode::SIMPLE_TRANSLATION,
+          CompoundPrediction::Average,
+          [FilterMode::REGULAR; 2],
         );
       }
       best.angle_delta
@@ -1680,6 +2088,162 @@ where
     }
   }
 
+  if filter_intra_allowed(
+    fi,
+    PredictionMode::DC_PRED,
+    &PaletteParams::default(),
+    bsize,
+  ) {
+    // Try the recursive intra filters in place of DC prediction, keeping the
+    // chroma prediction chosen so far
+    let (chroma_mode, angle_delta) = if best.pred_mode_luma.is_intra() {
+      (best.pred_mode_chroma, AngleDelta { y: 0, uv: best.angle_delta.uv })
+    } else {
+      (PredictionMode::DC_PRED, AngleDelta::default())
+    };
+    let mvs = [MotionVector::default(); 2];
+    let ref_frames = [INTRA_FRAME, NONE_FRAME];
+    let mv_stack = ArrayVec::<_, 9>::new();
+    for &filter_intra_mode in RAV1E_FILTER_INTRA_MODES {
+      luma_chroma_mode_rdo(
+        PredictionMode::DC_PRED,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        mvs,
+        ref_frames,
+        &[chroma_mode],
+        true,
+        0,
+        &mv_stack,
+        angle_delta,
+        &PaletteParams::default(),
+        Some(filter_intra_mode),
+        MotionMode::SIMPLE_TRANSLATION,
+        CompoundPrediction::Average,
+        [FilterMode::REGULAR; 2],
+      );
+    }
+  }
+
+  if fi.allow_screen_content_tools != 0 && bsize.palette_allowed() {
+    // Try palettes made of the few colors of screen content blocks
+    let visible_w = bsize.width().min((ts.mi_width - tile_bo.0.x) * MI_SIZE);
+    let visible_h = bsize.height().min((ts.mi_height - tile_bo.0.y) * MI_SIZE);
+    let area = Area::BlockStartingAt { bo: tile_bo.0 };
+    let luma_candidates = luma_palette_candidates(
+      &ts.input_tile.planes[0].subregion(area),
+      visible_w,
+      visible_h,
+    );
+    let chroma_candidate = if is_chroma_block {
+      let PlaneConfig { xdec, ydec, .. } = ts.input_tile.planes[1].plane_cfg;
+      let (_, _, width, height) =
+        palette_map_size(bsize, visible_w, visible_h, xdec, ydec);
+      chroma_palette_candidate(
+        &ts.input_tile.planes[1].subregion(area),
+        &ts.input_tile.planes[2].subregion(area),
+        width,
+        height,
+      )
+    } else {
+      None
+    };
+
+    let mvs = [MotionVector::default(); 2];
+    let ref_frames = [INTRA_FRAME, NONE_FRAME];
+    let mode_set_chroma = [PredictionMode::DC_PRED];
+    let mv_stack = ArrayVec::<_, 9>::new();
+    let mut palette = PaletteParams::default();
+    for chroma in iter::once(&[][..]).chain(chroma_candidate.as_deref()) {
+      palette.set_chroma(chroma);
+      for luma in
+        iter::once(&[][..]).chain(luma_candidates.iter().map(|c| &c[..]))
+      {
+        if luma.is_empty() && chroma.is_empty() {
+          continue;
+        }
+        palette.set_luma(luma);
+        luma_chroma_mode_rdo(
+          PredictionMode::DC_PRED,
+          fi,
+          bsize,
+          tile_bo,
+          ts,
+          cw,
+          rdo_type,
+          cw_checkpoint,
+          &mut best,
+          mvs,
+          ref_frames,
+          &mode_set_chroma,
+          true,
+          0,
+          &mv_stack,
+          AngleDelta::default(),
+          &palette,
+          None,
+          MotionMode::SIMPLE_TRANSLATION,
+          CompoundPrediction::Average,
+          [FilterMode::REGULAR; 2],
+        );
+      }
+    }
+  }
+
+  if fi.allow_intrabc {
+    // Try copying an exact repeat of the block from the coded area
+    let tile_rect = ts.tile_rect();
+    let width = ts.width.min(fi.width - tile_rect.x);
+    let height = ts.height.min(fi.height - tile_rect.y);
+    let src = &ts.input_tile.planes[0];
+    let hashes = ts
+      .intrabc_hashes
+      .get_or_insert_with(|| IntraBcHashes::new(src, width, height));
+    let ref_dv = cw.find_ref_dv(tile_bo, bsize, fi);
+    let dv = hashes.search(
+      src,
+      &ts.rec.planes[0].as_const(),
+      tile_bo,
+      bsize,
+      ref_dv,
+      ts.mi_width,
+      ts.mi_height,
+      fi.sequence.use_128x128_superblock,
+    );
+
+    if let Some(dv) = dv {
+      luma_chroma_mode_rdo(
+        PredictionMode::NEWMV,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        [dv, MotionVector::default()],
+        [INTRA_FRAME, NONE_FRAME],
+        &[PredictionMode::NEWMV],
+        false,
+        0,
+        &ArrayVec::new(),
+        AngleDelta::default(),
+        &PaletteParams::default(),
+        None,
+        MotionMode::SIMPLE_TRANSLATION,
+        CompoundPrediction::Average,
+        [FilterMode::REGULAR; 2],
+      );
+    }
+  }
+
   best
 }
 
@@ -1743,6 +2307,7 @@ where
         0,
         0,
         bsize,
+        fi.sb_size_log2(),
         po,
         uv_tx_size,
         fi.sequence.bit_depth,
@@ -2140,17 +2705,15 @@ where
     let mut child_modes = ArrayVec::<_, 4>::new();
 
     let cost = match partition {
-      PARTITION_NONE if bsize <= BlockSize::BLOCK_64X64 => {
-        Some(rdo_partition_none(
-          fi,
-          ts,
-          cw,
-          bsize,
-          tile_bo,
-          inter_cfg,
-          &mut child_modes,
-        ))
-      }
+      PARTITION_NONE => Some(rdo_partition_none(
+        fi,
+        ts,
+        cw,
+        bsize,
+        tile_bo,
+        inter_cfg,
+        &mut child_modes,
+      )),
       PARTITION_SPLIT | PARTITION_HORZ | PARTITION_VERT => {
         rdo_partition_simple(
           fi,
@@ -2203,10 +2766,8 @@ where
   i32: util::math::CastFromPrimitive<T>,
   u32: util::math::CastFromPrimitive<T>,
 {
-  let sb_w_blocks =
-    if fi.sequence.use_128x128_superblock { 16 } else { 8 } * sb_w;
-  let sb_h_blocks =
-    if fi.sequence.use_128x128_superblock { 16 } else { 8 } * sb_h;
+  let sb_w_blocks = 8 * sb_w;
+  let sb_h_blocks = 8 * sb_h;
   // Each direction block is 8x8 in y, potentially smaller if subsampled in chroma
   // accumulating in-frame and unpadded
   let mut err = Distortion::zero();
@@ -2267,6 +2828,188 @@ where
   err * fi.dist_scale[pli]
 }
 
+/// Primary strengths tried by [`CDEFSearchMethod::FastSearch`], each
+/// combined with every secondary strength.
+const CDEF_FAST_SEARCH_PRI_STRENGTHS: [u8; 8] = [0, 1, 2, 3, 5, 7, 10, 13];
+/// Number of candidates with the lowest distortion over the frame, for luma
+/// and for chroma, among which the strength pairs are searched.
+const CDEF_PAIR_SEARCH_CANDIDATES: usize = 8;
+
+/// Chooses the CDEF strengths of the frame, and how many of them to
+/// signal, by filtering every superblock of `deblocked` with each
+/// candidate strength.  `ts` and `blocks` must span the whole frame.
+///
+/// Superblocks select a strength index in `rdo_loop_decision`, so this
+/// only needs to find the set of strengths with the lowest total cost
+/// when each superblock uses its best entry.
+///
+/// Returns `cdef_bits` along with the luma and chroma strengths.
+#[profiling::function]
+pub fn rdo_cdef_strengths<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, blocks: &TileBlocks<'_>,
+  deblocked: &Frame<T>,
+) -> (u8, [u8; 8], [u8; 8])
+where
+  i32: util::math::CastFromPrimitive<T>,
+  u32: util::math::CastFromPrimitive<T>,
+{
+  let planes = if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    MAX_PLANES
+  };
+  let candidates: Vec<u8> = match fi.cdef_search_method {
+    // Every primary and secondary strength that fits in the 6 bits of
+    // the frame header.
+    CDEFSearchMethod::FullSearch => (0..64).collect(),
+    _ => CDEF_FAST_SEARCH_PRI_STRENGTHS
+      .iter()
+      .flat_map(|&pri| {
+        (0..CDEF_SEC_STRENGTHS).map(move |sec| pri * CDEF_SEC_STRENGTHS + sec)
+      })
+      .collect(),
+  };
+  let n = candidates.len();
+
+  // Distortion of each superblock with each candidate, for luma and for
+  // both chroma planes together.  Superblocks that are entirely skipped
+  // are left unfiltered and do not code an index.
+  let mut y_err = Vec::new();
+  let mut uv_err = Vec::new();
+  let mut work = deblocked.clone();
+  let sbo_0 = TileSuperBlockOffset(SuperBlockOffset { x: 0, y: 0 });
+  for sby in 0..ts.sb_height {
+    for sbx in 0..ts.sb_width {
+      let sbo = TileSuperBlockOffset(SuperBlockOffset { x: sbx, y: sby });
+      let bo = sbo.block_offset(0, 0).0;
+      let skip = (bo.y..(bo.y + 16).min(blocks.rows())).all(|y| {
+        blocks[y][bo.x..(bo.x + 16).min(blocks.cols())]
+          .iter()
+          .all(|block| block.skip)
+      });
+      if skip {
+        continue;
+      }
+
+      let cdef_dirs = cdef_analyze_superblock(fi, deblocked, blocks, sbo);
+      for &strength in &candidates {
+        cdef_filter_superblock_with_strengths(
+          fi,
+          deblocked,
+          &mut work.as_tile_mut(),
+          blocks,
+          sbo,
+          strength,
+          strength,
+          &cdef_dirs,
+        );
+        let mut err = [0; MAX_PLANES];
+        for (pli, err) in err.iter_mut().enumerate().take(planes) {
+          *err = rdo_loop_plane_error(
+            sbo_0,
+            sbo,
+            1,
+            1,
+            fi,
+            ts,
+            blocks,
+            &work,
+            &ts.input_tile,
+            pli,
+          )
+          .0;
+        }
+        y_err.push(err[0]);
+        uv_err.push(err[1] + err[2]);
+      }
+    }
+  }
+  let coded_sbs = y_err.len() / n;
+  if coded_sbs == 0 {
+    return (0, [0; 8], [0; 8]);
+  }
+
+  // Only pair the candidates with the lowest distortion over the frame, and
+  // the unfiltered one, so that the cost of the search below does not grow
+  // with the square of the number of candidates.
+  let top = |err: &[u64]| -> Vec<usize> {
+    let mut totals: Vec<(u64, usize)> = (0..n)
+      .map(|c| ((0..coded_sbs).map(|sb| err[sb * n + c]).sum(), c))
+      .collect();
+    totals.sort_unstable();
+    let mut top: Vec<usize> = totals
+      .iter()
+      .take(CDEF_PAIR_SEARCH_CANDIDATES)
+      .map(|&(_, c)| c)
+      .collect();
+    if !top.contains(&0) {
+      top.push(0);
+    }
+    top
+  };
+  let y_top = top(&y_err);
+  let uv_top = if planes > 1 { top(&uv_err) } else { vec![0] };
+
+  // Total distortion when every superblock picks its best entry of `set`.
+  let set_err = |set: &[(usize, usize)]| -> u64 {
+    (0..coded_sbs)
+      .map(|sb| {
+        let (y_err, uv_err) = (&y_err[sb * n..], &uv_err[sb * n..]);
+        set.iter().map(|&(y, uv)| y_err[y] + uv_err[uv]).min().unwrap()
+      })
+      .sum()
+  };
+  // The pair of candidates that minimizes the distortion of `set` with
+  // the entry at `slot` replaced by it.
+  let best_pair = |set: &mut Vec<(usize, usize)>, slot: usize| {
+    let mut best = (u64::MAX, (0, 0));
+    for &y in &y_top {
+      for &uv in &uv_top {
+        set[slot] = (y, uv);
+        let err = set_err(set);
+        if err < best.0 {
+          best = (err, (y, uv));
+        }
+      }
+    }
+    set[slot] = best.1;
+    best.0
+  };
+
+  let mut best = (f64::MAX, 0, vec![(0, 0)]);
+  for cdef_bits in 0..4 {
+    // Greedily add one strength pair at a time, then refine each entry
+    // once with the others fixed.
+    let mut set: Vec<(usize, usize)> = Vec::new();
+    let mut err = 0;
+    for slot in 0..1 << cdef_bits {
+      set.push((0, 0));
+      err = best_pair(&mut set, slot);
+    }
+    for slot in 0..set.len() {
+      err = best_pair(&mut set, slot);
+    }
+
+    let strength_bits = if planes > 1 { 12 } else { 6 };
+    let bits = cdef_bits * coded_sbs + strength_bits * set.len();
+    let cost =
+      compute_rd_cost(fi, (bits << OD_BITRES) as u32, ScaledDistortion(err));
+    if cost < best.0 {
+      best = (cost, cdef_bits, set);
+    }
+  }
+
+  let (_, cdef_bits, set) = best;
+  let mut y_strengths = [0; 8];
+  let mut uv_strengths = [0; 8];
+  for (i, &(y, uv)) in set.iter().enumerate() {
+    y_strengths[i] = candidates[y];
+    uv_strengths[i] = candidates[uv];
+  }
+  (cdef_bits as u8, y_strengths, uv_strengths)
+}
+
 /// Passed in a superblock offset representing the upper left corner of
 /// the LRU area we're optimizing.  This area covers the largest LRU in
 /// any of the present planes, but may consist of a number of
@@ -2291,13 +3034,16 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
     MAX_PLANES
   };
 
-  assert!(fi.sequence.enable_cdef || fi.sequence.enable_restoration);
+  assert!(fi.cdef_enabled() || fi.restoration_enabled());
   // Determine area of optimization: Which plane has the largest LRUs?
   // How many LRUs for each?
-  let mut sb_w = 1; // how many superblocks wide the largest LRU
-                    // is/how many SBs we're processing (same thing)
-  let mut sb_h = 1; // how many superblocks wide the largest LRU
-                    // is/how many SBs we're processing (same thing)
+  // Superblocks here are 64x64 units, a 128x128 superblock is always
+  // processed as a whole.
+  let sb_units = 1 << (fi.sb_size_log2() - SUPERBLOCK_TO_PLANE_SHIFT);
+  let mut sb_w = sb_units; // how many superblocks wide the largest LRU
+                           // is/how many SBs we're processing (same thing)
+  let mut sb_h = sb_units; // how many superblocks wide the largest LRU
+                           // is/how many SBs we're processing (same thing)
   let mut lru_w = [0; MAX_PLANES]; // how many LRUs we're processing
   let mut lru_h = [0; MAX_PLANES]; // how many LRUs we're processing
 
@@ -2369,7 +3115,11 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
   // to setting up for cdef.
   let mut cdef_skip = [true; MAX_SB_SIZE * MAX_SB_SIZE];
   let mut cdef_skip_all = true;
-  if fi.sequence.enable_cdef {
+  // A block larger than 64x64 codes a single CDEF index, used by all the
+  // 64x64 units it covers.  Those units take the index of the unit at the
+  // top-left of the block.
+  let mut cdef_tie = [None; MAX_SB_SIZE * MAX_SB_SIZE];
+  if fi.cdef_enabled() {
     for sby in 0..sb_h {
       for sbx in 0..sb_w {
         let blocks = tileblocks_subset.subregion(16 * sbx, 16 * sby, 16, 16);
@@ -2381,6 +3131,18 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
         }
         cdef_skip[sby * MAX_SB_SIZE + sbx] = skip;
         cdef_skip_all &= skip;
+
+        let bsize = blocks[0][0].bsize;
+        let dx = usize::from(
+          bsize.width() > 64 && (ts.sbo.0.x + base_sbo.0.x + sbx) & 1 != 0,
+        );
+        let dy = usize::from(
+          bsize.height() > 64 && (ts.sbo.0.y + base_sbo.0.y + sby) & 1 != 0,
+        );
+        if dx != 0 || dy != 0 {
+          cdef_tie[sby * MAX_SB_SIZE + sbx] =
+            Some((sby - dy) * sb_w + sbx - dx);
+        }
       }
     }
   }
@@ -2392,7 +3154,7 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
   // This should be the same as `cdef_skip_all`, except when cdef is disabled.
   let mut lru_skip_all = true;
   let mut lru_skip = [[true; MAX_PLANES]; MAX_LRU_SIZE * MAX_LRU_SIZE];
-  if fi.sequence.enable_restoration {
+  if fi.restoration_enabled() {
     if fi.config.speed_settings.lru_on_skip {
       lru_skip_all = false;
       lru_skip = [[false; MAX_PLANES]; MAX_LRU_SIZE * MAX_LRU_SIZE];
@@ -2566,8 +3328,13 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
           let loop_sbo =
             TileSuperBlockOffset(SuperBlockOffset { x: sbx, y: sby });
 
-          /* cdef index testing loop */
-          for cdef_index in 0..(1 << fi.cdef_bits) {
+          /* cdef index testing loop; a unit tied to another one can only
+          use its index */
+          let cdef_indices = match cdef_tie[sby * MAX_SB_SIZE + sbx] {
+            Some(tie) => best_index[tie] as u8..best_index[tie] as u8 + 1,
+            None => 0..(1 << fi.cdef_bits),
+          };
+          for cdef_index in cdef_indices {
             let mut err = ScaledDistortion::zero();
             let mut rate = 0;
 
@@ -2583,8 +3350,7 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
             // apply LRF if any
             for pli in 0..planes {
               // We need the cropped-to-visible-frame area of this SB
-              let wh =
-                if fi.sequence.use_128x128_superblock { 128 } else { 64 };
+              let wh = 1 << SUPERBLOCK_TO_PLANE_SHIFT;
               let cdef_ref_plane = cdef_ref.planes().nth(pli).unwrap();
               let cdef_ref_cfg = PlaneConfig::new(&cdef_ref_plane.geometry());
               let PlaneConfig { xdec, ydec, .. } = cdef_ref_cfg;
@@ -2619,7 +3385,7 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
                       &src_subset,
                       pli,
                     );
-                    rate += if fi.sequence.enable_restoration {
+                    rate += if fi.restoration_enabled() {
                       cw.fc.count_lrf_switchable(
                         w,
                         &ts.restoration.as_const(),
@@ -2682,7 +3448,41 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
                       pli,
                     );
                   }
-                  RestorationFilter::Wiener { .. } => unreachable!(), // coming soon
+                  RestorationFilter::Wiener { coeffs } => {
+                    // only run on this single superblock
+                    let loop_po = loop_sbo.plane_offset(&cdef_ref_cfg);
+                    let (x, y) = (loop_po.x as usize, loop_po.y as usize);
+                    wiener_rdo_filter(
+                      coeffs,
+                      fi,
+                      x + vis_width,
+                      y + vis_height,
+                      x,
+                      y,
+                      vis_width,
+                      vis_height,
+                      cdef_ref_plane,
+                      lrf_ref.planes_mut().nth(pli).unwrap(),
+                    );
+                    err += rdo_loop_plane_error(
+                      base_sbo,
+                      loop_sbo,
+                      1,
+                      1,
+                      fi,
+                      ts,
+                      &tileblocks_subset.as_const(),
+                      lrf_ref,
+                      &src_subset,
+                      pli,
+                    );
+                    rate += cw.fc.count_lrf_switchable(
+                      w,
+                      &ts.restoration.as_const(),
+                      best_lrf[lru_y * lru_w[pli] + lru_x][pli],
+                      pli,
+                    );
+                  }
                 }
               } else {
                 // No actual LRU here, compute error directly from CDEF output.
@@ -2824,7 +3624,6 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
                 }
               }
 
-              // Look for a self guided filter
               // We need the cropped-to-visible-frame computation area of this LRU
               let lrf_ref_plane = lrf_ref.planes().nth(pli).unwrap();
               let lrf_ref_cfg = PlaneConfig::new(&lrf_ref_plane.geometry());
@@ -2837,6 +3636,61 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
                   - loop_sbo.plane_offset(&lrf_ref_cfg).y as usize,
               );
 
+              // Look for a Wiener filter
+              let maybe_coeffs = if fi.config.speed_settings.lrf_wiener {
+                wiener_solve(
+                  &src_plane
+                    .subregion(Area::StartingAt { x: lrf_po.x, y: lrf_po.y }),
+                  &PlaneSlice::new(lrf_in_plane, lrf_po.x, lrf_po.y),
+                  vis_width,
+                  vis_height,
+                  pli > 0,
+                )
+              } else {
+                None
+              };
+              if let Some(coeffs) = maybe_coeffs {
+                let current_lrf = RestorationFilter::Wiener { coeffs };
+                let (x, y) = (lrf_po.x as usize, lrf_po.y as usize);
+                wiener_rdo_filter(
+                  coeffs,
+                  fi,
+                  x + vis_width,
+                  y + vis_height,
+                  x,
+                  y,
+                  vis_width,
+                  vis_height,
+                  lrf_in_plane,
+                  lrf_ref.planes_mut().nth(pli).unwrap(),
+                );
+                let err = rdo_loop_plane_error(
+                  base_sbo,
+                  loop_sbo,
+                  lru_sb_w,
+                  lru_sb_h,
+                  fi,
+                  ts,
+                  &tileblocks_subset.as_const(),
+                  lrf_ref,
+                  &src_subset,
+                  pli,
+                );
+                let rate = cw.fc.count_lrf_switchable(
+                  w,
+                  &ts.restoration.as_const(),
+                  current_lrf,
+                  pli,
+                );
+                let cost = compute_rd_cost(fi, rate, err);
+                if cost < best_cost {
+                  best_cost = cost;
+                  best_lrf_cost[lru_y * lru_w[pli] + lru_x][pli] = cost;
+                  best_new_lrf = current_lrf;
+                }
+              }
+
+              // Look for a self guided filter
               // todo: experiment with borrowing border pixels
               // rather than edge-extending. Right now this is
               // hard-clipping to the superblock boundary.
@@ -2928,6 +3782,218 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
   }
 }
 
+/// Error of the `width` by `height` samples at `x`, `y` of `test`, a plane
+/// of the frame `fi`, relative to its input `src`, measured as
+/// [`rdo_loop_plane_error`] does.
+fn lrf_unit_error<T: Pixel>(
+  fi: &FrameInvariants<T>, src: &Plane<T>, test: &Plane<T>, pli: usize,
+  x: usize, y: usize, width: usize, height: usize,
+) -> ScaledDistortion
+where
+  u32: util::math::CastFromPrimitive<T>,
+  i32: util::math::CastFromPrimitive<T>,
+{
+  let PlaneConfig { xdec, ydec, .. } = PlaneConfig::new(&src.geometry());
+  let (bw, bh) = (8 >> xdec, 8 >> ydec);
+  let mut err = Distortion::zero();
+  for by in (0..height).step_by(bh) {
+    for bx in (0..width).step_by(bw) {
+      let (w, h) = (bw.min(width - bx), bh.min(height - by));
+      let (px, py) = (x + bx, y + by);
+      let bias = distortion_scale(
+        fi,
+        PlaneBlockOffset(BlockOffset {
+          x: (px << xdec) >> MI_SIZE_LOG2,
+          y: (py << ydec) >> MI_SIZE_LOG2,
+        }),
+        BlockSize::BLOCK_8X8,
+      );
+      let area =
+        Area::Rect { x: px as isize, y: py as isize, width: w, height: h };
+      let src_region = src.region(area);
+      let test_region = test.region(area);
+      err += if pli == 0 {
+        RawDistortion(cdef_dist_kernel(
+          &src_region,
+          &test_region,
+          w,
+          h,
+          fi.sequence.bit_depth,
+          fi.cpu_feature_level,
+        ) as u64)
+          * bias
+      } else {
+        sse_wxh(
+          &src_region,
+          &test_region,
+          w,
+          h,
+          |_, _| bias,
+          fi.sequence.bit_depth,
+          fi.cpu_feature_level,
+        )
+      };
+    }
+  }
+  err * fi.dist_scale[pli]
+}
+
+/// Chooses the restoration filters of a frame coded with superres. They
+/// apply to the upscaled output of CDEF, `cdeffed`, so unlike in
+/// [`rdo_loop_decision`] they are decided for the whole frame once it is
+/// reconstructed. `fi` describes the upscaled frame and `src` is its input.
+///
+/// Each unit is solved and evaluated over at most `unit_size` samples in
+/// each direction, ignoring the stretch of the last units of the frame.
+#[profiling::function]
+pub fn rdo_lrf_upscaled<T: Pixel>(
+  fi: &FrameInvariants<T>, rs: &mut RestorationState, src: &Frame<T>,
+  cdeffed: &Frame<T>,
+) where
+  u32: util::math::CastFromPrimitive<T>,
+  i32: util::math::CastFromPrimitive<T>,
+{
+  let planes = if fi.sequence.chroma_sampling == ChromaSubsampling::Monochrome
+  {
+    1
+  } else {
+    MAX_PLANES
+  };
+
+  // Rates are counted with the initial CDFs and references, as in
+  // `rdo_loop_decision`.
+  let fc = CDFContext::new(fi.base_q_idx);
+  let w = WriterCounter::new();
+  let mut integral_buffer = IntegralImageBuffer::zeroed(SOLVE_IMAGE_SIZE);
+  let mut lrf_work = cdeffed.clone();
+  let mut filters = Vec::new();
+  {
+    let trs = TileRestorationState::new(
+      rs,
+      PlaneSuperBlockOffset(SuperBlockOffset { x: 0, y: 0 }),
+      fi.sb_width,
+      fi.sb_height,
+    );
+    for pli in 0..planes {
+      let rp_cfg = &rs.planes[pli].cfg;
+      let unit_size = rp_cfg.unit_size;
+      let src_plane = src.planes().nth(pli).unwrap();
+      let in_plane = cdeffed.planes().nth(pli).unwrap();
+      let out_plane = lrf_work.planes_mut().nth(pli).unwrap();
+      let PlaneConfig { xdec, ydec, .. } =
+        PlaneConfig::new(&in_plane.geometry());
+      let crop_w = (fi.width + (1 << xdec >> 1)) >> xdec;
+      let crop_h = (fi.height + (1 << ydec >> 1)) >> ydec;
+
+      for uy in 0..rp_cfg.rows {
+        for ux in 0..rp_cfg.cols {
+          let (x, y) = (ux * unit_size, uy * unit_size);
+          let vis_width = unit_size.min(crop_w - x);
+          let vis_height = unit_size.min(crop_h - y);
+          let src_region = src_plane
+            .region(Area::StartingAt { x: x as isize, y: y as isize });
+          let in_slice = PlaneSlice::new(in_plane, x as isize, y as isize);
+          let cost = |filter: RestorationFilter, test: &Plane<T>| {
+            let rate = fc.count_lrf_switchable(&w, &trs, filter, pli);
+            let err = lrf_unit_error(
+              fi, src_plane, test, pli, x, y, vis_width, vis_height,
+            );
+            compute_rd_cost(fi, rate, err)
+          };
+
+          let mut best_lrf = RestorationFilter::None;
+          let mut best_cost = cost(best_lrf, in_plane);
+
+          let maybe_coeffs = if fi.config.speed_settings.lrf_wiener {
+            wiener_solve(
+              &src_region,
+              &in_slice,
+              vis_width,
+              vis_height,
+              pli > 0,
+            )
+          } else {
+            None
+          };
+          if let Some(coeffs) = maybe_coeffs {
+            wiener_rdo_filter(
+              coeffs,
+              fi,
+              x + vis_width,
+              y + vis_height,
+              x,
+              y,
+              vis_width,
+              vis_height,
+              in_plane,
+              out_plane,
+            );
+            let current_lrf = RestorationFilter::Wiener { coeffs };
+            let current_cost = cost(current_lrf, out_plane);
+            if current_cost < best_cost {
+              best_cost = current_cost;
+              best_lrf = current_lrf;
+            }
+          }
+
+          setup_integral_image(
+            &mut integral_buffer,
+            SOLVE_IMAGE_STRIDE,
+            vis_width,
+            vis_height,
+            vis_width,
+            vis_height,
+            &in_slice,
+            &in_slice,
+          );
+          for &set in get_sgr_sets(fi.config.speed_settings.sgr_complexity) {
+            let (xqd0, xqd1) = sgrproj_solve(
+              set,
+              fi,
+              &integral_buffer,
+              &src_region,
+              &in_slice,
+              vis_width,
+              vis_height,
+            );
+            let xqd = [xqd0, xqd1];
+            sgrproj_stripe_filter(
+              set,
+              xqd,
+              fi,
+              &integral_buffer,
+              SOLVE_IMAGE_STRIDE,
+              &in_slice,
+              &mut out_plane.region_mut(Area::Rect {
+                x: x as isize,
+                y: y as isize,
+                width: vis_width,
+                height: vis_height,
+              }),
+            );
+            let current_lrf = RestorationFilter::Sgrproj { set, xqd };
+            let current_cost = cost(current_lrf, out_plane);
+            if current_cost < best_cost {
+              best_cost = current_cost;
+              best_lrf = current_lrf;
+            }
+          }
+          filters.push(best_lrf);
+        }
+      }
+    }
+  }
+
+  let mut filters = filters.into_iter();
+  for rp in rs.planes.iter_mut().take(planes) {
+    for uy in 0..rp.cfg.rows {
+      for ru in rp.units[uy].iter_mut() {
+        ru.filter = filters.next().unwrap();
+      }
+    }
+  }
+}
+
 #[test]
 fn estimate_rate_test() {
   assert_eq!(estimate_rate(0, TxSize::TX_4X4, 0), RDO_RATE_TABLE[0][0][0]);
@@ -172,9 +172,9 @@ pub fn get_has_tr_table(
 }
 
 pub fn has_top_right(
-  bsize: BlockSize, partition_bo: TileBlockOffset, top_available: bool,
-  right_available: bool, tx_size: TxSize, row_off: usize, col_off: usize,
-  ss_x: usize, _ss_y: usize,
+  bsize: BlockSize, partition_bo: TileBlockOffset, sb_size_log2: usize,
+  top_available: bool, right_available: bool, tx_size: TxSize, row_off: usize,
+  col_off: usize, ss_x: usize, _ss_y: usize,
 ) -> bool {
   if !top_available || !right_available {
     return false;
@@ -189,7 +189,6 @@ pub fn has_top_right(
 
   if row_off > 0 {
     // Just need to check if enough pixels on the right.
-    // 128x128 SB is not supported yet by rav1e
     if bsize.width() > BLOCK_64X64.width() {
       // Special case: For 128x128 blocks, the transform unit whose
       // top-right corner is at the center of the block does in fact have
@@ -212,7 +211,7 @@ pub fn has_top_right(
 
     let bw_in_mi_log2 = bsize.width_log2() - MI_SIZE_LOG2;
     let bh_in_mi_log2 = bsize.height_log2() - MI_SIZE_LOG2;
-    let sb_mi_size: usize = 16; // 64x64
+    let sb_mi_size: usize = 1 << (sb_size_log2 - MI_SIZE_LOG2);
     let blk_row_in_sb = (mi_row & (sb_mi_size - 1)) >> bh_in_mi_log2;
     let blk_col_in_sb = (mi_col & (sb_mi_size - 1)) >> bw_in_mi_log2;
 
@@ -372,9 +371,9 @@ pub fn get_has_bl_table(
 }
 
 pub fn has_bottom_left(
-  bsize: BlockSize, partition_bo: TileBlockOffset, bottom_available: bool,
-  left_available: bool, tx_size: TxSize, row_off: usize, col_off: usize,
-  _ss_x: usize, ss_y: usize,
+  bsize: BlockSize, partition_bo: TileBlockOffset, sb_size_log2: usize,
+  bottom_available: bool, left_available: bool, tx_size: TxSize,
+  row_off: usize, col_off: usize, _ss_x: usize, ss_y: usize,
 ) -> bool {
   if !bottom_available || !left_available {
     return false;
@@ -383,7 +382,6 @@ pub fn has_bottom_left(
   // Special case for 128x* blocks, when col_off is half the block width.
   // This is needed because 128x* superblocks are divided into 64x* blocks in
   // raster order
-  // 128x128 SB is not supported yet by rav1e
   if bsize.width() > BLOCK_64X64.width() && col_off > 0 {
     let plane_bw_unit_64 = BLOCK_64X64.width_mi() >> _ss_x;
     let col_off_64 = col_off % plane_bw_unit_64;
@@ -416,7 +414,7 @@ pub fn has_bottom_left(
 
     let bw_in_mi_log2 = bsize.width_log2() - MI_SIZE_LOG2;
     let bh_in_mi_log2 = bsize.height_log2() - MI_SIZE_LOG2;
-    let sb_mi_size: usize = 16; // 64x64
+    let sb_mi_size: usize = 1 << (sb_size_log2 - MI_SIZE_LOG2);
     let blk_row_in_sb = (mi_row & (sb_mi_size - 1)) >> bh_in_mi_log2;
     let blk_col_in_sb = (mi_col & (sb_mi_size - 1)) >> bw_in_mi_log2;
 
@@ -0,0 +1,419 @@
+// Copyright (c) 2025, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use crate::frame::{AsRegion, Frame, FrameAlloc, FrameIter, Plane, PlanePad};
+use crate::util::Pixel;
+use v_frame::chroma::ChromaSubsampling;
+
+/// Numerator of the horizontal scale factor of superres frames.
+pub const SUPERRES_NUM: u8 = 8;
+/// Smallest denominator of a frame coded at a reduced width.
+pub const SUPERRES_DENOM_MIN: u8 = 9;
+/// Largest denominator of a frame coded at a reduced width.
+pub const SUPERRES_DENOM_MAX: u8 = 16;
+/// Bits of the coded denominator in the frame header.
+pub const SUPERRES_DENOM_BITS: u32 = 3;
+
+const SUPERRES_SCALE_BITS: u32 = 14;
+const SUPERRES_SCALE_MASK: i32 = (1 << SUPERRES_SCALE_BITS) - 1;
+const SUPERRES_EXTRA_BITS: u32 = 8;
+const SUPERRES_FILTER_TAPS: usize = 8;
+const SUPERRES_FILTER_OFFSET: isize = 3;
+
+/// Filters of the normative upscaler, one for each 1/64th sample phase.
+const UPSCALE_FILTER: [[i16; SUPERRES_FILTER_TAPS]; 64] = [
+  [0, 0, 0, 128, 0, 0, 0, 0],
+  [0, 0, -1, 128, 2, -1, 0, 0],
+  [0, 1, -3, 127, 4, -2, 1, 0],
+  [0, 1, -4, 127, 6, -3, 1, 0],
+  [0, 2, -6, 126, 8, -3, 1, 0],
+  [0, 2, -7, 125, 11, -4, 1, 0],
+  [-1, 2, -8, 125, 13, -5, 2, 0],
+  [-1, 3, -9, 124, 15, -6, 2, 0],
+  [-1, 3, -10, 123, 18, -6, 2, -1],
+  [-1, 3, -11, 122, 20, -7, 3, -1],
+  [-1, 4, -12, 121, 22, -8, 3, -1],
+  [-1, 4, -13, 120, 25, -9, 3, -1],
+  [-1, 4, -14, 118, 28, -9, 3, -1],
+  [-1, 4, -15, 117, 30, -10, 4, -1],
+  [-1, 5, -16, 116, 32, -11, 4, -1],
+  [-1, 5, -16, 114, 35, -12, 4, -1],
+  [-1, 5, -17, 112, 38, -12, 4, -1],
+  [-1, 5, -18, 111, 40, -13, 5, -1],
+  [-1, 5, -18, 109, 43, -14, 5, -1],
+  [-1, 6, -19, 107, 45, -14, 5, -1],
+  [-1, 6, -19, 105, 48, -15, 5, -1],
+  [-1, 6, -19, 103, 51, -16, 5, -1],
+  [-1, 6, -20, 101, 53, -16, 6, -1],
+  [-1, 6, -20, 99, 56, -17, 6, -1],
+  [-1, 6, -20, 97, 58, -17, 6, -1],
+  [-1, 6, -20, 95, 61, -18, 6, -1],
+  [-2, 7, -20, 93, 64, -18, 6, -2],
+  [-2, 7, -20, 91, 66, -19, 6, -1],
+  [-2, 7, -20, 88, 69, -19, 6, -1],
+  [-2, 7, -20, 86, 71, -19, 6, -1],
+  [-2, 7, -20, 84, 74, -20, 7, -2],
+  [-2, 7, -20, 81, 76, -20, 7, -1],
+  [-2, 7, -20, 79, 79, -20, 7, -2],
+  [-1, 7, -20, 76, 81, -20, 7, -2],
+  [-2, 7, -20, 74, 84, -20, 7, -2],
+  [-1, 6, -19, 71, 86, -20, 7, -2],
+  [-1, 6, -19, 69, 88, -20, 7, -2],
+  [-1, 6, -19, 66, 91, -20, 7, -2],
+  [-2, 6, -18, 64, 93, -20, 7, -2],
+  [-1, 6, -18, 61, 95, -20, 6, -1],
+  [-1, 6, -17, 58, 97, -20, 6, -1],
+  [-1, 6, -17, 56, 99, -20, 6, -1],
+  [-1, 6, -16, 53, 101, -20, 6, -1],
+  [-1, 5, -16, 51, 103, -19, 6, -1],
+  [-1, 5, -15, 48, 105, -19, 6, -1],
+  [-1, 5, -14, 45, 107, -19, 6, -1],
+  [-1, 5, -14, 43, 109, -18, 5, -1],
+  [-1, 5, -13, 40, 111, -18, 5, -1],
+  [-1, 4, -12, 38, 112, -17, 5, -1],
+  [-1, 4, -12, 35, 114, -16, 5, -1],
+  [-1, 4, -11, 32, 116, -16, 5, -1],
+  [-1, 4, -10, 30, 117, -15, 4, -1],
+  [-1, 3, -9, 28, 118, -14, 4, -1],
+  [-1, 3, -9, 25, 120, -13, 4, -1],
+  [-1, 3, -8, 22, 121, -12, 4, -1],
+  [-1, 3, -7, 20, 122, -11, 3, -1],
+  [-1, 2, -6, 18, 123, -10, 3, -1],
+  [0, 2, -6, 15, 124, -9, 3, -1],
+  [0, 2, -5, 13, 125, -8, 2, -1],
+  [0, 1, -4, 11, 125, -7, 2, 0],
+  [0, 1, -3, 8, 126, -6, 2, 0],
+  [0, 1, -3, 6, 127, -4, 1, 0],
+  [0, 1, -2, 4, 127, -3, 1, 0],
+  [0, 0, -1, 2, 128, -1, 0, 0],
+];
+
+/// Returns the coded width of a frame `upscaled_width` samples wide with
+/// the superres denominator `denom` (`FrameWidth` in the specification).
+pub fn superres_width(upscaled_width: usize, denom: u8) -> usize {
+  let num = SUPERRES_NUM as usize;
+  let denom = denom as usize;
+  let min_width = upscaled_width.min(16);
+  ((upscaled_width * num + denom / 2) / denom).max(min_width)
+}
+
+/// Upscales the planes of `src`, a frame coded `width` samples wide, to
+/// the `upscaled_width` of `dst`, as decoders do with the frames coded
+/// with superres (section 7.16 of the specification).
+///
+/// The source is read up to the width of its mode info units, as the
+/// normative process does, so `src` must hold the decoded samples past
+/// `width` when it is not a multiple of 8.
+pub fn upscale_frame<T: Pixel>(
+  src: &Frame<T>, dst: &mut Frame<T>, width: usize, upscaled_width: usize,
+  height: usize, bit_depth: usize,
+) {
+  for (src_plane, dst_plane) in src.planes().zip(dst.planes_mut()) {
+    let geo = src_plane.geometry();
+    let xdec = geo.subsampling_x.get().trailing_zeros() as usize;
+    let ydec = geo.subsampling_y.get().trailing_zeros() as usize;
+    upscale_plane(
+      src_plane,
+      dst_plane,
+      (width + xdec) >> xdec,
+      ((width + 7) & !7) >> xdec,
+      (upscaled_width + xdec) >> xdec,
+      (height + ydec) >> ydec,
+      bit_depth,
+    );
+  }
+}
+
+fn upscale_plane<T: Pixel>(
+  src: &Plane<T>, dst: &mut Plane<T>, width: usize, max_width: usize,
+  upscaled_width: usize, height: usize, bit_depth: usize,
+) {
+  let down_w = width as i32;
+  let up_w = upscaled_width as i32;
+  let step = ((down_w << SUPERRES_SCALE_BITS) + up_w / 2) / up_w;
+  let err = up_w * step - (down_w << SUPERRES_SCALE_BITS);
+  let initial_subpel =
+    ((-((up_w - down_w) << (SUPERRES_SCALE_BITS - 1)) + up_w / 2) / up_w
+      + (1 << (SUPERRES_EXTRA_BITS - 1))
+      - err / 2)
+      & SUPERRES_SCALE_MASK;
+  let max_x = max_width as isize - 1;
+  let max_value = (1 << bit_depth) - 1;
+
+  let src = src.as_region();
+  let mut dst = dst.as_region_mut();
+  for y in 0..height {
+    let src_row = &src[y];
+    let dst_row = &mut dst[y][..upscaled_width];
+    for (x, out) in dst_row.iter_mut().enumerate() {
+      let src_x =
+        -(1 << SUPERRES_SCALE_BITS) + initial_subpel + x as i32 * step;
+      let phase = (src_x & SUPERRES_SCALE_MASK) >> SUPERRES_EXTRA_BITS;
+      let filter = &UPSCALE_FILTER[phase as usize];
+      let px = (src_x >> SUPERRES_SCALE_BITS) as isize;
+      let sum: i32 = filter
+        .iter()
+        .enumerate()
+        .map(|(k, &tap)| {
+          let sx = (px + k as isize - SUPERRES_FILTER_OFFSET).clamp(0, max_x);
+          src_row[sx as usize].to_i32() * tap as i32
+        })
+        .sum();
+      *out = T::cast_from(((sum + 64) >> 7).clamp(0, max_value));
+    }
+  }
+}
+
+const RESAMPLE_BITS: u32 = 14;
+
+/// Source samples an output sample is interpolated from: the first one,
+/// and the weights of those that follow, in units of `1 << RESAMPLE_BITS`.
+struct ResampleTaps {
+  first: isize,
+  weights: Vec<i32>,
+}
+
+/// Computes the taps of a triangle filter that resamples `src_len` samples
+/// into `dst_len`, spanning twice the distance between output samples when
+/// downscaling so that all of the source contributes to the output.
+fn resample_taps(src_len: usize, dst_len: usize) -> Vec<ResampleTaps> {
+  let scale = src_len as f64 / dst_len as f64;
+  let radius = scale.max(1.0);
+  (0..dst_len)
+    .map(|i| {
+      let center = (i as f64 + 0.5) * scale;
+      let first = (center - radius).floor() as isize;
+      let last = (center + radius).ceil() as isize;
+      let weights: Vec<f64> = (first..=last)
+        .map(|j| (1.0 - (j as f64 + 0.5 - center).abs() / radius).max(0.0))
+        .collect();
+      let sum: f64 = weights.iter().sum();
+      let mut weights: Vec<i32> = weights
+        .iter()
+        .map(|&w| (w / sum * (1 << RESAMPLE_BITS) as f64).round() as i32)
+        .collect();
+      // Give the rounding error to the largest weight.
+      let total: i32 = weights.iter().sum();
+      let largest = (0..weights.len()).max_by_key(|&j| weights[j]).unwrap();
+      weights[largest] += (1 << RESAMPLE_BITS) - total;
+      ResampleTaps { first, weights }
+    })
+    .collect()
+}
+
+/// Resamples a plane `src_w` by `src_h` samples into `dst`, `dst_w` by
+/// `dst_h`, for the motion search and rate-distortion decisions of frames
+/// coded at a reduced size. Unlike [`upscale_frame`], this is not part of
+/// the decoding process.
+pub fn resample_plane<T: Pixel>(
+  src: &Plane<T>, src_w: usize, src_h: usize, dst: &mut Plane<T>,
+  dst_w: usize, dst_h: usize,
+) {
+  let h_taps = resample_taps(src_w, dst_w);
+  let v_taps = resample_taps(src_h, dst_h);
+  let round = 1 << (RESAMPLE_BITS - 1);
+
+  let src = src.as_region();
+  let mut tmp = vec![0i32; dst_w * src_h];
+  for (y, tmp_row) in tmp.chunks_exact_mut(dst_w).enumerate() {
+    let src_row = &src[y];
+    for (out, taps) in tmp_row.iter_mut().zip(&h_taps) {
+      let sum: i32 = taps
+        .weights
+        .iter()
+        .enumerate()
+        .map(|(k, &w)| {
+          let sx = (taps.first + k as isize).clamp(0, src_w as isize - 1);
+          src_row[sx as usize].to_i32() * w
+        })
+        .sum();
+      *out = (sum + round) >> RESAMPLE_BITS;
+    }
+  }
+
+  {
+    let mut dst = dst.as_region_mut();
+    for (y, taps) in v_taps.iter().enumerate() {
+      let dst_row = &mut dst[y][..dst_w];
+      for (x, out) in dst_row.iter_mut().enumerate() {
+        let sum: i32 = taps
+          .weights
+          .iter()
+          .enumerate()
+          .map(|(k, &w)| {
+            let sy = (taps.first + k as isize).clamp(0, src_h as isize - 1);
+            tmp[sy as usize * dst_w + x] * w
+          })
+          .sum();
+        *out = T::cast_from((sum + round) >> RESAMPLE_BITS);
+      }
+    }
+  }
+  dst.pad(dst_w, dst_h);
+}
+
+/// Returns `src`, a frame `src_w` by `src_h` samples, resampled to a frame
+/// `dst_w` by `dst_h` samples with [`resample_plane`].
+pub fn resample_frame<T: Pixel>(
+  src: &Frame<T>, src_w: usize, src_h: usize, dst_w: usize, dst_h: usize,
+) -> Frame<T> {
+  let mut dst = Frame::new(dst_w, dst_h, src.subsampling);
+  for (src_plane, dst_plane) in src.planes().zip(dst.planes_mut()) {
+    let geo = src_plane.geometry();
+    let xdec = geo.subsampling_x.get().trailing_zeros() as usize;
+    let ydec = geo.subsampling_y.get().trailing_zeros() as usize;
+    resample_plane(
+      src_plane,
+      (src_w + xdec) >> xdec,
+      (src_h + ydec) >> ydec,
+      dst_plane,
+      (dst_w + xdec) >> xdec,
+      (dst_h + ydec) >> ydec,
+    );
+  }
+  dst
+}
+
+/// Returns a luma-only plane `src_w` by `src_h` samples resampled to
+/// `dst_w` by `dst_h`, with the padding of the frame planes.
+pub fn resampled_plane<T: Pixel>(
+  src: &Plane<T>, src_w: usize, src_h: usize, dst_w: usize, dst_h: usize,
+) -> Plane<T> {
+  let frame = Frame::new(dst_w, dst_h, ChromaSubsampling::Monochrome);
+  let mut dst = frame.planes().next().unwrap().clone();
+  resample_plane(src, src_w, src_h, &mut dst, dst_w, dst_h);
+  dst
+}
+
+/// Resamples a grid of per-block values `src_w` by `src_h` to `dst_w` by
+/// `dst_h`, taking for each block the value of the nearest source block.
+pub fn resample_grid<U: Copy>(
+  src: &[U], src_w: usize, src_h: usize, dst_w: usize, dst_h: usize,
+) -> Box<[U]> {
+  if src.is_empty() {
+    return Box::new([]);
+  }
+  let map = |i: usize, src_len: usize, dst_len: usize| {
+    ((2 * i + 1) * src_len / (2 * dst_len)).min(src_len - 1)
+  };
+  (0..dst_h)
+    .flat_map(|y| {
+      let sy = map(y, src_h, dst_h);
+      (0..dst_w).map(move |x| src[sy * src_w + map(x, src_w, dst_w)])
+    })
+    .collect()
+}
+
+/// Superres denominator of a frame in [`SuperresMode::Random`] mode, which
+/// only depends on its input frame number so that encodes are reproducible.
+///
+/// [`SuperresMode::Random`]: crate::encoder::SuperresMode::Random
+pub fn random_denom(input_frameno: u64) -> u8 {
+  // The finalizer of splitmix64.
+  let mut z = input_frameno.wrapping_add(0x9E37_79B9_7F4A_7C15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+  z ^= z >> 31;
+  SUPERRES_NUM + (z % (SUPERRES_DENOM_MAX - SUPERRES_NUM + 1) as u64) as u8
+}
+
+/// Superres denominator of a frame with base quantizer index `qindex` in
+/// [`SuperresMode::Dynamic`] mode: the width is reduced in proportion to
+/// how far the quantizer exceeds `qthresh`.
+///
+/// [`SuperresMode::Dynamic`]: crate::encoder::SuperresMode::Dynamic
+pub fn dynamic_denom(qindex: u8, qthresh: u8) -> u8 {
+  if qindex <= qthresh {
+    return SUPERRES_NUM;
+  }
+  let excess = (qindex - qthresh) as usize;
+  let range = 256 - qthresh as usize;
+  let steps = (SUPERRES_DENOM_MAX - SUPERRES_NUM) as usize;
+  SUPERRES_NUM + (excess * steps).div_ceil(range).max(1) as u8
+}
+
+/// Returns the denominator closest to `denom` that codes a frame
+/// `upscaled_width` samples wide at an even width, or `denom` if there is
+/// none.
+///
+/// Scaled motion compensation positions chroma with the luma scale factor
+/// while the upscaler uses the ratio of the chroma plane widths, so with
+/// horizontally subsampled chroma and an odd coded width predictions from
+/// full width references drift by up to 3/4 of a chroma sample across the
+/// frame.
+pub fn even_width_denom(upscaled_width: usize, denom: u8) -> u8 {
+  if denom == SUPERRES_NUM {
+    return denom;
+  }
+  (0..=SUPERRES_DENOM_MAX - SUPERRES_DENOM_MIN)
+    .flat_map(|d| [denom.saturating_add(d), denom.saturating_sub(d)])
+    .filter(|d| (SUPERRES_DENOM_MIN..=SUPERRES_DENOM_MAX).contains(d))
+    .find(|&d| superres_width(upscaled_width, d) % 2 == 0)
+    .unwrap_or(denom)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn upscale_filter_is_normalized() {
+    for filter in UPSCALE_FILTER.iter() {
+      assert_eq!(filter.iter().map(|&t| t as i32).sum::<i32>(), 128);
+    }
+  }
+
+  #[test]
+  fn coded_widths() {
+    assert_eq!(superres_width(1920, 16), 960);
+    assert_eq!(superres_width(1920, 9), 1707);
+    assert_eq!(superres_width(17, 16), 16);
+    assert_eq!(superres_width(12, 16), 12);
+  }
+
+  #[test]
+  fn dynamic_denoms() {
+    assert_eq!(dynamic_denom(100, 128), 8);
+    assert_eq!(dynamic_denom(129, 128), 9);
+    assert_eq!(dynamic_denom(255, 128), 16);
+    for frameno in 0..64 {
+      assert!((8..=16).contains(&random_denom(frameno)));
+    }
+  }
+
+  #[test]
+  fn even_width_denoms() {
+    // 352 samples are coded 235 wide with a denominator of 12.
+    assert_eq!(even_width_denom(352, 12), 11);
+    assert_eq!(even_width_denom(352, 16), 16);
+    assert_eq!(even_width_denom(352, 8), 8);
+  }
+
+  #[test]
+  fn resample_round_trip_of_flat_plane() {
+    let mut frame = Frame::<u8>::new(64, 32, ChromaSubsampling::Yuv420);
+    for plane in frame.planes_mut() {
+      for p in plane.data_mut() {
+        *p = 77;
+      }
+    }
+    let small = resample_frame(&frame, 64, 32, 40, 32);
+    let mut up = Frame::<u8>::new(64, 32, ChromaSubsampling::Yuv420);
+    upscale_frame(&small, &mut up, 40, 64, 32, 8);
+    for plane in up.planes() {
+      let region = plane.as_region();
+      let geo = plane.geometry();
+      let w = 64 >> geo.subsampling_x.get().trailing_zeros();
+      let h = 32 >> geo.subsampling_y.get().trailing_zeros();
+      for y in 0..h {
+        assert!(region[y][..w].iter().all(|&p| p == 77));
+      }
+    }
+  }
+}
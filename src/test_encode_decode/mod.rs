@@ -74,7 +74,7 @@ pub(crate) trait TestDecoder<T: Pixel> {
     min_keyint: u64, max_keyint: u64, switch_frame_interval: u64,
     low_latency: bool, error_resilient: bool, bitrate: i32,
     tile_cols_log2: usize, tile_rows_log2: usize, still_picture: bool,
-    grain_table: Option<Vec<GrainTableSegment>>,
+    lossless: bool, grain_table: Option<Vec<GrainTableSegment>>,
   ) {
     let mut ra = ChaChaRng::from_seed([0; 32]);
 
@@ -94,6 +94,7 @@ pub(crate) trait TestDecoder<T: Pixel> {
       tile_cols_log2,
       tile_rows_log2,
       still_picture,
+      lossless,
       grain_table,
     );
 
@@ -124,6 +125,39 @@ pub(crate) trait TestDecoder<T: Pixel> {
           ivf::write_ivf_frame(&mut out, pkt.input_frameno, &pkt.data);
 
           if let Some(pkt_rec) = pkt.rec {
+            // Lossless reconstructions must match the source exactly
+            if lossless {
+              let src = pkt.source.as_ref().unwrap();
+              let planes =
+                if chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+              for (pli, (rec_plane, src_plane)) in pkt_rec
+                .planes
+                .iter()
+                .zip(src.planes.iter())
+                .enumerate()
+                .take(planes)
+              {
+                let rec: Vec<u16> = rec_plane
+                  .data_origin()
+                  .iter()
+                  .map(|&v| u16::cast_from(v))
+                  .collect();
+                let src: Vec<u16> = src_plane
+                  .data_origin()
+                  .iter()
+                  .map(|&v| u16::cast_from(v))
+                  .collect();
+                compare_plane::<u16>(
+                  &rec[..],
+                  rec_plane.cfg.stride,
+                  &src[..],
+                  src_plane.cfg.stride,
+                  w >> rec_plane.cfg.xdec,
+                  h >> rec_plane.cfg.ydec,
+                  pli,
+                );
+              }
+            }
             rec_fifo.push_back((*pkt_rec).clone());
           }
           let packet = pkt.data;
@@ -180,11 +214,13 @@ fn setup_encoder<T: Pixel>(
   chroma_sampling: ChromaSampling, min_keyint: u64, max_keyint: u64,
   switch_frame_interval: u64, low_latency: bool, error_resilient: bool,
   bitrate: i32, tile_cols_log2: usize, tile_rows_log2: usize,
-  still_picture: bool, grain_table: Option<Vec<GrainTableSegment>>,
+  still_picture: bool, lossless: bool,
+  grain_table: Option<Vec<GrainTableSegment>>,
 ) -> Context<T> {
   assert!(bit_depth == 8 || std::mem::size_of::<T>() > 1);
   let mut enc = EncoderConfig::with_speed_preset(speed);
   enc.quantizer = quantizer;
+  enc.lossless = lossless;
   enc.min_key_frame_interval = min_keyint;
   enc.max_key_frame_interval = max_keyint;
   enc.switch_frame_interval = switch_frame_interval;
@@ -239,6 +275,7 @@ fn speed(s: u8, decoder: &str) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -333,6 +370,7 @@ fn dimension(w: usize, h: usize, decoder: &str) {
     0,
     0,
     still_picture,
+    false,
     None,
   );
 }
@@ -363,6 +401,7 @@ fn quantizer(decoder: &str, q: usize) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -414,6 +453,7 @@ fn bitrate(decoder: &str) {
         0,
         0,
         false,
+        false,
         None,
       );
     }
@@ -448,6 +488,7 @@ fn keyframes(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -481,6 +522,7 @@ fn reordering(decoder: &str) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -516,6 +558,7 @@ fn reordering_short_video(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -549,6 +592,7 @@ fn error_resilient(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -582,6 +626,7 @@ fn error_resilient_reordering(decoder: &str) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -616,6 +661,7 @@ fn switch_frame(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -649,6 +695,7 @@ fn odd_size_frame_with_full_rdo(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -683,6 +730,7 @@ fn low_bit_depth(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -713,6 +761,7 @@ fn high_bit_depth(decoder: &str, depth: usize) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -734,6 +783,49 @@ macro_rules! test_high_bit_depth {
 
 test_high_bit_depth! {10, 12}
 
+fn lossless<T: Pixel>(decoder: &str, bit_depth: usize) {
+  let quantizer = 100;
+  let limit = 3; // Include inter frames
+  let speed = 6;
+  let w = 64;
+  let h = 80;
+
+  let mut dec = get_decoder::<T>(decoder, w as usize, h as usize);
+  dec.encode_decode(
+    true,
+    w,
+    h,
+    speed,
+    quantizer,
+    limit,
+    bit_depth,
+    Default::default(),
+    15,
+    15,
+    0,
+    true,
+    false,
+    0,
+    0,
+    0,
+    false,
+    true,
+    None,
+  );
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn lossless_8(decoder: &str) {
+  lossless::<u8>(decoder, 8);
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn lossless_10(decoder: &str) {
+  lossless::<u16>(decoder, 10);
+}
+
 fn chroma_sampling(decoder: &str, cs: ChromaSampling) {
   let quantizer = 100;
   let limit = 3; // Include inter frames
@@ -744,7 +836,7 @@ fn chroma_sampling(decoder: &str, cs: ChromaSampling) {
   let mut dec = get_decoder::<u8>(decoder, w as usize, h as usize);
   dec.encode_decode(
     true, w, h, speed, quantizer, limit, 8, cs, 15, 15, 0, true, false, 0, 0,
-    0, false, None,
+    0, false, false, None,
   );
 }
 
@@ -796,6 +888,7 @@ fn tile_encoding_with_stretched_restoration_units(decoder: &str) {
     2,
     2,
     false,
+    false,
     None,
   );
 }
@@ -828,6 +921,7 @@ fn still_picture_mode(decoder: &str) {
     0,
     0,
     true,
+    false,
     None,
   );
 }
@@ -873,6 +967,7 @@ fn rdo_loop_decision_lrf_sanity(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -906,6 +1001,7 @@ fn rdo_loop_decision_cdef_sanity(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -939,6 +1035,7 @@ fn film_grain_table_luma_only(decoder: &str) {
     0,
     0,
     false,
+    false,
     Some(vec![GrainTableSegment {
       start_time: 0,
       end_time: 9223372036854775807,
@@ -1009,6 +1106,7 @@ fn film_grain_table_chroma(decoder: &str) {
     0,
     0,
     false,
+    false,
     Some(vec![GrainTableSegment {
       start_time: 0,
       end_time: 9223372036854775807,
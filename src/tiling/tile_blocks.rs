@@ -8,9 +8,12 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use crate::context::*;
-use crate::mc::MotionVector;
+use crate::mc::{FilterMode, MotionVector};
+use crate::palette::PaletteParams;
 use crate::partition::*;
-use crate::predict::PredictionMode;
+use crate::predict::{
+  CompoundPrediction, FilterIntraMode, MotionMode, PredictionMode,
+};
 use crate::transform::*;
 
 use std::cmp;
@@ -248,6 +251,46 @@ impl TileBlocksMut<'_> {
     self.for_each(bo, bsize, |block| block.txsize = tx_size);
   }
 
+  #[inline(always)]
+  pub fn set_palette(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize, palette: &PaletteParams,
+  ) {
+    self.for_each(bo, bsize, |block| block.palette = *palette);
+  }
+
+  #[inline(always)]
+  pub fn set_filter_intra_mode(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize,
+    filter_intra_mode: Option<FilterIntraMode>,
+  ) {
+    self.for_each(bo, bsize, |block| {
+      block.filter_intra_mode = filter_intra_mode
+    });
+  }
+
+  #[inline(always)]
+  pub fn set_motion_mode(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize, motion_mode: MotionMode,
+  ) {
+    self.for_each(bo, bsize, |block| block.motion_mode = motion_mode);
+  }
+
+  #[inline(always)]
+  pub fn set_compound(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize,
+    compound: CompoundPrediction,
+  ) {
+    self.for_each(bo, bsize, |block| block.compound = compound);
+  }
+
+  #[inline(always)]
+  pub fn set_interp_filter(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize,
+    interp_filter: [FilterMode; 2],
+  ) {
+    self.for_each(bo, bsize, |block| block.interp_filter = interp_filter);
+  }
+
   #[inline(always)]
   pub fn set_skip(
     &mut self, bo: TileBlockOffset, bsize: BlockSize, skip: bool,
@@ -279,7 +322,7 @@ impl TileBlocksMut<'_> {
   #[inline(always)]
   pub fn set_cdef(&mut self, sbo: TileSuperBlockOffset, cdef_index: u8) {
     let bo = sbo.block_offset(0, 0).0;
-    // Checkme: Is 16 still the right block unit for 128x128 superblocks?
+    // CDEF indices are per 64x64 unit, whatever the superblock size.
     let bw = cmp::min(bo.x + MIB_SIZE, self.cols);
     let bh = cmp::min(bo.y + MIB_SIZE, self.rows);
     for y in bo.y..bh {
@@ -12,6 +12,7 @@ use super::*;
 use crate::context::*;
 use crate::encoder::*;
 use crate::frame::*;
+use crate::intrabc::IntraBcHashes;
 use crate::lrf::{IntegralImageBuffer, SOLVE_IMAGE_SIZE};
 use crate::mc::MotionVector;
 use crate::me::FrameMEStats;
@@ -47,6 +48,10 @@ use std::sync::Arc;
 ///
 /// Some others (like `rec`) are written tile-wise, but must be accessible
 /// frame-wise once the tile views vanish (e.g. for deblocking).
+///
+/// `sbo`, `sb_width` and `sb_height` are expressed in 64x64 units, the
+/// granularity of CDEF and of the encoder's per-superblock state, whatever
+/// the coded superblock size `sb_size_log2` is.
 pub struct TileStateMut<'a, T: Pixel> {
   pub sbo: PlaneSuperBlockOffset,
   pub sb_size_log2: usize,
@@ -69,6 +74,7 @@ pub struct TileStateMut<'a, T: Pixel> {
   pub coded_block_info: MiTileState,
   pub integral_buffer: IntegralImageBuffer,
   pub inter_compound_buffers: InterCompoundBuffers,
+  pub intrabc_hashes: Option<IntraBcHashes>,
 }
 
 impl<'a, T: Pixel> fmt::Debug for TileStateMut<'a, T> {
@@ -158,17 +164,20 @@ impl<'a, T: Pixel> TileStateMut<'a, T> {
       "Tile width must be a multiple of MI_SIZE"
     );
 
-    let sb_rounded_width = width.align_power_of_two(sb_size_log2);
-    let sb_rounded_height = height.align_power_of_two(sb_size_log2);
+    let sb_rounded_width = width.align_power_of_two(SUPERBLOCK_TO_PLANE_SHIFT);
+    let sb_rounded_height =
+      height.align_power_of_two(SUPERBLOCK_TO_PLANE_SHIFT);
 
     let luma_rect = TileRect {
-      x: sbo.0.x << sb_size_log2,
-      y: sbo.0.y << sb_size_log2,
+      x: sbo.0.x << SUPERBLOCK_TO_PLANE_SHIFT,
+      y: sbo.0.y << SUPERBLOCK_TO_PLANE_SHIFT,
       width: sb_rounded_width,
       height: sb_rounded_height,
     };
-    let sb_width = width.align_power_of_two_and_shift(sb_size_log2);
-    let sb_height = height.align_power_of_two_and_shift(sb_size_log2);
+    let sb_width =
+      width.align_power_of_two_and_shift(SUPERBLOCK_TO_PLANE_SHIFT);
+    let sb_height =
+      height.align_power_of_two_and_shift(SUPERBLOCK_TO_PLANE_SHIFT);
 
     Self {
       sbo,
@@ -198,8 +207,8 @@ impl<'a, T: Pixel> TileStateMut<'a, T> {
         .map(|fmvs| {
           TileMEStatsMut::new(
             fmvs,
-            sbo.0.x << (sb_size_log2 - MI_SIZE_LOG2),
-            sbo.0.y << (sb_size_log2 - MI_SIZE_LOG2),
+            sbo.0.x << SUPERBLOCK_TO_BLOCK_SHIFT,
+            sbo.0.y << SUPERBLOCK_TO_BLOCK_SHIFT,
             width >> MI_SIZE_LOG2,
             height >> MI_SIZE_LOG2,
           )
@@ -211,14 +220,15 @@ impl<'a, T: Pixel> TileStateMut<'a, T> {
       ),
       integral_buffer: IntegralImageBuffer::zeroed(SOLVE_IMAGE_SIZE),
       inter_compound_buffers: InterCompoundBuffers::default(),
+      intrabc_hashes: None,
     }
   }
 
   #[inline(always)]
   pub fn tile_rect(&self) -> TileRect {
     TileRect {
-      x: self.sbo.0.x << self.sb_size_log2,
-      y: self.sbo.0.y << self.sb_size_log2,
+      x: self.sbo.0.x << SUPERBLOCK_TO_PLANE_SHIFT,
+      y: self.sbo.0.y << SUPERBLOCK_TO_PLANE_SHIFT,
       width: self.width,
       height: self.height,
     }
@@ -228,8 +238,8 @@ impl<'a, T: Pixel> TileStateMut<'a, T> {
   pub fn to_frame_block_offset(
     &self, tile_bo: TileBlockOffset,
   ) -> PlaneBlockOffset {
-    let bx = self.sbo.0.x << (self.sb_size_log2 - MI_SIZE_LOG2);
-    let by = self.sbo.0.y << (self.sb_size_log2 - MI_SIZE_LOG2);
+    let bx = self.sbo.0.x << SUPERBLOCK_TO_BLOCK_SHIFT;
+    let by = self.sbo.0.y << SUPERBLOCK_TO_BLOCK_SHIFT;
     PlaneBlockOffset(BlockOffset { x: bx + tile_bo.0.x, y: by + tile_bo.0.y })
   }
 
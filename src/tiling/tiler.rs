@@ -214,12 +214,14 @@ impl<'a, T: Pixel> Iterator for TileContextIterMut<'a, T> {
             let ptr = self.frame_me_stats.as_mut_ptr();
             std::slice::from_raw_parts_mut(ptr, len)
           };
+          // The tile state counts superblocks in 64x64 units.
+          let sb_shift = self.ti.sb_size_log2 - SUPERBLOCK_TO_PLANE_SHIFT;
           let sbo = PlaneSuperBlockOffset(SuperBlockOffset {
-            x: tile_col * self.ti.tile_width_sb,
-            y: tile_row * self.ti.tile_height_sb,
+            x: (tile_col * self.ti.tile_width_sb) << sb_shift,
+            y: (tile_row * self.ti.tile_height_sb) << sb_shift,
           });
-          let x = sbo.0.x << self.ti.sb_size_log2;
-          let y = sbo.0.y << self.ti.sb_size_log2;
+          let x = sbo.0.x << SUPERBLOCK_TO_PLANE_SHIFT;
+          let y = sbo.0.y << SUPERBLOCK_TO_PLANE_SHIFT;
           let tile_width = self.ti.tile_width_sb << self.ti.sb_size_log2;
           let tile_height = self.ti.tile_height_sb << self.ti.sb_size_log2;
           let width = tile_width.min(self.ti.frame_width - x);
@@ -442,6 +444,31 @@ pub mod test {
     }
   }
 
+  #[test]
+  fn test_tile_sbo_128x128_superblocks() {
+    let (_, mut fs, mut fb, frame_rate) = setup(320, 256);
+
+    // 2x2 tiles of 2x1 128x128 superblocks, except the last column
+    let ti =
+      TilingInfo::from_target_tiles(7, 320, 256, frame_rate, 1, 1, false);
+    assert_eq!((2, 1), (ti.tile_width_sb, ti.tile_height_sb));
+    let iter = ti.tile_iter_mut(&mut fs, &mut fb);
+    let tile_states = iter.map(|ctx| ctx.ts).collect::<Vec<_>>();
+
+    // The tile states count superblocks in 64x64 units
+    let sbo = |ts: &TileStateMut<'_, u16>| (ts.sbo.0.x, ts.sbo.0.y);
+    let sb_size = |ts: &TileStateMut<'_, u16>| (ts.sb_width, ts.sb_height);
+    assert_eq!(4, tile_states.len());
+    assert_eq!((0, 0), sbo(&tile_states[0]));
+    assert_eq!((4, 0), sbo(&tile_states[1]));
+    assert_eq!((0, 2), sbo(&tile_states[2]));
+    assert_eq!((4, 2), sbo(&tile_states[3]));
+    assert_eq!((4, 2), sb_size(&tile_states[0]));
+    assert_eq!((1, 2), sb_size(&tile_states[1]));
+    assert_eq!((256, 128), (tile_states[2].width, tile_states[2].height));
+    assert_eq!((256, 128, 64, 128), rect(&tile_states[3].rec.planes[0]));
+  }
+
   #[inline]
   fn rect<T: Pixel>(
     region: &PlaneRegionMut<'_, T>,
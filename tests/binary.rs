@@ -72,6 +72,128 @@ mod binary {
       .success();
   }
 
+  #[interpolate_test(mkv, "mkv", "matroska")]
+  #[interpolate_test(webm, "webm", "webm")]
+  fn matroska_output(extension: &str, doc_type: &str) {
+    let outfile = get_tempfile_path(extension);
+
+    get_rav1e_command(false)
+      .args(["--quantizer", "100"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let data = std::fs::read(&outfile).unwrap();
+    assert_eq!(data[..4], [0x1A, 0x45, 0xDF, 0xA3]);
+    assert!(data.windows(doc_type.len()).any(|w| w == doc_type.as_bytes()));
+    assert!(data.windows(5).any(|w| w == b"V_AV1"));
+  }
+
+  #[interpolate_test(progressive, false)]
+  #[interpolate_test(fragmented, true)]
+  fn mp4_output(fragmented: bool) {
+    let outfile = get_tempfile_path("mp4");
+
+    let mut cmd = get_rav1e_command(false);
+    if fragmented {
+      cmd.arg("--fragmented-mp4");
+    }
+    cmd
+      .args(["--quantizer", "100"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let data = std::fs::read(&outfile).unwrap();
+    assert_eq!(&data[4..8], b"ftyp");
+    assert!(data.windows(4).any(|w| w == b"av1C"));
+    assert_eq!(data.windows(4).any(|w| w == b"moof"), fragmented);
+  }
+
+  #[interpolate_test(opaque, false)]
+  #[interpolate_test(alpha, true)]
+  fn avif_output(alpha: bool) {
+    let outfile = get_tempfile_path("avif");
+
+    let mut cmd = get_rav1e_command(false);
+    if alpha {
+      let alphafile = get_tempfile_path("y4m");
+      std::fs::write(&alphafile, get_y4m_input()).unwrap();
+      cmd.arg("--alpha").arg(&alphafile);
+    }
+    cmd
+      .args(["--quantizer", "100", "--still-picture"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let data = std::fs::read(&outfile).unwrap();
+    assert_eq!(&data[4..12], b"ftypavif");
+    assert!(data.windows(4).any(|w| w == b"colr"));
+    assert_eq!(data.windows(4).any(|w| w == b"auxC"), alpha);
+  }
+
+  #[test]
+  fn obu_output() {
+    let outfile = get_tempfile_path("obu");
+
+    get_rav1e_command(false)
+      .args(["--quantizer", "100"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let data = std::fs::read(&outfile).unwrap();
+    // Temporal delimiter, then the sequence header.
+    assert_eq!(data[..3], [0x12, 0x00, 0x0A]);
+  }
+
+  #[test]
+  fn annex_b_output() {
+    let outfile = get_tempfile_path("av1");
+
+    get_rav1e_command(false)
+      .args(["--quantizer", "100", "--container", "annexb"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let data = std::fs::read(&outfile).unwrap();
+    let mut pos = 0;
+    let mut read_leb128 = || {
+      let mut value = 0;
+      for i in 0.. {
+        value |= ((data[pos] & 0x7F) as usize) << (i * 7);
+        pos += 1;
+        if data[pos - 1] & 0x80 == 0 {
+          return value;
+        }
+      }
+      unreachable!()
+    };
+    let temporal_unit_size = read_leb128();
+    let frame_unit_size = read_leb128();
+    assert!(frame_unit_size < temporal_unit_size);
+    // A temporal delimiter without its size field.
+    assert_eq!(read_leb128(), 1);
+    assert_eq!(data[pos], 0x10);
+  }
+
   #[interpolate_test(low_bitdepth, false)]
   #[interpolate_test(high_bitdepth, true)]
   fn one_pass_bitrate_based(high_bitdepth: bool) {
@@ -6,11 +6,8 @@ fn send_frame() -> Result<(), Box<dyn std::error::Error>> {
   let mut ctx: Context<u8> = cfg.new_context().unwrap();
   let f1 = ctx.new_frame();
   let f2 = f1.clone();
-  let info = FrameParameters {
-    frame_type_override: FrameTypeOverride::Key,
-    opaque: None,
-    ..Default::default()
-  };
+  let mut info = FrameParameters::default();
+  info.frame_type_override = FrameTypeOverride::Key;
 
   // Send the plain frame data
   ctx.send_frame(f1)?;